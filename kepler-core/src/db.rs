@@ -1,34 +1,61 @@
 use crate::events::{epoch_hash, Delegation, Event, HashError, Invocation, Operation, Revocation};
-use crate::hash::Hash;
+use crate::hash::{Hash, HashAlgorithm};
 use crate::keys::{get_did_key, Secrets};
 use crate::migrations::Migrator;
 use crate::models::*;
+use crate::pointer::{Pointer, PointerPayload};
+use crate::receipt::{Invoked, Receipt, ReceiptError, ReceiptPayload};
 use crate::relationships::*;
+use crate::replay::{Claims, ReplayProtection};
+use crate::resolver::DidResolver;
 use crate::storage::{
-    either::EitherError, Content, HashBuffer, ImmutableDeleteStore, ImmutableReadStore,
-    ImmutableStaging, ImmutableWriteStore, StorageSetup, StoreSize,
+    either::EitherError, with_timeout, Content, HashBuffer, ImmutableDeleteStore,
+    ImmutableReadStore, ImmutableStaging, ImmutableWriteStore, StorageSetup, StorageTimeout,
+    StoreSize,
 };
 use crate::types::{Metadata, OrbitIdWrap, Resource};
-use crate::util::{Capability, DelegationInfo};
+use crate::util::{Capability, DelegationInfo, MethodAllowlist};
+use futures::stream::{self, Stream, TryStreamExt};
 use kepler_lib::{
     authorization::{EncodingError, KeplerDelegation},
+    libipld::cid::Cid,
     resource::OrbitId,
 };
 use sea_orm::{
     entity::prelude::*,
     error::{DbErr, RuntimeErr, SqlxError},
     query::*,
-    sea_query::OnConflict,
+    sea_query::{OnConflict, Query, SelectStatement},
     ConnectionTrait, DatabaseTransaction, TransactionTrait,
 };
 use sea_orm_migration::MigratorTrait;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{info_span, Instrument};
 
 #[derive(Debug, Clone)]
 pub struct OrbitDatabase<C, B, S> {
     conn: C,
     storage: B,
     secrets: S,
+    hash: HashAlgorithm,
+    methods: MethodAllowlist,
+    read_only: bool,
+    maintenance: Arc<AtomicBool>,
+    max_depth: usize,
+    max_parents: Option<usize>,
+    replay: Option<ReplayProtection>,
+    storage_timeout: Option<Duration>,
+    tombstone_retention: Option<Duration>,
+    resolver: DidResolver,
+    pointers: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -37,6 +64,8 @@ pub struct Commit {
     pub seq: i64,
     pub committed_events: Vec<Hash>,
     pub consumed_epochs: Vec<Hash>,
+    /// The receipt of the invocation committed to the orbit, if the commit is of one.
+    pub receipt: Option<Hash>,
 }
 
 #[non_exhaustive]
@@ -62,6 +91,8 @@ pub enum TxError<S: StorageSetup, K: Secrets> {
     StoreSetup(S::Error),
     #[error(transparent)]
     Secrets(K::Error),
+    #[error(transparent)]
+    Receipt(#[from] ReceiptError),
     #[error("Orbit not found")]
     OrbitNotFound,
 }
@@ -87,6 +118,27 @@ where
     Io(#[from] std::io::Error),
     #[error("Missing Input for requested action")]
     MissingInput,
+    #[error("Invocation has {count} operations, more than the limit of {limit}")]
+    TooManyOperations { count: usize, limit: usize },
+    #[error("Invocation targets orbit {0}, which is not granted by its delegations")]
+    UndelegatedOrbit(OrbitId),
+    #[error("Node is read only, refusing {1} on {0}")]
+    ReadOnly(Resource, String),
+    #[error("Node is in maintenance, refusing {1} on {0}")]
+    Maintenance(Resource, String),
+    /// The database references content which the block store does not have.
+    #[error("content {} for key {key} in orbit {orbit} is missing from block storage", .hash.to_cid(0x55))]
+    MissingContent {
+        orbit: OrbitId,
+        key: String,
+        hash: Hash,
+    },
+    #[error("Invocation {} was already received", .0.to_cid(0x55))]
+    Replayed(Hash),
+    #[error("Invalid key {0}, keys can't have `..` segments")]
+    InvalidKey(String),
+    #[error(transparent)]
+    StorageTimeout(#[from] StorageTimeout),
 }
 
 impl<B, S, K> From<DbErr> for TxStoreError<B, S, K>
@@ -108,10 +160,118 @@ impl<B, K> OrbitDatabase<DatabaseConnection, B, K> {
             conn,
             storage,
             secrets,
+            hash: HashAlgorithm::default(),
+            methods: MethodAllowlist::default(),
+            read_only: false,
+            maintenance: Arc::new(AtomicBool::new(false)),
+            max_depth: delegation::MAX_DEPTH,
+            max_parents: None,
+            replay: None,
+            storage_timeout: None,
+            tombstone_retention: None,
+            resolver: DidResolver::default(),
+            pointers: false,
         })
     }
 }
 
+impl<C, B, K> OrbitDatabase<C, B, K> {
+    /// Set the algorithm which orbits created from now on address their content with.
+    ///
+    /// Existing orbits keep the algorithm they were created with.
+    pub fn with_hash_algorithm(mut self, hash: HashAlgorithm) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// Only accept events issued by, or delegating to, DIDs of the allowed methods.
+    pub fn with_did_methods(mut self, methods: MethodAllowlist) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    /// Resolve the DIDs signing events with `resolver`, e.g. to cache their documents.
+    pub fn with_did_resolver(mut self, resolver: DidResolver) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Refuse invocations which write or delete content, while still serving reads, e.g.
+    /// for replicas sharing the database and block storage of a writable node.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Start in maintenance mode, see [`OrbitDatabase::set_maintenance`].
+    pub fn with_maintenance(self, maintenance: bool) -> Self {
+        self.set_maintenance(maintenance);
+        self
+    }
+
+    /// Whether the node is in maintenance mode.
+    pub fn maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::SeqCst)
+    }
+
+    /// Enter or leave maintenance mode, returning whether the node was in it before.
+    ///
+    /// In maintenance, invocations which write or delete content are refused, as with
+    /// [`OrbitDatabase::with_read_only`], but the mode can be toggled at runtime. The mode is
+    /// shared by every clone of the database.
+    pub fn set_maintenance(&self, maintenance: bool) -> bool {
+        self.maintenance.swap(maintenance, Ordering::SeqCst)
+    }
+
+    /// Reject delegations ending chains of more than this many delegations, counting the
+    /// root delegation, at least 1. Defaults to [`delegation::MAX_DEPTH`].
+    pub fn with_max_delegation_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth.max(1);
+        self
+    }
+
+    /// Link each new epoch to at most this many parents, at least 2. When an orbit has
+    /// more heads, e.g. after many concurrent writes, they are first joined by merge
+    /// epochs, which order no events.
+    pub fn with_max_parents(mut self, max_parents: Option<usize>) -> Self {
+        self.max_parents = max_parents.map(|m| m.max(2));
+        self
+    }
+
+    /// Reject invocations which were already received, rather than applying them again as
+    /// retries.
+    pub fn with_replay_protection(mut self, replay: Option<ReplayProtection>) -> Self {
+        self.replay = replay;
+        self
+    }
+
+    /// Fail invocations whose reads or writes of block storage take longer than this, rather
+    /// than waiting on an unresponsive backend.
+    pub fn with_storage_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.storage_timeout = timeout;
+        self
+    }
+
+    /// Keep deletes, and the writes they deleted, through compactions until they are this
+    /// old, rather than removing them at the first compaction.
+    pub fn with_tombstone_retention(mut self, retention: Option<Duration>) -> Self {
+        self.tombstone_retention = retention;
+        self
+    }
+
+    /// Sign and store a pointer to an orbit's heads whenever events are committed to it,
+    /// like an IPNS record naming the orbit's latest state.
+    pub fn with_pointers(mut self, pointers: bool) -> Self {
+        self.pointers = pointers;
+        self
+    }
+
+    /// The block storage holding the content of the orbits.
+    pub fn storage(&self) -> &B {
+        &self.storage
+    }
+}
+
 impl<C, B, K> OrbitDatabase<C, B, K>
 where
     K: Secrets,
@@ -142,674 +302,5717 @@ where
     }
 }
 
-impl<C, B, K> OrbitDatabase<C, B, K>
-where
-    C: TransactionTrait,
-{
-    pub async fn check_db_connection(&self) -> Result<(), DbErr> {
-        // there's a `ping` method on the connection, but we can't access it from here
-        // but starting a transaction should be enough to check the connection
-        self.conn.begin().await.map(|_| ())
-    }
+/// What was removed by [`OrbitDatabase::purge`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PurgeOutcome {
+    /// content blocks removed from block storage
+    pub blocks: u64,
+    /// kv writes and deletes removed
+    pub kv_entries: u64,
+    /// delegations, invocations and revocations removed
+    pub events: u64,
+    /// epochs removed
+    pub epochs: u64,
+    /// whether the orbit itself was removed
+    pub orbit: bool,
+    /// whether a stored keypair was removed
+    pub keypair: bool,
 }
 
-pub type InvocationInputs<W> = HashMap<(OrbitId, String), (Metadata, HashBuffer<W>)>;
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum PurgeError<B: ImmutableDeleteStore, K: Secrets> {
+    #[error("database error: {0}")]
+    Db(#[from] DbErr),
+    #[error(transparent)]
+    StoreDelete(B::Error),
+    #[error(transparent)]
+    Secrets(K::Error),
+    #[error("Purge invocation {} was already accepted", .0.to_cid(0x55))]
+    Replayed(Hash),
+}
 
 impl<C, B, K> OrbitDatabase<C, B, K>
 where
     C: TransactionTrait,
-    B: StorageSetup,
+    B: ImmutableDeleteStore,
     K: Secrets,
 {
-    async fn transact(
+    /// Remove an orbit along with all of its content, events and keys.
+    ///
+    /// Events which are also ordered in other orbits, and delegations which other
+    /// orbits still depend on, are kept. Purging an orbit which was already purged
+    /// removes nothing.
+    ///
+    /// Content is removed after the database rows are, so if removing it fails the
+    /// content which was not yet removed is left unreferenced.
+    ///
+    /// The hash of the `invocation` requesting the purge, if any, is recorded with it, and
+    /// a purge by an invocation already recorded fails with [`PurgeError::Replayed`], so a
+    /// captured invocation can't purge the orbit again once it is recreated.
+    pub async fn purge(
         &self,
-        events: Vec<Event>,
-    ) -> Result<HashMap<OrbitId, Commit>, TxError<B, K>> {
-        let tx = self
-            .conn
-            .begin_with_config(Some(sea_orm::IsolationLevel::ReadUncommitted), None)
+        orbit: &OrbitId,
+        invocation: Option<Hash>,
+    ) -> Result<PurgeOutcome, PurgeError<B, K>> {
+        let tx = self.conn.begin().await?;
+        let o = || OrbitIdWrap(orbit.clone());
+        let mut outcome = PurgeOutcome::default();
+
+        if let Some(id) = invocation {
+            if purge_invocation::Entity::find_by_id(id)
+                .one(&tx)
+                .await?
+                .is_some()
+            {
+                return Err(PurgeError::Replayed(id));
+            }
+            purge_invocation::Entity::insert(purge_invocation::ActiveModel::from(
+                purge_invocation::Model { id, orbit: o() },
+            ))
+            .exec(&tx)
+            .await?;
+        }
+
+        // content is only removed once the rows referencing it are gone, so a failure part
+        // way through leaves no kv entry whose content was removed
+        let blocks: HashSet<Hash> = kv_write::Entity::find()
+            .filter(kv_write::Column::Orbit.eq(o()))
+            .all(&tx)
+            .await?
+            .into_iter()
+            .map(|kv| kv.value)
+            .collect();
+
+        // events which are only ordered in this orbit
+        let events: Vec<Hash> = event_order::Entity::find()
+            .filter(event_order::Column::Orbit.eq(o()))
+            .all(&tx)
+            .await?
+            .into_iter()
+            .map(|e| e.event)
+            .collect();
+        let shared: HashSet<Hash> = event_order::Entity::find()
+            .filter(event_order::Column::Event.is_in(events.clone()))
+            .filter(event_order::Column::Orbit.ne(o()))
+            .all(&tx)
+            .await?
+            .into_iter()
+            .map(|e| e.event)
+            .collect();
+        let exclusive: Vec<Hash> = events.into_iter().filter(|e| !shared.contains(e)).collect();
+
+        // delegations which are parents of, or revoked by, events we keep
+        let depended_on: HashSet<Hash> = parent_delegations::Entity::find()
+            .filter(parent_delegations::Column::Parent.is_in(exclusive.clone()))
+            .filter(parent_delegations::Column::Child.is_not_in(exclusive.clone()))
+            .all(&tx)
+            .await?
+            .into_iter()
+            .map(|p| p.parent)
+            .chain(
+                revocation::Entity::find()
+                    .filter(revocation::Column::Revoked.is_in(exclusive.clone()))
+                    .filter(revocation::Column::Id.is_not_in(exclusive.clone()))
+                    .all(&tx)
+                    .await?
+                    .into_iter()
+                    .map(|r| r.revoked),
+            )
+            .collect();
+        let delegations: Vec<Hash> = exclusive
+            .iter()
+            .filter(|e| !depended_on.contains(e))
+            .cloned()
+            .collect();
+
+        outcome.kv_entries += kv_delete::Entity::delete_many()
+            .filter(kv_delete::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?
+            .rows_affected;
+        outcome.kv_entries += kv_write::Entity::delete_many()
+            .filter(kv_write::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?
+            .rows_affected;
+
+        invoked_abilities::Entity::delete_many()
+            .filter(invoked_abilities::Column::Invocation.is_in(exclusive.clone()))
+            .exec(&tx)
             .await?;
+        outcome.events += invocation::Entity::delete_many()
+            .filter(invocation::Column::Id.is_in(exclusive.clone()))
+            .exec(&tx)
+            .await?
+            .rows_affected;
+        outcome.events += revocation::Entity::delete_many()
+            .filter(revocation::Column::Id.is_in(exclusive))
+            .exec(&tx)
+            .await?
+            .rows_affected;
+
+        abilities::Entity::delete_many()
+            .filter(abilities::Column::Delegation.is_in(delegations.clone()))
+            .exec(&tx)
+            .await?;
+        parent_delegations::Entity::delete_many()
+            .filter(parent_delegations::Column::Child.is_in(delegations.clone()))
+            .exec(&tx)
+            .await?;
+        outcome.events += delegation::Entity::delete_many()
+            .filter(delegation::Column::Id.is_in(delegations))
+            .exec(&tx)
+            .await?
+            .rows_affected;
 
-        let commit = transact(&tx, &self.storage, &self.secrets, events).await?;
+        epoch_order::Entity::delete_many()
+            .filter(epoch_order::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?;
+        event_order::Entity::delete_many()
+            .filter(event_order::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?;
+        outcome.epochs = epoch::Entity::delete_many()
+            .filter(epoch::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?
+            .rows_affected;
+        orbit_feature::Entity::delete_many()
+            .filter(orbit_feature::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?;
+        orbit_alias::Entity::delete_many()
+            .filter(orbit_alias::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?;
+        receipt::Entity::delete_many()
+            .filter(receipt::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?;
+        orbit_pointer::Entity::delete_many()
+            .filter(orbit_pointer::Column::Orbit.eq(o()))
+            .exec(&tx)
+            .await?;
+        outcome.orbit = orbit::Entity::delete_many()
+            .filter(orbit::Column::Id.eq(o()))
+            .exec(&tx)
+            .await?
+            .rows_affected
+            > 0;
 
         tx.commit().await?;
 
-        Ok(commit)
-    }
+        for block in blocks {
+            if self
+                .storage
+                .remove(orbit, &block)
+                .await
+                .map_err(PurgeError::StoreDelete)?
+                .is_some()
+            {
+                outcome.blocks += 1;
+            }
+        }
 
-    pub async fn delegate(
-        &self,
-        delegation: Delegation,
-    ) -> Result<HashMap<OrbitId, Commit>, TxError<B, K>> {
-        self.transact(vec![Event::Delegation(Box::new(delegation))])
+        outcome.keypair = self
+            .secrets
+            .remove_keypair(orbit)
             .await
-    }
+            .map_err(PurgeError::Secrets)?;
 
-    pub async fn revoke(
-        &self,
-        revocation: Revocation,
-    ) -> Result<HashMap<OrbitId, Commit>, TxError<B, K>> {
-        self.transact(vec![Event::Revocation(Box::new(revocation))])
-            .await
+        Ok(outcome)
     }
+}
 
-    pub async fn invoke<S>(
-        &self,
-        invocation: Invocation,
-        mut inputs: InvocationInputs<S::Writable>,
-    ) -> Result<
-        (
-            HashMap<OrbitId, Commit>,
-            Vec<InvocationOutcome<B::Readable>>,
-        ),
-        TxStoreError<B, S, K>,
-    >
-    where
-        B: ImmutableWriteStore<S> + ImmutableDeleteStore + ImmutableReadStore,
-        S: ImmutableStaging,
-        S::Writable: 'static + Unpin,
-    {
-        let mut stages = HashMap::new();
-        let mut ops = Vec::new();
-        // for each capability being invoked
-        for cap in invocation.0.capabilities.iter() {
-            match cap
-                .resource
-                .kepler_resource()
-                .and_then(|r| Some((r.service()?, cap.action.as_str(), r.orbit(), r.path()?)))
-            {
-                // stage inputs for content writes
-                Some(("kv", "put", orbit, path)) => {
-                    let (metadata, mut stage) = inputs
-                        .remove(&(orbit.clone(), path.to_string()))
-                        .ok_or(TxStoreError::MissingInput)?;
+/// What is known of a provisioned orbit, from [`OrbitDatabase::orbit_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrbitInfo {
+    /// When the orbit was created, if it was created since this was recorded.
+    pub created_at: Option<OffsetDateTime>,
+    /// Sequence number of the orbit's latest commit.
+    pub seq: i64,
+}
 
-                    let value = stage.hash();
+/// The latest epochs of an orbit, from [`OrbitDatabase::heads`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrbitHeads {
+    /// Epochs which no other epoch follows yet, sorted.
+    pub heads: Vec<Hash>,
+    /// The largest sequence number of the heads.
+    pub height: i64,
+}
 
-                    let norm_path = normalize_path(path);
+/// What was removed by [`OrbitDatabase::compact`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct CompactOutcome {
+    /// epochs removed
+    pub epochs: u64,
+    /// event orderings removed
+    pub events: u64,
+    /// superseded kv writes, and the deletes of them, removed
+    pub kv_entries: u64,
+}
 
-                    stages.insert((orbit.clone(), norm_path.to_string()), stage);
-                    // add write for tx
-                    ops.push(Operation::KvWrite {
-                        orbit: orbit.clone(),
-                        key: norm_path.to_string(),
-                        metadata,
-                        value,
-                    });
-                }
-                // add delete for tx
-                Some(("kv", "del", orbit, path)) => {
-                    ops.push(Operation::KvDelete {
-                        orbit: orbit.clone(),
-                        key: normalize_path(path).to_string(),
-                        version: None,
-                    });
+impl<C, B, K> OrbitDatabase<C, B, K>
+where
+    C: TransactionTrait,
+{
+    /// Collapse the fully superseded epochs of an orbit's history.
+    ///
+    /// An epoch is removed, along with its event orderings and kv writes, if it is not
+    /// a head, orders only invocations, at least one, and every kv write it made in the
+    /// orbit has been overwritten or deleted. The parents of removed epochs are linked to
+    /// their children, so the heads and the order of the remaining epochs are unchanged.
+    /// Delegations, revocations and the invocations themselves are kept, so reads,
+    /// listings and authorization are unaffected, but the removed events no longer
+    /// appear in the orbit's history or changes. As the invocations are kept, one whose
+    /// epoch was removed is still known to be committed, so a late replay of a write
+    /// which was deleted doesn't write the key again.
+    ///
+    /// A deleted write, and its delete, are kept until the delete is older than the
+    /// [tombstone retention](Self::with_tombstone_retention), if set.
+    pub async fn compact(&self, orbit: &OrbitId) -> Result<CompactOutcome, DbErr> {
+        let tx = self.conn.begin().await?;
+        let o = || OrbitIdWrap(orbit.clone());
+        let mut outcome = CompactOutcome::default();
+
+        let epochs: Vec<epoch::Model> = epoch::Entity::find()
+            .filter(epoch::Column::Orbit.eq(o()))
+            .order_by_asc(epoch::Column::Seq)
+            .all(&tx)
+            .await?;
+        let links: Vec<epoch_order::Model> = epoch_order::Entity::find()
+            .filter(epoch_order::Column::Orbit.eq(o()))
+            .all(&tx)
+            .await?;
+        // epochs which can't be removed: heads, ...
+        let mut kept: HashSet<Hash> = {
+            let parents: HashSet<Hash> = links.iter().map(|l| l.parent).collect();
+            epochs
+                .iter()
+                .map(|e| e.id)
+                .filter(|e| !parents.contains(e))
+                .collect()
+        };
+
+        // ... merge epochs, which order no events and bound the parents of later epochs, ...
+        let orderings: Vec<event_order::Model> = event_order::Entity::find()
+            .filter(event_order::Column::Orbit.eq(o()))
+            .all(&tx)
+            .await?;
+        let ordering: HashSet<Hash> = orderings.iter().map(|e| e.epoch).collect();
+        kept.extend(
+            epochs
+                .iter()
+                .map(|e| e.id)
+                .filter(|e| !ordering.contains(e)),
+        );
+
+        // ... those ordering delegations or revocations, ...
+        let invocations: HashSet<Hash> = invocation::Entity::find()
+            .filter(invocation::Column::Id.is_in(orderings.iter().map(|e| e.event)))
+            .select_only()
+            .column(invocation::Column::Id)
+            .into_tuple::<Hash>()
+            .all(&tx)
+            .await?
+            .into_iter()
+            .collect();
+        kept.extend(
+            orderings
+                .iter()
+                .filter(|e| !invocations.contains(&e.event))
+                .map(|e| e.epoch),
+        );
+
+        // ... and those with a live kv write, the latest write of its key not deleted
+        let mut writes: Vec<(kv_write::Model, Option<kv_delete::Model>)> = kv_write::Entity::find()
+            .filter(kv_write::Column::Orbit.eq(o()))
+            .order_by_asc(kv_write::Column::Key)
+            .order_by_desc(kv_write::Column::Seq)
+            .order_by_desc(kv_write::Column::Epoch)
+            .order_by_desc(kv_write::Column::EpochSeq)
+            .find_also_related(kv_delete::Entity)
+            .all(&tx)
+            .await?;
+        let mut latest: Option<&str> = None;
+        for (kv, deleted) in &writes {
+            if latest != Some(kv.key.as_str()) {
+                latest = Some(kv.key.as_str());
+                if deleted.is_none() {
+                    kept.insert(kv.epoch);
                 }
-                _ => {}
             }
         }
 
-        let tx = self
-            .conn
-            .begin_with_config(Some(sea_orm::IsolationLevel::ReadUncommitted), None)
-            .await?;
-        let caps = invocation.0.capabilities.clone();
-        //  verify and commit invocation and kv operations
-        let commit = transact(
-            &tx,
-            &self.storage,
-            &self.secrets,
-            vec![Event::Invocation(Box::new(invocation), ops)],
-        )
-        .await?;
+        // ... or with a write whose delete is still retained
+        if let Some(retention) = self.tombstone_retention {
+            let cutoff = OffsetDateTime::now_utc() - retention;
+            let retained: HashSet<Hash> = invocation::Entity::find()
+                .filter(
+                    invocation::Column::Id.is_in(
+                        writes
+                            .iter()
+                            .filter_map(|(_, d)| Some(d.as_ref()?.invocation_id)),
+                    ),
+                )
+                .filter(invocation::Column::IssuedAt.gt(cutoff))
+                .select_only()
+                .column(invocation::Column::Id)
+                .into_tuple::<Hash>()
+                .all(&tx)
+                .await?
+                .into_iter()
+                .collect();
+            kept.extend(writes.iter().filter_map(|(kv, d)| {
+                retained
+                    .contains(&d.as_ref()?.invocation_id)
+                    .then_some(kv.epoch)
+            }));
+        }
 
-        let mut results = Vec::new();
-        // perform and record side effects
-        for cap in caps {
-            match (
-                cap.resource
-                    .kepler_resource()
-                    .and_then(|r| Some((r.orbit(), r.service()?, normalize_path(r.path()?)))),
-                cap.action.as_str(),
-            ) {
-                (Some((orbit, "kv", path)), "get") => results.push(InvocationOutcome::KvRead(
-                    get_kv(&tx, &self.storage, orbit, path)
-                        .await
-                        .map_err(|e| match e {
-                            EitherError::A(e) => TxStoreError::Tx(e.into()),
-                            EitherError::B(e) => TxStoreError::StoreRead(e),
-                        })?,
-                )),
-                (Some((orbit, "kv", path)), "list") => {
-                    results.push(InvocationOutcome::KvList(list(&tx, orbit, path).await?))
-                }
-                (Some((orbit, "kv", path)), "del") => {
-                    let kv = get_kv_entity(&tx, orbit, path).await?;
-                    if let Some(kv) = kv {
-                        self.storage
-                            .remove(orbit, &kv.value)
-                            .await
-                            .map_err(TxStoreError::StoreDelete)?;
-                    }
-                    results.push(InvocationOutcome::KvDelete)
-                }
-                (Some((orbit, "kv", path)), "put") => {
-                    if let Some(stage) = stages.remove(&(orbit.clone(), path.to_string())) {
-                        self.storage
-                            .persist(orbit, stage)
-                            .await
-                            .map_err(TxStoreError::StoreWrite)?;
-                        results.push(InvocationOutcome::KvWrite)
-                    }
-                }
-                (Some((orbit, "kv", path)), "metadata") => results.push(
-                    InvocationOutcome::KvMetadata(metadata(&tx, orbit, path).await?),
-                ),
-                (Some((orbit, "capabilities", "all")), "read") => results.push(
-                    InvocationOutcome::OpenSessions(get_valid_delegations(&tx, orbit).await?),
+        let removed: Vec<&epoch::Model> = epochs.iter().filter(|e| !kept.contains(&e.id)).collect();
+        if removed.is_empty() {
+            return Ok(outcome);
+        }
+        let removed_ids: HashSet<Hash> = removed.iter().map(|e| e.id).collect();
+
+        // link children of removed epochs to their nearest remaining ancestors, resolving
+        // parents first, as they always have a lower sequence number
+        let mut parents: HashMap<Hash, Vec<Hash>> = HashMap::new();
+        for l in &links {
+            parents.entry(l.child).or_default().push(l.parent);
+        }
+        let mut ancestors: HashMap<Hash, HashSet<Hash>> = HashMap::new();
+        for e in &removed {
+            let resolved = parents
+                .get(&e.id)
+                .into_iter()
+                .flatten()
+                .flat_map(|p| match ancestors.get(p) {
+                    Some(a) => a.iter().copied().collect::<Vec<_>>(),
+                    None => vec![*p],
+                })
+                .collect();
+            ancestors.insert(e.id, resolved);
+        }
+        let existing: HashSet<(Hash, Hash)> = links.iter().map(|l| (l.parent, l.child)).collect();
+        let relinked: HashSet<(Hash, Hash)> = links
+            .iter()
+            .filter(|l| removed_ids.contains(&l.parent) && !removed_ids.contains(&l.child))
+            .flat_map(|l| ancestors[&l.parent].iter().map(|a| (*a, l.child)))
+            .filter(|link| !existing.contains(link))
+            .collect();
+
+        // remove the superseded writes, and deletes of them, made in removed epochs
+        writes.retain(|(kv, _)| removed_ids.contains(&kv.epoch));
+        outcome.kv_entries += kv_delete::Entity::delete_many()
+            .filter(kv_delete::Column::Orbit.eq(o()))
+            .filter(
+                kv_delete::Column::InvocationId.is_in(
+                    writes
+                        .iter()
+                        .filter_map(|(_, d)| Some(d.as_ref()?.invocation_id)),
                 ),
-                _ => {}
-            }
+            )
+            .exec(&tx)
+            .await?
+            .rows_affected;
+        outcome.kv_entries += kv_write::Entity::delete_many()
+            .filter(kv_write::Column::Orbit.eq(o()))
+            .filter(kv_write::Column::Epoch.is_in(removed_ids.iter().copied()))
+            .exec(&tx)
+            .await?
+            .rows_affected;
+
+        epoch_order::Entity::delete_many()
+            .filter(epoch_order::Column::Orbit.eq(o()))
+            .filter(
+                Condition::any()
+                    .add(epoch_order::Column::Parent.is_in(removed_ids.iter().copied()))
+                    .add(epoch_order::Column::Child.is_in(removed_ids.iter().copied())),
+            )
+            .exec(&tx)
+            .await?;
+        if !relinked.is_empty() {
+            epoch_order::Entity::insert_many(relinked.into_iter().map(|(parent, child)| {
+                epoch_order::ActiveModel::from(epoch_order::Model {
+                    parent,
+                    child,
+                    orbit: o(),
+                })
+            }))
+            .exec(&tx)
+            .await?;
         }
+        outcome.events = event_order::Entity::delete_many()
+            .filter(event_order::Column::Orbit.eq(o()))
+            .filter(event_order::Column::Epoch.is_in(removed_ids.iter().copied()))
+            .exec(&tx)
+            .await?
+            .rows_affected;
+        outcome.epochs = epoch::Entity::delete_many()
+            .filter(epoch::Column::Orbit.eq(o()))
+            .filter(epoch::Column::Id.is_in(removed_ids))
+            .exec(&tx)
+            .await?
+            .rows_affected;
 
-        // commit tx if all side effects worked
         tx.commit().await?;
-        Ok((commit, results))
+        Ok(outcome)
     }
 }
 
-#[derive(Debug)]
-pub enum InvocationOutcome<R> {
-    KvList(Vec<String>),
-    KvDelete,
-    KvMetadata(Option<Metadata>),
-    KvWrite,
-    KvRead(Option<(Metadata, Content<R>)>),
-    OpenSessions(HashMap<Hash, DelegationInfo>),
-}
+impl<C, B, K> OrbitDatabase<C, B, K>
+where
+    C: ConnectionTrait,
+{
+    /// Every orbit hosted here.
+    pub async fn orbits(&self) -> Result<Vec<OrbitId>, DbErr> {
+        Ok(orbit::Entity::find()
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|o| o.id.0)
+            .collect())
+    }
 
-impl<S: StorageSetup, K: Secrets> From<delegation::Error> for TxError<S, K> {
-    fn from(e: delegation::Error) -> Self {
-        match e {
-            delegation::Error::InvalidDelegation(e) => Self::InvalidDelegation(e),
-            delegation::Error::Db(e) => Self::Db(e),
-        }
+    /// When an orbit was created and its latest sequence number, or `None` if the orbit
+    /// doesn't exist.
+    pub async fn orbit_info(&self, orbit: &OrbitId) -> Result<Option<OrbitInfo>, DbErr> {
+        let o = OrbitIdWrap(orbit.clone());
+        let created_at = match orbit::Entity::find_by_id(o.clone()).one(&self.conn).await? {
+            Some(o) => o.created_at,
+            None => return Ok(None),
+        };
+        let seq = event_order::Entity::find()
+            .filter(event_order::Column::Orbit.eq(o))
+            .select_only()
+            .column_as(event_order::Column::Seq.max(), "max_seq")
+            .into_tuple::<Option<i64>>()
+            .one(&self.conn)
+            .await?
+            .flatten()
+            .unwrap_or(0);
+        Ok(Some(OrbitInfo { created_at, seq }))
     }
-}
 
-impl<S: StorageSetup, K: Secrets> From<invocation::Error> for TxError<S, K> {
-    fn from(e: invocation::Error) -> Self {
-        match e {
-            invocation::Error::InvalidInvocation(e) => Self::InvalidInvocation(e),
-            invocation::Error::Db(e) => Self::Db(e),
+    /// The orbits an event, such as a delegation, was committed to, which a revocation of
+    /// it is committed to as well. Empty if the event is unknown.
+    pub async fn event_orbits(&self, event: Hash) -> Result<Vec<OrbitId>, DbErr> {
+        Ok(event_order::Entity::find()
+            .filter(event_order::Column::Event.eq(event))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|o| o.orbit.0)
+            .collect())
+    }
+
+    /// The current heads of an orbit, which the next epoch committed to it follows, or `None`
+    /// if the orbit doesn't exist. Hosts which have applied the same epochs of an orbit
+    /// have the same heads.
+    pub async fn heads(&self, orbit: &OrbitId) -> Result<Option<OrbitHeads>, DbErr> {
+        if orbit::Entity::find_by_id(OrbitIdWrap(orbit.clone()))
+            .one(&self.conn)
+            .await?
+            .is_none()
+        {
+            return Ok(None);
         }
+        orbit_heads(&self.conn, orbit).await.map(Some)
     }
-}
 
-impl<S: StorageSetup, K: Secrets> From<revocation::Error> for TxError<S, K> {
-    fn from(e: revocation::Error) -> Self {
-        match e {
-            revocation::Error::InvalidRevocation(e) => Self::InvalidRevocation(e),
-            revocation::Error::Db(e) => Self::Db(e),
+    /// The DAG-CBOR block of the pointer last published for an orbit, or `None` if none was.
+    pub async fn pointer(&self, orbit: &OrbitId) -> Result<Option<Vec<u8>>, DbErr> {
+        Ok(
+            orbit_pointer::Entity::find_by_id(OrbitIdWrap(orbit.clone()))
+                .one(&self.conn)
+                .await?
+                .map(|p| p.serialization),
+        )
+    }
+
+    /// Get the feature flags set for an orbit, or `None` if the orbit doesn't exist.
+    pub async fn features(&self, orbit: &OrbitId) -> Result<Option<BTreeMap<String, bool>>, DbErr> {
+        let o = OrbitIdWrap(orbit.clone());
+        if orbit::Entity::find_by_id(o.clone())
+            .one(&self.conn)
+            .await?
+            .is_none()
+        {
+            return Ok(None);
         }
+        Ok(Some(
+            orbit_feature::Entity::find()
+                .filter(orbit_feature::Column::Orbit.eq(o))
+                .all(&self.conn)
+                .await?
+                .into_iter()
+                .map(|f| (f.flag, f.enabled))
+                .collect(),
+        ))
     }
-}
 
-async fn event_orbits<'a, C: ConnectionTrait>(
-    db: &C,
-    ev: &'a [(Hash, Event)],
-) -> Result<HashMap<OrbitId, Vec<&'a (Hash, Event)>>, DbErr> {
-    // get orderings of events listed as revoked by events in the ev list
-    let mut orbits = HashMap::<OrbitId, Vec<&'a (Hash, Event)>>::new();
-    let revoked_events = event_order::Entity::find()
-        .filter(
-            event_order::Column::Event.is_in(ev.iter().filter_map(|(_, e)| match e {
-                Event::Revocation(r) => Some(Hash::from(r.0.revoked)),
-                _ => None,
-            })),
+    /// Check a delegation without applying it, returning every reason it is invalid.
+    pub async fn check_delegation(
+        &self,
+        delegation: &Delegation,
+    ) -> Result<Vec<delegation::DelegationError>, DbErr> {
+        delegation::check(&self.conn, &self.resolver, self.max_depth, delegation).await
+    }
+
+    /// Check an invocation without applying it, returning every reason it is invalid.
+    pub async fn check_invocation(
+        &self,
+        invocation: &Invocation,
+    ) -> Result<Vec<invocation::InvocationError>, DbErr> {
+        invocation::check(&self.conn, &self.resolver, invocation).await
+    }
+
+    /// The abilities held by the signer of `invocation` through its parent delegations.
+    pub async fn granted_abilities(
+        &self,
+        invocation: &Invocation,
+    ) -> Result<Vec<abilities::Model>, invocation::Error> {
+        invocation::granted(&self.conn, &self.resolver, invocation).await
+    }
+
+    /// Get the algorithm an orbit's content is addressed with, or `None` if the orbit doesn't exist.
+    pub async fn hash_algorithm(&self, orbit: &OrbitId) -> Result<Option<HashAlgorithm>, DbErr> {
+        Ok(orbit::Entity::find_by_id(OrbitIdWrap(orbit.clone()))
+            .one(&self.conn)
+            .await?
+            .map(|o| o.hash))
+    }
+
+    /// Whether a feature flag is enabled for an orbit. Unset flags are disabled.
+    pub async fn feature_enabled(&self, orbit: &OrbitId, flag: &str) -> Result<bool, DbErr> {
+        feature_enabled(&self.conn, orbit, flag).await
+    }
+
+    /// Set a feature flag for an orbit, returning the orbit's flags afterwards, or
+    /// `None` if the orbit doesn't exist.
+    pub async fn set_feature(
+        &self,
+        orbit: &OrbitId,
+        flag: &str,
+        enabled: bool,
+    ) -> Result<Option<BTreeMap<String, bool>>, DbErr> {
+        if self.features(orbit).await?.is_none() {
+            return Ok(None);
+        }
+        orbit_feature::Entity::insert(orbit_feature::ActiveModel::from(orbit_feature::Model {
+            orbit: OrbitIdWrap(orbit.clone()),
+            flag: flag.to_string(),
+            enabled,
+        }))
+        .on_conflict(
+            OnConflict::columns([orbit_feature::Column::Orbit, orbit_feature::Column::Flag])
+                .update_column(orbit_feature::Column::Enabled)
+                .to_owned(),
         )
-        .all(db)
+        .exec(&self.conn)
         .await?;
-    for e in ev {
-        match &e.1 {
-            Event::Delegation(d) => {
-                for orbit in d.0.orbits() {
-                    let entry = orbits.entry(orbit.clone()).or_insert_with(Vec::new);
-                    if !entry.iter().any(|(h, _)| h == &e.0) {
-                        entry.push(e);
-                    }
-                }
-            }
-            Event::Invocation(i, _) => {
-                for orbit in i.0.orbits() {
-                    let entry = orbits.entry(orbit.clone()).or_insert_with(Vec::new);
-                    if !entry.iter().any(|(h, _)| h == &e.0) {
-                        entry.push(e);
-                    }
-                }
-            }
-            Event::Revocation(r) => {
-                let r_hash = Hash::from(r.0.revoked);
-                for revoked in &revoked_events {
-                    if r_hash == revoked.event {
-                        let entry = orbits
-                            .entry(revoked.orbit.0.clone())
-                            .or_insert_with(Vec::new);
-                        if !entry.iter().any(|(h, _)| h == &e.0) {
-                            entry.push(e);
-                        }
-                    }
-                }
-            }
-        }
+        self.features(orbit).await
+    }
+
+    /// Get the DAG-CBOR block of a receipt, or `None` if there is no receipt with this hash.
+    pub async fn receipt(&self, id: Hash) -> Result<Option<Vec<u8>>, DbErr> {
+        Ok(receipt::Entity::find_by_id(id)
+            .one(&self.conn)
+            .await?
+            .map(|r| r.serialization))
+    }
+
+    /// Get the bytes of block storage an orbit may use, or `None` if it has no limit of its
+    /// own or doesn't exist.
+    pub async fn storage_limit(&self, orbit: &OrbitId) -> Result<Option<u64>, DbErr> {
+        Ok(orbit::Entity::find_by_id(OrbitIdWrap(orbit.clone()))
+            .one(&self.conn)
+            .await?
+            .and_then(|o| o.storage_limit)
+            .map(|l| l.max(0) as u64))
+    }
+
+    /// Set the bytes of block storage an orbit may use, overriding the global limit, or
+    /// remove its limit with `None`. Returns whether the orbit exists.
+    pub async fn set_storage_limit(
+        &self,
+        orbit: &OrbitId,
+        limit: Option<u64>,
+    ) -> Result<bool, DbErr> {
+        let mut o: orbit::ActiveModel = match orbit::Entity::find_by_id(OrbitIdWrap(orbit.clone()))
+            .one(&self.conn)
+            .await?
+        {
+            Some(o) => o.into(),
+            None => return Ok(false),
+        };
+        o.storage_limit =
+            sea_orm::ActiveValue::Set(limit.map(|l| i64::try_from(l).unwrap_or(i64::MAX)));
+        o.update(&self.conn).await?;
+        Ok(true)
     }
-    Ok(orbits)
 }
 
-pub(crate) async fn transact<C: ConnectionTrait, S: StorageSetup, K: Secrets>(
-    db: &C,
-    store_setup: &S,
-    secrets: &K,
-    events: Vec<Event>,
-) -> Result<HashMap<OrbitId, Commit>, TxError<S, K>> {
-    // for each event, get the hash and the relevent orbit(s)
-    let event_hashes = events
-        .into_iter()
-        .map(|e| (e.hash(), e))
-        .collect::<Vec<(Hash, Event)>>();
-    let event_orbits = event_orbits(db, &event_hashes).await?;
-    let mut new_orbits = event_hashes
-        .iter()
-        .filter_map(|(_, e)| match e {
-            Event::Delegation(d) => Some(d.0.capabilities.iter().filter_map(|c| {
-                match (&c.resource, c.action.as_str()) {
-                    (Resource::Kepler(r), "host")
-                        if r.path().is_none()
-                            && r.service().is_none()
-                            && r.fragment().is_none() =>
-                    {
-                        Some(OrbitIdWrap(r.orbit().clone()))
-                    }
-                    _ => None,
-                }
-            })),
-            _ => None,
-        })
-        .flatten()
-        .collect::<Vec<OrbitIdWrap>>();
-    new_orbits.dedup();
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum AliasError {
+    #[error("database error: {0}")]
+    Db(#[from] DbErr),
+    #[error("Invalid alias, aliases are 1 to 64 ASCII letters, digits, '-' or '_'")]
+    InvalidAlias,
+    #[error("Alias already in use")]
+    Duplicate,
+    #[error("Orbit not found")]
+    OrbitNotFound,
+}
 
-    if !new_orbits.is_empty() {
-        match orbit::Entity::insert_many(
-            new_orbits
-                .iter()
-                .cloned()
-                .map(|id| orbit::Model { id })
-                .map(orbit::ActiveModel::from),
-        )
+impl<C, B, K> OrbitDatabase<C, B, K>
+where
+    C: ConnectionTrait,
+{
+    /// Map a new alias to an existing orbit. Aliases are never reassigned, remove one
+    /// first to point it at another orbit.
+    pub async fn set_alias(&self, alias: &str, orbit: &OrbitId) -> Result<(), AliasError> {
+        if !orbit_alias::is_valid_alias(alias) {
+            return Err(AliasError::InvalidAlias);
+        }
+        if orbit::Entity::find_by_id(OrbitIdWrap(orbit.clone()))
+            .one(&self.conn)
+            .await?
+            .is_none()
+        {
+            return Err(AliasError::OrbitNotFound);
+        }
+        match orbit_alias::Entity::insert(orbit_alias::ActiveModel::from(orbit_alias::Model {
+            alias: alias.to_string(),
+            orbit: OrbitIdWrap(orbit.clone()),
+        }))
         .on_conflict(
-            OnConflict::column(orbit::Column::Id)
+            OnConflict::column(orbit_alias::Column::Alias)
                 .do_nothing()
                 .to_owned(),
         )
-        .exec(db)
+        .exec(&self.conn)
         .await
         {
-            Err(DbErr::RecordNotInserted) => (),
-            r => {
-                r?;
+            Ok(_) => Ok(()),
+            Err(DbErr::RecordNotInserted) => Err(AliasError::Duplicate),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove an alias, returning whether it existed.
+    pub async fn remove_alias(&self, alias: &str) -> Result<bool, DbErr> {
+        Ok(orbit_alias::Entity::delete_by_id(alias.to_string())
+            .exec(&self.conn)
+            .await?
+            .rows_affected
+            > 0)
+    }
+
+    /// Get the orbit an alias refers to.
+    pub async fn resolve_alias(&self, alias: &str) -> Result<Option<OrbitId>, DbErr> {
+        Ok(orbit_alias::Entity::find_by_id(alias.to_string())
+            .one(&self.conn)
+            .await?
+            .map(|a| a.orbit.0))
+    }
+}
+
+impl<C, B, K> OrbitDatabase<C, B, K>
+where
+    C: ConnectionTrait,
+    B: ImmutableDeleteStore,
+{
+    /// Remove the content of kv entries which expired no later than `now`, unless it is
+    /// still referenced by an entry which hasn't expired.
+    ///
+    /// Every expired entry is considered, including those which had expired before they
+    /// were written, and content which was already removed is skipped. Expired entries are
+    /// already treated as absent, this only reclaims their storage. Returns the number of
+    /// blocks removed.
+    pub async fn reap_expired(
+        &self,
+        now: OffsetDateTime,
+    ) -> Result<u64, EitherError<DbErr, B::Error>> {
+        let expired: HashSet<(OrbitIdWrap, Hash)> = kv_write::Entity::find()
+            .filter(kv_write::Column::Expiry.lte(now))
+            .all(&self.conn)
+            .await
+            .map_err(EitherError::A)?
+            .into_iter()
+            .map(|kv| (kv.orbit, kv.value))
+            .collect();
+        let mut removed = 0;
+        for (orbit, value) in expired {
+            let live = kv_write::Entity::find()
+                .filter(kv_write::Column::Orbit.eq(orbit.clone()))
+                .filter(kv_write::Column::Value.eq(value))
+                .filter(
+                    Condition::any()
+                        .add(kv_write::Column::Expiry.is_null())
+                        .add(kv_write::Column::Expiry.gt(now)),
+                )
+                .one(&self.conn)
+                .await
+                .map_err(EitherError::A)?;
+            if live.is_none()
+                && self
+                    .storage
+                    .remove(&orbit.0, &value)
+                    .await
+                    .map_err(EitherError::B)?
+                    .is_some()
+            {
+                removed += 1;
             }
-        };
+        }
+        Ok(removed)
     }
+}
 
-    // get max sequence for each of the orbits
-    let mut max_seqs = event_order::Entity::find()
-        .filter(event_order::Column::Orbit.is_in(event_orbits.keys().cloned().map(OrbitIdWrap)))
-        .select_only()
-        .column(event_order::Column::Orbit)
-        .column_as(event_order::Column::Seq.max(), "max_seq")
-        .group_by(event_order::Column::Orbit)
-        .into_tuple::<(OrbitIdWrap, i64)>()
-        .all(db)
-        .await?
-        .into_iter()
-        .fold(HashMap::new(), |mut m, (orbit, seq)| {
-            m.insert(orbit, seq + 1);
-            m
-        });
+/// The kind of an event ordered in an orbit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Delegation,
+    Invocation,
+    Revocation,
+}
 
-    // get 'most recent' epochs for each of the orbits
-    let mut most_recent = epoch::Entity::find()
-        .select_only()
-        .left_join(epoch_order::Entity)
-        .filter(
-            Condition::all()
-                .add(epoch::Column::Orbit.is_in(event_orbits.keys().cloned().map(OrbitIdWrap)))
-                .add(epoch_order::Column::Child.is_null()),
+/// An event in an orbit's history, as returned by [`OrbitDatabase::query_events_since`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventRecord {
+    pub hash: Hash,
+    pub kind: EventKind,
+    pub seq: i64,
+    pub epoch: Hash,
+    pub epoch_seq: i64,
+}
+
+/// A delegation in an orbit's history, as returned by [`OrbitDatabase::query_delegations`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelegationRecord {
+    pub hash: Hash,
+    pub seq: i64,
+    pub delegator: String,
+    pub delegatee: String,
+    pub capabilities: Vec<Capability>,
+    pub expiry: Option<OffsetDateTime>,
+    pub not_before: Option<OffsetDateTime>,
+    pub issued_at: Option<OffsetDateTime>,
+}
+
+/// An invocation in an orbit's history, as returned by [`OrbitDatabase::query_invocations`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvocationRecord {
+    pub hash: Hash,
+    pub seq: i64,
+    pub invoker: String,
+    pub capabilities: Vec<Capability>,
+    pub issued_at: OffsetDateTime,
+}
+
+/// Read-only queries over an orbit's history.
+///
+/// Each query returns a page of records with a sequence number greater than `since`,
+/// in order. A page always ends on a complete sequence number, so it may hold more than
+/// `limit` records. Pass the `seq` of the last record as `since` to get the next page.
+impl<C, B, K> OrbitDatabase<C, B, K>
+where
+    C: ConnectionTrait,
+{
+    /// A page of the events ordered in an orbit after the sequence number `since`, in
+    /// order. A page holds at least `limit` events if there are that many, and ends with
+    /// the last event of a sequence number, so the next page is the one after the last
+    /// event's `seq`.
+    pub async fn query_events_since(
+        &self,
+        orbit: &OrbitId,
+        since: i64,
+        limit: u64,
+    ) -> Result<Vec<EventRecord>, DbErr> {
+        let page = events_page(&self.conn, orbit, since, limit, None).await?;
+        let ids: Vec<Hash> = page.iter().map(|e| e.event).collect();
+        let delegations: HashSet<Hash> = delegation::Entity::find()
+            .filter(delegation::Column::Id.is_in(ids.clone()))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        let invocations: HashSet<Hash> = invocation::Entity::find()
+            .filter(invocation::Column::Id.is_in(ids))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|i| i.id)
+            .collect();
+        Ok(page
+            .into_iter()
+            .map(|e| EventRecord {
+                kind: if delegations.contains(&e.event) {
+                    EventKind::Delegation
+                } else if invocations.contains(&e.event) {
+                    EventKind::Invocation
+                } else {
+                    EventKind::Revocation
+                },
+                hash: e.event,
+                seq: e.seq,
+                epoch: e.epoch,
+                epoch_seq: e.epoch_seq,
+            })
+            .collect())
+    }
+
+    /// A page of the delegations ordered in an orbit after the sequence number `since`,
+    /// paged as by [`Self::query_events_since`].
+    pub async fn query_delegations(
+        &self,
+        orbit: &OrbitId,
+        since: i64,
+        limit: u64,
+    ) -> Result<Vec<DelegationRecord>, DbErr> {
+        let page = events_page(
+            &self.conn,
+            orbit,
+            since,
+            limit,
+            Some(
+                Query::select()
+                    .column(delegation::Column::Id)
+                    .from(delegation::Entity)
+                    .to_owned(),
+            ),
         )
-        .column(epoch::Column::Orbit)
-        .column(epoch::Column::Id)
-        .into_tuple::<(OrbitIdWrap, Hash)>()
-        .all(db)
-        .await?
-        .into_iter()
-        .fold(HashMap::new(), |mut m, (orbit, epoch)| {
-            m.entry(orbit).or_insert_with(Vec::new).push(epoch);
-            m
-        });
+        .await?;
+        let ids: Vec<Hash> = page.iter().map(|e| e.event).collect();
+        let mut capabilities: HashMap<Hash, Vec<Capability>> = HashMap::new();
+        for a in abilities::Entity::find()
+            .filter(abilities::Column::Delegation.is_in(ids.clone()))
+            .all(&self.conn)
+            .await?
+        {
+            capabilities
+                .entry(a.delegation)
+                .or_default()
+                .push(Capability {
+                    resource: a.resource,
+                    action: a.ability,
+                });
+        }
+        let mut delegations: HashMap<Hash, delegation::Model> = delegation::Entity::find()
+            .filter(delegation::Column::Id.is_in(ids))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|d| (d.id, d))
+            .collect();
+        Ok(page
+            .into_iter()
+            .filter_map(|e| {
+                let d = delegations.remove(&e.event)?;
+                Some(DelegationRecord {
+                    hash: d.id,
+                    seq: e.seq,
+                    capabilities: capabilities.remove(&d.id).unwrap_or_default(),
+                    delegator: d.delegator,
+                    delegatee: d.delegatee,
+                    expiry: d.expiry,
+                    not_before: d.not_before,
+                    issued_at: d.issued_at,
+                })
+            })
+            .collect())
+    }
+
+    /// A page of the invocations ordered in an orbit after the sequence number `since`,
+    /// paged as by [`Self::query_events_since`].
+    pub async fn query_invocations(
+        &self,
+        orbit: &OrbitId,
+        since: i64,
+        limit: u64,
+    ) -> Result<Vec<InvocationRecord>, DbErr> {
+        let page = events_page(
+            &self.conn,
+            orbit,
+            since,
+            limit,
+            Some(
+                Query::select()
+                    .column(invocation::Column::Id)
+                    .from(invocation::Entity)
+                    .to_owned(),
+            ),
+        )
+        .await?;
+        let ids: Vec<Hash> = page.iter().map(|e| e.event).collect();
+        let mut capabilities: HashMap<Hash, Vec<Capability>> = HashMap::new();
+        for a in invoked_abilities::Entity::find()
+            .filter(invoked_abilities::Column::Invocation.is_in(ids.clone()))
+            .all(&self.conn)
+            .await?
+        {
+            capabilities
+                .entry(a.invocation)
+                .or_default()
+                .push(Capability {
+                    resource: a.resource,
+                    action: a.ability,
+                });
+        }
+        let mut invocations: HashMap<Hash, invocation::Model> = invocation::Entity::find()
+            .filter(invocation::Column::Id.is_in(ids))
+            .all(&self.conn)
+            .await?
+            .into_iter()
+            .map(|i| (i.id, i))
+            .collect();
+        Ok(page
+            .into_iter()
+            .filter_map(|e| {
+                let i = invocations.remove(&e.event)?;
+                Some(InvocationRecord {
+                    hash: i.id,
+                    seq: e.seq,
+                    capabilities: capabilities.remove(&i.id).unwrap_or_default(),
+                    invoker: i.invoker,
+                    issued_at: i.issued_at,
+                })
+            })
+            .collect())
+    }
+}
+
+// a page of an orbit's ordered events after `since`, optionally only those whose hash is
+// selected by `events`, extended to end on a complete sequence number
+async fn events_page<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    since: i64,
+    limit: u64,
+    events: Option<SelectStatement>,
+) -> Result<Vec<event_order::Model>, DbErr> {
+    let query = || {
+        let q = event_order::Entity::find()
+            .filter(event_order::Column::Orbit.eq(OrbitIdWrap(orbit.clone())))
+            .order_by_asc(event_order::Column::Seq)
+            .order_by_asc(event_order::Column::EpochSeq);
+        match &events {
+            Some(e) => q.filter(event_order::Column::Event.in_subquery(e.clone())),
+            None => q,
+        }
+    };
+    let mut page = query()
+        .filter(event_order::Column::Seq.gt(since))
+        .limit(limit)
+        .all(db)
+        .await?;
+    if let Some(last) = page.last().filter(|_| page.len() as u64 == limit).cloned() {
+        page.extend(
+            query()
+                .filter(event_order::Column::Seq.eq(last.seq))
+                .filter(event_order::Column::EpochSeq.gt(last.epoch_seq))
+                .all(db)
+                .await?,
+        );
+    }
+    Ok(page)
+}
+
+impl<C, B, K> OrbitDatabase<C, B, K>
+where
+    C: TransactionTrait,
+{
+    pub async fn check_db_connection(&self) -> Result<(), DbErr> {
+        // there's a `ping` method on the connection, but we can't access it from here
+        // but starting a transaction should be enough to check the connection
+        self.conn.begin().await.map(|_| ())
+    }
+}
+
+pub type InvocationInputs<W> = HashMap<(OrbitId, String), (Metadata, HashBuffer<W>)>;
+
+// an invocation which passed the checks made before its transaction, with its staged inputs
+struct PreparedInvocation<W> {
+    hash: Hash,
+    caps: Vec<Capability>,
+    expiry: i64,
+    stages: HashMap<(OrbitId, String), HashBuffer<W>>,
+    written: HashSet<(OrbitId, Hash)>,
+}
+
+/// Options for how an invocation is performed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InvokeOptions {
+    /// Make `kv/list` return only the keys written or deleted after this orbit sequence
+    /// number, as [`InvocationOutcome::KvChanges`].
+    pub list_since: Option<i64>,
+    /// Make `kv/list` return its keys as [`InvocationOutcome::KvKeys`], read a page at a
+    /// time as they are consumed rather than all at once. `list_since` takes precedence.
+    pub stream_list: bool,
+    /// Reject invocations with more operations than this.
+    pub max_operations: Option<usize>,
+    /// Reject invocations of orbits which the invoker neither controls nor was granted by
+    /// the invocation's parent delegations, in addition to the usual authorization checks.
+    pub check_orbits: bool,
+    /// Make `kv/get` return the hash of the content rather than reading it from storage,
+    /// as [`InvocationOutcome::KvLocation`].
+    pub locate: bool,
+    /// Make `kv/get` and `kv/metadata` read the write of a key with this version rather than
+    /// its latest one, even if it has since been overwritten or deleted.
+    pub version: Option<Version>,
+    /// Authorize the invocation and return the commits and outcomes it would have, without
+    /// committing it, persisting the content it writes or removing the content it deletes.
+    pub dry_run: bool,
+    /// Page through, and filter, the delegations returned by a `read` of `capabilities/all`.
+    pub sessions: SessionsQuery,
+}
+
+/// A page of the valid delegations of an orbit, ordered by their hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionsQuery {
+    /// Only return delegations after this one, the cursor of the previous page.
+    pub after: Option<Hash>,
+    /// Return at most this many delegations, all of them if unset.
+    pub limit: Option<u64>,
+    /// Only return delegations to this DID.
+    pub delegate: Option<String>,
+}
+
+/// The number of operations granted by `capabilities` and the limit, if it is over the limit.
+fn operations_over_limit(
+    capabilities: &[Capability],
+    limit: Option<usize>,
+) -> Option<(usize, usize)> {
+    limit
+        .filter(|l| capabilities.len() > *l)
+        .map(|l| (capabilities.len(), l))
+}
+
+/// The first of `capabilities` which writes or deletes content.
+fn write_capability(capabilities: &[Capability]) -> Option<&Capability> {
+    capabilities.iter().find(|c| {
+        c.resource.kepler_resource().and_then(|r| r.service()) == Some("kv")
+            && matches!(c.action.as_str(), "put" | "del")
+    })
+}
+
+/// The first orbit invoked by `capabilities` which is neither controlled by `invoker` nor
+/// granted by one of the `parents` delegations.
+async fn undelegated_orbit<C: ConnectionTrait>(
+    db: &C,
+    invoker: &str,
+    capabilities: &[Capability],
+    parents: &[Cid],
+) -> Result<Option<OrbitId>, DbErr> {
+    let granted = abilities::Entity::find()
+        .filter(abilities::Column::Delegation.is_in(parents.iter().map(|c| Hash::from(*c))))
+        .all(db)
+        .await?;
+    let granted: HashSet<&OrbitId> = granted.iter().filter_map(|a| a.resource.orbit()).collect();
+    Ok(capabilities
+        .iter()
+        .filter_map(|c| c.resource.orbit())
+        .find(|o| !crate::util::is_controller(o, invoker) && !granted.contains(o))
+        .cloned())
+}
+
+impl<C, B, K> OrbitDatabase<C, B, K>
+where
+    // streamed listings read from their own handle on the connection
+    C: TransactionTrait + ConnectionTrait + Clone + Send + Sync + 'static,
+    B: StorageSetup,
+    K: Secrets,
+{
+    #[tracing::instrument(skip_all, fields(events = events.len()))]
+    async fn transact(
+        &self,
+        events: Vec<Event>,
+    ) -> Result<HashMap<OrbitId, Commit>, TxError<B, K>> {
+        let tx = self
+            .conn
+            .begin_with_config(Some(sea_orm::IsolationLevel::ReadUncommitted), None)
+            .await?;
+
+        let commit = transact(
+            &tx,
+            &self.storage,
+            &self.secrets,
+            self.hash,
+            &self.methods,
+            &self.resolver,
+            self.max_depth,
+            self.max_parents,
+            events,
+        )
+        .await?;
+        publish_pointers(&tx, &self.secrets, self.pointers, commit.keys()).await?;
+
+        tx.commit().await?;
+
+        Ok(commit)
+    }
+
+    pub async fn delegate(
+        &self,
+        delegation: Delegation,
+    ) -> Result<HashMap<OrbitId, Commit>, TxError<B, K>> {
+        self.transact(vec![Event::Delegation(Box::new(delegation))])
+            .await
+    }
+
+    pub async fn revoke(
+        &self,
+        revocation: Revocation,
+    ) -> Result<HashMap<OrbitId, Commit>, TxError<B, K>> {
+        self.transact(vec![Event::Revocation(Box::new(revocation))])
+            .await
+    }
+
+    pub async fn invoke<S>(
+        &self,
+        invocation: Invocation,
+        inputs: InvocationInputs<S::Writable>,
+    ) -> Result<
+        (
+            HashMap<OrbitId, Commit>,
+            Vec<InvocationOutcome<B::Readable>>,
+        ),
+        TxStoreError<B, S, K>,
+    >
+    where
+        B: ImmutableWriteStore<S> + ImmutableDeleteStore + ImmutableReadStore,
+        S: ImmutableStaging,
+        S::Writable: 'static + Unpin,
+    {
+        self.invoke_with::<S>(invocation, inputs, InvokeOptions::default())
+            .await
+    }
+
+    #[tracing::instrument(skip_all, fields(invoker = %invocation.0.invoker))]
+    pub async fn invoke_with<S>(
+        &self,
+        invocation: Invocation,
+        inputs: InvocationInputs<S::Writable>,
+        options: InvokeOptions,
+    ) -> Result<
+        (
+            HashMap<OrbitId, Commit>,
+            Vec<InvocationOutcome<B::Readable>>,
+        ),
+        TxStoreError<B, S, K>,
+    >
+    where
+        B: ImmutableWriteStore<S> + ImmutableDeleteStore + ImmutableReadStore,
+        S: ImmutableStaging,
+        S::Writable: 'static + Unpin,
+    {
+        let (prepared, event) = self.prepare::<S>(invocation, inputs, &options)?;
+
+        let tx = self
+            .conn
+            .begin_with_config(Some(sea_orm::IsolationLevel::ReadUncommitted), None)
+            .instrument(info_span!("begin"))
+            .await?;
+        let mut claims = Claims::new(self.replay.as_ref());
+        let replay = self
+            .admit::<S>(
+                &tx,
+                &event,
+                prepared.hash,
+                prepared.expiry,
+                &mut claims,
+                &options,
+            )
+            .await?;
+        //  verify and commit invocation and kv operations
+        let mut commit = transact(
+            &tx,
+            &self.storage,
+            &self.secrets,
+            self.hash,
+            &self.methods,
+            &self.resolver,
+            self.max_depth,
+            self.max_parents,
+            vec![event],
+        )
+        .await?;
+
+        let (results, removals) = self
+            .effects::<S>(&tx, &prepared.caps, prepared.stages, replay, &options)
+            .await?;
+
+        if options.dry_run {
+            tx.rollback().instrument(info_span!("rollback")).await?;
+            return Ok((commit, results));
+        }
+
+        // the receipts are committed with the invocation, so they exist only if it does
+        issue_receipts(
+            &tx,
+            &self.secrets,
+            prepared.hash,
+            &prepared.caps,
+            &mut commit,
+            replay,
+        )
+        .await?;
+        if !replay {
+            publish_pointers(&tx, &self.secrets, self.pointers, commit.keys()).await?;
+        }
+
+        // commit tx if all side effects worked
+        tx.commit().instrument(info_span!("commit")).await?;
+        claims.keep();
+        self.remove_deleted(removals, &prepared.written).await;
+        Ok((commit, results))
+    }
+
+    /// Apply several invocations in one transaction, so that either all of them are applied
+    /// or none is. Each orbit gets one epoch holding the batch's events for it, then the
+    /// invocations' reads and writes are performed in order, so reads see every write of
+    /// the batch.
+    ///
+    /// Returns the commits and outcomes of each invocation, in order. On failure, returns
+    /// the position of the invocation which failed, or `None` if the batch failed as a
+    /// whole, with the error.
+    #[allow(clippy::type_complexity)]
+    #[tracing::instrument(skip_all, fields(invocations = invocations.len()))]
+    pub async fn invoke_batch<S>(
+        &self,
+        invocations: Vec<(Invocation, InvocationInputs<S::Writable>)>,
+        options: InvokeOptions,
+    ) -> Result<
+        Vec<(
+            HashMap<OrbitId, Commit>,
+            Vec<InvocationOutcome<B::Readable>>,
+        )>,
+        (Option<usize>, TxStoreError<B, S, K>),
+    >
+    where
+        B: ImmutableWriteStore<S> + ImmutableDeleteStore + ImmutableReadStore,
+        S: ImmutableStaging,
+        S::Writable: 'static + Unpin,
+    {
+        let mut prepared = Vec::with_capacity(invocations.len());
+        let mut events = Vec::with_capacity(invocations.len());
+        for (i, (invocation, inputs)) in invocations.into_iter().enumerate() {
+            let (p, event) = self
+                .prepare::<S>(invocation, inputs, &options)
+                .map_err(|e| (Some(i), e))?;
+            // the same invocation twice would only be applied once
+            if prepared
+                .iter()
+                .any(|q: &PreparedInvocation<S::Writable>| q.hash == p.hash)
+            {
+                return Err((Some(i), TxStoreError::Replayed(p.hash)));
+            }
+            prepared.push(p);
+            events.push(event);
+        }
+
+        let tx = self
+            .conn
+            .begin_with_config(Some(sea_orm::IsolationLevel::ReadUncommitted), None)
+            .instrument(info_span!("begin"))
+            .await
+            .map_err(|e| (None, TxStoreError::from(e)))?;
+        let mut claims = Claims::new(self.replay.as_ref());
+        let mut replays = Vec::with_capacity(prepared.len());
+        for (i, (p, event)) in prepared.iter().zip(&events).enumerate() {
+            let replay = self
+                .admit::<S>(&tx, event, p.hash, p.expiry, &mut claims, &options)
+                .await
+                .map_err(|e| (Some(i), e))?;
+            // authorized on its own first, so that a failure is attributed to the invocation
+            if let Event::Invocation(invocation, _) = event {
+                let failure = invocation::check(&tx, &self.resolver, invocation)
+                    .await
+                    .map_err(|e| (None, TxStoreError::from(e)))?
+                    .into_iter()
+                    .next();
+                if let Some(e) = failure {
+                    return Err((Some(i), TxStoreError::Tx(TxError::InvalidInvocation(e))));
+                }
+            }
+            replays.push(replay);
+        }
+        let fresh = replays.iter().any(|replay| !replay);
+
+        let commit = transact(
+            &tx,
+            &self.storage,
+            &self.secrets,
+            self.hash,
+            &self.methods,
+            &self.resolver,
+            self.max_depth,
+            self.max_parents,
+            events,
+        )
+        .await
+        .map_err(|e| (None, TxStoreError::from(e)))?;
+
+        let mut applied = Vec::with_capacity(prepared.len());
+        let mut removals = Vec::new();
+        let mut written = HashSet::new();
+        for (i, (p, replay)) in prepared.into_iter().zip(replays).enumerate() {
+            let (results, deleted) = self
+                .effects::<S>(&tx, &p.caps, p.stages, replay, &options)
+                .await
+                .map_err(|e| (Some(i), e))?;
+            // an invocation's commits are those of the orbits it invokes
+            let mut commits: HashMap<OrbitId, Commit> = commit
+                .iter()
+                .filter(|(orbit, _)| p.caps.iter().any(|c| c.resource.orbit() == Some(*orbit)))
+                .map(|(orbit, c)| (orbit.clone(), c.clone()))
+                .collect();
+            if !options.dry_run {
+                issue_receipts(&tx, &self.secrets, p.hash, &p.caps, &mut commits, replay)
+                    .await
+                    .map_err(|e| (Some(i), TxStoreError::from(e)))?;
+            }
+            removals.extend(deleted);
+            written.extend(p.written);
+            applied.push((commits, results));
+        }
+
+        if options.dry_run {
+            tx.rollback()
+                .instrument(info_span!("rollback"))
+                .await
+                .map_err(|e| (None, TxStoreError::from(e)))?;
+        } else {
+            if fresh {
+                publish_pointers(&tx, &self.secrets, self.pointers, commit.keys())
+                    .await
+                    .map_err(|e| (None, TxStoreError::from(e)))?;
+            }
+            tx.commit()
+                .instrument(info_span!("commit"))
+                .await
+                .map_err(|e| (None, TxStoreError::from(e)))?;
+            claims.keep();
+            // content written by any invocation of the batch is kept
+            self.remove_deleted(removals, &written).await;
+        }
+        Ok(applied)
+    }
+
+    // check an invocation against the node's settings, and make its event, matching each
+    // write to its staged content
+    fn prepare<S>(
+        &self,
+        invocation: Invocation,
+        mut inputs: InvocationInputs<S::Writable>,
+        options: &InvokeOptions,
+    ) -> Result<(PreparedInvocation<S::Writable>, Event), TxStoreError<B, S, K>>
+    where
+        B: ImmutableWriteStore<S> + ImmutableDeleteStore + ImmutableReadStore,
+        S: ImmutableStaging,
+        S::Writable: 'static + Unpin,
+    {
+        if let Some((count, limit)) =
+            operations_over_limit(&invocation.0.capabilities, options.max_operations)
+        {
+            return Err(TxStoreError::TooManyOperations { count, limit });
+        }
+        if let Some(c) = write_capability(&invocation.0.capabilities) {
+            if self.read_only {
+                return Err(TxStoreError::ReadOnly(c.resource.clone(), c.action.clone()));
+            }
+            if self.maintenance() {
+                return Err(TxStoreError::Maintenance(
+                    c.resource.clone(),
+                    c.action.clone(),
+                ));
+            }
+        }
+
+        // keys are used in their canonical form, which those with `..` segments don't have
+        if let Some(path) = invocation
+            .0
+            .capabilities
+            .iter()
+            .filter_map(|c| c.resource.kepler_resource()?.path())
+            .find(|p| normalize_path(p).is_none())
+        {
+            return Err(TxStoreError::InvalidKey(path.to_string()));
+        }
+
+        let mut stages = HashMap::new();
+        let mut written = HashSet::new();
+        let mut ops = Vec::new();
+        // for each capability being invoked
+        for cap in invocation.0.capabilities.iter() {
+            match cap
+                .resource
+                .kepler_resource()
+                .and_then(|r| Some((r.service()?, cap.action.as_str(), r.orbit(), r.path()?)))
+            {
+                // stage inputs for content writes
+                Some(("kv", "put", orbit, path)) => {
+                    let (metadata, mut stage) = inputs
+                        .remove(&(orbit.clone(), path.to_string()))
+                        .ok_or(TxStoreError::MissingInput)?;
+
+                    let value = stage.hash();
+                    let size = stage.size();
+
+                    let norm_path = normalize_path(path)
+                        .ok_or_else(|| TxStoreError::InvalidKey(path.to_string()))?;
+
+                    stages.insert((orbit.clone(), norm_path.clone()), stage);
+                    written.insert((orbit.clone(), value));
+                    // add write for tx
+                    ops.push(Operation::KvWrite {
+                        orbit: orbit.clone(),
+                        key: norm_path,
+                        metadata,
+                        value,
+                        size,
+                    });
+                }
+                // add delete for tx
+                Some(("kv", "del", orbit, path)) => {
+                    ops.push(Operation::KvDelete {
+                        orbit: orbit.clone(),
+                        key: normalize_path(path)
+                            .ok_or_else(|| TxStoreError::InvalidKey(path.to_string()))?,
+                        version: None,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let caps = invocation.0.capabilities.clone();
+        let expiry = invocation.0.invocation.payload.expiration.as_seconds() as i64;
+        let event = Event::Invocation(Box::new(invocation), ops);
+        Ok((
+            PreparedInvocation {
+                hash: event.hash(),
+                caps,
+                expiry,
+                stages,
+                written,
+            },
+            event,
+        ))
+    }
+
+    // the checks of an invocation made within its transaction, returning whether it is a
+    // replay of one already committed. Unless it is a dry run, the invocation is claimed in
+    // the replay cache until `expiry`, so that concurrent submissions of it are rejected.
+    async fn admit<S>(
+        &self,
+        tx: &DatabaseTransaction,
+        event: &Event,
+        hash: Hash,
+        expiry: i64,
+        claims: &mut Claims,
+        options: &InvokeOptions,
+    ) -> Result<bool, TxStoreError<B, S, K>>
+    where
+        B: ImmutableWriteStore<S> + ImmutableDeleteStore + ImmutableReadStore,
+        S: ImmutableStaging,
+        S::Writable: 'static + Unpin,
+    {
+        if let (true, Event::Invocation(invocation, _)) = (options.check_orbits, event) {
+            if let Some(orbit) = undelegated_orbit(
+                tx,
+                &invocation.0.invoker,
+                &invocation.0.capabilities,
+                &invocation.0.parents,
+            )
+            .await?
+            {
+                return Err(TxStoreError::UndelegatedOrbit(orbit));
+            }
+        }
+        // a retried invocation has already had its effects on storage
+        let replay = !committed_invocations(tx, [hash]).await?.is_empty();
+        match &self.replay {
+            Some(ReplayProtection::Database) if replay => return Err(TxStoreError::Replayed(hash)),
+            Some(ReplayProtection::Memory(_)) if options.dry_run && claims.contains(&hash) => {
+                return Err(TxStoreError::Replayed(hash))
+            }
+            Some(ReplayProtection::Memory(_))
+                if !options.dry_run && !claims.claim(hash, expiry) =>
+            {
+                return Err(TxStoreError::Replayed(hash))
+            }
+            _ => {}
+        }
+        Ok(replay)
+    }
+
+    // perform and record the side effects of a committed invocation's capabilities,
+    // returning their outcomes and the content its deletes leave unreferenced
+    #[allow(clippy::type_complexity)]
+    async fn effects<S>(
+        &self,
+        tx: &DatabaseTransaction,
+        caps: &[Capability],
+        mut stages: HashMap<(OrbitId, String), HashBuffer<S::Writable>>,
+        replay: bool,
+        options: &InvokeOptions,
+    ) -> Result<
+        (
+            Vec<InvocationOutcome<B::Readable>>,
+            Vec<(OrbitId, String, Hash)>,
+        ),
+        TxStoreError<B, S, K>,
+    >
+    where
+        B: ImmutableWriteStore<S> + ImmutableDeleteStore + ImmutableReadStore,
+        S: ImmutableStaging,
+        S::Writable: 'static + Unpin,
+    {
+        let mut results = Vec::new();
+        let mut removals = Vec::new();
+        // perform and record side effects
+        for cap in caps {
+            let resource = cap
+                .resource
+                .kepler_resource()
+                .and_then(|r| Some((r.orbit(), r.service()?, normalize_path(r.path()?)?)));
+            match (
+                resource
+                    .as_ref()
+                    .map(|(orbit, service, path)| (*orbit, *service, path.as_str())),
+                cap.action.as_str(),
+            ) {
+                (Some((orbit, "kv", path)), "get") if options.locate => {
+                    results.push(InvocationOutcome::KvLocation(
+                        match get_kv_entity(tx, orbit, path, None).await? {
+                            Some(kv) => {
+                                let value = kv.value;
+                                Some((write_metadata(tx, kv).await?, value))
+                            }
+                            None => None,
+                        },
+                    ))
+                }
+                (Some((orbit, "kv", path)), "get") => results.push(InvocationOutcome::KvRead(
+                    with_timeout(
+                        self.storage_timeout,
+                        get_kv(tx, &self.storage, orbit, path, options.version),
+                    )
+                    .instrument(info_span!("read", %orbit, path))
+                    .await?
+                    .map_err(|e| match e {
+                        EitherError::A(e) => TxStoreError::Tx(e.into()),
+                        EitherError::B(e) => TxStoreError::StoreRead(e),
+                    })?
+                    .and_then(|(md, c)| match c {
+                        Ok(c) => Some(Ok((md, c))),
+                        // content of a prior version is removed if it was deleted
+                        Err(_) if options.version.is_some() => None,
+                        Err(hash) => Some(Err(TxStoreError::MissingContent {
+                            orbit: orbit.clone(),
+                            key: path.to_string(),
+                            hash,
+                        })),
+                    })
+                    .transpose()?,
+                )),
+                // content is read by its CID, whichever keys reference it
+                (Some((orbit, "blocks", path)), "get") => {
+                    let block = match path.parse::<Cid>() {
+                        Ok(cid) => with_timeout(
+                            self.storage_timeout,
+                            get_block(tx, &self.storage, orbit, cid.into()),
+                        )
+                        .instrument(info_span!("read", %orbit, path))
+                        .await?
+                        .map_err(|e| match e {
+                            EitherError::A(e) => TxStoreError::Tx(e.into()),
+                            EitherError::B(e) => TxStoreError::StoreRead(e),
+                        })?,
+                        Err(_) => None,
+                    };
+                    results.push(InvocationOutcome::KvRead(block))
+                }
+                (Some((orbit, "kv", path)), "versions") => results.push(
+                    InvocationOutcome::KvVersions(list_versions(tx, orbit, path).await?),
+                ),
+                (Some((orbit, "kv", path)), "list") => match options.list_since {
+                    Some(since) => results.push(InvocationOutcome::KvChanges(
+                        list_since(tx, orbit, path, since).await?,
+                    )),
+                    None if options.stream_list => {
+                        results.push(InvocationOutcome::KvKeys(list_stream(
+                            self.conn.clone(),
+                            orbit.clone(),
+                            path.to_string(),
+                            LIST_PAGE,
+                        )))
+                    }
+                    None => results.push(InvocationOutcome::KvList(list(tx, orbit, path).await?)),
+                },
+                (Some((orbit, "kv", path)), "del") => {
+                    // the key may have been written again since a replayed delete
+                    let kv = match replay {
+                        false => get_kv_entity(tx, orbit, path, None).await?,
+                        true => None,
+                    };
+                    if let Some(kv) = kv {
+                        removals.push((orbit.clone(), path.to_string(), kv.value));
+                    }
+                    results.push(InvocationOutcome::KvDelete)
+                }
+                (Some((orbit, "kv", path)), "put") => {
+                    if let Some(stage) = stages.remove(&(orbit.clone(), path.to_string())) {
+                        if !replay && !options.dry_run {
+                            with_timeout(self.storage_timeout, self.storage.persist(orbit, stage))
+                                .instrument(info_span!("persist", %orbit, path))
+                                .await?
+                                .map_err(TxStoreError::StoreWrite)?;
+                        }
+                        results.push(InvocationOutcome::KvWrite)
+                    }
+                }
+                (Some((orbit, "kv", path)), "metadata") => {
+                    results.push(InvocationOutcome::KvMetadata(
+                        metadata(tx, orbit, path, options.version).await?,
+                    ))
+                }
+                (Some((orbit, "kv", path)), "exists") => {
+                    let exists = match get_kv_entity(tx, orbit, path, None).await? {
+                        Some(kv) => with_timeout(
+                            self.storage_timeout,
+                            self.storage.contains(orbit, &kv.value),
+                        )
+                        .await?
+                        .map_err(TxStoreError::StoreRead)?,
+                        None => false,
+                    };
+                    results.push(InvocationOutcome::KvExists(exists))
+                }
+                (Some((orbit, "capabilities", "all")), "read") => {
+                    let (sessions, next) =
+                        get_valid_delegations(tx, orbit, &options.sessions).await?;
+                    results.push(InvocationOutcome::OpenSessions(sessions, next))
+                }
+                _ => {}
+            }
+        }
+
+        Ok((results, removals))
+    }
+
+    async fn remove_deleted(
+        &self,
+        removals: Vec<(OrbitId, String, Hash)>,
+        written: &HashSet<(OrbitId, Hash)>,
+    ) where
+        B: ImmutableDeleteStore,
+    {
+        // content is only removed once its deletion is committed, so that a failure part way
+        // through an invocation leaves no orbit referencing content which is gone. Content
+        // written by the same invocation is kept, as another key now references it
+        for (orbit, path, hash) in removals {
+            if written.contains(&(orbit.clone(), hash)) {
+                continue;
+            }
+            let error = match with_timeout(self.storage_timeout, self.storage.remove(&orbit, &hash))
+                .instrument(info_span!("remove", %orbit, path = path.as_str()))
+                .await
+            {
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => e.to_string(),
+                Err(e) => e.to_string(),
+            };
+            tracing::warn!(%orbit, path = path.as_str(), %error, "failed to remove deleted content");
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InvocationOutcome<R> {
+    KvList(Vec<String>),
+    /// The keys of a `kv/list`, which are read as the stream is polled, after the
+    /// invocation is committed, and so may include changes committed since.
+    KvKeys(KeyStream),
+    KvChanges(Vec<KvChange>),
+    KvDelete,
+    KvMetadata(Option<Metadata>),
+    KvWrite,
+    KvRead(Option<(Metadata, Content<R>)>),
+    /// Every write of a key, latest first.
+    KvVersions(Vec<KvVersion>),
+    KvLocation(Option<(Metadata, Hash)>),
+    /// Whether a key is set and its content is in block storage.
+    KvExists(bool),
+    /// A page of the orbit's valid delegations, and the cursor of the next page if there
+    /// are more.
+    OpenSessions(HashMap<Hash, DelegationInfo>, Option<Hash>),
+}
+
+impl<S: StorageSetup, K: Secrets> From<delegation::Error> for TxError<S, K> {
+    fn from(e: delegation::Error) -> Self {
+        match e {
+            delegation::Error::InvalidDelegation(e) => Self::InvalidDelegation(e),
+            delegation::Error::Db(e) => Self::Db(e),
+        }
+    }
+}
+
+impl<S: StorageSetup, K: Secrets> From<invocation::Error> for TxError<S, K> {
+    fn from(e: invocation::Error) -> Self {
+        match e {
+            invocation::Error::InvalidInvocation(e) => Self::InvalidInvocation(e),
+            invocation::Error::Db(e) => Self::Db(e),
+        }
+    }
+}
+
+impl<S: StorageSetup, K: Secrets> From<revocation::Error> for TxError<S, K> {
+    fn from(e: revocation::Error) -> Self {
+        match e {
+            revocation::Error::InvalidRevocation(e) => Self::InvalidRevocation(e),
+            revocation::Error::Db(e) => Self::Db(e),
+        }
+    }
+}
+
+async fn event_orbits<'a, C: ConnectionTrait>(
+    db: &C,
+    ev: &'a [(Hash, Event)],
+) -> Result<HashMap<OrbitId, Vec<&'a (Hash, Event)>>, DbErr> {
+    // get orderings of events listed as revoked by events in the ev list
+    let mut orbits = HashMap::<OrbitId, Vec<&'a (Hash, Event)>>::new();
+    let revoked_events = event_order::Entity::find()
+        .filter(
+            event_order::Column::Event.is_in(ev.iter().filter_map(|(_, e)| match e {
+                Event::Revocation(r) => Some(Hash::from(r.0.revoked)),
+                _ => None,
+            })),
+        )
+        .all(db)
+        .await?;
+    for e in ev {
+        match &e.1 {
+            Event::Delegation(d) => {
+                for orbit in d.0.orbits() {
+                    let entry = orbits.entry(orbit.clone()).or_insert_with(Vec::new);
+                    if !entry.iter().any(|(h, _)| h == &e.0) {
+                        entry.push(e);
+                    }
+                }
+            }
+            Event::Invocation(i, _) => {
+                for orbit in i.0.orbits() {
+                    let entry = orbits.entry(orbit.clone()).or_insert_with(Vec::new);
+                    if !entry.iter().any(|(h, _)| h == &e.0) {
+                        entry.push(e);
+                    }
+                }
+            }
+            Event::Revocation(r) => {
+                let r_hash = Hash::from(r.0.revoked);
+                for revoked in &revoked_events {
+                    if r_hash == revoked.event {
+                        let entry = orbits
+                            .entry(revoked.orbit.0.clone())
+                            .or_insert_with(Vec::new);
+                        if !entry.iter().any(|(h, _)| h == &e.0) {
+                            entry.push(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(orbits)
+}
+
+// the error for an event issued by, or delegating to, a DID of a disallowed method
+fn unauthorized_method<S: StorageSetup, K: Secrets>(
+    methods: &MethodAllowlist,
+    event: &Event,
+) -> Result<(), TxError<S, K>> {
+    match event {
+        Event::Delegation(d) => {
+            let orbits: Vec<_> = d.0.orbits().collect();
+            match [&d.0.delegator, &d.0.delegate]
+                .into_iter()
+                .find(|did| !methods.allows(did, orbits.iter().copied()))
+            {
+                Some(did) => Err(TxError::InvalidDelegation(
+                    delegation::DelegationError::UnauthorizedMethod(did.clone()),
+                )),
+                None => Ok(()),
+            }
+        }
+        Event::Invocation(i, _) if !methods.allows(&i.0.invoker, i.0.orbits()) => {
+            Err(TxError::InvalidInvocation(
+                invocation::InvocationError::UnauthorizedMethod(i.0.invoker.clone()),
+            ))
+        }
+        Event::Revocation(r) if !methods.allows(&r.0.revoker, []) => {
+            Err(TxError::InvalidRevocation(
+                revocation::RevocationError::UnauthorizedMethod(r.0.revoker.clone()),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// orderings of those of the given events which have already been committed
+async fn committed_orderings<C: ConnectionTrait>(
+    db: &C,
+    events: impl IntoIterator<Item = Hash>,
+) -> Result<Vec<event_order::Model>, DbErr> {
+    event_order::Entity::find()
+        .filter(event_order::Column::Event.is_in(events))
+        .all(db)
+        .await
+}
+
+// invocations which were committed, including those whose orderings were compacted away
+async fn committed_invocations<C: ConnectionTrait>(
+    db: &C,
+    events: impl IntoIterator<Item = Hash>,
+) -> Result<HashSet<Hash>, DbErr> {
+    Ok(invocation::Entity::find()
+        .filter(invocation::Column::Id.is_in(events))
+        .select_only()
+        .column(invocation::Column::Id)
+        .into_tuple::<Hash>()
+        .all(db)
+        .await?
+        .into_iter()
+        .collect())
+}
+
+// reconstruct the commits which first applied the given event orderings
+async fn replayed_commits<C: ConnectionTrait>(
+    db: &C,
+    orderings: &[event_order::Model],
+) -> Result<HashMap<OrbitId, Commit>, DbErr> {
+    let mut commits = HashMap::new();
+    for ordering in orderings {
+        if commits.contains_key(&ordering.orbit.0) {
+            continue;
+        }
+        let committed_events = event_order::Entity::find()
+            .filter(event_order::Column::Orbit.eq(ordering.orbit.clone()))
+            .filter(event_order::Column::Epoch.eq(ordering.epoch))
+            .order_by_asc(event_order::Column::EpochSeq)
+            .select_only()
+            .column(event_order::Column::Event)
+            .into_tuple::<Hash>()
+            .all(db)
+            .await?;
+        let consumed_epochs = epoch_order::Entity::find()
+            .filter(epoch_order::Column::Orbit.eq(ordering.orbit.clone()))
+            .filter(epoch_order::Column::Child.eq(ordering.epoch))
+            .select_only()
+            .column(epoch_order::Column::Parent)
+            .into_tuple::<Hash>()
+            .all(db)
+            .await?;
+        commits.insert(
+            ordering.orbit.0.clone(),
+            Commit {
+                rev: ordering.epoch,
+                seq: ordering.seq,
+                committed_events,
+                consumed_epochs,
+                receipt: None,
+            },
+        );
+    }
+    Ok(commits)
+}
+
+// sign and store a receipt of the invocation for each orbit it was committed to, or for a
+// replayed invocation find those stored when it was first committed
+async fn issue_receipts<C: ConnectionTrait, S: StorageSetup, K: Secrets>(
+    db: &C,
+    secrets: &K,
+    invocation: Hash,
+    caps: &[Capability],
+    commits: &mut HashMap<OrbitId, Commit>,
+    replay: bool,
+) -> Result<(), TxError<S, K>> {
+    if replay {
+        for r in receipt::Entity::find()
+            .filter(receipt::Column::Invocation.eq(invocation))
+            .all(db)
+            .await?
+        {
+            if let Some(commit) = commits.get_mut(&r.orbit.0) {
+                commit.receipt = Some(r.id);
+            }
+        }
+        return Ok(());
+    }
+    let algorithms = event_hash_algorithms(db, commits.keys()).await?;
+    for (orbit, commit) in commits.iter_mut() {
+        let invoked = caps
+            .iter()
+            .filter(|c| c.resource.orbit() == Some(orbit))
+            .map(|c| Invoked {
+                resource: c.resource.to_string(),
+                ability: c.action.clone(),
+            })
+            .collect();
+        let keypair = secrets.get_keypair(orbit).await.map_err(TxError::Secrets)?;
+        let serialization = Receipt::sign(
+            ReceiptPayload::new(
+                orbit,
+                invocation,
+                commit.rev,
+                commit.seq,
+                invoked,
+                keypair.public(),
+            ),
+            &keypair,
+        )?
+        .encode()?;
+        let id = crate::hash::hash_with(
+            algorithms.get(orbit).copied().unwrap_or_default(),
+            &serialization,
+        );
+        receipt::Entity::insert(receipt::ActiveModel::from(receipt::Model {
+            id,
+            orbit: OrbitIdWrap(orbit.clone()),
+            invocation,
+            serialization,
+        }))
+        .exec(db)
+        .await?;
+        commit.receipt = Some(id);
+    }
+    Ok(())
+}
+
+// the epochs of an orbit which no other epoch follows, sorted, and the greatest of their
+// sequence numbers
+async fn orbit_heads<C: ConnectionTrait>(db: &C, orbit: &OrbitId) -> Result<OrbitHeads, DbErr> {
+    let mut heads = epoch::Entity::find()
+        .select_only()
+        .left_join(epoch_order::Entity)
+        .filter(
+            Condition::all()
+                .add(epoch::Column::Orbit.eq(OrbitIdWrap(orbit.clone())))
+                .add(epoch_order::Column::Child.is_null()),
+        )
+        .column(epoch::Column::Id)
+        .column(epoch::Column::Seq)
+        .into_tuple::<(Hash, i64)>()
+        .all(db)
+        .await?;
+    heads.sort();
+    Ok(OrbitHeads {
+        height: heads.iter().map(|(_, seq)| *seq).max().unwrap_or(0),
+        heads: heads.into_iter().map(|(id, _)| id).collect(),
+    })
+}
+
+/// The feature flag which publishes pointers for an orbit when they aren't published for
+/// every orbit.
+pub const POINTERS_FEATURE: &str = "pointers";
+
+async fn feature_enabled<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    flag: &str,
+) -> Result<bool, DbErr> {
+    Ok(
+        orbit_feature::Entity::find_by_id((OrbitIdWrap(orbit.clone()), flag.to_string()))
+            .one(db)
+            .await?
+            .map(|f| f.enabled)
+            .unwrap_or(false),
+    )
+}
+
+// sign and store a pointer to the current heads of each of `orbits`, superseding the one
+// published before, if pointers are published for `all` orbits or the orbit has the
+// pointers feature enabled
+async fn publish_pointers<'a, C: ConnectionTrait, S: StorageSetup, K: Secrets>(
+    db: &C,
+    secrets: &K,
+    all: bool,
+    orbits: impl Iterator<Item = &'a OrbitId>,
+) -> Result<(), TxError<S, K>> {
+    let issued = OffsetDateTime::now_utc().unix_timestamp();
+    for orbit in orbits {
+        if !all && !feature_enabled(db, orbit, POINTERS_FEATURE).await? {
+            continue;
+        }
+        let heads = orbit_heads(db, orbit).await?;
+        let sequence = orbit_pointer::Entity::find_by_id(OrbitIdWrap(orbit.clone()))
+            .one(db)
+            .await?
+            .map(|p| p.sequence + 1)
+            .unwrap_or(0);
+        let keypair = secrets.get_keypair(orbit).await.map_err(TxError::Secrets)?;
+        let serialization = Pointer::sign(
+            PointerPayload::new(
+                orbit,
+                &heads.heads,
+                heads.height,
+                sequence,
+                issued,
+                keypair.public(),
+            ),
+            &keypair,
+        )?
+        .encode()?;
+        orbit_pointer::Entity::insert(orbit_pointer::ActiveModel::from(orbit_pointer::Model {
+            orbit: OrbitIdWrap(orbit.clone()),
+            sequence,
+            serialization,
+        }))
+        .on_conflict(
+            OnConflict::column(orbit_pointer::Column::Orbit)
+                .update_columns([
+                    orbit_pointer::Column::Sequence,
+                    orbit_pointer::Column::Serialization,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    }
+    Ok(())
+}
+
+// join `heads` with merge epochs, each with at most `max_parents` parents, until at most
+// that many remain, returning the remaining heads and the merges with their parents
+fn merge_heads(
+    orbit: &OrbitId,
+    mut heads: Vec<Hash>,
+    max_parents: Option<usize>,
+    algorithm: HashAlgorithm,
+) -> Result<(Vec<Hash>, Vec<(Hash, Vec<Hash>)>), HashError> {
+    let max = match max_parents {
+        Some(max) => max.max(2),
+        None => return Ok((heads, vec![])),
+    };
+    let mut merges = Vec::new();
+    while heads.len() > max {
+        // sorted, so concurrent commits merging the same heads make the same merges
+        heads.sort();
+        heads = heads
+            .chunks(max)
+            .map(|chunk| match chunk {
+                [head] => Ok(*head),
+                _ => {
+                    let merge = epoch_hash(orbit, &[], chunk, algorithm)?;
+                    merges.push((merge, chunk.to_vec()));
+                    Ok(merge)
+                }
+            })
+            .collect::<Result<_, HashError>>()?;
+    }
+    Ok((heads, merges))
+}
+
+// the algorithms the given orbits hash their epochs with
+async fn event_hash_algorithms<'a, C: ConnectionTrait>(
+    db: &C,
+    orbits: impl Iterator<Item = &'a OrbitId>,
+) -> Result<HashMap<OrbitId, HashAlgorithm>, DbErr> {
+    Ok(orbit::Entity::find()
+        .filter(orbit::Column::Id.is_in(orbits.cloned().map(OrbitIdWrap)))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|o| (o.id.0, o.event_hash))
+        .collect())
+}
+
+#[tracing::instrument(name = "apply", skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn transact<C: ConnectionTrait, S: StorageSetup, K: Secrets>(
+    db: &C,
+    store_setup: &S,
+    secrets: &K,
+    hash: HashAlgorithm,
+    methods: &MethodAllowlist,
+    resolver: &DidResolver,
+    max_depth: usize,
+    max_parents: Option<usize>,
+    events: Vec<Event>,
+) -> Result<HashMap<OrbitId, Commit>, TxError<S, K>> {
+    // reject events of disallowed DID methods before verifying any signatures
+    for event in &events {
+        unauthorized_method(methods, event)?;
+    }
+
+    // for each event, get the hash and the relevent orbit(s)
+    let event_hashes = events
+        .into_iter()
+        .map(|e| (e.hash(), e))
+        .collect::<Vec<(Hash, Event)>>();
+
+    // events which were already committed, e.g. by a retried request, are not applied
+    // again, instead the commits which first applied them are returned
+    let committed = committed_orderings(db, event_hashes.iter().map(|(h, _)| *h)).await?;
+    let mut commits = replayed_commits(db, &committed).await?;
+    // invocations whose epochs were compacted have no commit to return, but are still
+    // not applied again, lest a write which was deleted since be written again
+    let compacted = committed_invocations(
+        db,
+        event_hashes
+            .iter()
+            .filter(|(h, e)| {
+                matches!(e, Event::Invocation(..)) && !committed.iter().any(|o| &o.event == h)
+            })
+            .map(|(h, _)| *h),
+    )
+    .await?;
+    let (replays, event_hashes): (Vec<_>, Vec<_>) = event_hashes
+        .into_iter()
+        .partition(|(h, _)| compacted.contains(h) || committed.iter().any(|o| &o.event == h));
+    for (_, event) in &replays {
+        // a replayed invocation may still read, so it must still be authorized
+        if let Event::Invocation(i, _) = event {
+            if let Some(e) = invocation::check(db, resolver, i).await?.into_iter().next() {
+                return Err(TxError::InvalidInvocation(e));
+            }
+        }
+    }
+    if event_hashes.is_empty() && !replays.is_empty() {
+        return Ok(commits);
+    }
+
+    let event_orbits = event_orbits(db, &event_hashes).await?;
+    let mut new_orbits = event_hashes
+        .iter()
+        .filter_map(|(_, e)| match e {
+            Event::Delegation(d) => Some(d.0.capabilities.iter().filter_map(|c| {
+                match (&c.resource, c.action.as_str()) {
+                    (Resource::Kepler(r), "host")
+                        if r.path().is_none()
+                            && r.service().is_none()
+                            && r.fragment().is_none() =>
+                    {
+                        Some(OrbitIdWrap(r.orbit().clone()))
+                    }
+                    _ => None,
+                }
+            })),
+            _ => None,
+        })
+        .flatten()
+        .collect::<Vec<OrbitIdWrap>>();
+    new_orbits.dedup();
+
+    if !new_orbits.is_empty() {
+        match orbit::Entity::insert_many(
+            new_orbits
+                .iter()
+                .cloned()
+                .map(|id| orbit::Model {
+                    id,
+                    hash,
+                    event_hash: hash,
+                    storage_limit: None,
+                    created_at: Some(OffsetDateTime::now_utc()),
+                })
+                .map(orbit::ActiveModel::from),
+        )
+        .on_conflict(
+            OnConflict::column(orbit::Column::Id)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec(db)
+        .await
+        {
+            Err(DbErr::RecordNotInserted) => (),
+            r => {
+                r?;
+            }
+        };
+    }
+
+    // get max sequence for each of the orbits
+    let mut max_seqs = event_order::Entity::find()
+        .filter(event_order::Column::Orbit.is_in(event_orbits.keys().cloned().map(OrbitIdWrap)))
+        .select_only()
+        .column(event_order::Column::Orbit)
+        .column_as(event_order::Column::Seq.max(), "max_seq")
+        .group_by(event_order::Column::Orbit)
+        .into_tuple::<(OrbitIdWrap, i64)>()
+        .all(db)
+        .await?
+        .into_iter()
+        .fold(HashMap::new(), |mut m, (orbit, seq)| {
+            m.insert(orbit, seq + 1);
+            m
+        });
+
+    // get 'most recent' epochs for each of the orbits
+    let mut most_recent = epoch::Entity::find()
+        .select_only()
+        .left_join(epoch_order::Entity)
+        .filter(
+            Condition::all()
+                .add(epoch::Column::Orbit.is_in(event_orbits.keys().cloned().map(OrbitIdWrap)))
+                .add(epoch_order::Column::Child.is_null()),
+        )
+        .column(epoch::Column::Orbit)
+        .column(epoch::Column::Id)
+        .into_tuple::<(OrbitIdWrap, Hash)>()
+        .all(db)
+        .await?
+        .into_iter()
+        .fold(HashMap::new(), |mut m, (orbit, epoch)| {
+            m.entry(orbit).or_insert_with(Vec::new).push(epoch);
+            m
+        });
+
+    let algorithms = event_hash_algorithms(db, event_orbits.keys()).await?;
 
     // get all the orderings and associated data
+    let mut merges = Vec::new();
     let (epoch_order, orbit_order, event_order, epochs) = event_orbits
         .into_iter()
-        .map(|(orbit, events)| {
-            let parents = most_recent.remove(&orbit).unwrap_or_default();
-            let epoch = epoch_hash(&orbit, &events, &parents)?;
-            let seq = max_seqs.remove(&orbit).unwrap_or(0);
-            Ok((orbit, (epoch, events, seq, parents)))
+        .map(|(orbit, events)| {
+            let heads = most_recent.remove(&orbit).unwrap_or_default();
+            let algorithm = algorithms.get(&orbit).copied().unwrap_or_default();
+            let seq = max_seqs.remove(&orbit).unwrap_or(0);
+            let (parents, orbit_merges) = merge_heads(&orbit, heads, max_parents, algorithm)?;
+            merges.extend(
+                orbit_merges
+                    .into_iter()
+                    .map(|(merge, parents)| (orbit.clone(), seq, merge, parents)),
+            );
+            let epoch = epoch_hash(&orbit, &events, &parents, algorithm)?;
+            Ok((orbit, (epoch, events, seq, parents)))
+        })
+        .collect::<Result<HashMap<_, _>, HashError>>()?
+        .into_iter()
+        .map(|(orbit, (epoch, hashes, seq, parents))| {
+            (
+                parents
+                    .iter()
+                    .map(|parent| epoch_order::Model {
+                        parent: *parent,
+                        child: epoch,
+                        orbit: orbit.clone().into(),
+                    })
+                    .map(epoch_order::ActiveModel::from)
+                    .collect::<Vec<epoch_order::ActiveModel>>(),
+                (
+                    orbit.clone(),
+                    (
+                        seq,
+                        epoch,
+                        parents,
+                        hashes
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (h, _))| (*h, i as i64))
+                            .collect::<HashMap<_, _>>(),
+                    ),
+                ),
+                hashes
+                    .into_iter()
+                    .enumerate()
+                    .map(|(es, (hash, _))| event_order::Model {
+                        event: *hash,
+                        orbit: orbit.clone().into(),
+                        seq,
+                        epoch,
+                        epoch_seq: es as i64,
+                    })
+                    .map(event_order::ActiveModel::from)
+                    .collect::<Vec<event_order::ActiveModel>>(),
+                epoch::Model {
+                    seq,
+                    id: epoch,
+                    orbit: orbit.into(),
+                },
+            )
+        })
+        .fold(
+            (
+                Vec::<epoch_order::ActiveModel>::new(),
+                HashMap::<OrbitId, (i64, Hash, Vec<Hash>, HashMap<Hash, i64>)>::new(),
+                Vec::<event_order::ActiveModel>::new(),
+                Vec::<epoch::ActiveModel>::new(),
+            ),
+            |(mut eo, mut oo, mut ev, mut ep), (eo2, order, ev2, ep2)| {
+                eo.extend(eo2);
+                ev.extend(ev2);
+                oo.insert(order.0, order.1);
+                ep.push(ep2.into());
+                (eo, oo, ev, ep)
+            },
+        );
+
+    // save merge epochs, which a concurrent commit merging the same heads may have saved
+    if !merges.is_empty() {
+        let merge_epochs = merges.iter().map(|(orbit, seq, merge, _)| {
+            epoch::ActiveModel::from(epoch::Model {
+                seq: *seq,
+                id: *merge,
+                orbit: orbit.clone().into(),
+            })
+        });
+        let merge_links = merges.iter().flat_map(|(orbit, _, merge, parents)| {
+            parents.iter().map(|parent| {
+                epoch_order::ActiveModel::from(epoch_order::Model {
+                    parent: *parent,
+                    child: *merge,
+                    orbit: orbit.clone().into(),
+                })
+            })
+        });
+        for r in [
+            epoch::Entity::insert_many(merge_epochs)
+                .on_conflict(
+                    OnConflict::columns([epoch::Column::Id, epoch::Column::Orbit])
+                        .do_nothing()
+                        .to_owned(),
+                )
+                .exec(db)
+                .await
+                .map(|_| ()),
+            epoch_order::Entity::insert_many(merge_links)
+                .on_conflict(
+                    OnConflict::columns([
+                        epoch_order::Column::Parent,
+                        epoch_order::Column::Child,
+                        epoch_order::Column::Orbit,
+                    ])
+                    .do_nothing()
+                    .to_owned(),
+                )
+                .exec(db)
+                .await
+                .map(|_| ()),
+        ] {
+            match r {
+                Err(DbErr::RecordNotInserted) => (),
+                r => r?,
+            }
+        }
+    }
+
+    // save epochs
+    epoch::Entity::insert_many(epochs)
+        .exec(db)
+        .await
+        .map_err(|e| match e {
+            DbErr::Exec(RuntimeErr::SqlxError(SqlxError::Database(_))) => TxError::OrbitNotFound,
+            _ => e.into(),
+        })?;
+
+    // save epoch orderings
+    if !epoch_order.is_empty() {
+        epoch_order::Entity::insert_many(epoch_order)
+            .exec(db)
+            .await?;
+    }
+
+    // save event orderings
+    event_order::Entity::insert_many(event_order)
+        .exec(db)
+        .await?;
+
+    for (hash, event) in event_hashes {
+        match event {
+            Event::Delegation(d) => delegation::process(db, resolver, max_depth, *d).await?,
+            Event::Invocation(i, ops) => {
+                invocation::process(
+                    db,
+                    resolver,
+                    *i,
+                    ops.into_iter()
+                        .map(|op| {
+                            let v = orbit_order
+                                .get(op.orbit())
+                                .and_then(|(s, e, _, h)| Some((s, e, h.get(&hash)?)))
+                                .unwrap();
+                            op.version(*v.0, *v.1, *v.2)
+                        })
+                        .collect(),
+                )
+                .await?
+            }
+            Event::Revocation(r) => revocation::process(db, *r).await?,
+        };
+    }
+
+    for orbit in new_orbits {
+        store_setup
+            .create(&orbit.0)
+            .await
+            .map_err(TxError::StoreSetup)?;
+        secrets
+            .save_keypair(&orbit.0)
+            .await
+            .map_err(TxError::Secrets)?;
+    }
+
+    commits.extend(
+        orbit_order
+            .into_iter()
+            .map(|(o, (seq, rev, consumed_epochs, h))| {
+                (
+                    o,
+                    Commit {
+                        seq,
+                        rev,
+                        consumed_epochs,
+                        committed_events: h.keys().cloned().collect(),
+                        receipt: None,
+                    },
+                )
+            }),
+    );
+    Ok(commits)
+}
+
+/// The latest write or delete of a key, at orbit sequence number `seq`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct KvChange {
+    pub key: String,
+    pub seq: i64,
+    pub deleted: bool,
+}
+
+/// List the keys under `prefix` which were written or deleted after sequence number
+/// `since`, with the latest change of each, in the order the changes were made.
+async fn list_since<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    prefix: &str,
+    since: i64,
+) -> Result<Vec<KvChange>, DbErr> {
+    let o = || OrbitIdWrap(orbit.clone());
+    let mut changes: Vec<((i64, Hash, i64), KvChange)> = kv_write::Entity::find()
+        .filter(kv_write::Column::Orbit.eq(o()))
+        .filter(kv_write::Column::Key.starts_with(prefix))
+        .filter(kv_write::Column::Seq.gt(since))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|kv| {
+            (
+                (kv.seq, kv.epoch, kv.epoch_seq),
+                KvChange {
+                    key: kv.key,
+                    seq: kv.seq,
+                    deleted: false,
+                },
+            )
         })
-        .collect::<Result<HashMap<_, _>, HashError>>()?
+        .collect();
+
+    let deletes = kv_delete::Entity::find()
+        .filter(kv_delete::Column::Orbit.eq(o()))
+        .filter(kv_delete::Column::Key.starts_with(prefix))
+        .filter(
+            kv_delete::Column::InvocationId.in_subquery(
+                Query::select()
+                    .column(event_order::Column::Event)
+                    .from(event_order::Entity)
+                    .and_where(event_order::Column::Orbit.eq(o()))
+                    .and_where(event_order::Column::Seq.gt(since))
+                    .to_owned(),
+            ),
+        )
+        .all(db)
+        .await?;
+    let versions: HashMap<Hash, (i64, Hash, i64)> = event_order::Entity::find()
+        .filter(event_order::Column::Orbit.eq(o()))
+        .filter(event_order::Column::Event.is_in(deletes.iter().map(|d| d.invocation_id)))
+        .all(db)
+        .await?
         .into_iter()
-        .map(|(orbit, (epoch, hashes, seq, parents))| {
-            (
-                parents
-                    .iter()
-                    .map(|parent| epoch_order::Model {
-                        parent: *parent,
-                        child: epoch,
-                        orbit: orbit.clone().into(),
-                    })
-                    .map(epoch_order::ActiveModel::from)
-                    .collect::<Vec<epoch_order::ActiveModel>>(),
-                (
-                    orbit.clone(),
-                    (
-                        seq,
-                        epoch,
-                        parents,
-                        hashes
-                            .iter()
-                            .enumerate()
-                            .map(|(i, (h, _))| (*h, i as i64))
-                            .collect::<HashMap<_, _>>(),
-                    ),
-                ),
-                hashes
+        .map(|e| (e.event, (e.seq, e.epoch, e.epoch_seq)))
+        .collect();
+    changes.extend(deletes.into_iter().filter_map(|d| {
+        let version = *versions.get(&d.invocation_id)?;
+        Some((
+            version,
+            KvChange {
+                key: d.key,
+                seq: version.0,
+                deleted: true,
+            },
+        ))
+    }));
+
+    // keep only the latest change of each key
+    changes.sort_by(|(va, a), (vb, b)| a.key.cmp(&b.key).then(vb.cmp(va)));
+    changes.dedup_by(|(_, a), (_, b)| a.key == b.key);
+    changes.sort_by(|(va, _), (vb, _)| va.cmp(vb));
+    Ok(changes.into_iter().map(|(_, c)| c).collect())
+}
+
+/// Identifies a write of a key by the orbit sequence number, epoch and position in the epoch
+/// of the invocation which made it.
+pub type Version = (i64, Hash, i64);
+
+/// A write of a key which has not been compacted away, and whether it was deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvVersion {
+    pub version: Version,
+    pub metadata: Metadata,
+    pub deleted: bool,
+}
+
+/// List the writes of `key`, latest first, leaving out expired ones.
+async fn list_versions<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    key: &str,
+) -> Result<Vec<KvVersion>, DbErr> {
+    let now = OffsetDateTime::now_utc();
+    Ok(kv_write::Entity::find()
+        .filter(
+            Condition::all()
+                .add(kv_write::Column::Key.eq(key))
+                .add(kv_write::Column::Orbit.eq(OrbitIdWrap(orbit.clone()))),
+        )
+        .order_by_desc(kv_write::Column::Seq)
+        .order_by_desc(kv_write::Column::Epoch)
+        .order_by_desc(kv_write::Column::EpochSeq)
+        .find_also_related(kv_delete::Entity)
+        .all(db)
+        .await?
+        .into_iter()
+        .filter(|(kv, _)| !is_expired(kv, now))
+        .map(|(kv, deleted)| KvVersion {
+            version: (kv.seq, kv.epoch, kv.epoch_seq),
+            metadata: kv.metadata,
+            deleted: deleted.is_some(),
+        })
+        .collect())
+}
+
+/// Rows of `kv_write` read per query when listing keys.
+const LIST_PAGE: u64 = 1000;
+
+async fn list<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    prefix: &str,
+) -> Result<Vec<String>, DbErr> {
+    let now = OffsetDateTime::now_utc();
+    let mut list = Vec::new();
+    let mut after = None;
+    loop {
+        let (keys, next) = list_page(db, orbit, prefix, after.as_deref(), LIST_PAGE, now).await?;
+        list.extend(keys);
+        match next {
+            Some(next) => after = Some(next),
+            None => return Ok(list),
+        }
+    }
+}
+
+/// The keys under `prefix` in up to `page` rows of writes after the key `after`, and the
+/// key to read the next page after if there may be more.
+///
+/// A key whose writes continue on the next page is not listed again, as its latest write,
+/// the one which counts, is read first.
+async fn list_page<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    prefix: &str,
+    after: Option<&str>,
+    page: u64,
+    now: OffsetDateTime,
+) -> Result<(Vec<String>, Option<String>), DbErr> {
+    let mut condition = Condition::all()
+        .add(kv_write::Column::Key.starts_with(prefix))
+        .add(kv_write::Column::Orbit.eq(OrbitIdWrap(orbit.clone())));
+    if let Some(after) = after {
+        condition = condition.add(kv_write::Column::Key.gt(after));
+    }
+    let mut writes = kv_write::Entity::find()
+        .filter(condition)
+        .order_by_asc(kv_write::Column::Key)
+        .order_by_desc(kv_write::Column::Seq)
+        .order_by_desc(kv_write::Column::Epoch)
+        .order_by_desc(kv_write::Column::EpochSeq)
+        .find_also_related(kv_delete::Entity)
+        .filter(kv_delete::Column::InvocationId.is_null())
+        .limit(page)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|(kv, _)| kv)
+        .collect::<Vec<kv_write::Model>>();
+    let next = match writes.len() as u64 {
+        n if n < page => None,
+        _ => writes.last().map(|kv| kv.key.clone()),
+    };
+    // keep only the latest write of each key, which is first
+    writes.dedup_by(|a, b| a.key == b.key);
+    Ok((
+        writes
+            .into_iter()
+            .filter(|kv| !is_expired(kv, now))
+            .map(|kv| kv.key)
+            .collect(),
+        next,
+    ))
+}
+
+/// The keys listed by a `kv/list`, read from the database a page at a time as the stream
+/// is polled, so that only a page of a listing of any size is held in memory and a slow
+/// consumer holds back the reads rather than having keys buffered for it.
+pub struct KeyStream(stream::Peekable<Pin<Box<dyn Stream<Item = Result<String, DbErr>> + Send>>>);
+
+impl KeyStream {
+    pub fn new(keys: impl Stream<Item = Result<String, DbErr>> + Send + 'static) -> Self {
+        use futures::stream::StreamExt;
+        Self((Box::pin(keys) as Pin<Box<dyn Stream<Item = _> + Send>>).peekable())
+    }
+
+    /// Whether the listing has no keys, reading its first page if it wasn't yet read. A
+    /// listing which fails to read is not empty, the error being the first item.
+    pub async fn is_empty(&mut self) -> bool {
+        Pin::new(&mut self.0).peek().await.is_none()
+    }
+}
+
+impl std::fmt::Debug for KeyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KeyStream")
+    }
+}
+
+impl Stream for KeyStream {
+    type Item = Result<String, DbErr>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+/// Stream the keys under `prefix` as [`list`] lists them, reading `page` rows at a time.
+fn list_stream<C>(db: C, orbit: OrbitId, prefix: String, page: u64) -> KeyStream
+where
+    C: ConnectionTrait + Send + Sync + 'static,
+{
+    let now = OffsetDateTime::now_utc();
+    KeyStream::new(
+        stream::try_unfold(
+            Some((db, None)),
+            move |state: Option<(C, Option<String>)>| {
+                let (orbit, prefix) = (orbit.clone(), prefix.clone());
+                async move {
+                    let (db, after) = match state {
+                        Some(state) => state,
+                        None => return Ok(None),
+                    };
+                    let (keys, next) =
+                        list_page(&db, &orbit, &prefix, after.as_deref(), page, now).await?;
+                    Ok(Some((keys, next.map(|next| (db, Some(next))))))
+                }
+            },
+        )
+        .map_ok(|keys| stream::iter(keys.into_iter().map(Ok)))
+        .try_flatten(),
+    )
+}
+
+async fn metadata<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    key: &str,
+    version: Option<Version>,
+) -> Result<Option<Metadata>, DbErr> {
+    match get_kv_entity(db, orbit, key, version).await? {
+        Some(entry) => Ok(Some(write_metadata(db, entry).await?)),
+        None => Ok(None),
+    }
+}
+
+/// The metadata of a write, with the time its invocation was committed as its
+/// [`LAST_MODIFIED`](crate::types::LAST_MODIFIED) entry.
+async fn write_metadata<C: ConnectionTrait>(
+    db: &C,
+    write: kv_write::Model,
+) -> Result<Metadata, DbErr> {
+    let mut metadata = write.metadata;
+    if let Some(invocation) = invocation::Entity::find_by_id(write.invocation)
+        .one(db)
+        .await?
+    {
+        metadata.set_last_modified(invocation.issued_at);
+    }
+    Ok(metadata)
+}
+
+type KvEntry<R> = Option<(Metadata, Result<Content<R>, Hash>)>;
+
+async fn get_kv<C: ConnectionTrait, B: ImmutableReadStore>(
+    db: &C,
+    store: &B,
+    orbit: &OrbitId,
+    key: &str,
+    version: Option<Version>,
+) -> Result<KvEntry<B::Readable>, EitherError<DbErr, B::Error>> {
+    let e = match get_kv_entity(db, orbit, key, version)
+        .await
+        .map_err(EitherError::A)?
+    {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    // an entry whose content is missing is reported with the hash of that content
+    let c = store
+        .read(orbit, &e.value)
+        .await
+        .map_err(EitherError::B)?
+        .ok_or(e.value);
+    let metadata = write_metadata(db, e).await.map_err(EitherError::A)?;
+    Ok(Some((metadata, c)))
+}
+
+/// The content of an orbit with the given hash, if it is in block storage, with the metadata
+/// of its latest write to any key.
+async fn get_block<C: ConnectionTrait, B: ImmutableReadStore>(
+    db: &C,
+    store: &B,
+    orbit: &OrbitId,
+    hash: Hash,
+) -> Result<Option<(Metadata, Content<B::Readable>)>, EitherError<DbErr, B::Error>> {
+    let content = match store.read(orbit, &hash).await.map_err(EitherError::B)? {
+        Some(content) => content,
+        None => return Ok(None),
+    };
+    let metadata = kv_write::Entity::find()
+        .filter(kv_write::Column::Orbit.eq(OrbitIdWrap(orbit.clone())))
+        .filter(kv_write::Column::Value.eq(hash))
+        .order_by_desc(kv_write::Column::Seq)
+        .order_by_desc(kv_write::Column::Epoch)
+        .order_by_desc(kv_write::Column::EpochSeq)
+        .one(db)
+        .await
+        .map_err(EitherError::A)?
+        .map(|kv| kv.metadata)
+        .unwrap_or_else(|| Metadata(Default::default()));
+    Ok(Some((metadata, content)))
+}
+
+/// The write of `key` with the given version, or if none is given its latest write, unless
+/// that was deleted. Expired writes are treated as absent.
+async fn get_kv_entity<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    key: &str,
+    version: Option<Version>,
+) -> Result<Option<kv_write::Model>, DbErr> {
+    let writes = kv_write::Entity::find().filter(
+        Condition::all()
+            .add(kv_write::Column::Key.eq(key))
+            .add(kv_write::Column::Orbit.eq(OrbitIdWrap(orbit.clone()))),
+    );
+    Ok(match version {
+        Some((seq, epoch, epoch_seq)) => {
+            writes
+                .filter(kv_write::Column::Seq.eq(seq))
+                .filter(kv_write::Column::Epoch.eq(epoch))
+                .filter(kv_write::Column::EpochSeq.eq(epoch_seq))
+                .one(db)
+                .await?
+        }
+        // we want to find the latest kv_write which is not deleted
+        None => writes
+            .order_by_desc(kv_write::Column::Seq)
+            .order_by_desc(kv_write::Column::Epoch)
+            .order_by_desc(kv_write::Column::EpochSeq)
+            .find_also_related(kv_delete::Entity)
+            .filter(kv_delete::Column::InvocationId.is_null())
+            .one(db)
+            .await?
+            .map(|(kv, _)| kv),
+    }
+    .filter(|kv| !is_expired(kv, OffsetDateTime::now_utc())))
+}
+
+fn is_expired(kv: &kv_write::Model, now: OffsetDateTime) -> bool {
+    kv.expiry.map(|e| e <= now).unwrap_or(false)
+}
+
+// a page of the unrevoked, currently valid delegations with abilities in `orbit`, ordered
+// by their hash, and the cursor of the next page if there are more
+async fn valid_delegations<C: ConnectionTrait>(
+    db: &C,
+    orbit: &OrbitId,
+    page: &SessionsQuery,
+) -> Result<(Vec<delegation::Model>, Option<Hash>), DbErr> {
+    let now = time::OffsetDateTime::now_utc();
+    let o = orbit.to_string();
+    let mut query = delegation::Entity::find()
+        .left_join(revocation::Entity)
+        .filter(revocation::Column::Id.is_null())
+        .filter(
+            Condition::any()
+                .add(delegation::Column::Expiry.is_null())
+                .add(delegation::Column::Expiry.gt(now)),
+        )
+        .filter(
+            Condition::any()
+                .add(delegation::Column::NotBefore.is_null())
+                .add(delegation::Column::NotBefore.lte(now)),
+        )
+        .filter(
+            delegation::Column::Id.in_subquery(
+                Query::select()
+                    .column(abilities::Column::Delegation)
+                    .from(abilities::Entity)
+                    .cond_where(
+                        Condition::any()
+                            .add(abilities::Column::Resource.eq(o.clone()))
+                            .add(abilities::Column::Resource.starts_with(&format!("{o}/")))
+                            .add(abilities::Column::Resource.starts_with(&format!("{o}#"))),
+                    )
+                    .to_owned(),
+            ),
+        )
+        .order_by_asc(delegation::Column::Id);
+    if let Some(after) = page.after {
+        query = query.filter(delegation::Column::Id.gt(after));
+    }
+    if let Some(delegate) = &page.delegate {
+        query = query.filter(delegation::Column::Delegatee.eq(delegate.clone()));
+    }
+    // one more than the limit is fetched to tell whether another page follows
+    if let Some(limit) = page.limit {
+        query = query.limit(limit.saturating_add(1));
+    }
+    let mut dels = query.all(db).await?;
+    let next = match page.limit {
+        Some(limit) if dels.len() as u64 > limit => {
+            dels.truncate(limit as usize);
+            dels.last().map(|d| d.id)
+        }
+        _ => None,
+    };
+    Ok((dels, next))
+}
+
+async fn get_valid_delegations<C: ConnectionTrait, S: StorageSetup, K: Secrets>(
+    db: &C,
+    orbit: &OrbitId,
+    page: &SessionsQuery,
+) -> Result<(HashMap<Hash, DelegationInfo>, Option<Hash>), TxError<S, K>> {
+    let (dels, next) = valid_delegations(db, orbit, page).await?;
+    let abilities = dels.load_many(abilities::Entity, db).await?;
+    let parents = dels.load_many(parent_delegations::Entity, db).await?;
+    let sessions = dels
+        .into_iter()
+        .zip(abilities)
+        .zip(parents)
+        // `_` in the orbit ID matches any character in the prefixes of the query, so
+        // delegations only in other orbits are ruled out here
+        .filter(|((_, ability), _)| ability.iter().any(|a| a.resource.orbit() == Some(orbit)))
+        .map(|((del, ability), parents)| {
+            Ok((
+                del.id,
+                DelegationInfo {
+                    delegation: KeplerDelegation::from_bytes(&del.serialization)?,
+                    delegator: del.delegator,
+                    delegate: del.delegatee,
+                    parents: parents.into_iter().map(|p| p.parent.to_cid(0x55)).collect(),
+                    expiry: del.expiry,
+                    not_before: del.not_before,
+                    issued_at: del.issued_at,
+                    capabilities: ability
+                        .into_iter()
+                        .map(|a| Capability {
+                            resource: a.resource,
+                            action: a.ability,
+                        })
+                        .collect(),
+                },
+            ))
+        })
+        .collect::<Result<HashMap<Hash, DelegationInfo>, EncodingError>>()?;
+    Ok((sessions, next))
+}
+
+/// The canonical form of a key, without empty or `.` segments, so `/a/b`, `a//b` and
+/// `a/./b` are all the key `a/b`. A trailing `/` is kept, as keys are listed by prefix and
+/// `a/` lists only the keys under `a`. Keys with `..` segments have no canonical form, so
+/// none is mistaken for a path to another key.
+pub(crate) fn normalize_path(p: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in p.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            s => segments.push(s),
+        }
+    }
+    let mut key = segments.join("/");
+    if !key.is_empty() && p.ends_with('/') {
+        key.push('/');
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::memory::{MemoryBlockStore, MemoryStaging};
+    use async_std::test;
+
+    async fn get_db(_o: OrbitId) -> Result<OrbitDatabase<DatabaseConnection, (), ()>, DbErr> {
+        OrbitDatabase::new(sea_orm::Database::connect("sqlite::memory:").await?, (), ()).await
+    }
+
+    // an orbit with the default hash algorithms and no storage limit
+    fn orbit_model(id: &OrbitId) -> orbit::Model {
+        orbit::Model {
+            id: id.clone().into(),
+            hash: HashAlgorithm::default(),
+            event_hash: HashAlgorithm::default(),
+            storage_limit: None,
+            created_at: None,
+        }
+    }
+
+    #[test]
+    async fn basic() {
+        let _db = get_db(OrbitId::new(
+            "example:alice".to_string(),
+            "default".to_string(),
+        ))
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    async fn orbit_features() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let bob = OrbitId::new("example:bob".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+
+        // flags can't be set on orbits which don't exist
+        assert_eq!(
+            db.set_feature(&alice, "compression", true).await.unwrap(),
+            None
+        );
+        assert_eq!(db.features(&alice).await.unwrap(), None);
+
+        orbit::Entity::insert_many(
+            [alice.clone(), bob.clone()].map(|id| orbit::ActiveModel::from(orbit_model(&id))),
+        )
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        assert!(!db.feature_enabled(&alice, "compression").await.unwrap());
+        assert_eq!(db.features(&bob).await.unwrap(), Some(BTreeMap::new()));
+
+        // enabling a flag on one orbit leaves the other unchanged
+        let flags = db.set_feature(&alice, "compression", true).await.unwrap();
+        assert_eq!(flags.unwrap().get("compression"), Some(&true));
+        assert!(db.feature_enabled(&alice, "compression").await.unwrap());
+        assert!(!db.feature_enabled(&bob, "compression").await.unwrap());
+        assert_eq!(db.features(&bob).await.unwrap(), Some(BTreeMap::new()));
+
+        // and it can be toggled back off
+        db.set_feature(&alice, "compression", false).await.unwrap();
+        assert!(!db.feature_enabled(&alice, "compression").await.unwrap());
+    }
+
+    #[test]
+    async fn orbit_storage_limits() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let bob = OrbitId::new("example:bob".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+
+        assert!(!db.set_storage_limit(&alice, Some(10)).await.unwrap());
+        orbit::Entity::insert_many(
+            [alice.clone(), bob.clone()].map(|id| orbit::ActiveModel::from(orbit_model(&id))),
+        )
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        assert_eq!(db.storage_limit(&alice).await.unwrap(), None);
+
+        // each orbit keeps its own limit
+        assert!(db.set_storage_limit(&alice, Some(10)).await.unwrap());
+        assert!(db.set_storage_limit(&bob, Some(1000)).await.unwrap());
+        assert_eq!(db.storage_limit(&alice).await.unwrap(), Some(10));
+        assert_eq!(db.storage_limit(&bob).await.unwrap(), Some(1000));
+
+        // and can have it removed, falling back to the global limit
+        assert!(db.set_storage_limit(&alice, None).await.unwrap());
+        assert_eq!(db.storage_limit(&alice).await.unwrap(), None);
+        assert_eq!(db.storage_limit(&bob).await.unwrap(), Some(1000));
+    }
+
+    #[test]
+    async fn kv_expiry() {
+        let now = OffsetDateTime::now_utc();
+        let write = |expires: Option<i64>| {
+            let metadata = Metadata(
+                expires
+                    .map(|t| ("X-Kepler-Expires".to_string(), t.to_string()))
                     .into_iter()
-                    .enumerate()
-                    .map(|(es, (hash, _))| event_order::Model {
-                        event: *hash,
-                        orbit: orbit.clone().into(),
-                        seq,
-                        epoch,
-                        epoch_seq: es as i64,
-                    })
-                    .map(event_order::ActiveModel::from)
-                    .collect::<Vec<event_order::ActiveModel>>(),
-                epoch::Model {
+                    .collect(),
+            );
+            kv_write::Model {
+                orbit: OrbitId::new("example:alice".to_string(), "default".to_string()).into(),
+                key: "session".to_string(),
+                invocation: crate::hash::hash(b"invocation"),
+                seq: 0,
+                epoch: crate::hash::hash(b"epoch"),
+                epoch_seq: 0,
+                value: crate::hash::hash(b"value"),
+                expiry: metadata.expiry(),
+                metadata,
+            }
+        };
+
+        assert!(!is_expired(&write(None), now));
+        assert!(!is_expired(&write(Some(now.unix_timestamp() + 60)), now));
+        assert!(is_expired(&write(Some(now.unix_timestamp() - 60)), now));
+        assert!(is_expired(&write(Some(now.unix_timestamp())), now));
+    }
+
+    #[test]
+    async fn reap_expired() {
+        use futures::io::AsyncWriteExt;
+        use kepler_lib::authorization::{make_invocation, HeaderEncode, KeplerInvocation};
+
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let fail = OrbitId::new("example:alice".to_string(), "fail".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&fail, &[&alice]).await;
+
+        // `a` had expired when it was written and shares its content with `b`, which
+        // doesn't expire, and `c` had expired with content of its own
+        let expired = (OffsetDateTime::now_utc() - time::Duration::minutes(1)).unix_timestamp();
+        let writes = [
+            ("a", "shared", Some(expired)),
+            ("b", "shared", None),
+            ("c", "own", Some(expired)),
+        ];
+        let ucan = make_invocation(
+            writes
+                .iter()
+                .map(|(key, _, _)| {
+                    alice.clone().to_resource(
+                        Some("kv".to_string()),
+                        Some(key.to_string()),
+                        Some("put".to_string()),
+                    )
+                })
+                .collect(),
+            delegation.to_cid(0x71),
+            &jwk,
+            session.clone(),
+            (OffsetDateTime::now_utc() + time::Duration::minutes(1)).unix_timestamp() as f64,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let mut inputs = HashMap::new();
+        for (key, value, expires) in writes {
+            let mut stage = MemoryStaging.stage(&alice).await.unwrap();
+            stage.write_all(value.as_bytes()).await.unwrap();
+            let metadata = Metadata(
+                expires
+                    .map(|t| ("X-Kepler-Expires".to_string(), t.to_string()))
+                    .into_iter()
+                    .collect(),
+            );
+            inputs.insert((alice.clone(), key.to_string()), (metadata, stage));
+        }
+        let invocation =
+            Invocation::from_header_ser::<KeplerInvocation>(&ucan.encode().unwrap()).unwrap();
+        if let Err(e) = db.invoke::<MemoryStaging>(invocation, inputs).await {
+            panic!("invocation failed: {e}");
+        }
+
+        // expired keys are absent before they are reaped
+        for (key, live) in [("a", false), ("b", true), ("c", false)] {
+            assert_eq!(
+                get_kv_entity(&db.conn, &alice, key, None)
+                    .await
+                    .unwrap()
+                    .is_some(),
+                live,
+                "{key}"
+            );
+        }
+        assert_eq!(list(&db.conn, &alice, "").await.unwrap(), vec!["b"]);
+
+        // only content which no live key references is removed, and only once
+        let now = OffsetDateTime::now_utc();
+        assert_eq!(db.reap_expired(now).await.unwrap(), 1);
+        let store = &db.storage().inner;
+        assert!(store
+            .contains(&alice, &crate::hash::hash(b"shared"))
+            .await
+            .unwrap());
+        assert!(!store
+            .contains(&alice, &crate::hash::hash(b"own"))
+            .await
+            .unwrap());
+        assert_eq!(db.reap_expired(now).await.unwrap(), 0);
+        assert_eq!(list(&db.conn, &alice, "").await.unwrap(), vec!["b"]);
+    }
+
+    #[test]
+    async fn event_pages() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        orbit::Entity::insert(orbit::ActiveModel::from(orbit_model(&alice)))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        actor::Entity::insert(actor::ActiveModel::from(actor::Model {
+            id: "example:alice".to_string(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+
+        // four epochs of three events each, alternately delegations and invocations
+        let event = |seq: i64, epoch_seq: i64| {
+            crate::hash::hash(format!("event {seq} {epoch_seq}").as_bytes())
+        };
+        for seq in 1..=4 {
+            let epoch = crate::hash::hash(format!("epoch {seq}").as_bytes());
+            epoch::Entity::insert(epoch::ActiveModel::from(epoch::Model {
+                seq,
+                id: epoch,
+                orbit: alice.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+            for epoch_seq in 0..3 {
+                let id = event(seq, epoch_seq);
+                if (seq + epoch_seq) % 2 == 0 {
+                    delegation::Entity::insert(delegation::ActiveModel::from(delegation::Model {
+                        id,
+                        delegator: "example:alice".to_string(),
+                        delegatee: "example:alice".to_string(),
+                        expiry: None,
+                        issued_at: None,
+                        not_before: None,
+                        facts: None,
+                        serialization: vec![],
+                    }))
+                    .exec(&db.conn)
+                    .await
+                    .unwrap();
+                } else {
+                    invocation::Entity::insert(invocation::ActiveModel::from(invocation::Model {
+                        id,
+                        invoker: "example:alice".to_string(),
+                        issued_at: OffsetDateTime::now_utc(),
+                        facts: None,
+                        serialization: vec![],
+                    }))
+                    .exec(&db.conn)
+                    .await
+                    .unwrap();
+                }
+                event_order::Entity::insert(event_order::ActiveModel::from(event_order::Model {
                     seq,
-                    id: epoch,
-                    orbit: orbit.into(),
-                },
+                    epoch,
+                    epoch_seq,
+                    event: id,
+                    orbit: alice.clone().into(),
+                }))
+                .exec(&db.conn)
+                .await
+                .unwrap();
+            }
+        }
+
+        let all = db.query_events_since(&alice, 0, 100).await.unwrap();
+        assert_eq!(
+            all.iter().map(|e| (e.seq, e.epoch_seq)).collect::<Vec<_>>(),
+            (1..=4)
+                .flat_map(|seq| (0..3).map(move |epoch_seq| (seq, epoch_seq)))
+                .collect::<Vec<_>>()
+        );
+        for e in &all {
+            assert_eq!(e.hash, event(e.seq, e.epoch_seq));
+            let kind = match (e.seq + e.epoch_seq) % 2 {
+                0 => EventKind::Delegation,
+                _ => EventKind::Invocation,
+            };
+            assert_eq!(e.kind, kind);
+        }
+        let of_kind = |kind| {
+            all.iter()
+                .filter(|e| e.kind == kind)
+                .map(|e| (e.hash, e.seq))
+                .collect::<Vec<_>>()
+        };
+
+        // pages end on a complete sequence number, so paging from the last one read
+        // neither skips nor repeats events, whatever the page size
+        for limit in 1..=5 {
+            let (mut events, mut delegations, mut invocations) = (vec![], vec![], vec![]);
+            let mut since = 0;
+            loop {
+                let page = db.query_events_since(&alice, since, limit).await.unwrap();
+                match page.last() {
+                    Some(last) => since = last.seq,
+                    None => break,
+                }
+                assert!(page.len() as u64 >= limit.min(12 - events.len() as u64));
+                events.extend(page);
+            }
+            assert_eq!(events, all, "limit {limit}");
+
+            let mut since = 0;
+            loop {
+                let page = db.query_delegations(&alice, since, limit).await.unwrap();
+                match page.last() {
+                    Some(last) => since = last.seq,
+                    None => break,
+                }
+                delegations.extend(page.into_iter().map(|d| (d.hash, d.seq)));
+            }
+            assert_eq!(delegations, of_kind(EventKind::Delegation), "limit {limit}");
+
+            let mut since = 0;
+            loop {
+                let page = db.query_invocations(&alice, since, limit).await.unwrap();
+                match page.last() {
+                    Some(last) => since = last.seq,
+                    None => break,
+                }
+                invocations.extend(page.into_iter().map(|i| (i.hash, i.seq)));
+            }
+            assert_eq!(invocations, of_kind(EventKind::Invocation), "limit {limit}");
+        }
+
+        // from a sequence number, only the events after it
+        assert_eq!(
+            db.query_events_since(&alice, 2, 100).await.unwrap(),
+            all[6..].to_vec()
+        );
+        // a page ending mid-epoch is completed
+        assert_eq!(
+            db.query_events_since(&alice, 2, 1).await.unwrap(),
+            all[6..9].to_vec()
+        );
+        // nothing follows the head
+        for since in [4, 10] {
+            assert!(db
+                .query_events_since(&alice, since, 100)
+                .await
+                .unwrap()
+                .is_empty());
+            assert!(db
+                .query_delegations(&alice, since, 100)
+                .await
+                .unwrap()
+                .is_empty());
+            assert!(db
+                .query_invocations(&alice, since, 100)
+                .await
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    async fn kv_list_since() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        orbit::Entity::insert(orbit::ActiveModel::from(orbit_model(&alice)))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        actor::Entity::insert(actor::ActiveModel::from(actor::Model {
+            id: "example:alice".to_string(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+
+        // one invocation per epoch, each writing or deleting a key
+        let ops = [
+            (1, "a", false),
+            (2, "b", false),
+            (3, "a", false),
+            (4, "c", false),
+            (5, "b", true),
+        ];
+        let mut writes = HashMap::new();
+        for (seq, key, delete) in ops {
+            let invocation = crate::hash::hash(format!("invocation {seq}").as_bytes());
+            let epoch = crate::hash::hash(format!("epoch {seq}").as_bytes());
+            invocation::Entity::insert(invocation::ActiveModel::from(invocation::Model {
+                id: invocation,
+                invoker: "example:alice".to_string(),
+                issued_at: OffsetDateTime::now_utc(),
+                facts: None,
+                serialization: vec![],
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+            epoch::Entity::insert(epoch::ActiveModel::from(epoch::Model {
+                seq,
+                id: epoch,
+                orbit: alice.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+            event_order::Entity::insert(event_order::ActiveModel::from(event_order::Model {
+                seq,
+                epoch,
+                epoch_seq: 0,
+                event: invocation,
+                orbit: alice.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+            if delete {
+                kv_delete::Entity::insert(kv_delete::ActiveModel::from(kv_delete::Model {
+                    invocation_id: invocation,
+                    orbit: alice.clone().into(),
+                    key: key.to_string(),
+                    deleted_invocation_id: writes[key],
+                }))
+                .exec(&db.conn)
+                .await
+                .unwrap();
+            } else {
+                kv_write::Entity::insert(kv_write::ActiveModel::from(kv_write::Model {
+                    orbit: alice.clone().into(),
+                    key: key.to_string(),
+                    invocation,
+                    seq,
+                    epoch,
+                    epoch_seq: 0,
+                    value: crate::hash::hash(key.as_bytes()),
+                    metadata: Metadata(BTreeMap::new()),
+                    expiry: None,
+                }))
+                .exec(&db.conn)
+                .await
+                .unwrap();
+                writes.insert(key, invocation);
+            }
+        }
+
+        let change = |key: &str, seq, deleted| KvChange {
+            key: key.to_string(),
+            seq,
+            deleted,
+        };
+        assert_eq!(
+            list_since(&db.conn, &alice, "", 2).await.unwrap(),
+            vec![
+                change("a", 3, false),
+                change("c", 4, false),
+                change("b", 5, true)
+            ]
+        );
+        assert_eq!(
+            list_since(&db.conn, &alice, "", 0).await.unwrap(),
+            vec![
+                change("a", 3, false),
+                change("c", 4, false),
+                change("b", 5, true)
+            ]
+        );
+        assert_eq!(
+            list_since(&db.conn, &alice, "b", 1).await.unwrap(),
+            vec![change("b", 5, true)]
+        );
+        assert_eq!(list_since(&db.conn, &alice, "", 5).await.unwrap(), vec![]);
+
+        // streamed a row at a time, the two writes of `a` span pages and `b` is deleted
+        assert_eq!(list(&db.conn, &alice, "").await.unwrap(), vec!["a", "c"]);
+        for page in [1, 2, LIST_PAGE] {
+            let keys: Vec<String> =
+                list_stream(db.conn.clone(), alice.clone(), String::new(), page)
+                    .try_collect()
+                    .await
+                    .unwrap();
+            assert_eq!(keys, vec!["a", "c"]);
+        }
+    }
+
+    #[test]
+    async fn compaction() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        orbit::Entity::insert(orbit::ActiveModel::from(orbit_model(&alice)))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        actor::Entity::insert(actor::ActiveModel::from(actor::Model {
+            id: "example:alice".to_string(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        let epoch = |seq: i64| crate::hash::hash(format!("epoch {seq}").as_bytes());
+        let event = |seq: i64| crate::hash::hash(format!("event {seq}").as_bytes());
+        delegation::Entity::insert(delegation::ActiveModel::from(delegation::Model {
+            id: event(0),
+            delegator: "example:alice".to_string(),
+            delegatee: "example:alice".to_string(),
+            expiry: None,
+            issued_at: None,
+            not_before: None,
+            facts: None,
+            serialization: vec![],
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+
+        // a chain of epochs, a delegation followed by one invocation per epoch, each
+        // writing or deleting a key
+        let ops = [
+            (1, "a", false),
+            (2, "b", false),
+            (3, "b", true),
+            (4, "a", false),
+        ];
+        for seq in 0..=4 {
+            epoch::Entity::insert(epoch::ActiveModel::from(epoch::Model {
+                seq,
+                id: epoch(seq),
+                orbit: alice.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+            if seq > 0 {
+                epoch_order::Entity::insert(epoch_order::ActiveModel::from(epoch_order::Model {
+                    parent: epoch(seq - 1),
+                    child: epoch(seq),
+                    orbit: alice.clone().into(),
+                }))
+                .exec(&db.conn)
+                .await
+                .unwrap();
+            }
+            event_order::Entity::insert(event_order::ActiveModel::from(event_order::Model {
+                seq,
+                epoch: epoch(seq),
+                epoch_seq: 0,
+                event: event(seq),
+                orbit: alice.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        }
+        let mut writes = HashMap::new();
+        for (seq, key, delete) in ops {
+            invocation::Entity::insert(invocation::ActiveModel::from(invocation::Model {
+                id: event(seq),
+                invoker: "example:alice".to_string(),
+                issued_at: OffsetDateTime::now_utc(),
+                facts: None,
+                serialization: vec![],
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+            if delete {
+                kv_delete::Entity::insert(kv_delete::ActiveModel::from(kv_delete::Model {
+                    invocation_id: event(seq),
+                    orbit: alice.clone().into(),
+                    key: key.to_string(),
+                    deleted_invocation_id: writes[key],
+                }))
+                .exec(&db.conn)
+                .await
+                .unwrap();
+            } else {
+                kv_write::Entity::insert(kv_write::ActiveModel::from(kv_write::Model {
+                    orbit: alice.clone().into(),
+                    key: key.to_string(),
+                    invocation: event(seq),
+                    seq,
+                    epoch: epoch(seq),
+                    epoch_seq: 0,
+                    value: event(seq),
+                    metadata: Metadata(BTreeMap::new()),
+                    expiry: None,
+                }))
+                .exec(&db.conn)
+                .await
+                .unwrap();
+                writes.insert(key, event(seq));
+            }
+        }
+
+        // prior versions can be read until they are compacted, even if deleted
+        let version = |seq: i64| (seq, epoch(seq), 0);
+        assert_eq!(
+            get_kv_entity(&db.conn, &alice, "a", Some(version(1)))
+                .await
+                .unwrap()
+                .map(|kv| kv.value),
+            Some(event(1))
+        );
+        assert_eq!(
+            get_kv_entity(&db.conn, &alice, "b", Some(version(2)))
+                .await
+                .unwrap()
+                .map(|kv| kv.value),
+            Some(event(2))
+        );
+        assert!(get_kv_entity(&db.conn, &alice, "a", Some(version(2)))
+            .await
+            .unwrap()
+            .is_none());
+        let versions = |key| {
+            let db = &db;
+            let alice = &alice;
+            async move {
+                list_versions(&db.conn, alice, key)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|v| (v.version, v.deleted))
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            versions("a").await,
+            vec![(version(4), false), (version(1), false)]
+        );
+        assert_eq!(versions("b").await, vec![(version(2), true)]);
+
+        // the overwritten write of `a` and the deleted write of `b` go, along with the
+        // delete itself, but the delegation and the head are kept
+        assert_eq!(
+            db.compact(&alice).await.unwrap(),
+            CompactOutcome {
+                epochs: 3,
+                events: 3,
+                kv_entries: 3,
+            }
+        );
+        assert_eq!(
+            get_kv_entity(&db.conn, &alice, "a", None)
+                .await
+                .unwrap()
+                .map(|kv| kv.value),
+            Some(event(4))
+        );
+        assert!(get_kv_entity(&db.conn, &alice, "b", None)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(list(&db.conn, &alice, "").await.unwrap(), vec!["a"]);
+        let links: Vec<_> = epoch_order::Entity::find()
+            .all(&db.conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| (l.parent, l.child))
+            .collect();
+        assert_eq!(links, vec![(epoch(0), epoch(4))]);
+        assert_eq!(
+            committed_orderings(&db.conn, [event(0), event(1), event(4)])
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|e| e.seq)
+                .collect::<HashSet<_>>(),
+            [0, 4].into()
+        );
+
+        assert_eq!(versions("a").await, vec![(version(4), false)]);
+        assert!(versions("b").await.is_empty());
+
+        // nothing more is superseded
+        assert_eq!(db.compact(&alice).await.unwrap(), CompactOutcome::default());
+    }
+
+    #[test]
+    async fn event_hashing() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let bob = OrbitId::new("example:bob".to_string(), "default".to_string());
+        let carol = OrbitId::new("example:carol".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        orbit::Entity::insert_many([
+            orbit::ActiveModel::from(orbit::Model {
+                hash: HashAlgorithm::Sha2_256,
+                event_hash: HashAlgorithm::Sha2_256,
+                ..orbit_model(&alice)
+            }),
+            // as orbits created before epochs could be hashed with another algorithm are
+            orbit::ActiveModel::from(orbit::Model {
+                hash: HashAlgorithm::Sha2_256,
+                event_hash: HashAlgorithm::Blake3_256,
+                ..orbit_model(&bob)
+            }),
+        ])
+        .exec(&db.conn)
+        .await
+        .unwrap();
+
+        let algorithms = event_hash_algorithms(&db.conn, [&alice, &bob, &carol].into_iter())
+            .await
+            .unwrap();
+        assert_eq!(algorithms.get(&alice), Some(&HashAlgorithm::Sha2_256));
+        assert_eq!(algorithms.get(&bob), Some(&HashAlgorithm::Blake3_256));
+        assert_eq!(algorithms.get(&carol), None);
+
+        let parents = [crate::hash::hash(b"parent")];
+        let sha = epoch_hash(&alice, &[], &parents, HashAlgorithm::Sha2_256).unwrap();
+        let blake = epoch_hash(&alice, &[], &parents, HashAlgorithm::Blake3_256).unwrap();
+        assert_eq!(sha.to_cid(0x71).hash().code(), 0x12);
+        assert_eq!(blake.to_cid(0x71).hash().code(), 0x1e);
+
+        // epochs hashed with either algorithm read back unchanged
+        for (seq, id) in [sha, blake].into_iter().enumerate() {
+            epoch::Entity::insert(epoch::ActiveModel::from(epoch::Model {
+                seq: seq as i64,
+                id,
+                orbit: alice.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        }
+        let mut epochs: Vec<Hash> = epoch::Entity::find()
+            .all(&db.conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        epochs.sort();
+        let mut expected = vec![sha, blake];
+        expected.sort();
+        assert_eq!(epochs, expected);
+    }
+
+    #[test]
+    async fn operation_limit() {
+        let caps: Vec<Capability> = ["a", "b", "c"]
+            .into_iter()
+            .map(|k| Capability {
+                resource: Resource::Kepler(
+                    format!("kepler:example://default/kv/{k}").parse().unwrap(),
+                ),
+                action: "put".to_string(),
+            })
+            .collect();
+        assert_eq!(operations_over_limit(&caps, None), None);
+        assert_eq!(operations_over_limit(&caps, Some(3)), None);
+        assert_eq!(operations_over_limit(&caps, Some(2)), Some((3, 2)));
+        assert_eq!(operations_over_limit(&caps[..2], Some(2)), None);
+    }
+
+    #[test]
+    async fn max_operations() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let fail = OrbitId::new("example:alice".to_string(), "fail".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&fail, &[&one, &two]).await;
+        let options = |max_operations| InvokeOptions {
+            max_operations,
+            ..Default::default()
+        };
+
+        // an invocation of more operations than the limit is rejected before any is applied
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        match db
+            .invoke_with::<MemoryStaging>(invocation, inputs, options(Some(1)))
+            .await
+        {
+            Err(TxStoreError::TooManyOperations { count: 2, limit: 1 }) => {}
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("invocation over the limit was applied"),
+        }
+        assert_unchanged(&db, &[&one, &two]).await;
+
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        if let Err(e) = db
+            .invoke_with::<MemoryStaging>(invocation, inputs, options(Some(2)))
+            .await
+        {
+            panic!("invocation within the limit failed: {e}");
+        }
+        for orbit in [&one, &two] {
+            assert!(get_kv_entity(&db.conn, orbit, "key", None)
+                .await
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[test]
+    async fn read_only() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let caps = ["get", "list", "metadata", "exists"].map(|a| kv_cap(&alice, a));
+        assert!(write_capability(&caps).is_none());
+
+        let caps = ["get", "del", "put"].map(|a| kv_cap(&alice, a));
+        assert_eq!(
+            write_capability(&caps).map(|c| c.action.as_str()),
+            Some("del")
+        );
+
+        // other services are not written to by invocations
+        let caps = [Capability {
+            resource: Resource::Kepler(alice.to_resource(
+                Some("capabilities".to_string()),
+                None,
+                None,
+            )),
+            action: "put".to_string(),
+        }];
+        assert!(write_capability(&caps).is_none());
+    }
+
+    // alice delegates `kv/get` in her orbit to bob
+    async fn delegate_to_bob(
+        db: &OrbitDatabase<DatabaseConnection, (), ()>,
+        alice: &OrbitId,
+    ) -> Hash {
+        actor::Entity::insert_many(
+            ["did:example:alice", "did:key:bob"]
+                .map(|id| actor::ActiveModel::from(actor::Model { id: id.to_string() })),
+        )
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        let delegation = crate::hash::hash(b"delegation");
+        delegation::Entity::insert(delegation::ActiveModel::from(delegation::Model {
+            id: delegation,
+            delegator: "did:example:alice".to_string(),
+            delegatee: "did:key:bob".to_string(),
+            expiry: None,
+            issued_at: None,
+            not_before: None,
+            facts: None,
+            serialization: vec![],
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        abilities::Entity::insert(abilities::ActiveModel::from(abilities::Model {
+            resource: Resource::Kepler(alice.clone().to_resource(
+                Some("kv".to_string()),
+                None,
+                None,
+            )),
+            ability: "get".to_string(),
+            delegation,
+            caveats: Default::default(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        delegation
+    }
+
+    fn kv_cap(orbit: &OrbitId, action: &str) -> Capability {
+        Capability {
+            resource: Resource::Kepler(orbit.clone().to_resource(
+                Some("kv".to_string()),
+                Some("key".to_string()),
+                None,
+            )),
+            action: action.to_string(),
+        }
+    }
+
+    #[test]
+    async fn maintenance() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+
+        assert!(!db.set_maintenance(true));
+        assert!(db.maintenance());
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        match db.invoke::<MemoryStaging>(invocation, inputs).await {
+            Err(TxStoreError::Maintenance(_, action)) => assert_eq!(action, "put"),
+            _ => panic!("write was not refused"),
+        }
+        assert!(get_kv_entity(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .is_none());
+
+        // writes are accepted again as soon as the mode is left
+        assert!(db.set_maintenance(false));
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        assert!(db.invoke::<MemoryStaging>(invocation, inputs).await.is_ok());
+    }
+
+    #[test]
+    async fn invocation_failures() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let carol = OrbitId::new("example:carol".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        let parents = [delegate_to_bob(&db, &alice).await.to_cid(0x71)];
+        let caps = [
+            kv_cap(&alice, "get"),
+            kv_cap(&alice, "put"),
+            kv_cap(&carol, "get"),
+        ];
+
+        let failures = invocation::authorization_failures(
+            &db.conn,
+            "did:key:bob",
+            &caps,
+            &parents,
+            None,
+            |_| None,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            &failures[..],
+            [
+                invocation::InvocationError::UnauthorizedAction(a, put),
+                invocation::InvocationError::UnauthorizedAction(c, get),
+            ] if a == &caps[1].resource && put == "put" && c == &caps[2].resource && get == "get"
+        ));
+
+        // someone other than the delegate also fails as the wrong invoker
+        let failures = invocation::authorization_failures(
+            &db.conn,
+            "did:key:mallory",
+            &caps,
+            &parents,
+            None,
+            |_| None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(failures.len(), 3);
+        assert!(matches!(
+            &failures[0],
+            invocation::InvocationError::UnauthorizedInvoker(i) if i == "did:key:mallory"
+        ));
+
+        assert!(invocation::authorization_failures(
+            &db.conn,
+            "did:key:bob",
+            &caps[..1],
+            &parents,
+            None,
+            |_| None
+        )
+        .await
+        .unwrap()
+        .is_empty());
+        assert!(matches!(
+            &invocation::authorization_failures(&db.conn, "did:key:bob", &caps, &[], None, |_| {
+                None
+            })
+            .await
+            .unwrap()[..],
+            [invocation::InvocationError::MissingParents]
+        ));
+    }
+
+    #[test]
+    async fn expired_delegation() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        let delegation = delegate_to_bob(&db, &alice).await;
+        let now = OffsetDateTime::now_utc();
+        delegation::Entity::update(delegation::ActiveModel {
+            id: sea_orm::ActiveValue::Unchanged(delegation),
+            expiry: sea_orm::ActiveValue::Set(Some(now - time::Duration::minutes(1))),
+            ..Default::default()
+        })
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        let parents = [delegation.to_cid(0x71)];
+        let caps = [kv_cap(&alice, "get")];
+        let failures = |time| {
+            invocation::authorization_failures(
+                &db.conn,
+                "did:key:bob",
+                &caps,
+                &parents,
+                Some(time),
+                |_| None,
+            )
+        };
+
+        // the delegation was valid when the invocation may claim to have been made,
+        assert!(failures(now - time::Duration::minutes(2))
+            .await
+            .unwrap()
+            .is_empty());
+        // but it has expired by the time the server checks it
+        assert!(matches!(
+            &failures(now).await.unwrap()[..],
+            [invocation::InvocationError::InvalidParentTime(r, get)]
+                if r == &caps[0].resource && get == "get"
+        ));
+        // and is no longer reported as granting anything
+        assert!(
+            invocation::granted_to(&db.conn, "did:key:bob", &parents, now)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    async fn delegation_depth() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice).await.unwrap();
+        // a chain of 10 delegations, each delegating to the next
+        let links: Vec<Hash> = (0..10u8).map(|i| crate::hash::hash(&[i])).collect();
+        actor::Entity::insert_many((0..=links.len()).map(|i| {
+            actor::ActiveModel::from(actor::Model {
+                id: format!("did:key:{i}"),
+            })
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        for (i, link) in links.iter().enumerate() {
+            delegation::Entity::insert(delegation::ActiveModel::from(delegation::Model {
+                id: *link,
+                delegator: format!("did:key:{i}"),
+                delegatee: format!("did:key:{}", i + 1),
+                expiry: None,
+                issued_at: None,
+                not_before: None,
+                facts: None,
+                serialization: vec![],
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+            if i > 0 {
+                parent_delegations::Entity::insert(parent_delegations::ActiveModel::from(
+                    parent_delegations::Model {
+                        parent: links[i - 1],
+                        child: *link,
+                    },
+                ))
+                .exec(&db.conn)
+                .await
+                .unwrap();
+            }
+        }
+        let exceeds = |parents: &[Hash], max_depth| {
+            let parents: Vec<Cid> = parents.iter().map(|h| h.to_cid(0x71)).collect();
+            let conn = &db.conn;
+            async move {
+                delegation::exceeds_depth(conn, &parents, max_depth)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // extending the chain makes 11 delegations
+        assert!(exceeds(&links[9..], 10).await);
+        assert!(!exceeds(&links[9..], 11).await);
+        assert!(exceeds(&links[9..], 1).await);
+        // root delegations are always short enough
+        assert!(!exceeds(&[], 1).await);
+        assert!(exceeds(&links[..1], 1).await);
+        assert!(!exceeds(&links[..1], 2).await);
+        // the longest chain through any parent counts
+        assert!(exceeds(&[links[0], links[5]], 6).await);
+        assert!(!exceeds(&[links[0], links[5]], 7).await);
+    }
+
+    #[test]
+    async fn did_web_root() {
+        let web = "did:web:example.com%3A8443:users:alice";
+        let orbit: OrbitId = "kepler:web:example.com%3A8443:users:alice://default"
+            .parse()
+            .unwrap();
+        assert_eq!(orbit.did(), web);
+        let db = get_db(orbit.clone()).await.unwrap();
+
+        // the controller of a did:web orbit needs no delegation, whichever of its
+        // verification methods signs
+        let caps = [kv_cap(&orbit, "put")];
+        for invoker in [web.to_string(), format!("{web}#key-1")] {
+            assert!(invocation::authorization_failures(
+                &db.conn,
+                &invoker,
+                &caps,
+                &[],
+                None,
+                |_| None
             )
-        })
-        .fold(
-            (
-                Vec::<epoch_order::ActiveModel>::new(),
-                HashMap::<OrbitId, (i64, Hash, Vec<Hash>, HashMap<Hash, i64>)>::new(),
-                Vec::<event_order::ActiveModel>::new(),
-                Vec::<epoch::ActiveModel>::new(),
-            ),
-            |(mut eo, mut oo, mut ev, mut ep), (eo2, order, ev2, ep2)| {
-                eo.extend(eo2);
-                ev.extend(ev2);
-                oo.insert(order.0, order.1);
-                ep.push(ep2.into());
-                (eo, oo, ev, ep)
+            .await
+            .unwrap()
+            .is_empty());
+        }
+        // but another DID on the same domain does
+        assert!(matches!(
+            &invocation::authorization_failures(
+                &db.conn,
+                "did:web:example.com%3A8443:users:mallory",
+                &caps,
+                &[],
+                None,
+                |_| None
+            )
+            .await
+            .unwrap()[..],
+            [invocation::InvocationError::MissingParents]
+        ));
+
+        // the did:web controller delegates `kv/get` to a did:key session, which invokes it
+        actor::Entity::insert_many(
+            [web, "did:key:session"]
+                .map(|id| actor::ActiveModel::from(actor::Model { id: id.to_string() })),
+        )
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        let delegation = crate::hash::hash(b"web delegation");
+        delegation::Entity::insert(delegation::ActiveModel::from(delegation::Model {
+            id: delegation,
+            delegator: web.to_string(),
+            delegatee: "did:key:session".to_string(),
+            expiry: None,
+            issued_at: None,
+            not_before: None,
+            facts: None,
+            serialization: vec![],
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        abilities::Entity::insert(abilities::ActiveModel::from(abilities::Model {
+            resource: Resource::Kepler(orbit.clone().to_resource(
+                Some("kv".to_string()),
+                None,
+                None,
+            )),
+            ability: "get".to_string(),
+            delegation,
+            caveats: Default::default(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        assert!(invocation::authorization_failures(
+            &db.conn,
+            "did:key:session",
+            &[kv_cap(&orbit, "get")],
+            &[delegation.to_cid(0x71)],
+            None,
+            |_| None
+        )
+        .await
+        .unwrap()
+        .is_empty());
+
+        let allowlist = crate::util::MethodAllowlist {
+            global: ["web".to_string()].into(),
+            orbits: Default::default(),
+        };
+        assert!(allowlist.allows(web, [&orbit]));
+        assert!(!allowlist.allows("did:key:session", [&orbit]));
+    }
+
+    #[test]
+    async fn exists_ability() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        let parents = [delegate_to_bob(&db, &alice).await.to_cid(0x71)];
+        let failures = |action| {
+            let caps = [kv_cap(&alice, action)];
+            let db = &db;
+            async move {
+                invocation::authorization_failures(
+                    &db.conn,
+                    "did:key:bob",
+                    &caps,
+                    &parents,
+                    None,
+                    |_| None,
+                )
+                .await
+                .unwrap()
+            }
+        };
+        // `kv/get` covers `kv/exists`, but not other reads
+        assert!(failures("exists").await.is_empty());
+        assert_eq!(failures("metadata").await.len(), 1);
+        assert!(invocation::covers("metadata", "exists"));
+        assert!(!invocation::covers("exists", "get"));
+    }
+
+    #[test]
+    async fn caveats() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        let delegation = delegate_to_bob(&db, &alice).await;
+        // bob may also write up to 10 bytes
+        abilities::Entity::insert(abilities::ActiveModel::from(abilities::Model {
+            resource: Resource::Kepler(alice.clone().to_resource(
+                Some("kv".to_string()),
+                None,
+                None,
+            )),
+            ability: "put".to_string(),
+            delegation,
+            caveats: crate::types::Caveats([("maxSize".to_string(), serde_json::json!(10))].into()),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        let parents = [delegation.to_cid(0x71)];
+        let caps = [kv_cap(&alice, "put")];
+
+        let failures = |size| {
+            invocation::authorization_failures(
+                &db.conn,
+                "did:key:bob",
+                &caps,
+                &parents,
+                None,
+                move |_| size,
+            )
+        };
+        assert!(failures(Some(10)).await.unwrap().is_empty());
+        assert!(matches!(
+            &failures(Some(11)).await.unwrap()[..],
+            [invocation::InvocationError::UnauthorizedAction(r, put)]
+                if r == &caps[0].resource && put == "put"
+        ));
+
+        let now = OffsetDateTime::now_utc();
+        let until = |t: OffsetDateTime| {
+            crate::types::Caveats(
+                [(
+                    "validUntil".to_string(),
+                    serde_json::json!(t.unix_timestamp()),
+                )]
+                .into(),
+            )
+        };
+        assert!(invocation::permits(
+            &until(now + time::Duration::minutes(1)),
+            now,
+            None
+        ));
+        assert!(!invocation::permits(
+            &until(now - time::Duration::minutes(1)),
+            now,
+            None
+        ));
+        // malformed caveats permit nothing, unknown ones are ignored
+        let caveats = |name: &str, value| crate::types::Caveats([(name.to_string(), value)].into());
+        assert!(!invocation::permits(
+            &caveats("maxSize", serde_json::json!("big")),
+            now,
+            Some(1)
+        ));
+        assert!(invocation::permits(
+            &caveats("color", serde_json::json!("red")),
+            now,
+            Some(1)
+        ));
+    }
+
+    #[test]
+    async fn cross_orbit_invocation() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let carol = OrbitId::new("example:carol".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        let delegation = delegate_to_bob(&db, &alice).await;
+
+        let get = |orbit: &OrbitId| kv_cap(orbit, "get");
+        let parents = [delegation.to_cid(0x71)];
+
+        assert_eq!(
+            undelegated_orbit(&db.conn, "did:key:bob", &[get(&alice)], &parents)
+                .await
+                .unwrap(),
+            None
+        );
+        // the chain is valid, but grants nothing in carol's orbit
+        assert_eq!(
+            undelegated_orbit(
+                &db.conn,
+                "did:key:bob",
+                &[get(&alice), get(&carol)],
+                &parents
+            )
+            .await
+            .unwrap(),
+            Some(carol.clone())
+        );
+        // controllers can invoke their own orbits without delegations
+        assert_eq!(
+            undelegated_orbit(&db.conn, "did:example:alice", &[get(&alice)], &[])
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            undelegated_orbit(&db.conn, "did:example:alice", &[get(&carol)], &[])
+                .await
+                .unwrap(),
+            Some(carol)
+        );
+    }
+
+    /// Memory store which fails to persist content to one orbit.
+    struct FailingStore {
+        inner: MemoryBlockStore,
+        fail: OrbitId,
+    }
+
+    #[sea_orm_migration::async_trait::async_trait]
+    impl StorageSetup for FailingStore {
+        type Error = std::convert::Infallible;
+        async fn create(&self, orbit: &OrbitId) -> Result<(), Self::Error> {
+            self.inner.create(orbit).await
+        }
+    }
+
+    #[sea_orm_migration::async_trait::async_trait]
+    impl ImmutableReadStore for FailingStore {
+        type Error = std::convert::Infallible;
+        type Readable = futures::io::Cursor<Vec<u8>>;
+        async fn contains(&self, orbit: &OrbitId, id: &Hash) -> Result<bool, Self::Error> {
+            self.inner.contains(orbit, id).await
+        }
+        async fn read(
+            &self,
+            orbit: &OrbitId,
+            id: &Hash,
+        ) -> Result<Option<Content<Self::Readable>>, Self::Error> {
+            self.inner.read(orbit, id).await
+        }
+    }
+
+    #[sea_orm_migration::async_trait::async_trait]
+    impl ImmutableWriteStore<MemoryStaging> for FailingStore {
+        type Error = std::io::Error;
+        async fn persist(
+            &self,
+            orbit: &OrbitId,
+            staged: HashBuffer<Vec<u8>>,
+        ) -> Result<Hash, Self::Error> {
+            if orbit == &self.fail {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "unavailable",
+                ));
+            }
+            ImmutableWriteStore::<MemoryStaging>::persist(&self.inner, orbit, staged).await
+        }
+    }
+
+    #[sea_orm_migration::async_trait::async_trait]
+    impl ImmutableDeleteStore for FailingStore {
+        type Error = std::convert::Infallible;
+        async fn remove(&self, orbit: &OrbitId, id: &Hash) -> Result<Option<()>, Self::Error> {
+            self.inner.remove(orbit, id).await
+        }
+    }
+
+    type SessionDb = OrbitDatabase<DatabaseConnection, FailingStore, crate::keys::StaticSecret>;
+
+    // alice delegates `kv/put` in her orbits to a session key, returning the session key,
+    // its verification method and the delegation
+    async fn delegate_puts(
+        fail: &OrbitId,
+        orbits: &[&OrbitId],
+    ) -> (SessionDb, kepler_lib::ssi::jwk::JWK, String, Hash) {
+        use kepler_lib::{
+            resolver::DID_METHODS,
+            ssi::{did::Source, jwk::JWK},
+        };
+
+        let db = OrbitDatabase::new(
+            sea_orm::Database::connect("sqlite::memory:").await.unwrap(),
+            FailingStore {
+                inner: Default::default(),
+                fail: fail.clone(),
+            },
+            crate::keys::StaticSecret::new(vec![0; 32]).unwrap(),
+        )
+        .await
+        .unwrap();
+        for orbit in orbits {
+            orbit::Entity::insert(orbit::ActiveModel::from(orbit_model(orbit)))
+                .exec(&db.conn)
+                .await
+                .unwrap();
+        }
+
+        let mut jwk = JWK::generate_ed25519().unwrap();
+        jwk.algorithm = Some(kepler_lib::ssi::jwk::Algorithm::EdDSA);
+        let did = DID_METHODS
+            .generate(&Source::KeyAndPattern(&jwk, "key"))
+            .unwrap();
+        let session = format!("{did}#{}", did.trim_start_matches("did:key:"));
+        actor::Entity::insert_many(
+            ["did:example:alice", &session]
+                .map(|id| actor::ActiveModel::from(actor::Model { id: id.to_string() })),
+        )
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        let delegation = crate::hash::hash(b"delegation");
+        delegation::Entity::insert(delegation::ActiveModel::from(delegation::Model {
+            id: delegation,
+            delegator: "did:example:alice".to_string(),
+            delegatee: session.clone(),
+            expiry: None,
+            issued_at: None,
+            not_before: None,
+            facts: None,
+            serialization: vec![],
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        abilities::Entity::insert_many(orbits.iter().map(|orbit| {
+            abilities::ActiveModel::from(abilities::Model {
+                resource: Resource::Kepler((*orbit).clone().to_resource(
+                    Some("kv".to_string()),
+                    None,
+                    None,
+                )),
+                ability: "put".to_string(),
+                delegation,
+                caveats: Default::default(),
+            })
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        (db, jwk, session, delegation)
+    }
+
+    // the session writes to `key` in each of the orbits
+    async fn put_invocation(
+        jwk: &kepler_lib::ssi::jwk::JWK,
+        session: &str,
+        delegation: Hash,
+        orbits: &[&OrbitId],
+    ) -> (Invocation, InvocationInputs<Vec<u8>>) {
+        use futures::io::AsyncWriteExt;
+        use kepler_lib::authorization::{make_invocation, HeaderEncode, KeplerInvocation};
+
+        let expiration = (OffsetDateTime::now_utc() + time::Duration::minutes(1)).unix_timestamp();
+        let ucan = make_invocation(
+            orbits
+                .iter()
+                .map(|orbit| {
+                    (*orbit).clone().to_resource(
+                        Some("kv".to_string()),
+                        Some("key".to_string()),
+                        Some("put".to_string()),
+                    )
+                })
+                .collect(),
+            delegation.to_cid(0x71),
+            jwk,
+            session.to_string(),
+            expiration as f64,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let mut inputs = HashMap::new();
+        for orbit in orbits {
+            let mut stage = MemoryStaging.stage(orbit).await.unwrap();
+            stage.write_all(b"value").await.unwrap();
+            inputs.insert(
+                ((*orbit).clone(), "key".to_string()),
+                (Metadata(Default::default()), stage),
+            );
+        }
+        (
+            Invocation::from_header_ser::<KeplerInvocation>(&ucan.encode().unwrap()).unwrap(),
+            inputs,
+        )
+    }
+
+    async fn assert_unchanged(db: &SessionDb, orbits: &[&OrbitId]) {
+        assert_eq!(epoch::Entity::find().count(&db.conn).await.unwrap(), 0);
+        assert_eq!(invocation::Entity::find().count(&db.conn).await.unwrap(), 0);
+        for orbit in orbits {
+            assert!(get_kv_entity(&db.conn, orbit, "key", None)
+                .await
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    async fn atomic_invocation() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&two, &[&one, &two]).await;
+
+        // writing to the second orbit fails, so neither orbit has a new epoch, or a key
+        // referencing content
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        assert!(matches!(
+            db.invoke::<MemoryStaging>(invocation, inputs).await,
+            Err(TxStoreError::StoreWrite(_))
+        ));
+        assert_unchanged(&db, &[&one, &two]).await;
+    }
+
+    #[test]
+    async fn batch_atomic() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&two, &[&one, &two]).await;
+
+        // the second invocation fails to write, so the first isn't applied either
+        let batch = vec![
+            put_invocation(&jwk, &session, delegation, &[&one]).await,
+            put_invocation(&jwk, &session, delegation, &[&two]).await,
+        ];
+        assert!(matches!(
+            db.invoke_batch::<MemoryStaging>(batch, InvokeOptions::default())
+                .await,
+            Err((Some(1), TxStoreError::StoreWrite(_)))
+        ));
+        assert_unchanged(&db, &[&one, &two]).await;
+    }
+
+    #[test]
+    async fn batch_continue() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&two, &[&one, &two]).await;
+
+        // invoked one at a time, a failure leaves the other invocations applied
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&two]).await;
+        assert!(matches!(
+            db.invoke::<MemoryStaging>(invocation, inputs).await,
+            Err(TxStoreError::StoreWrite(_))
+        ));
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        assert!(db.invoke::<MemoryStaging>(invocation, inputs).await.is_ok());
+        assert!(get_kv_entity(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(get_kv_entity(&db.conn, &two, "key", None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    async fn missing_content() {
+        use kepler_lib::authorization::{make_invocation, HeaderEncode, KeplerInvocation};
+
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let none = OrbitId::new("example:alice".to_string(), "none".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&none, &[&one]).await;
+        abilities::Entity::insert(abilities::ActiveModel::from(abilities::Model {
+            resource: Resource::Kepler(one.clone().to_resource(Some("kv".to_string()), None, None)),
+            ability: "get".to_string(),
+            delegation,
+            caveats: Default::default(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        db.invoke::<MemoryStaging>(invocation, inputs)
+            .await
+            .unwrap();
+        let value = get_kv_entity(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .unwrap()
+            .value;
+        // the block is lost from storage, while the database still references it
+        assert!(db
+            .storage()
+            .inner
+            .remove(&one, &value)
+            .await
+            .unwrap()
+            .is_some());
+
+        let expiration = (OffsetDateTime::now_utc() + time::Duration::minutes(1)).unix_timestamp();
+        let ucan = make_invocation(
+            vec![one.clone().to_resource(
+                Some("kv".to_string()),
+                Some("key".to_string()),
+                Some("get".to_string()),
+            )],
+            delegation.to_cid(0x71),
+            &jwk,
+            session.clone(),
+            expiration as f64,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let invocation =
+            Invocation::from_header_ser::<KeplerInvocation>(&ucan.encode().unwrap()).unwrap();
+        assert!(matches!(
+            db.invoke::<MemoryStaging>(invocation, HashMap::new()).await,
+            Err(TxStoreError::MissingContent { orbit, key, hash })
+                if orbit == one && key == "key" && hash == value
+        ));
+    }
+
+    #[test]
+    async fn purge() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let none = OrbitId::new("example:alice".to_string(), "none".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&none, &[&one, &two]).await;
+
+        // one invocation is ordered in both orbits, the other only in the first
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        db.invoke::<MemoryStaging>(invocation, inputs)
+            .await
+            .unwrap();
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        db.invoke::<MemoryStaging>(invocation, inputs)
+            .await
+            .unwrap();
+        let value = get_kv_entity(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .unwrap()
+            .value;
+
+        // the session's delegation, and another, are ordered in the first orbit, while a
+        // delegation ordered in the second orbit depends on the session's
+        let other = crate::hash::hash(b"other delegation");
+        let child = crate::hash::hash(b"child delegation");
+        delegation::Entity::insert_many([other, child].map(|id| {
+            delegation::ActiveModel::from(delegation::Model {
+                id,
+                delegator: session.clone(),
+                delegatee: "did:example:alice".to_string(),
+                expiry: None,
+                issued_at: None,
+                not_before: None,
+                facts: None,
+                serialization: vec![],
+            })
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        parent_delegations::Entity::insert(parent_delegations::ActiveModel::from(
+            parent_delegations::Model {
+                parent: delegation,
+                child,
             },
+        ))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        let epoch = crate::hash::hash(b"epoch");
+        for (orbit, events) in [(&one, vec![delegation, other]), (&two, vec![child])] {
+            epoch::Entity::insert(epoch::ActiveModel::from(epoch::Model {
+                seq: 10,
+                id: epoch,
+                orbit: orbit.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+            event_order::Entity::insert_many(events.into_iter().enumerate().map(|(i, event)| {
+                event_order::ActiveModel::from(event_order::Model {
+                    seq: 10,
+                    epoch,
+                    epoch_seq: i as i64,
+                    event,
+                    orbit: orbit.clone().into(),
+                })
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        }
+
+        let outcome = db.purge(&one, None).await.unwrap();
+        assert_eq!(
+            outcome,
+            PurgeOutcome {
+                blocks: 1,
+                kv_entries: 2,
+                // the exclusive invocation and the delegation nothing depends on
+                events: 2,
+                epochs: 3,
+                orbit: true,
+                keypair: false,
+            }
+        );
+        assert!(get_kv_entity(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(!db.storage().inner.contains(&one, &value).await.unwrap());
+        assert_eq!(db.orbit_info(&one).await.unwrap(), None);
+
+        // the second orbit keeps its content, the invocation shared with it, and the
+        // delegations it depends on
+        assert!(get_kv_entity(&db.conn, &two, "key", None)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(db.storage().inner.contains(&two, &value).await.unwrap());
+        assert_eq!(invocation::Entity::find().count(&db.conn).await.unwrap(), 1);
+        for kept in [delegation, child] {
+            assert!(delegation::Entity::find_by_id(kept)
+                .one(&db.conn)
+                .await
+                .unwrap()
+                .is_some());
+        }
+        assert!(delegation::Entity::find_by_id(other)
+            .one(&db.conn)
+            .await
+            .unwrap()
+            .is_none());
+
+        // purging again removes nothing
+        assert_eq!(db.purge(&one, None).await.unwrap(), PurgeOutcome::default());
+        assert_eq!(invocation::Entity::find().count(&db.conn).await.unwrap(), 1);
+
+        // a purge invocation is accepted once, even after the orbit is recreated
+        let purge = crate::hash::hash(b"purge");
+        assert_eq!(
+            db.purge(&one, Some(purge)).await.unwrap(),
+            PurgeOutcome::default()
+        );
+        orbit::Entity::insert(orbit::ActiveModel::from(orbit_model(&one)))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        assert!(matches!(
+            db.purge(&one, Some(purge)).await,
+            Err(PurgeError::Replayed(h)) if h == purge
+        ));
+        assert!(db.orbit_info(&one).await.unwrap().is_some());
+    }
+
+    #[test]
+    async fn batch_order() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one, &two]).await;
+
+        let batch = vec![
+            put_invocation(&jwk, &session, delegation, &[&two]).await,
+            put_invocation(&jwk, &session, delegation, &[&one]).await,
+            put_invocation(&jwk, &session, delegation, &[&one, &two]).await,
+        ];
+        let hashes: Vec<Hash> = batch
+            .iter()
+            .map(|(invocation, _)| crate::hash::hash(&invocation.1))
+            .collect();
+        let applied = match db
+            .invoke_batch::<MemoryStaging>(batch, InvokeOptions::default())
+            .await
+        {
+            Ok(applied) => applied,
+            Err((i, e)) => panic!("invocation {i:?} failed: {e}"),
+        };
+
+        // a result per invocation, in order, with the commits of the orbits it invokes
+        let orbits: Vec<Vec<&OrbitId>> = applied
+            .iter()
+            .map(|(commits, _)| {
+                let mut orbits: Vec<_> = commits.keys().collect();
+                orbits.sort_by_key(|o| o.to_string());
+                orbits
+            })
+            .collect();
+        assert_eq!(orbits, vec![vec![&two], vec![&one], vec![&one, &two]]);
+        for ((commits, outcomes), hash) in applied.iter().zip(&hashes) {
+            assert!(outcomes
+                .iter()
+                .all(|o| matches!(o, InvocationOutcome::KvWrite)));
+            for commit in commits.values() {
+                let block = db.receipt(commit.receipt.unwrap()).await.unwrap().unwrap();
+                let receipt: Receipt = serde_ipld_dagcbor::from_slice(&block).unwrap();
+                assert_eq!(Hash::from(receipt.payload.invocation), *hash);
+            }
+        }
+
+        // the batch's events are grouped in one epoch per orbit
+        assert_eq!(epoch::Entity::find().count(&db.conn).await.unwrap(), 2);
+        for orbit in [&one, &two] {
+            assert!(get_kv_entity(&db.conn, orbit, "key", None)
+                .await
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[test]
+    async fn receipts() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one, &two]).await;
+
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        let invocation_hash = crate::hash::hash(&invocation.1);
+        let commits = match db.invoke::<MemoryStaging>(invocation, inputs).await {
+            Ok((commits, _)) => commits,
+            Err(e) => panic!("invocation failed: {e}"),
+        };
+
+        // each orbit's commit has a receipt, signed with the orbit's key
+        for orbit in [&one, &two] {
+            let commit = &commits[orbit];
+            let block = db.receipt(commit.receipt.unwrap()).await.unwrap().unwrap();
+            let receipt: Receipt = serde_ipld_dagcbor::from_slice(&block).unwrap();
+            assert_eq!(receipt.payload.orbit, orbit.to_string());
+            assert_eq!(Hash::from(receipt.payload.invocation), invocation_hash);
+            assert_eq!(Hash::from(receipt.payload.rev), commit.rev);
+            assert_eq!(receipt.payload.seq, commit.seq);
+            assert_eq!(receipt.payload.invoked.len(), 1);
+            let key = db.secrets.get_pubkey(orbit).await.unwrap();
+            assert!(receipt.verify(&key).unwrap());
+            let other_key = db.secrets.get_pubkey(&other).await.unwrap();
+            assert!(!receipt.verify(&other_key).unwrap());
+        }
+        assert_eq!(
+            db.receipt(crate::hash::hash(b"receipt")).await.unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn key_normalization() {
+        for (path, key) in [
+            ("a/b", "a/b"),
+            ("/a/b", "a/b"),
+            ("a//b", "a/b"),
+            ("//a///b", "a/b"),
+            ("a/./b", "a/b"),
+            ("./a", "a"),
+            ("a/", "a/"),
+            ("a//", "a/"),
+            ("/", ""),
+            ("", ""),
+            ("a/.../b", "a/.../b"),
+            ("a/..b", "a/..b"),
+        ] {
+            assert_eq!(normalize_path(path).as_deref(), Some(key), "{path}");
+        }
+        for path in ["..", "../a", "a/..", "a/../b", "/../../etc", "a//../b"] {
+            assert_eq!(normalize_path(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    async fn canonical_keys() {
+        use futures::io::AsyncWriteExt;
+        use kepler_lib::authorization::{make_invocation, HeaderEncode, KeplerInvocation};
+
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+        let put = |path: &str| {
+            let (jwk, session) = (jwk.clone(), session.clone());
+            let (one, path) = (one.clone(), path.to_string());
+            async move {
+                let expiration =
+                    (OffsetDateTime::now_utc() + time::Duration::minutes(1)).unix_timestamp();
+                let ucan = make_invocation(
+                    vec![one.clone().to_resource(
+                        Some("kv".to_string()),
+                        Some(path.clone()),
+                        Some("put".to_string()),
+                    )],
+                    delegation.to_cid(0x71),
+                    &jwk,
+                    session,
+                    expiration as f64,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+                let mut stage = MemoryStaging.stage(&one).await.unwrap();
+                stage.write_all(path.as_bytes()).await.unwrap();
+                let mut inputs = HashMap::new();
+                inputs.insert((one, path), (Metadata(Default::default()), stage));
+                (
+                    Invocation::from_header_ser::<KeplerInvocation>(&ucan.encode().unwrap())
+                        .unwrap(),
+                    inputs,
+                )
+            }
+        };
+
+        // differently written keys are the same key
+        let (invocation, inputs) = put("/dir//key").await;
+        assert!(db.invoke::<MemoryStaging>(invocation, inputs).await.is_ok());
+        let first = get_kv_entity(&db.conn, &one, "dir/key", None)
+            .await
+            .unwrap()
+            .unwrap();
+        let (invocation, inputs) = put("dir/./key").await;
+        assert!(db.invoke::<MemoryStaging>(invocation, inputs).await.is_ok());
+        let second = get_kv_entity(&db.conn, &one, "dir/key", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(first.value, second.value);
+
+        // keys can't climb out of a prefix
+        let (invocation, inputs) = put("dir/../../key").await;
+        assert!(matches!(
+            db.invoke::<MemoryStaging>(invocation, inputs).await,
+            Err(TxStoreError::InvalidKey(key)) if key == "dir/../../key"
+        ));
+    }
+
+    #[test]
+    async fn tombstone_retention() {
+        use kepler_lib::authorization::{make_invocation, HeaderEncode, KeplerInvocation};
+
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+        abilities::Entity::insert(abilities::ActiveModel::from(abilities::Model {
+            resource: Resource::Kepler(one.clone().to_resource(Some("kv".to_string()), None, None)),
+            ability: "del".to_string(),
+            delegation,
+            caveats: Default::default(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+
+        let (put, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        let replayed = crate::events::SerializedEvent(put.0.clone(), put.1.clone());
+        assert!(db.invoke::<MemoryStaging>(put, inputs).await.is_ok());
+        let expiration = (OffsetDateTime::now_utc() + time::Duration::minutes(1)).unix_timestamp();
+        let del = make_invocation(
+            vec![one.clone().to_resource(
+                Some("kv".to_string()),
+                Some("key".to_string()),
+                Some("del".to_string()),
+            )],
+            delegation.to_cid(0x71),
+            &jwk,
+            session.clone(),
+            expiration as f64,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let del = Invocation::from_header_ser::<KeplerInvocation>(&del.encode().unwrap()).unwrap();
+        assert!(db
+            .invoke::<MemoryStaging>(del, HashMap::new())
+            .await
+            .is_ok());
+
+        // the delete is kept while it is retained
+        let retaining = db
+            .clone()
+            .with_tombstone_retention(Some(Duration::from_secs(3600)));
+        assert_eq!(
+            retaining.compact(&one).await.unwrap(),
+            CompactOutcome::default()
+        );
+        assert_eq!(list_versions(&db.conn, &one, "key").await.unwrap().len(), 1);
+
+        // and goes with the write it deleted once it isn't
+        assert_eq!(
+            db.compact(&one).await.unwrap(),
+            CompactOutcome {
+                epochs: 1,
+                events: 1,
+                kv_entries: 2,
+            }
+        );
+        assert!(list_versions(&db.conn, &one, "key")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // a late replay of the deleted write is known to be applied, so doesn't write the
+        // key again
+        let (_, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        match db.invoke::<MemoryStaging>(replayed, inputs).await {
+            Ok((commits, _)) => assert!(commits.is_empty()),
+            Err(e) => panic!("replay failed: {e}"),
+        }
+        assert!(get_kv_entity(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    async fn orbit_heads() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+
+        assert_eq!(db.heads(&other).await.unwrap(), None);
+        assert_eq!(
+            db.heads(&one).await.unwrap(),
+            Some(OrbitHeads {
+                heads: vec![],
+                height: 0
+            })
+        );
+
+        // the heads are the latest epoch
+        for _ in 0..2 {
+            let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+            let commits = match db.invoke::<MemoryStaging>(invocation, inputs).await {
+                Ok((commits, _)) => commits,
+                Err(e) => panic!("invocation failed: {e}"),
+            };
+            assert_eq!(
+                db.heads(&one).await.unwrap(),
+                Some(OrbitHeads {
+                    heads: vec![commits[&one].rev],
+                    height: commits[&one].seq
+                })
+            );
+        }
+    }
+
+    #[test]
+    async fn last_modified() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        assert!(db.invoke::<MemoryStaging>(invocation, inputs).await.is_ok());
+
+        // reads are stamped with the time the write was committed, which isn't stored
+        let write = get_kv_entity(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(write.metadata.last_modified(), None);
+        let committed = invocation::Entity::find_by_id(write.invocation)
+            .one(&db.conn)
+            .await
+            .unwrap()
+            .unwrap()
+            .issued_at;
+        let md = metadata(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            md.last_modified().map(|t| t.unix_timestamp()),
+            Some(committed.unix_timestamp())
         );
+        match get_kv(&db.conn, &db.storage, &one, "key", None).await {
+            Ok(Some((read, _))) => assert_eq!(read, md),
+            _ => panic!("read failed"),
+        }
+    }
 
-    // save epochs
-    epoch::Entity::insert_many(epochs)
-        .exec(db)
+    #[test]
+    async fn pointers() {
+        use crate::pointer::Pointer;
+
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+        let db = db.with_pointers(true);
+        assert_eq!(db.pointer(&one).await.unwrap(), None);
+
+        // each commit publishes a pointer to the orbit's heads, superseding the last
+        for sequence in 0..2 {
+            let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+            let commits = match db.invoke::<MemoryStaging>(invocation, inputs).await {
+                Ok((commits, _)) => commits,
+                Err(e) => panic!("invocation failed: {e}"),
+            };
+            let block = db.pointer(&one).await.unwrap().unwrap();
+            let pointer: Pointer = serde_ipld_dagcbor::from_slice(&block).unwrap();
+            assert_eq!(pointer.payload.orbit, one.to_string());
+            assert_eq!(pointer.payload.heads, vec![commits[&one].rev.to_cid(0x71)]);
+            assert_eq!(pointer.payload.height, commits[&one].seq);
+            assert_eq!(pointer.payload.sequence, sequence);
+            let key = db.secrets.get_pubkey(&one).await.unwrap();
+            assert!(pointer.verify(&key).unwrap());
+            let other_key = db.secrets.get_pubkey(&other).await.unwrap();
+            assert!(!pointer.verify(&other_key).unwrap());
+        }
+    }
+
+    #[test]
+    async fn delegation_orbits() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let fail = OrbitId::new("example:alice".to_string(), "fail".to_string());
+        let (db, _, _, delegation) = delegate_puts(&fail, &[&one, &two]).await;
+
+        let mut orbits = db.event_orbits(delegation).await.unwrap();
+        orbits.sort();
+        assert_eq!(orbits, vec![one, two]);
+        assert!(db.event_orbits(hash(b"unknown")).await.unwrap().is_empty());
+    }
+
+    #[test]
+    async fn orbit_pointers() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let fail = OrbitId::new("example:alice".to_string(), "fail".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&fail, &[&one, &two]).await;
+
+        // with pointers published for no orbit, the flag enables them for one only
+        db.set_feature(&one, POINTERS_FEATURE, true).await.unwrap();
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        if let Err(e) = db.invoke::<MemoryStaging>(invocation, inputs).await {
+            panic!("invocation failed: {e}");
+        }
+        assert!(db.pointer(&one).await.unwrap().is_some());
+        assert_eq!(db.pointer(&two).await.unwrap(), None);
+    }
+
+    #[test]
+    async fn session_pages() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let (db, _, session, delegation) = delegate_puts(&two, &[&one, &two]).await;
+        let now = OffsetDateTime::now_utc();
+        actor::Entity::insert(actor::ActiveModel::from(actor::Model {
+            id: "did:example:bob".to_string(),
+        }))
+        .exec(&db.conn)
         .await
-        .map_err(|e| match e {
-            DbErr::Exec(RuntimeErr::SqlxError(SqlxError::Database(_))) => TxError::OrbitNotFound,
-            _ => e.into(),
-        })?;
+        .unwrap();
+        let delegate = |name: &str,
+                        orbit: &OrbitId,
+                        expiry: Option<OffsetDateTime>,
+                        not_before: Option<OffsetDateTime>| {
+            let id = crate::hash::hash(name.as_bytes());
+            let orbit = orbit.clone();
+            let conn = &db.conn;
+            async move {
+                delegation::Entity::insert(delegation::ActiveModel::from(delegation::Model {
+                    id,
+                    delegator: "did:example:alice".to_string(),
+                    delegatee: "did:example:bob".to_string(),
+                    expiry,
+                    issued_at: None,
+                    not_before,
+                    facts: None,
+                    serialization: vec![],
+                }))
+                .exec(conn)
+                .await
+                .unwrap();
+                abilities::Entity::insert(abilities::ActiveModel::from(abilities::Model {
+                    resource: Resource::Kepler(orbit.to_resource(
+                        Some("kv".to_string()),
+                        Some("bob/".to_string()),
+                        None,
+                    )),
+                    ability: "get".to_string(),
+                    delegation: id,
+                    caveats: Default::default(),
+                }))
+                .exec(conn)
+                .await
+                .unwrap();
+                id
+            }
+        };
+        let bob = delegate("bob", &one, None, None).await;
+        delegate("expired", &one, Some(now - time::Duration::hours(1)), None).await;
+        delegate("pending", &one, None, Some(now + time::Duration::hours(1))).await;
+        delegate("elsewhere", &two, None, None).await;
 
-    // save epoch orderings
-    if !epoch_order.is_empty() {
-        epoch_order::Entity::insert_many(epoch_order)
-            .exec(db)
-            .await?;
+        let page = |after, limit, delegate: Option<&str>| SessionsQuery {
+            after,
+            limit,
+            delegate: delegate.map(str::to_string),
+        };
+        let ids = |dels: Vec<delegation::Model>| dels.into_iter().map(|d| d.id).collect::<Vec<_>>();
+
+        // only unexpired delegations with abilities in the orbit, ordered by hash
+        let (all, next) = valid_delegations(&db.conn, &one, &page(None, None, None))
+            .await
+            .unwrap();
+        let mut expected = vec![delegation, bob];
+        expected.sort_by_key(|h| Vec::<u8>::from(*h));
+        assert_eq!(ids(all), expected);
+        assert_eq!(next, None);
+
+        // each page ends with the cursor of the next, until the last
+        let (first, next) = valid_delegations(&db.conn, &one, &page(None, Some(1), None))
+            .await
+            .unwrap();
+        assert_eq!(ids(first), expected[..1]);
+        assert_eq!(next, Some(expected[0]));
+        let (second, next) = valid_delegations(&db.conn, &one, &page(next, Some(1), None))
+            .await
+            .unwrap();
+        assert_eq!(ids(second), expected[1..]);
+        assert_eq!(next, None);
+
+        // delegations may be filtered by delegate
+        let (bobs, _) =
+            valid_delegations(&db.conn, &one, &page(None, None, Some("did:example:bob")))
+                .await
+                .unwrap();
+        assert_eq!(ids(bobs), vec![bob]);
+        let (sessions, _) = valid_delegations(&db.conn, &one, &page(None, None, Some(&session)))
+            .await
+            .unwrap();
+        assert_eq!(ids(sessions), vec![delegation]);
     }
 
-    // save event orderings
-    event_order::Entity::insert_many(event_order)
-        .exec(db)
-        .await?;
+    #[test]
+    async fn orbit_info() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
 
-    for (hash, event) in event_hashes {
-        match event {
-            Event::Delegation(d) => delegation::process(db, *d).await?,
-            Event::Invocation(i, ops) => {
-                invocation::process(
-                    db,
-                    *i,
-                    ops.into_iter()
-                        .map(|op| {
-                            let v = orbit_order
-                                .get(op.orbit())
-                                .and_then(|(s, e, _, h)| Some((s, e, h.get(&hash)?)))
-                                .unwrap();
-                            op.version(*v.0, *v.1, *v.2)
-                        })
-                        .collect(),
+        assert_eq!(db.orbit_info(&other).await.unwrap(), None);
+        assert_eq!(
+            db.orbit_info(&one).await.unwrap(),
+            Some(OrbitInfo {
+                created_at: None,
+                seq: 0
+            })
+        );
+
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        let commits = match db.invoke::<MemoryStaging>(invocation, inputs).await {
+            Ok((commits, _)) => commits,
+            Err(e) => panic!("invocation failed: {e}"),
+        };
+        let info = db.orbit_info(&one).await.unwrap().unwrap();
+        assert_eq!(info.seq, commits[&one].seq);
+    }
+
+    #[test]
+    async fn bounded_parents() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+        let db = db.with_max_parents(Some(3));
+
+        // many concurrent writers leave the orbit with many heads
+        let heads: Vec<Hash> = (0..10)
+            .map(|i| crate::hash::hash(format!("head {i}").as_bytes()))
+            .collect();
+        epoch::Entity::insert_many(heads.iter().map(|head| {
+            epoch::ActiveModel::from(epoch::Model {
+                seq: 0,
+                id: *head,
+                orbit: one.clone().into(),
+            })
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        let commits = match db.invoke::<MemoryStaging>(invocation, inputs).await {
+            Ok((commits, _)) => commits,
+            Err(e) => panic!("invocation failed: {e}"),
+        };
+        let commit = &commits[&one];
+        assert_eq!(commit.seq, 1);
+        assert!(commit.consumed_epochs.len() <= 3);
+
+        // the new epoch is the only head, and follows every old head through merge
+        // epochs with at most 3 parents each
+        let links = epoch_order::Entity::find().all(&db.conn).await.unwrap();
+        let mut parents: HashMap<Hash, Vec<Hash>> = HashMap::new();
+        for l in &links {
+            parents.entry(l.child).or_default().push(l.parent);
+        }
+        assert!(parents.values().all(|p| p.len() <= 3));
+        let epochs = epoch::Entity::find().all(&db.conn).await.unwrap();
+        let children: HashSet<Hash> = parents.values().flatten().copied().collect();
+        let current: Vec<Hash> = epochs
+            .iter()
+            .map(|e| e.id)
+            .filter(|e| !children.contains(e))
+            .collect();
+        assert_eq!(current, vec![commit.rev]);
+        let mut ancestors = HashSet::new();
+        let mut queue = vec![commit.rev];
+        while let Some(epoch) = queue.pop() {
+            for p in parents.get(&epoch).into_iter().flatten() {
+                if ancestors.insert(*p) {
+                    queue.push(*p);
+                }
+            }
+        }
+        assert!(heads.iter().all(|h| ancestors.contains(h)));
+
+        // merge epochs order no events, so compaction keeps them
+        let merges: Vec<Hash> = epochs
+            .iter()
+            .map(|e| e.id)
+            .filter(|e| *e != commit.rev && !heads.contains(e))
+            .collect();
+        assert!(!merges.is_empty());
+        assert_eq!(
+            event_order::Entity::find()
+                .filter(event_order::Column::Epoch.is_in(merges.clone()))
+                .count(&db.conn)
+                .await
+                .unwrap(),
+            0
+        );
+        db.compact(&one).await.unwrap();
+        assert_eq!(
+            epoch::Entity::find()
+                .filter(epoch::Column::Id.is_in(merges.clone()))
+                .count(&db.conn)
+                .await
+                .unwrap(),
+            merges.len() as u64
+        );
+    }
+
+    #[test]
+    async fn block_reads() {
+        use kepler_lib::authorization::{make_invocation, HeaderEncode, KeplerInvocation};
+
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+        abilities::Entity::insert(abilities::ActiveModel::from(abilities::Model {
+            resource: Resource::Kepler(one.clone().to_resource(
+                Some("blocks".to_string()),
+                None,
+                None,
+            )),
+            ability: "get".to_string(),
+            delegation,
+            caveats: Default::default(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        assert!(db.invoke::<MemoryStaging>(invocation, inputs).await.is_ok());
+        let value = get_kv_entity(&db.conn, &one, "key", None)
+            .await
+            .unwrap()
+            .unwrap()
+            .value;
+
+        let read = |cid: String| {
+            let (jwk, session) = (jwk.clone(), session.clone());
+            let one = one.clone();
+            async move {
+                let expiration =
+                    (OffsetDateTime::now_utc() + time::Duration::minutes(1)).unix_timestamp();
+                let ucan = make_invocation(
+                    vec![one.to_resource(
+                        Some("blocks".to_string()),
+                        Some(cid),
+                        Some("get".to_string()),
+                    )],
+                    delegation.to_cid(0x71),
+                    &jwk,
+                    session,
+                    expiration as f64,
+                    None,
+                    None,
                 )
-                .await?
+                .await
+                .unwrap();
+                Invocation::from_header_ser::<KeplerInvocation>(&ucan.encode().unwrap()).unwrap()
             }
-            Event::Revocation(r) => revocation::process(db, *r).await?,
         };
+
+        // content is read by its CID, with the metadata it was written with
+        let invocation = read(value.to_cid(0x55).to_string()).await;
+        match db.invoke::<MemoryStaging>(invocation, HashMap::new()).await {
+            Ok((_, outcomes)) => match &outcomes[..] {
+                [InvocationOutcome::KvRead(Some((md, content)))] => {
+                    assert_eq!(md, &Metadata(Default::default()));
+                    assert_eq!(content.len(), 5);
+                }
+                _ => panic!("unexpected outcomes"),
+            },
+            Err(e) => panic!("read failed: {e}"),
+        }
+
+        // content which isn't stored, and paths which aren't CIDs, are not found
+        for cid in [
+            crate::hash::hash(b"missing").to_cid(0x55).to_string(),
+            "key".to_string(),
+        ] {
+            let invocation = read(cid).await;
+            match db.invoke::<MemoryStaging>(invocation, HashMap::new()).await {
+                Ok((_, outcomes)) => {
+                    assert!(matches!(&outcomes[..], [InvocationOutcome::KvRead(None)]))
+                }
+                Err(e) => panic!("read failed: {e}"),
+            }
+        }
     }
 
-    for orbit in new_orbits {
-        store_setup
-            .create(&orbit.0)
+    #[test]
+    async fn storage_timeouts() {
+        let limit = Duration::from_millis(10);
+        assert_eq!(with_timeout(Some(limit), async { 1 }).await, Ok(1));
+        assert_eq!(
+            with_timeout(Some(limit), futures::future::pending::<()>()).await,
+            Err(StorageTimeout(limit))
+        );
+        assert_eq!(with_timeout(None, async { 1 }).await, Ok(1));
+    }
+
+    #[test]
+    async fn replay_protection() {
+        use crate::replay::ReplayCache;
+
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let other = OrbitId::new("example:alice".to_string(), "other".to_string());
+        for protection in [
+            None,
+            Some(ReplayProtection::Database),
+            Some(ReplayProtection::Memory(ReplayCache::new(16))),
+        ] {
+            let (db, jwk, session, delegation) = delegate_puts(&other, &[&one]).await;
+            let db = db.with_replay_protection(protection.clone());
+            let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+            let replayed =
+                crate::events::SerializedEvent(invocation.0.clone(), invocation.1.clone());
+            let (_, replayed_inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+            assert!(db.invoke::<MemoryStaging>(invocation, inputs).await.is_ok());
+
+            // without protection the replay is applied again as a retry, otherwise it is
+            // rejected
+            let res = db.invoke::<MemoryStaging>(replayed, replayed_inputs).await;
+            match protection {
+                None => assert!(res.is_ok()),
+                Some(_) => assert!(matches!(res, Err(TxStoreError::Replayed(_)))),
+            }
+        }
+    }
+
+    #[test]
+    async fn replay_claims() {
+        use crate::replay::ReplayCache;
+
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let cache = ReplayCache::new(16);
+        let (db, jwk, session, delegation) = delegate_puts(&two, &[&one, &two]).await;
+        let db = db.with_replay_protection(Some(ReplayProtection::Memory(cache.clone())));
+
+        // an invocation which isn't committed is forgotten, so it may be retried
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        let retried = crate::events::SerializedEvent(invocation.0.clone(), invocation.1.clone());
+        let (_, retried_inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        assert!(db
+            .invoke::<MemoryStaging>(invocation, inputs)
             .await
-            .map_err(TxError::StoreSetup)?;
-        secrets
-            .save_keypair(&orbit.0)
+            .is_err());
+        assert!(cache.is_empty());
+        assert!(!matches!(
+            db.invoke::<MemoryStaging>(retried, retried_inputs).await,
+            Err(TxStoreError::Replayed(_))
+        ));
+        assert!(cache.is_empty());
+
+        // nor is a dry run remembered
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        let options = InvokeOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        assert!(db
+            .invoke_with::<MemoryStaging>(invocation, inputs, options)
             .await
-            .map_err(TxError::Secrets)?;
+            .is_ok());
+        assert!(cache.is_empty());
+
+        // a committed invocation is
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one]).await;
+        assert!(db.invoke::<MemoryStaging>(invocation, inputs).await.is_ok());
+        assert_eq!(cache.len(), 1);
     }
 
-    Ok(orbit_order
-        .into_iter()
-        .map(|(o, (seq, rev, consumed_epochs, h))| {
-            (
-                o,
-                Commit {
-                    seq,
-                    rev,
-                    consumed_epochs,
-                    committed_events: h.keys().cloned().collect(),
+    #[test]
+    async fn dry_run() {
+        let one = OrbitId::new("example:alice".to_string(), "one".to_string());
+        let two = OrbitId::new("example:alice".to_string(), "two".to_string());
+        let (db, jwk, session, delegation) = delegate_puts(&two, &[&one, &two]).await;
+
+        // nothing is persisted, so the write which would fail doesn't either
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&one, &two]).await;
+        let outcomes = match db
+            .invoke_with::<MemoryStaging>(
+                invocation,
+                inputs,
+                InvokeOptions {
+                    dry_run: true,
+                    ..Default::default()
                 },
             )
-        })
-        .collect())
-}
+            .await
+        {
+            Ok((commits, outcomes)) => {
+                assert!(commits.values().all(|c| c.receipt.is_none()));
+                outcomes
+            }
+            Err(e) => panic!("dry run failed: {e}"),
+        };
+        assert!(matches!(
+            &outcomes[..],
+            [InvocationOutcome::KvWrite, InvocationOutcome::KvWrite]
+        ));
+        assert_unchanged(&db, &[&one, &two]).await;
+        assert_eq!(db.storage.inner.total_size(&one).await.unwrap(), None);
 
-async fn list<C: ConnectionTrait>(
-    db: &C,
-    orbit: &OrbitId,
-    prefix: &str,
-) -> Result<Vec<String>, DbErr> {
-    // get content id for key from db
-    let mut list = kv_write::Entity::find()
-        .filter(
-            Condition::all()
-                .add(kv_write::Column::Key.starts_with(prefix))
-                .add(kv_write::Column::Orbit.eq(OrbitIdWrap(orbit.clone()))),
-        )
-        .find_also_related(kv_delete::Entity)
-        .filter(kv_delete::Column::InvocationId.is_null())
-        .all(db)
-        .await?
-        .into_iter()
-        .map(|(kv, _)| kv.key)
-        .collect::<Vec<String>>();
-    list.dedup();
-    Ok(list)
-}
+        // an unauthorized invocation fails as it would otherwise
+        let carol = OrbitId::new("example:carol".to_string(), "default".to_string());
+        let (invocation, inputs) = put_invocation(&jwk, &session, delegation, &[&carol]).await;
+        assert!(db
+            .invoke_with::<MemoryStaging>(
+                invocation,
+                inputs,
+                InvokeOptions {
+                    dry_run: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .is_err());
+    }
 
-async fn metadata<C: ConnectionTrait>(
-    db: &C,
-    orbit: &OrbitId,
-    key: &str,
-    // TODO version: Option<(i64, Hash, i64)>,
-) -> Result<Option<Metadata>, DbErr> {
-    match get_kv_entity(db, orbit, key).await? {
-        Some(entry) => Ok(Some(entry.metadata)),
-        None => Ok(None),
+    #[test]
+    async fn granted_abilities() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        let parents = [delegate_to_bob(&db, &alice).await.to_cid(0x71)];
+        let now = OffsetDateTime::now_utc();
+
+        let granted = invocation::granted_to(&db.conn, "did:key:bob", &parents, now)
+            .await
+            .unwrap();
+        assert_eq!(granted.len(), 1);
+        assert_eq!(granted[0].ability, "get");
+        // a verification method of the delegatee holds its abilities too
+        assert_eq!(
+            invocation::granted_to(&db.conn, "did:key:bob#bob", &parents, now)
+                .await
+                .unwrap(),
+            granted
+        );
+        assert!(
+            invocation::granted_to(&db.conn, "did:key:carol", &parents, now)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // expired parents grant nothing
+        delegation::Entity::update_many()
+            .col_expr(
+                delegation::Column::Expiry,
+                sea_orm::sea_query::Expr::value(now - time::Duration::minutes(1)),
+            )
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        assert!(
+            invocation::granted_to(&db.conn, "did:key:bob", &parents, now)
+                .await
+                .unwrap()
+                .is_empty()
+        );
     }
-}
 
-async fn get_kv<C: ConnectionTrait, B: ImmutableReadStore>(
-    db: &C,
-    store: &B,
-    orbit: &OrbitId,
-    key: &str,
-    // TODO version: Option<(i64, Hash, i64)>,
-) -> Result<Option<(Metadata, Content<B::Readable>)>, EitherError<DbErr, B::Error>> {
-    let e = match get_kv_entity(db, orbit, key)
-        .await
-        .map_err(EitherError::A)?
-    {
-        Some(entry) => entry,
-        None => return Ok(None),
-    };
-    let c = match store.read(orbit, &e.value).await.map_err(EitherError::B)? {
-        Some(c) => c,
-        None => return Ok(None),
-    };
-    Ok(Some((e.metadata, c)))
-}
+    #[test]
+    async fn did_method_allowlist() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let bob = OrbitId::new("example:bob".to_string(), "default".to_string());
+        let pkh = "did:pkh:eip155:1:0xb9c5714089478a327f09197987f16f9e5d936e8a";
+        let key = "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK#z6MkhaXgBZ";
 
-async fn get_kv_entity<C: ConnectionTrait>(
-    db: &C,
-    orbit: &OrbitId,
-    key: &str,
-    // TODO version: Option<(i64, Hash, i64)>,
-) -> Result<Option<kv_write::Model>, DbErr> {
-    // Ok(if let Some((seq, epoch, epoch_seq)) = version {
-    //     event_order::Entity::find_by_id((epoch, epoch_seq, orbit.clone().into()))
-    //         .reverse_join(kv_write::Entity)
-    //         .find_also_related(kv_delete::Entity)
-    //         .filter(
-    //             Condition::all()
-    //                 .add(kv_write::Column::Key.eq(key))
-    //                 .add(kv_write::Column::Orbit.eq(orbit.clone().into()))
-    //                 .add(kv_delete::Column::InvocationId.is_null()),
-    //         )
-    //         .one(db)
-    //         .await?
-    //         .map(|(kv, _)| kv)
-    // } else {
-    // we want to find the latest kv_write which is not deleted
-    Ok(kv_write::Entity::find()
-        .filter(
-            Condition::all()
-                .add(kv_write::Column::Key.eq(key))
-                .add(kv_write::Column::Orbit.eq(OrbitIdWrap(orbit.clone()))),
-        )
-        .order_by_desc(kv_write::Column::Seq)
-        .order_by_desc(kv_write::Column::Epoch)
-        .order_by_desc(kv_write::Column::EpochSeq)
-        .find_also_related(kv_delete::Entity)
-        .filter(kv_delete::Column::InvocationId.is_null())
-        .one(db)
-        .await?
-        .map(|(kv, _)| kv))
-    // })
-}
+        // empty lists allow every method
+        let mut methods = MethodAllowlist::default();
+        assert!(methods.allows("did:web:example.com", [&alice]));
 
-async fn get_valid_delegations<C: ConnectionTrait, S: StorageSetup, K: Secrets>(
-    db: &C,
-    orbit: &OrbitId,
-) -> Result<HashMap<Hash, DelegationInfo>, TxError<S, K>> {
-    let (dels, abilities): (Vec<delegation::Model>, Vec<Vec<abilities::Model>>) =
-        delegation::Entity::find()
-            .left_join(revocation::Entity)
-            .filter(revocation::Column::Id.is_null())
-            .find_with_related(abilities::Entity)
-            .all(db)
-            .await?
-            .into_iter()
-            .unzip();
-    let parents = dels.load_many(parent_delegations::Entity, db).await?;
-    let now = time::OffsetDateTime::now_utc();
-    Ok(dels
-        .into_iter()
-        .zip(abilities)
-        .zip(parents)
-        .filter_map(|((del, ability), parents)| {
-            if del.expiry.map(|e| e > now).unwrap_or(true)
-                && del.not_before.map(|n| n <= now).unwrap_or(true)
-                && ability.iter().any(|a| a.resource.orbit() == Some(orbit))
-            {
-                Some(match KeplerDelegation::from_bytes(&del.serialization) {
-                    Ok(delegation) => Ok((
-                        del.id,
-                        DelegationInfo {
-                            delegator: del.delegator,
-                            delegate: del.delegatee,
-                            parents: parents.into_iter().map(|p| p.parent.to_cid(0x55)).collect(),
-                            expiry: del.expiry,
-                            not_before: del.not_before,
-                            issued_at: del.issued_at,
-                            capabilities: ability
-                                .into_iter()
-                                .map(|a| Capability {
-                                    resource: a.resource,
-                                    action: a.ability,
-                                })
-                                .collect(),
-                            delegation,
-                        },
-                    )),
-                    Err(e) => Err(e),
-                })
-            } else {
-                None
-            }
-        })
-        .collect::<Result<HashMap<Hash, DelegationInfo>, EncodingError>>()?)
-}
+        methods.global = ["key".to_string()].into();
+        assert!(methods.allows(key, [&alice]));
+        assert!(!methods.allows(pkh, [&alice]));
+        assert!(!methods.allows(pkh, []));
+        // a method name must match whole
+        assert!(!methods.allows("did:keys:z6Mkh", []));
 
-fn normalize_path(p: &str) -> &str {
-    if p.starts_with('/') {
-        p.get(1..).unwrap_or("")
-    } else {
-        p
+        // orbits may allow other methods than the global ones
+        methods
+            .orbits
+            .insert(bob.clone(), ["pkh:eip155".to_string()].into());
+        assert!(methods.allows(pkh, [&bob]));
+        assert!(!methods.allows(key, [&bob]));
+        assert!(!methods.allows("did:pkh:solana:abc", [&bob]));
+        // and events touching several orbits must be allowed in each
+        assert!(!methods.allows(pkh, [&alice, &bob]));
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use async_std::test;
+    #[test]
+    async fn replayed_events() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+        orbit::Entity::insert(orbit::ActiveModel::from(orbit_model(&alice)))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+
+        // two epochs, the second committing two events
+        let [first, second] = [b"epoch 0", b"epoch 1"].map(|e| crate::hash::hash(e));
+        let events = [b"event 0", b"event 1", b"event 2"].map(|e| crate::hash::hash(e));
+        for (seq, id) in [first, second].into_iter().enumerate() {
+            epoch::Entity::insert(epoch::ActiveModel::from(epoch::Model {
+                seq: seq as i64,
+                id,
+                orbit: alice.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        }
+        epoch_order::Entity::insert(epoch_order::ActiveModel::from(epoch_order::Model {
+            parent: first,
+            child: second,
+            orbit: alice.clone().into(),
+        }))
+        .exec(&db.conn)
+        .await
+        .unwrap();
+        for (seq, epoch, epoch_seq, event) in [
+            (0, first, 0, events[0]),
+            (1, second, 0, events[1]),
+            (1, second, 1, events[2]),
+        ] {
+            event_order::Entity::insert(event_order::ActiveModel::from(event_order::Model {
+                seq,
+                epoch,
+                epoch_seq,
+                event,
+                orbit: alice.clone().into(),
+            }))
+            .exec(&db.conn)
+            .await
+            .unwrap();
+        }
+
+        let unseen = crate::hash::hash(b"event 3");
+        assert!(committed_orderings(&db.conn, [unseen])
+            .await
+            .unwrap()
+            .is_empty());
+
+        // a retried event gets the commit which first applied it
+        let committed = committed_orderings(&db.conn, [events[2], unseen])
+            .await
+            .unwrap();
+        let commits = replayed_commits(&db.conn, &committed).await.unwrap();
+        let commit = &commits[&alice];
+        assert_eq!(commit.rev, second);
+        assert_eq!(commit.seq, 1);
+        assert_eq!(commit.committed_events, vec![events[1], events[2]]);
+        assert_eq!(commit.consumed_epochs, vec![first]);
 
-    async fn get_db(o: OrbitId) -> Result<OrbitDatabase, DbErr> {
-        OrbitDatabase::new("sqlite::memory:", o).await
+        let committed = committed_orderings(&db.conn, [events[0]]).await.unwrap();
+        let commits = replayed_commits(&db.conn, &committed).await.unwrap();
+        assert_eq!(commits[&alice].rev, first);
+        assert!(commits[&alice].consumed_epochs.is_empty());
     }
 
     #[test]
-    async fn basic() {
-        let db = get_db(OrbitId::new(
-            "example:alice".to_string(),
-            "default".to_string(),
-        ))
+    async fn orbit_aliases() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let bob = OrbitId::new("example:bob".to_string(), "default".to_string());
+        let db = get_db(alice.clone()).await.unwrap();
+
+        assert!(matches!(
+            db.set_alias("alice", &alice).await,
+            Err(AliasError::OrbitNotFound)
+        ));
+
+        orbit::Entity::insert_many(
+            [alice.clone(), bob.clone()].map(|id| orbit::ActiveModel::from(orbit_model(&id))),
+        )
+        .exec(&db.conn)
         .await
         .unwrap();
+
+        db.set_alias("alice", &alice).await.unwrap();
+        assert_eq!(
+            db.resolve_alias("alice").await.unwrap(),
+            Some(alice.clone())
+        );
+        assert_eq!(db.resolve_alias("bob").await.unwrap(), None);
+
+        // aliases are unique
+        assert!(matches!(
+            db.set_alias("alice", &bob).await,
+            Err(AliasError::Duplicate)
+        ));
+        assert_eq!(
+            db.resolve_alias("alice").await.unwrap(),
+            Some(alice.clone())
+        );
+
+        // and can't look like orbit ids
+        assert!(matches!(
+            db.set_alias(&bob.to_string(), &bob).await,
+            Err(AliasError::InvalidAlias)
+        ));
+        assert!(matches!(
+            db.set_alias("", &bob).await,
+            Err(AliasError::InvalidAlias)
+        ));
+
+        assert!(db.remove_alias("alice").await.unwrap());
+        assert!(!db.remove_alias("alice").await.unwrap());
+        db.set_alias("alice", &bob).await.unwrap();
+        assert_eq!(db.resolve_alias("alice").await.unwrap(), Some(bob));
     }
 }
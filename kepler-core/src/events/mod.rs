@@ -1,5 +1,5 @@
 use crate::{
-    hash::{hash, Hash},
+    hash::{hash, hash_with, Hash, HashAlgorithm},
     types::Metadata,
     util::{DelegationInfo, InvocationInfo, RevocationInfo},
 };
@@ -38,6 +38,17 @@ impl<T> SerializedEvent<T> {
             .map_err(FromReqErr::from)
             .and_then(|(i, s)| Ok(Self(T::try_from(i).map_err(FromReqErr::TryFrom)?, s)))
     }
+
+    /// Like [`SerializedEvent::from_header_ser`], but rejects non-canonical CBOR encodings.
+    pub fn from_header_ser_strict<I>(s: &str) -> Result<Self, FromReqErr<T::Error>>
+    where
+        T: TryFrom<I>,
+        I: HeaderEncode,
+    {
+        I::decode_strict(s)
+            .map_err(FromReqErr::from)
+            .and_then(|(i, s)| Ok(Self(T::try_from(i).map_err(FromReqErr::TryFrom)?, s)))
+    }
 }
 
 pub type Delegation = SerializedEvent<DelegationInfo>;
@@ -51,6 +62,8 @@ pub(crate) enum Operation {
         key: String,
         value: Hash,
         metadata: Metadata,
+        /// Length of the content in bytes.
+        size: u64,
     },
     KvDelete {
         orbit: OrbitId,
@@ -67,11 +80,13 @@ impl Operation {
                 key,
                 value,
                 metadata,
+                size,
             } => VersionedOperation::KvWrite {
                 orbit,
                 key,
                 value,
                 metadata,
+                size,
                 seq,
                 epoch,
                 epoch_seq,
@@ -103,6 +118,7 @@ pub(crate) enum VersionedOperation {
         key: String,
         value: Hash,
         metadata: Metadata,
+        size: u64,
         seq: i64,
         epoch: Hash,
         epoch_seq: i64,
@@ -153,30 +169,42 @@ pub enum HashError {
     HashError(#[from] MultihashError),
 }
 
+/// Hash of an epoch of an orbit, and of the operations its invocations make, with the
+/// orbit's event hashing algorithm. Events themselves are hashed with the default
+/// algorithm, as they may belong to several orbits.
 pub(crate) fn epoch_hash(
     orbit: &OrbitId,
     events: &[&(Hash, Event)],
     parents: &[Hash],
+    algorithm: HashAlgorithm,
 ) -> Result<Hash, HashError> {
-    Ok(hash(&serde_ipld_dagcbor::to_vec(&Epoch {
-        parents: parents.iter().map(|h| h.to_cid(0x71)).collect(),
-        events: events
-            .iter()
-            .map(|(h, e)| {
-                Ok(match e {
-                    Event::Invocation(_, ops) => hash_inv(h, orbit, ops)?,
-                    Event::Delegation(_) => OneOrMany::One(h.to_cid(RAW_CODEC)),
-                    Event::Revocation(_) => OneOrMany::One(h.to_cid(RAW_CODEC)),
+    Ok(hash_with(
+        algorithm,
+        &serde_ipld_dagcbor::to_vec(&Epoch {
+            parents: parents.iter().map(|h| h.to_cid(0x71)).collect(),
+            events: events
+                .iter()
+                .map(|(h, e)| {
+                    Ok(match e {
+                        Event::Invocation(_, ops) => hash_inv(h, orbit, ops, algorithm)?,
+                        Event::Delegation(_) => OneOrMany::One(h.to_cid(RAW_CODEC)),
+                        Event::Revocation(_) => OneOrMany::One(h.to_cid(RAW_CODEC)),
+                    })
                 })
-            })
-            .collect::<Result<Vec<OneOrMany>, HashError>>()?,
-    })?))
+                .collect::<Result<Vec<OneOrMany>, HashError>>()?,
+        })?,
+    ))
 }
 
 const CBOR_CODEC: u64 = 0x71;
 const RAW_CODEC: u64 = 0x55;
 
-fn hash_inv(inv_hash: &Hash, o: &OrbitId, ops: &[Operation]) -> Result<OneOrMany, HashError> {
+fn hash_inv(
+    inv_hash: &Hash,
+    o: &OrbitId,
+    ops: &[Operation],
+    algorithm: HashAlgorithm,
+) -> Result<OneOrMany, HashError> {
     #[derive(Debug, Serialize)]
     #[serde(untagged)]
     enum Op<'a> {
@@ -199,6 +227,7 @@ fn hash_inv(inv_hash: &Hash, o: &OrbitId, ops: &[Operation]) -> Result<OneOrMany
                 key,
                 value,
                 metadata,
+                ..
             } if orbit == o => Some(Op::KvWrite {
                 key,
                 value: value.to_cid(CBOR_CODEC),
@@ -214,7 +243,7 @@ fn hash_inv(inv_hash: &Hash, o: &OrbitId, ops: &[Operation]) -> Result<OneOrMany
             }),
             _ => None,
         })
-        .map(|op| Ok(hash(&serde_ipld_dagcbor::to_vec(&op)?).to_cid(CBOR_CODEC)))
+        .map(|op| Ok(hash_with(algorithm, &serde_ipld_dagcbor::to_vec(&op)?).to_cid(CBOR_CODEC)))
         .collect::<Result<Vec<_>, HashError>>()?;
 
     Ok(if ops.is_empty() {
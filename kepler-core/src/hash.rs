@@ -1,29 +1,89 @@
 use kepler_lib::libipld::cid::{
-    multihash::{Blake3_256, Code, Hasher as MHasher, Multihash, MultihashDigest},
+    multihash::{Blake3_256, Code, Hasher as MHasher, Multihash, MultihashDigest, Sha2_256},
     Cid,
 };
 use sea_orm::entity::prelude::*;
 use sea_orm::DbErr;
+use serde::{Deserialize, Serialize};
 
 pub fn hash(data: &[u8]) -> Hash {
     Hasher::new().update(data).finalize()
 }
 
-#[derive(Debug, Default)]
-pub struct Hasher(Blake3_256);
+pub fn hash_with(algorithm: HashAlgorithm, data: &[u8]) -> Hash {
+    Hasher::with_algorithm(algorithm).update(data).finalize()
+}
+
+/// Multihash algorithm which an orbit's content is addressed with.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    DeriveActiveEnum,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+pub enum HashAlgorithm {
+    #[default]
+    #[serde(rename = "blake3-256")]
+    #[sea_orm(string_value = "blake3-256")]
+    Blake3_256,
+    #[serde(rename = "sha2-256")]
+    #[sea_orm(string_value = "sha2-256")]
+    Sha2_256,
+}
+
+#[derive(Debug)]
+enum HasherState {
+    Blake3_256(Blake3_256),
+    Sha2_256(Sha2_256),
+}
+
+#[derive(Debug)]
+pub struct Hasher(HasherState);
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Hasher {
     pub fn new() -> Self {
-        Self(Blake3_256::default())
+        Self::with_algorithm(HashAlgorithm::default())
+    }
+
+    pub fn with_algorithm(algorithm: HashAlgorithm) -> Self {
+        Self(match algorithm {
+            HashAlgorithm::Blake3_256 => HasherState::Blake3_256(Blake3_256::default()),
+            HashAlgorithm::Sha2_256 => HasherState::Sha2_256(Sha2_256::default()),
+        })
     }
 
     pub fn update(&mut self, data: &[u8]) -> &mut Self {
-        self.0.update(data);
+        match &mut self.0 {
+            HasherState::Blake3_256(h) => h.update(data),
+            HasherState::Sha2_256(h) => h.update(data),
+        };
         self
     }
 
     pub fn finalize(&mut self) -> Hash {
-        Hash(Code::Blake3_256.wrap(self.0.finalize()).unwrap())
+        Hash(
+            match &mut self.0 {
+                HasherState::Blake3_256(h) => Code::Blake3_256.wrap(h.finalize()),
+                HasherState::Sha2_256(h) => Code::Sha2_256.wrap(h.finalize()),
+            }
+            .unwrap(),
+        )
     }
 }
 
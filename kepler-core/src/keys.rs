@@ -34,9 +34,14 @@ pub trait Secrets {
     }
     async fn stage_keypair(&self, orbit: &OrbitId) -> Result<PublicKey, Self::Error>;
     async fn save_keypair(&self, orbit: &OrbitId) -> Result<(), Self::Error>;
+    /// Remove the stored keypair for an orbit, returning whether there was one to remove.
+    async fn remove_keypair(&self, orbit: &OrbitId) -> Result<bool, Self::Error>;
     async fn get_peer_id(&self, orbit: &OrbitId) -> Result<PeerId, Self::Error> {
         Ok(self.get_pubkey(orbit).await?.to_peer_id())
     }
+    /// The key encrypting an orbit's content at rest, which only the backend's master
+    /// secret recovers.
+    async fn get_data_key(&self, orbit: &OrbitId) -> Result<[u8; 32], Self::Error>;
 }
 
 #[async_trait]
@@ -78,6 +83,20 @@ impl Secrets for StaticSecret {
     async fn save_keypair(&self, _orbit: &OrbitId) -> Result<(), Self::Error> {
         Ok(())
     }
+    async fn remove_keypair(&self, _orbit: &OrbitId) -> Result<bool, Self::Error> {
+        // keys are derived from the secret, nothing is stored per orbit
+        Ok(false)
+    }
+    async fn get_data_key(&self, orbit: &OrbitId) -> Result<[u8; 32], Self::Error> {
+        // orbit ids never start with the label, so this can't be an orbit's keypair
+        let mut hasher = Blake3_256::default();
+        hasher.update(&self.secret);
+        hasher.update(b"data key:");
+        hasher.update(orbit.to_string().as_bytes());
+        let mut key = [0; 32];
+        key.copy_from_slice(hasher.finalize());
+        Ok(key)
+    }
 }
 
 #[async_trait]
@@ -89,3 +108,22 @@ impl SecretsSetup for StaticSecret {
         Ok(self.clone())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::test;
+    use kepler_lib::peer::peer_id_from_did_key;
+
+    #[test]
+    async fn peer_ids() {
+        let secrets = StaticSecret::new(vec![0; 32]).unwrap();
+        let orbit = OrbitId::new("example:alice".to_string(), "default".to_string());
+        // the did:key returned by `/peer/generate` gives the orbit's peer ID offline
+        let did = get_did_key(secrets.stage_keypair(&orbit).await.unwrap());
+        assert_eq!(
+            peer_id_from_did_key(&did).unwrap(),
+            secrets.get_peer_id(&orbit).await.unwrap().to_string()
+        );
+    }
+}
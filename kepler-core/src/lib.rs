@@ -5,12 +5,21 @@ pub mod keys;
 pub mod manifest;
 pub mod migrations;
 pub mod models;
+pub mod pointer;
+pub mod receipt;
 pub mod relationships;
+pub mod replay;
+pub mod resolver;
 pub mod storage;
 pub mod types;
 pub mod util;
 
-pub use db::{Commit, InvocationOutcome, OrbitDatabase, TxError, TxStoreError};
+pub use db::{
+    AliasError, Commit, CompactOutcome, DelegationRecord, EventKind, EventRecord,
+    InvocationOutcome, InvocationRecord, InvokeOptions, KeyStream, KvChange, KvVersion,
+    OrbitDatabase, OrbitHeads, OrbitInfo, PurgeError, PurgeOutcome, SessionsQuery, TxError,
+    TxStoreError, Version,
+};
 pub use libp2p;
 pub use sea_orm;
 pub use sea_orm_migration;
@@ -0,0 +1,38 @@
+use crate::models::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // databases created after the column was added to the entity already have it
+        if manager.has_column("kv_write", "expiry").await? {
+            return Ok(());
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(kv_write::Entity)
+                    .add_column(
+                        ColumnDef::new(kv_write::Column::Expiry)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(kv_write::Entity)
+                    .drop_column(kv_write::Column::Expiry)
+                    .to_owned(),
+            )
+            .await
+    }
+}
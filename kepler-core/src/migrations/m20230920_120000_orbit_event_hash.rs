@@ -0,0 +1,41 @@
+use crate::models::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // databases created after the column was added to the entity already have it
+        if manager.has_column("orbit", "event_hash").await? {
+            return Ok(());
+        }
+        // orbits created before then hash their epochs with blake3, whatever their content
+        // is addressed with
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(orbit::Entity)
+                    .add_column(
+                        ColumnDef::new(orbit::Column::EventHash)
+                            .string_len(16)
+                            .not_null()
+                            .default("blake3-256"),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(orbit::Entity)
+                    .drop_column(orbit::Column::EventHash)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,35 @@
+use crate::models::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // databases created after the column was added to the entity already have it
+        if manager.has_column("orbit", "storage_limit").await? {
+            return Ok(());
+        }
+        // orbits created before then have no limit of their own
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(orbit::Entity)
+                    .add_column(ColumnDef::new(orbit::Column::StorageLimit).big_integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(orbit::Entity)
+                    .drop_column(orbit::Column::StorageLimit)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,35 @@
+use crate::models::*;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // databases created after the column was added to the entity already have it
+        if manager.has_column("orbit", "created_at").await? {
+            return Ok(());
+        }
+        // the creation time of orbits created before then is unknown
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(orbit::Entity)
+                    .add_column(ColumnDef::new(orbit::Column::CreatedAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(orbit::Entity)
+                    .drop_column(orbit::Column::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
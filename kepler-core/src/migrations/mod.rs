@@ -1,11 +1,33 @@
 use sea_orm_migration::prelude::*;
 pub mod m20230510_101010_init_tables;
+pub mod m20230901_120000_orbit_features;
+pub mod m20230905_120000_orbit_aliases;
+pub mod m20230910_120000_kv_expiry;
+pub mod m20230915_120000_orbit_hash;
+pub mod m20230920_120000_orbit_event_hash;
+pub mod m20230925_120000_orbit_storage_limit;
+pub mod m20230930_120000_receipts;
+pub mod m20231005_120000_orbit_created;
+pub mod m20231010_120000_orbit_pointers;
+pub mod m20231015_120000_purge_invocations;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20230510_101010_init_tables::Migration)]
+        vec![
+            Box::new(m20230510_101010_init_tables::Migration),
+            Box::new(m20230901_120000_orbit_features::Migration),
+            Box::new(m20230905_120000_orbit_aliases::Migration),
+            Box::new(m20230910_120000_kv_expiry::Migration),
+            Box::new(m20230915_120000_orbit_hash::Migration),
+            Box::new(m20230920_120000_orbit_event_hash::Migration),
+            Box::new(m20230925_120000_orbit_storage_limit::Migration),
+            Box::new(m20230930_120000_receipts::Migration),
+            Box::new(m20231005_120000_orbit_created::Migration),
+            Box::new(m20231010_120000_orbit_pointers::Migration),
+            Box::new(m20231015_120000_purge_invocations::Migration),
+        ]
     }
 }
@@ -1,10 +1,15 @@
 use crate::hash::Hash;
+use crate::resolver::DidResolver;
 use crate::types::{Facts, Resource};
 use crate::{events::Delegation, models::*, relationships::*, util};
-use kepler_lib::{authorization::KeplerDelegation, resolver::DID_METHODS};
+use kepler_lib::{authorization::KeplerDelegation, libipld::Cid};
 use sea_orm::{entity::prelude::*, sea_query::OnConflict, ConnectionTrait};
+use std::collections::HashSet;
 use time::OffsetDateTime;
 
+/// Default limit on the length of delegation chains, counting the root delegation.
+pub const MAX_DEPTH: usize = 32;
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
 #[sea_orm(table_name = "delegation")]
 pub struct Model {
@@ -115,49 +120,122 @@ pub enum DelegationError {
     UnauthorizedCapability(Resource, String),
     #[error("Cannot find parent delegation")]
     MissingParents,
+    #[error("DID method not allowed: {0}")]
+    UnauthorizedMethod(String),
+    #[error("Delegation chain is longer than the limit of {0}")]
+    ChainTooDeep(usize),
 }
 
 pub(crate) async fn process<C: ConnectionTrait>(
     db: &C,
+    resolver: &DidResolver,
+    max_depth: usize,
     delegation: Delegation,
 ) -> Result<Hash, Error> {
     let (d, ser) = (delegation.0, delegation.1);
-    verify(&d.delegation).await?;
+    verify(&d.delegation, resolver).await?;
 
-    validate(db, &d).await?;
+    validate(db, &d, max_depth).await?;
 
     save(db, d, ser).await
 }
 
 // verify signatures and time
-async fn verify(delegation: &KeplerDelegation) -> Result<(), Error> {
-    match delegation {
-        KeplerDelegation::Ucan(ref ucan) => {
-            ucan.verify_signature(DID_METHODS.to_resolver())
-                .await
-                .map_err(|_| DelegationError::InvalidSignature)?;
-            ucan.payload
-                .validate_time(None)
-                .map_err(|_| DelegationError::InvalidTime)?;
-        }
+#[tracing::instrument(skip_all)]
+async fn verify(delegation: &KeplerDelegation, resolver: &DidResolver) -> Result<(), Error> {
+    match verify_all(delegation, resolver).await.into_iter().next() {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+// every signature and time failure
+async fn verify_all(delegation: &KeplerDelegation, resolver: &DidResolver) -> Vec<DelegationError> {
+    let (signed, timely) = match delegation {
+        KeplerDelegation::Ucan(ref ucan) => (
+            ucan.verify_signature(resolver).await.is_ok(),
+            ucan.payload.validate_time(None).is_ok(),
+        ),
         KeplerDelegation::Cacao(ref cacao) => {
-            cacao
-                .verify()
-                .await
-                .map_err(|_| DelegationError::InvalidSignature)?;
-            if !cacao.payload().valid_now() {
-                return Err(DelegationError::InvalidTime)?;
-            }
+            (cacao.verify().await.is_ok(), cacao.payload().valid_now())
         }
     };
-    Ok(())
+    let mut failures = Vec::new();
+    if !signed {
+        failures.push(DelegationError::InvalidSignature);
+    }
+    if !timely {
+        failures.push(DelegationError::InvalidTime);
+    }
+    failures
 }
 
 // verify parenthood and authorization
+#[tracing::instrument(skip_all)]
 async fn validate<C: ConnectionTrait>(
     db: &C,
     delegation: &util::DelegationInfo,
+    max_depth: usize,
 ) -> Result<(), Error> {
+    match authorization_failures(db, delegation, max_depth)
+        .await?
+        .into_iter()
+        .next()
+    {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Check a delegation without saving it, returning every reason it is invalid rather
+/// than only the first.
+pub(crate) async fn check<C: ConnectionTrait>(
+    db: &C,
+    resolver: &DidResolver,
+    max_depth: usize,
+    delegation: &Delegation,
+) -> Result<Vec<DelegationError>, DbErr> {
+    let mut failures = verify_all(&delegation.0.delegation, resolver).await;
+    failures.extend(authorization_failures(db, &delegation.0, max_depth).await?);
+    Ok(failures)
+}
+
+/// Whether a delegation with `parents` would end a chain of more than `max_depth`
+/// delegations. Ancestors are found a generation at a time, so however long the chain is,
+/// it is rejected after at most `max_depth` queries.
+pub(crate) async fn exceeds_depth<C: ConnectionTrait>(
+    db: &C,
+    parents: &[Cid],
+    max_depth: usize,
+) -> Result<bool, DbErr> {
+    let mut generation: HashSet<Hash> = parents.iter().map(|c| Hash::from(*c)).collect();
+    // the delegation itself is the first link of the chain, its parents the second
+    for _ in 1..max_depth {
+        if generation.is_empty() {
+            return Ok(false);
+        }
+        generation = parent_delegations::Entity::find()
+            .filter(parent_delegations::Column::Child.is_in(generation))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|p| p.parent)
+            .collect();
+    }
+    Ok(!generation.is_empty())
+}
+
+// every parenthood and authorization failure
+async fn authorization_failures<C: ConnectionTrait>(
+    db: &C,
+    delegation: &util::DelegationInfo,
+    max_depth: usize,
+) -> Result<Vec<DelegationError>, DbErr> {
+    // the rest of the chain isn't worth checking if it is too long
+    if exceeds_depth(db, &delegation.parents, max_depth).await? {
+        return Ok(vec![DelegationError::ChainTooDeep(max_depth)]);
+    }
+
     // get caps which rely on delegated caps
     let dependant_caps: Vec<_> = delegation
         .capabilities
@@ -166,16 +244,16 @@ async fn validate<C: ConnectionTrait>(
             // remove caps for which the delegator is the root authority
             c.resource
                 .orbit()
-                .map(|o| o.did() != delegation.delegator)
+                .map(|o| !util::is_controller(o, &delegation.delegator))
                 .unwrap_or(true)
         })
         .collect();
 
     match (dependant_caps.is_empty(), delegation.parents.is_empty()) {
         // no dependant caps, no parents needed, must be valid
-        (true, _) => Ok(()),
+        (true, _) => Ok(Vec::new()),
         // dependant caps, no parents, invalid
-        (false, true) => Err(DelegationError::MissingParents.into()),
+        (false, true) => Ok(vec![DelegationError::MissingParents]),
         // dependant caps, parents, check parents
         (false, false) => {
             // get parents which have
@@ -200,19 +278,18 @@ async fn validate<C: ConnectionTrait>(
             let parent_abilities = parents.load_many(abilities::Entity, db).await?;
 
             // check each dependant cap is supported by at least one parent cap
-            match dependant_caps.iter().find(|c| {
-                !parent_abilities
-                    .iter()
-                    .flatten()
-                    .any(|pc| c.resource.extends(&pc.resource) && c.action == pc.ability)
-            }) {
-                Some(c) => Err(DelegationError::UnauthorizedCapability(
-                    c.resource.clone(),
-                    c.action.clone(),
-                )
-                .into()),
-                None => Ok(()),
-            }
+            Ok(dependant_caps
+                .iter()
+                .filter(|c| {
+                    !parent_abilities.iter().flatten().any(|pc| {
+                        c.resource.extends(&pc.resource)
+                            && invocation::covers(&pc.ability, &c.action)
+                    })
+                })
+                .map(|c| {
+                    DelegationError::UnauthorizedCapability(c.resource.clone(), c.action.clone())
+                })
+                .collect())
         }
     }
 }
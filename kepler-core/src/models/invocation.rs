@@ -4,9 +4,11 @@ use super::super::{
     relationships::*,
     util,
 };
+use crate::db::normalize_path;
 use crate::hash::Hash;
-use crate::types::{Facts, OrbitIdWrap, Resource};
-use kepler_lib::{authorization::KeplerInvocation, resolver::DID_METHODS};
+use crate::resolver::DidResolver;
+use crate::types::{Caveats, Facts, OrbitIdWrap, Resource};
+use kepler_lib::{authorization::KeplerInvocation, libipld::Cid};
 use sea_orm::{entity::prelude::*, sea_query::OnConflict, Condition, ConnectionTrait, QueryOrder};
 use time::OffsetDateTime;
 
@@ -67,120 +69,264 @@ pub enum InvocationError {
     UnauthorizedInvoker(String),
     #[error("Unauthorized Action: {0} / {1}")]
     UnauthorizedAction(Resource, String),
+    #[error("Delegation of {0} / {1} expired or not yet valid")]
+    InvalidParentTime(Resource, String),
     #[error("Cannot find parent delegation")]
     MissingParents,
     #[error("No Such Key: {0}")]
     MissingKvWrite(String),
+    #[error("DID method not allowed: {0}")]
+    UnauthorizedMethod(String),
 }
 
 pub(crate) async fn process<C: ConnectionTrait>(
     db: &C,
+    resolver: &DidResolver,
     invocation: Invocation,
     ops: Vec<VersionedOperation>,
 ) -> Result<Hash, Error> {
     let (i, serialized) = (invocation.0, invocation.1);
-    verify(&i.invocation).await?;
+    verify(&i.invocation, resolver).await?;
 
     let now = OffsetDateTime::now_utc();
-    validate(db, &i, Some(now)).await?;
+    validate(db, &i, Some(now), &ops).await?;
 
     save(db, i, Some(now), serialized, ops).await
 }
 
-async fn verify(invocation: &KeplerInvocation) -> Result<(), Error> {
-    invocation
-        .verify_signature(DID_METHODS.to_resolver())
-        .await
-        .map_err(|_| InvocationError::InvalidSignature)?;
-    invocation
-        .payload
-        .validate_time(None)
-        .map_err(|_| InvocationError::InvalidTime)?;
-    Ok(())
+#[tracing::instrument(skip_all)]
+async fn verify(invocation: &KeplerInvocation, resolver: &DidResolver) -> Result<(), Error> {
+    match verify_all(invocation, resolver).await.into_iter().next() {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+// every signature and time failure
+async fn verify_all(invocation: &KeplerInvocation, resolver: &DidResolver) -> Vec<InvocationError> {
+    let mut failures = Vec::new();
+    if invocation.verify_signature(resolver).await.is_err() {
+        failures.push(InvocationError::InvalidSignature);
+    }
+    if invocation.payload.validate_time(None).is_err() {
+        failures.push(InvocationError::InvalidTime);
+    }
+    failures
 }
 
 // verify parenthood and authorization
+#[tracing::instrument(skip_all)]
 async fn validate<C: ConnectionTrait>(
     db: &C,
     invocation: &util::InvocationInfo,
     time: Option<OffsetDateTime>,
+    ops: &[VersionedOperation],
 ) -> Result<(), Error> {
+    match authorization_failures(
+        db,
+        &invocation.invoker,
+        &invocation.capabilities,
+        &invocation.parents,
+        time,
+        |c| write_size(ops, c),
+    )
+    .await?
+    .into_iter()
+    .next()
+    {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Check an invocation without saving it, returning every reason it is invalid rather
+/// than only the first.
+pub(crate) async fn check<C: ConnectionTrait>(
+    db: &C,
+    resolver: &DidResolver,
+    invocation: &Invocation,
+) -> Result<Vec<InvocationError>, DbErr> {
+    let i = &invocation.0;
+    let mut failures = verify_all(&i.invocation, resolver).await;
+    failures.extend(
+        authorization_failures(
+            db,
+            &i.invoker,
+            &i.capabilities,
+            &i.parents,
+            Some(OffsetDateTime::now_utc()),
+            // the content isn't known, so size caveats can't be checked
+            |_| None,
+        )
+        .await?,
+    );
+    Ok(failures)
+}
+
+// the size of the content written by a `kv/put` capability
+fn write_size(ops: &[VersionedOperation], cap: &util::Capability) -> Option<u64> {
+    let r = cap.resource.kepler_resource()?;
+    let path = normalize_path(r.path()?)?;
+    ops.iter().find_map(|op| match op {
+        VersionedOperation::KvWrite {
+            orbit, key, size, ..
+        } if cap.action == "put" && orbit == r.orbit() && *key == path => Some(*size),
+        _ => None,
+    })
+}
+
+/// Whether the caveats of a granted ability permit invoking it at `time`, writing `size`
+/// bytes of content if it is a write.
+///
+/// `maxSize` limits the size of written content in bytes, and `validUntil` the unix time
+/// the ability may be invoked until. Other caveats are not evaluated, and a size caveat
+/// is satisfied if the size is not known.
+pub(crate) fn permits(caveats: &Caveats, time: OffsetDateTime, size: Option<u64>) -> bool {
+    caveats
+        .0
+        .iter()
+        .all(|(name, value)| match (name.as_str(), value.as_u64()) {
+            ("maxSize", Some(max)) => size.map_or(true, |s| s <= max),
+            ("validUntil", Some(until)) => {
+                i64::try_from(until).map_or(true, |until| time.unix_timestamp() <= until)
+            }
+            // malformed numeric caveats permit nothing
+            ("maxSize" | "validUntil", None) => false,
+            _ => true,
+        })
+}
+
+// every parenthood and authorization failure, in the order they are checked
+pub(crate) async fn authorization_failures<C: ConnectionTrait>(
+    db: &C,
+    invoker: &str,
+    capabilities: &[util::Capability],
+    parents: &[Cid],
+    time: Option<OffsetDateTime>,
+    size: impl Fn(&util::Capability) -> Option<u64>,
+) -> Result<Vec<InvocationError>, DbErr> {
     // get caps which rely on delegated caps
-    let dependant_caps: Vec<_> = invocation
-        .capabilities
+    let dependant_caps: Vec<_> = capabilities
         .iter()
         .filter(|c| {
             // remove caps for which the invoker is the root authority
             c.resource
                 .orbit()
-                .map(|o| o.did() != invocation.invoker)
+                .map(|o| !util::is_controller(o, invoker))
                 .unwrap_or(true)
         })
         .collect();
 
-    match (dependant_caps.is_empty(), invocation.parents.is_empty()) {
+    match (dependant_caps.is_empty(), parents.is_empty()) {
         // no dependant caps, no parents needed, must be valid
-        (true, _) => Ok(()),
+        (true, _) => Ok(Vec::new()),
         // dependant caps, no parents, invalid
-        (false, true) => Err(InvocationError::MissingParents.into()),
+        (false, true) => Ok(vec![InvocationError::MissingParents]),
         // dependant caps, parents, check parents
         (false, false) => {
-            // get parents which have
-            let parents = delegation::Entity::find()
-                // the correct id
-                .filter(
-                    delegation::Column::Id.is_in(invocation.parents.iter().map(|c| Hash::from(*c))),
-                )
-                // and also get their abilities
-                .find_with_related(abilities::Entity)
-                .all(db)
-                .await?;
+            let parents = find_parents(db, parents).await?;
 
-            // check parent identifies correct invoker
-            parents
+            // check each parent identifies the correct invoker
+            let mut failures: Vec<_> = parents
                 .iter()
-                .map(|(p, _)| {
-                    if p.delegatee != invocation.invoker
-                        && !invocation.invoker.starts_with(&p.delegatee)
-                    {
-                        Err(InvocationError::UnauthorizedInvoker(
-                            invocation.invoker.clone(),
-                        ))
-                    } else {
-                        Ok(())
-                    }
-                })
-                .collect::<Result<Vec<_>, _>>()?;
+                .filter(|(p, _)| !is_delegated_to(p, invoker))
+                .map(|_| InvocationError::UnauthorizedInvoker(invoker.to_string()))
+                .collect();
 
+            // the server's clock, not the time the invocation claims, decides whether
+            // parents have expired
             let now = time.unwrap_or_else(OffsetDateTime::now_utc);
 
             // only use parents which are valid at the time of invocation
-            let parents: Vec<_> = parents
-                .into_iter()
-                .filter(|(p, _)| {
-                    p.expiry.map(|pexp| now < pexp).unwrap_or(true)
-                        && p.not_before.map(|pnbf| now >= pnbf).unwrap_or(true)
+            let (parents, invalid): (Vec<_>, Vec<_>) =
+                parents.into_iter().partition(|(p, _)| is_valid_at(p, now));
+            let supports = |parents: &[(delegation::Model, Vec<abilities::Model>)],
+                            c: &util::Capability| {
+                let size = size(c);
+                parents.iter().flat_map(|(_, a)| a).any(|pc| {
+                    c.resource.extends(&pc.resource)
+                        && covers(&pc.ability, &c.action)
+                        && permits(&pc.caveats, now, size)
                 })
-                .collect();
+            };
 
             // check each dependant cap is supported by at least one parent cap
-            match dependant_caps.iter().find(|c| {
-                !parents
+            failures.extend(
+                dependant_caps
                     .iter()
-                    .flat_map(|(_, a)| a)
-                    .any(|pc| c.resource.extends(&pc.resource) && c.action == pc.ability)
-            }) {
-                Some(c) => Err(InvocationError::UnauthorizedAction(
-                    c.resource.clone(),
-                    c.action.clone(),
-                )
-                .into()),
-                None => Ok(()),
-            }
+                    .filter(|c| !supports(&parents, c))
+                    .map(|c| {
+                        let (r, a) = (c.resource.clone(), c.action.clone());
+                        // distinguish caps which only an expired parent supports
+                        if supports(&invalid, c) {
+                            InvocationError::InvalidParentTime(r, a)
+                        } else {
+                            InvocationError::UnauthorizedAction(r, a)
+                        }
+                    }),
+            );
+            Ok(failures)
         }
     }
 }
 
+// whether a granted ability authorizes invoking an action. Checking that a key exists or
+// listing its versions reveals no more than reading it or its metadata, so those abilities
+// cover `exists` and `versions`
+pub(crate) fn covers(ability: &str, action: &str) -> bool {
+    ability == action
+        || (matches!(action, "exists" | "versions") && matches!(ability, "get" | "metadata"))
+}
+
+// get the parent delegations with the given ids, and their abilities
+async fn find_parents<C: ConnectionTrait>(
+    db: &C,
+    parents: &[Cid],
+) -> Result<Vec<(delegation::Model, Vec<abilities::Model>)>, DbErr> {
+    delegation::Entity::find()
+        .filter(delegation::Column::Id.is_in(parents.iter().map(|c| Hash::from(*c))))
+        .find_with_related(abilities::Entity)
+        .all(db)
+        .await
+}
+
+fn is_delegated_to(delegation: &delegation::Model, invoker: &str) -> bool {
+    delegation.delegatee == invoker || invoker.starts_with(&delegation.delegatee)
+}
+
+fn is_valid_at(delegation: &delegation::Model, time: OffsetDateTime) -> bool {
+    delegation.expiry.map(|exp| time < exp).unwrap_or(true)
+        && delegation.not_before.map(|nbf| time >= nbf).unwrap_or(true)
+}
+
+/// The abilities held by the signer of an invocation through its parent delegations, i.e.
+/// those which its invocations are authorized by. Parents which are expired, not yet valid
+/// or delegated to someone else grant nothing.
+pub(crate) async fn granted<C: ConnectionTrait>(
+    db: &C,
+    resolver: &DidResolver,
+    invocation: &Invocation,
+) -> Result<Vec<abilities::Model>, Error> {
+    let i = &invocation.0;
+    verify(&i.invocation, resolver).await?;
+    Ok(granted_to(db, &i.invoker, &i.parents, OffsetDateTime::now_utc()).await?)
+}
+
+// the abilities granted to `invoker` by `parents` at `time`
+pub(crate) async fn granted_to<C: ConnectionTrait>(
+    db: &C,
+    invoker: &str,
+    parents: &[Cid],
+    time: OffsetDateTime,
+) -> Result<Vec<abilities::Model>, DbErr> {
+    Ok(find_parents(db, parents)
+        .await?
+        .into_iter()
+        .filter(|(p, _)| is_delegated_to(p, invoker) && is_valid_at(p, time))
+        .flat_map(|(_, abilities)| abilities)
+        .collect())
+}
+
 async fn save<C: ConnectionTrait>(
     db: &C,
     invocation: util::InvocationInfo,
@@ -242,12 +388,14 @@ async fn save<C: ConnectionTrait>(
                 seq,
                 epoch,
                 epoch_seq,
+                ..
             } => {
                 kv_write::Entity::insert(kv_write::ActiveModel::from(kv_write::Model {
                     invocation: hash,
                     key,
                     value,
                     orbit: orbit.into(),
+                    expiry: metadata.expiry(),
                     metadata,
                     seq,
                     epoch,
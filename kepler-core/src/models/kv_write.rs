@@ -2,6 +2,7 @@ use crate::hash::Hash;
 use crate::types::{Metadata, OrbitIdWrap};
 use crate::{models::*, relationships::*};
 use sea_orm::entity::prelude::*;
+use time::OffsetDateTime;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
 #[sea_orm(table_name = "kv_write")]
@@ -17,6 +18,8 @@ pub struct Model {
     pub epoch_seq: i64,
     pub value: Hash,
     pub metadata: Metadata,
+    /// After this time the entry is treated as absent and its content may be reaped.
+    pub expiry: Option<OffsetDateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
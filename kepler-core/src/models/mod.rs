@@ -6,4 +6,9 @@ pub mod invocation;
 pub mod kv_delete;
 pub mod kv_write;
 pub mod orbit;
+pub mod orbit_alias;
+pub mod orbit_feature;
+pub mod orbit_pointer;
+pub mod purge_invocation;
+pub mod receipt;
 pub mod revocation;
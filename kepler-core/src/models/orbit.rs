@@ -1,13 +1,24 @@
+use crate::hash::HashAlgorithm;
 use crate::models::*;
 use crate::relationships::*;
 use crate::types::OrbitIdWrap;
 use sea_orm::entity::prelude::*;
+use time::OffsetDateTime;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, PartialOrd, Ord)]
 #[sea_orm(table_name = "orbit")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false, unique)]
     pub id: OrbitIdWrap,
+    /// Algorithm the orbit's content is addressed with, chosen when it is created.
+    pub hash: HashAlgorithm,
+    /// Algorithm the orbit's epochs and their operations are hashed with, chosen when it
+    /// is created.
+    pub event_hash: HashAlgorithm,
+    /// Bytes of block storage the orbit may use, overriding the global limit if set.
+    pub storage_limit: Option<i64>,
+    /// When the orbit was created, unset for orbits created before it was recorded.
+    pub created_at: Option<OffsetDateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -18,6 +29,38 @@ pub enum Relation {
     Epochs,
     #[sea_orm(has_many = "epoch_order::Entity")]
     EpochOrdering,
+    #[sea_orm(has_many = "orbit_feature::Entity")]
+    Features,
+    #[sea_orm(has_many = "orbit_alias::Entity")]
+    Aliases,
+    #[sea_orm(has_many = "receipt::Entity")]
+    Receipts,
+    #[sea_orm(has_many = "orbit_pointer::Entity")]
+    Pointer,
+}
+
+impl Related<orbit_pointer::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Pointer.def()
+    }
+}
+
+impl Related<receipt::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Receipts.def()
+    }
+}
+
+impl Related<orbit_alias::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Aliases.def()
+    }
+}
+
+impl Related<orbit_feature::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Features.def()
+    }
 }
 
 impl Related<epoch_order::Entity> for Entity {
@@ -0,0 +1,41 @@
+use super::*;
+use crate::types::OrbitIdWrap;
+use sea_orm::entity::prelude::*;
+
+/// A short, human-friendly name for an orbit.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "orbit_alias")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, unique)]
+    pub alias: String,
+
+    pub orbit: OrbitIdWrap,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "orbit::Entity",
+        from = "Column::Orbit",
+        to = "orbit::Column::Id"
+    )]
+    Orbit,
+}
+
+impl Related<orbit::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Orbit.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Whether a string may be used as an alias: 1 to 64 ASCII letters, digits, `-` or `_`.
+///
+/// This never overlaps with orbit ID syntax, which always contains `:`.
+pub fn is_valid_alias(alias: &str) -> bool {
+    (1..=64).contains(&alias.len())
+        && alias
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
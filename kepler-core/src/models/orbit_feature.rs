@@ -0,0 +1,33 @@
+use super::*;
+use crate::types::OrbitIdWrap;
+use sea_orm::entity::prelude::*;
+
+/// A feature flag set for an orbit. Flags which have no row are disabled.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "orbit_feature")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub orbit: OrbitIdWrap,
+    #[sea_orm(primary_key)]
+    pub flag: String,
+
+    pub enabled: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "orbit::Entity",
+        from = "Column::Orbit",
+        to = "orbit::Column::Id"
+    )]
+    Orbit,
+}
+
+impl Related<orbit::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Orbit.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,32 @@
+use super::*;
+use crate::types::OrbitIdWrap;
+use sea_orm::entity::prelude::*;
+
+/// The pointer last published for an orbit, kept as its DAG-CBOR block.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "orbit_pointer")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, unique)]
+    pub orbit: OrbitIdWrap,
+
+    pub sequence: i64,
+    pub serialization: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "orbit::Entity",
+        from = "Column::Orbit",
+        to = "orbit::Column::Id"
+    )]
+    Orbit,
+}
+
+impl Related<orbit::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Orbit.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,19 @@
+use crate::hash::Hash;
+use crate::types::OrbitIdWrap;
+use sea_orm::entity::prelude::*;
+
+/// An invocation which purged an orbit. Purges remove the orbit's events, so these are
+/// kept apart from them, to reject a captured purge once the orbit is recreated.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "purge_invocation")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, unique)]
+    pub id: Hash,
+
+    pub orbit: OrbitIdWrap,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
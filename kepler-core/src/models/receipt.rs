@@ -0,0 +1,34 @@
+use super::*;
+use crate::hash::Hash;
+use crate::types::OrbitIdWrap;
+use sea_orm::entity::prelude::*;
+
+/// A signed receipt of an invocation committed to an orbit, kept as its DAG-CBOR block.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "receipt")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, unique)]
+    pub id: Hash,
+
+    pub orbit: OrbitIdWrap,
+    pub invocation: Hash,
+    pub serialization: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "orbit::Entity",
+        from = "Column::Orbit",
+        to = "orbit::Column::Id"
+    )]
+    Orbit,
+}
+
+impl Related<orbit::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Orbit.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -63,6 +63,8 @@ pub enum RevocationError {
     UnauthorizedRevoker(String),
     #[error("Cannot find parent delegation")]
     MissingParents,
+    #[error("DID method not allowed: {0}")]
+    UnauthorizedMethod(String),
 }
 
 pub(crate) async fn process<C: ConnectionTrait>(
@@ -0,0 +1,70 @@
+use crate::{hash::Hash, keys::get_did_key, receipt::ReceiptError};
+use kepler_lib::{libipld::cid::Cid, resource::OrbitId};
+use libp2p::identity::{Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
+
+const CBOR_CODEC: u64 = 0x71;
+
+/// What an orbit's host attests to as the orbit's latest state, like an IPNS record naming
+/// the orbit's heads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointerPayload {
+    pub orbit: String,
+    /// The orbit's heads, which the next epoch committed to it follows.
+    pub heads: Vec<Cid>,
+    /// The greatest sequence number of the heads.
+    pub height: i64,
+    /// Incremented each time the pointer is published, so that a later pointer supersedes
+    /// an earlier one.
+    pub sequence: i64,
+    /// Unix time in seconds the pointer was published at.
+    pub issued: i64,
+    /// The `did:key` of the orbit's key, which signs the pointer.
+    pub issuer: String,
+}
+
+/// Pointer to the latest state of an orbit, signed with the orbit's key and encoded as a
+/// DAG-CBOR block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pointer {
+    pub payload: PointerPayload,
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+impl PointerPayload {
+    pub fn new(
+        orbit: &OrbitId,
+        heads: &[Hash],
+        height: i64,
+        sequence: i64,
+        issued: i64,
+        issuer: PublicKey,
+    ) -> Self {
+        Self {
+            orbit: orbit.to_string(),
+            heads: heads.iter().map(|h| h.to_cid(CBOR_CODEC)).collect(),
+            height,
+            sequence,
+            issued,
+            issuer: get_did_key(issuer),
+        }
+    }
+}
+
+impl Pointer {
+    /// Sign the DAG-CBOR encoding of `payload` with `keypair`.
+    pub fn sign(payload: PointerPayload, keypair: &Keypair) -> Result<Self, ReceiptError> {
+        let signature = keypair.sign(&serde_ipld_dagcbor::to_vec(&payload)?)?;
+        Ok(Self { payload, signature })
+    }
+
+    /// Whether the pointer was signed by `key`.
+    pub fn verify(&self, key: &PublicKey) -> Result<bool, ReceiptError> {
+        Ok(key.verify(&serde_ipld_dagcbor::to_vec(&self.payload)?, &self.signature))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, ReceiptError> {
+        Ok(serde_ipld_dagcbor::to_vec(self)?)
+    }
+}
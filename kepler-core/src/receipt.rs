@@ -0,0 +1,84 @@
+use crate::{hash::Hash, keys::get_did_key};
+use kepler_lib::{libipld::cid::Cid, resource::OrbitId};
+use libp2p::identity::{Keypair, PublicKey, SigningError};
+use serde::{Deserialize, Serialize};
+use serde_ipld_dagcbor::EncodeError;
+use std::collections::TryReserveError;
+
+const CBOR_CODEC: u64 = 0x71;
+const RAW_CODEC: u64 = 0x55;
+
+/// An ability invoked on an orbit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invoked {
+    pub resource: String,
+    pub ability: String,
+}
+
+/// What an orbit's host attests to when an invocation is committed to the orbit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiptPayload {
+    pub orbit: String,
+    pub invocation: Cid,
+    /// The epoch the invocation was committed in, and its orbit sequence number.
+    pub rev: Cid,
+    pub seq: i64,
+    pub invoked: Vec<Invoked>,
+    /// The `did:key` of the orbit's key, which signs the receipt.
+    pub issuer: String,
+}
+
+/// Proof that an invocation was committed to an orbit, signed with the orbit's key and
+/// stored as a DAG-CBOR block, addressed by its CID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub payload: ReceiptPayload,
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum ReceiptError {
+    #[error("encoding error: {0}")]
+    Encode(#[from] EncodeError<TryReserveError>),
+    #[error("signing error: {0}")]
+    Signing(#[from] SigningError),
+}
+
+impl ReceiptPayload {
+    pub fn new(
+        orbit: &OrbitId,
+        invocation: Hash,
+        rev: Hash,
+        seq: i64,
+        invoked: Vec<Invoked>,
+        issuer: PublicKey,
+    ) -> Self {
+        Self {
+            orbit: orbit.to_string(),
+            invocation: invocation.to_cid(RAW_CODEC),
+            rev: rev.to_cid(CBOR_CODEC),
+            seq,
+            invoked,
+            issuer: get_did_key(issuer),
+        }
+    }
+}
+
+impl Receipt {
+    /// Sign the DAG-CBOR encoding of `payload` with `keypair`.
+    pub fn sign(payload: ReceiptPayload, keypair: &Keypair) -> Result<Self, ReceiptError> {
+        let signature = keypair.sign(&serde_ipld_dagcbor::to_vec(&payload)?)?;
+        Ok(Self { payload, signature })
+    }
+
+    /// Whether the receipt was signed by `key`.
+    pub fn verify(&self, key: &PublicKey) -> Result<bool, ReceiptError> {
+        Ok(key.verify(&serde_ipld_dagcbor::to_vec(&self.payload)?, &self.signature))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, ReceiptError> {
+        Ok(serde_ipld_dagcbor::to_vec(self)?)
+    }
+}
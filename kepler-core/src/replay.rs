@@ -0,0 +1,231 @@
+use crate::hash::Hash;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+use time::OffsetDateTime;
+
+/// Protection against replays of captured invocations, which are otherwise applied again
+/// as a retry of the invocation would be.
+///
+/// Invocations are identified by the hash of their serialization, which covers their
+/// nonce, so only identical invocations are rejected.
+#[derive(Debug, Clone)]
+pub enum ReplayProtection {
+    /// Reject invocations already committed to the database.
+    Database,
+    /// Reject invocations already received by this node, remembered until they expire.
+    Memory(ReplayCache),
+}
+
+/// Invocations received by this node, each remembered until it expires.
+///
+/// At most `capacity` invocations are remembered, those expiring soonest being forgotten
+/// first to make room, so memory stays bounded.
+#[derive(Debug, Clone)]
+pub struct ReplayCache {
+    seen: Arc<Mutex<Seen>>,
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    expiries: HashMap<Hash, i64>,
+    by_expiry: BTreeSet<(i64, Hash)>,
+}
+
+impl Seen {
+    fn forget_expired(&mut self, now: i64) {
+        while let Some(&(expiry, id)) = self.by_expiry.iter().next() {
+            if expiry > now {
+                break;
+            }
+            self.by_expiry.remove(&(expiry, id));
+            self.expiries.remove(&id);
+        }
+    }
+}
+
+impl ReplayCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            seen: Default::default(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Whether the invocation `id` was received and has not yet expired.
+    pub fn contains(&self, id: &Hash) -> bool {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.forget_expired(OffsetDateTime::now_utc().unix_timestamp());
+        seen.expiries.contains_key(id)
+    }
+
+    /// Remember the invocation `id` until the unix time `expiry`, in seconds.
+    pub fn insert(&self, id: Hash, expiry: i64) {
+        self.insert_new(id, expiry);
+    }
+
+    /// Remember the invocation `id` as [`ReplayCache::insert`] does, returning whether it
+    /// was not already remembered.
+    pub fn insert_new(&self, id: Hash, expiry: i64) -> bool {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        seen.forget_expired(now);
+        if expiry <= now {
+            return true;
+        }
+        if let Some(previous) = seen.expiries.insert(id, expiry) {
+            seen.by_expiry.remove(&(previous, id));
+            seen.by_expiry.insert((expiry, id));
+            return false;
+        }
+        seen.by_expiry.insert((expiry, id));
+        while seen.expiries.len() > self.capacity {
+            match seen.by_expiry.iter().next().copied() {
+                Some(first) => {
+                    seen.by_expiry.remove(&first);
+                    seen.expiries.remove(&first.1);
+                }
+                None => break,
+            }
+        }
+        true
+    }
+
+    /// Forget the invocation `id`, so that it may be received again.
+    pub fn remove(&self, id: &Hash) {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(expiry) = seen.expiries.remove(id) {
+            seen.by_expiry.remove(&(expiry, *id));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .expiries
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The invocations claimed in a [`ReplayCache`] while they are applied, so that concurrent
+/// submissions of one are rejected. They are forgotten again when dropped unless kept, so
+/// that an invocation which was not committed may be retried.
+#[derive(Debug, Default)]
+pub(crate) struct Claims {
+    cache: Option<ReplayCache>,
+    ids: Vec<Hash>,
+}
+
+impl Claims {
+    pub(crate) fn new(protection: Option<&ReplayProtection>) -> Self {
+        Self {
+            cache: match protection {
+                Some(ReplayProtection::Memory(cache)) => Some(cache.clone()),
+                _ => None,
+            },
+            ids: Vec::new(),
+        }
+    }
+
+    /// Claim the invocation `id` until the unix time `expiry`, returning whether it was not
+    /// already received.
+    pub(crate) fn claim(&mut self, id: Hash, expiry: i64) -> bool {
+        match &self.cache {
+            Some(cache) if cache.insert_new(id, expiry) => {
+                self.ids.push(id);
+                true
+            }
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    /// Whether the invocation `id` was already received, without claiming it.
+    pub(crate) fn contains(&self, id: &Hash) -> bool {
+        self.cache
+            .as_ref()
+            .map_or(false, |cache| cache.contains(id))
+    }
+
+    /// Keep the claimed invocations remembered, once they are committed.
+    pub(crate) fn keep(mut self) {
+        self.ids.clear();
+    }
+}
+
+impl Drop for Claims {
+    fn drop(&mut self) {
+        if let Some(cache) = &self.cache {
+            for id in &self.ids {
+                cache.remove(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bounded() {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let id = |i: i64| crate::hash::hash(format!("invocation {i}").as_bytes());
+        let cache = ReplayCache::new(2);
+
+        // expired invocations are not remembered
+        cache.insert(id(0), now - 1);
+        assert!(!cache.contains(&id(0)));
+
+        cache.insert(id(1), now + 60);
+        cache.insert(id(2), now + 120);
+        assert!(cache.contains(&id(1)));
+        assert!(cache.contains(&id(2)));
+
+        // the invocation expiring soonest makes room
+        cache.insert(id(3), now + 90);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&id(1)));
+        assert!(cache.contains(&id(2)));
+        assert!(cache.contains(&id(3)));
+
+        // only the first insertion of an invocation is new
+        assert!(!cache.insert_new(id(3), now + 90));
+        assert!(cache.insert_new(id(4), now + 150));
+    }
+
+    #[test]
+    fn claims() {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let id = |i: i64| crate::hash::hash(format!("invocation {i}").as_bytes());
+        let cache = ReplayCache::new(16);
+        let protection = Some(ReplayProtection::Memory(cache.clone()));
+
+        // an invocation claimed but not yet committed is rejected, and forgotten if the
+        // claim is dropped
+        let mut claims = Claims::new(protection.as_ref());
+        assert!(claims.claim(id(0), now + 60));
+        assert!(!Claims::new(protection.as_ref()).claim(id(0), now + 60));
+        drop(claims);
+        assert!(cache.is_empty());
+
+        // a kept claim stays remembered
+        let mut claims = Claims::new(protection.as_ref());
+        assert!(claims.claim(id(0), now + 60));
+        claims.keep();
+        assert!(cache.contains(&id(0)));
+        assert!(!Claims::new(protection.as_ref()).claim(id(0), now + 60));
+
+        // without a cache everything may be claimed
+        let mut claims = Claims::new(Some(&ReplayProtection::Database));
+        assert!(claims.claim(id(0), now + 60));
+        assert!(claims.claim(id(0), now + 60));
+    }
+}
@@ -0,0 +1,296 @@
+use kepler_lib::{
+    resolver::DID_METHODS,
+    ssi::{
+        did::{Document, PrimaryDIDURL},
+        did_resolve::{
+            Content, ContentMetadata, DIDResolver, DereferencingInputMetadata,
+            DereferencingMetadata, DocumentMetadata, ResolutionInputMetadata, ResolutionMetadata,
+        },
+    },
+};
+use sea_orm_migration::async_trait::async_trait;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Methods whose DIDs describe their own document, which is resolved without any network
+/// call and so isn't worth caching.
+const SELF_DESCRIBING: [&str; 2] = ["key", "pkh"];
+
+type Resolved = (
+    ResolutionMetadata,
+    Option<Document>,
+    Option<DocumentMetadata>,
+);
+
+/// Outcome of resolving a DID through a [`DidResolver`], as given to its observer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// The DID's document, or the failure to resolve it, was cached.
+    Hit,
+    /// The DID was resolved and the outcome cached.
+    Miss,
+    /// The DID describes its own document, so it was resolved without the cache.
+    Bypass,
+}
+
+impl Resolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hit => "hit",
+            Self::Miss => "miss",
+            Self::Bypass => "bypass",
+        }
+    }
+}
+
+/// Resolver of the DIDs signing delegations and invocations, by the methods of
+/// [`DID_METHODS`].
+///
+/// Unless caching is enabled every verification resolves its signer again, which for
+/// methods like `did:web` means a network call. When it is enabled, documents are cached
+/// by DID for a while, as are failures to resolve them, so a DID which doesn't resolve
+/// isn't retried on each request. DIDs of self-describing methods bypass the cache.
+#[derive(Clone, Default)]
+pub struct DidResolver {
+    cache: Option<Arc<DidCache>>,
+    observer: Option<Arc<dyn Fn(Resolution) + Send + Sync>>,
+}
+
+struct DidCache {
+    entries: Mutex<Entries>,
+    ttl: Duration,
+    failure_ttl: Duration,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct Entries {
+    resolved: HashMap<String, (Instant, Resolved)>,
+    by_expiry: BTreeSet<(Instant, String)>,
+}
+
+impl Entries {
+    fn forget_expired(&mut self, now: Instant) {
+        while let Some((expiry, did)) = self.by_expiry.iter().next().cloned() {
+            if expiry > now {
+                break;
+            }
+            self.by_expiry.remove(&(expiry, did.clone()));
+            self.resolved.remove(&did);
+        }
+    }
+}
+
+impl DidCache {
+    fn get(&self, did: &str) -> Option<Resolved> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.forget_expired(Instant::now());
+        entries.resolved.get(did).map(|(_, r)| r.clone())
+    }
+
+    fn insert(&self, did: &str, resolved: Resolved) {
+        let failed = resolved.0.error.is_some() || resolved.1.is_none();
+        let ttl = if failed { self.failure_ttl } else { self.ttl };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        entries.forget_expired(now);
+        if ttl.is_zero() {
+            return;
+        }
+        let expiry = now + ttl;
+        if let Some((previous, _)) = entries.resolved.insert(did.to_string(), (expiry, resolved)) {
+            entries.by_expiry.remove(&(previous, did.to_string()));
+        }
+        entries.by_expiry.insert((expiry, did.to_string()));
+        // the entry expiring soonest makes room
+        while entries.resolved.len() > self.capacity {
+            match entries.by_expiry.pop_first() {
+                Some((_, did)) => {
+                    entries.resolved.remove(&did);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl DidResolver {
+    /// Cache documents for `ttl`, and failures to resolve them for `failure_ttl`, keeping
+    /// at most `capacity` DIDs, those expiring soonest being forgotten first.
+    pub fn cached(ttl: Duration, failure_ttl: Duration, capacity: usize) -> Self {
+        Self {
+            cache: Some(Arc::new(DidCache {
+                entries: Default::default(),
+                ttl,
+                failure_ttl,
+                capacity: capacity.max(1),
+            })),
+            observer: None,
+        }
+    }
+
+    /// Call `observer` with the outcome of each resolution, e.g. to count cache hits.
+    pub fn with_observer(mut self, observer: impl Fn(Resolution) + Send + Sync + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Number of DIDs cached, including those which failed to resolve.
+    pub fn len(&self) -> usize {
+        self.cache
+            .as_ref()
+            .map(|c| {
+                c.entries
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .resolved
+                    .len()
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn observe(&self, resolution: Resolution) {
+        if let Some(observer) = &self.observer {
+            observer(resolution);
+        }
+    }
+}
+
+impl fmt::Debug for DidResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DidResolver")
+            .field("cached", &self.cache.is_some())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+fn self_describing(did: &str) -> bool {
+    did.strip_prefix("did:")
+        .and_then(|d| d.split(':').next())
+        .map(|method| SELF_DESCRIBING.contains(&method))
+        .unwrap_or(false)
+}
+
+#[async_trait]
+impl DIDResolver for DidResolver {
+    async fn resolve(&self, did: &str, input_metadata: &ResolutionInputMetadata) -> Resolved {
+        let resolver = DID_METHODS.to_resolver();
+        let cache = match &self.cache {
+            Some(_) if self_describing(did) => {
+                self.observe(Resolution::Bypass);
+                return resolver.resolve(did, input_metadata).await;
+            }
+            Some(cache) => cache,
+            None => return resolver.resolve(did, input_metadata).await,
+        };
+        if let Some(resolved) = cache.get(did) {
+            self.observe(Resolution::Hit);
+            return resolved;
+        }
+        self.observe(Resolution::Miss);
+        let resolved = resolver.resolve(did, input_metadata).await;
+        cache.insert(did, resolved.clone());
+        resolved
+    }
+
+    async fn dereference(
+        &self,
+        primary_did_url: &PrimaryDIDURL,
+        input_metadata: &DereferencingInputMetadata,
+    ) -> Option<(DereferencingMetadata, Content, ContentMetadata)> {
+        DID_METHODS
+            .to_resolver()
+            .dereference(primary_did_url, input_metadata)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::test;
+    use kepler_lib::ssi::{did::Source, jwk::JWK};
+
+    fn observed(resolver: DidResolver) -> (DidResolver, Arc<Mutex<Vec<Resolution>>>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = seen.clone();
+        (
+            resolver.with_observer(move |r| s.lock().unwrap().push(r)),
+            seen,
+        )
+    }
+
+    #[test]
+    async fn cache() {
+        let (resolver, seen) = observed(DidResolver::cached(
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+            1,
+        ));
+
+        // failures are cached too
+        let (meta, doc, _) = resolver
+            .resolve("did:example:alice", &Default::default())
+            .await;
+        assert!(meta.error.is_some() && doc.is_none());
+        let (meta, _, _) = resolver
+            .resolve("did:example:alice", &Default::default())
+            .await;
+        assert!(meta.error.is_some());
+        assert_eq!(resolver.len(), 1);
+
+        // self-describing DIDs aren't cached
+        let did = DID_METHODS
+            .generate(&Source::KeyAndPattern(
+                &JWK::generate_ed25519().unwrap(),
+                "key",
+            ))
+            .unwrap();
+        let (_, doc, _) = resolver.resolve(&did, &Default::default()).await;
+        assert_eq!(doc.unwrap().id, did);
+        assert_eq!(resolver.len(), 1);
+
+        // at most `capacity` DIDs are cached
+        resolver
+            .resolve("did:example:bob", &Default::default())
+            .await;
+        assert_eq!(resolver.len(), 1);
+        resolver
+            .resolve("did:example:alice", &Default::default())
+            .await;
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                Resolution::Miss,
+                Resolution::Hit,
+                Resolution::Bypass,
+                Resolution::Miss,
+                Resolution::Miss,
+            ]
+        );
+
+        // failures aren't cached if their time to live is zero
+        let (resolver, seen) = observed(DidResolver::cached(
+            Duration::from_secs(60),
+            Duration::ZERO,
+            10,
+        ));
+        resolver
+            .resolve("did:example:alice", &Default::default())
+            .await;
+        resolver
+            .resolve("did:example:alice", &Default::default())
+            .await;
+        assert!(resolver.is_empty());
+        assert_eq!(*seen.lock().unwrap(), [Resolution::Miss, Resolution::Miss]);
+    }
+}
@@ -1,6 +1,18 @@
-use crate::storage::{ImmutableStaging, StorageConfig};
+use crate::{
+    hash::Hash,
+    storage::{
+        Content, HashBuffer, ImmutableDeleteStore, ImmutableReadStore, ImmutableStaging,
+        ImmutableWriteStore, IntoBytes, StorageConfig, StorageSetup, StoreSize,
+    },
+};
+use futures::io::Cursor;
 use kepler_lib::resource::OrbitId;
 use sea_orm_migration::async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, RwLock},
+};
 
 #[derive(Default, Debug, Clone, Hash, PartialEq, Eq)]
 pub struct MemoryStaging;
@@ -21,3 +33,166 @@ impl StorageConfig<MemoryStaging> for MemoryStaging {
         Ok(Self)
     }
 }
+
+/// Configuration of a [`MemoryBlockStore`], each opening of which starts out empty.
+#[derive(Default, Debug, Clone, Hash, PartialEq, Eq)]
+pub struct MemoryBlockConfig;
+
+#[async_trait]
+impl StorageConfig<MemoryBlockStore> for MemoryBlockConfig {
+    type Error = Infallible;
+    async fn open(&self) -> Result<MemoryBlockStore, Self::Error> {
+        Ok(MemoryBlockStore::default())
+    }
+}
+
+/// Block store keeping content in memory, for tests and ephemeral nodes.
+///
+/// Clones share the same content, and nothing outlives the last of them.
+#[derive(Default, Debug, Clone)]
+pub struct MemoryBlockStore {
+    inner: Arc<RwLock<MemoryBlocks>>,
+}
+
+#[derive(Default, Debug)]
+struct MemoryBlocks {
+    blocks: HashMap<(OrbitId, Hash), Vec<u8>>,
+    sizes: HashMap<OrbitId, u64>,
+}
+
+impl MemoryBlockStore {
+    // the lock is never held across an await or a panicking call, so it can't be poisoned
+    fn read_blocks(&self) -> std::sync::RwLockReadGuard<'_, MemoryBlocks> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write_blocks(&self) -> std::sync::RwLockWriteGuard<'_, MemoryBlocks> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl StorageSetup for MemoryBlockStore {
+    type Error = Infallible;
+    async fn create(&self, orbit: &OrbitId) -> Result<(), Self::Error> {
+        self.write_blocks().sizes.entry(orbit.clone()).or_default();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableReadStore for MemoryBlockStore {
+    type Error = Infallible;
+    type Readable = Cursor<Vec<u8>>;
+    async fn contains(&self, orbit: &OrbitId, id: &Hash) -> Result<bool, Self::Error> {
+        Ok(self
+            .read_blocks()
+            .blocks
+            .contains_key(&(orbit.clone(), *id)))
+    }
+    async fn read(
+        &self,
+        orbit: &OrbitId,
+        id: &Hash,
+    ) -> Result<Option<Content<Self::Readable>>, Self::Error> {
+        Ok(self
+            .read_blocks()
+            .blocks
+            .get(&(orbit.clone(), *id))
+            .map(|b| Content::new(b.len() as u64, Cursor::new(b.clone()))))
+    }
+}
+
+#[async_trait]
+impl<S> ImmutableWriteStore<S> for MemoryBlockStore
+where
+    S: ImmutableStaging,
+    S::Writable: IntoBytes + 'static,
+{
+    type Error = std::io::Error;
+    async fn persist(
+        &self,
+        orbit: &OrbitId,
+        staged: HashBuffer<S::Writable>,
+    ) -> Result<Hash, Self::Error> {
+        let (mut h, b) = staged.into_inner();
+        let hash = h.finalize();
+        if !self
+            .read_blocks()
+            .blocks
+            .contains_key(&(orbit.clone(), hash))
+        {
+            let bytes = b.into_bytes().await?;
+            let mut blocks = self.write_blocks();
+            let size = bytes.len() as u64;
+            if blocks.blocks.insert((orbit.clone(), hash), bytes).is_none() {
+                *blocks.sizes.entry(orbit.clone()).or_default() += size;
+            }
+        }
+        Ok(hash)
+    }
+}
+
+#[async_trait]
+impl ImmutableDeleteStore for MemoryBlockStore {
+    type Error = Infallible;
+    async fn remove(&self, orbit: &OrbitId, id: &Hash) -> Result<Option<()>, Self::Error> {
+        let mut blocks = self.write_blocks();
+        Ok(blocks.blocks.remove(&(orbit.clone(), *id)).map(|b| {
+            if let Some(size) = blocks.sizes.get_mut(orbit) {
+                *size = size.saturating_sub(b.len() as u64);
+            }
+        }))
+    }
+}
+
+#[async_trait]
+impl StoreSize for MemoryBlockStore {
+    type Error = Infallible;
+    async fn total_size(&self, orbit: &OrbitId) -> Result<Option<u64>, Self::Error> {
+        Ok(self.read_blocks().sizes.get(orbit).copied())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::test;
+    use futures::io::AsyncWriteExt;
+
+    #[test]
+    async fn block_store() {
+        let orbit = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let store = MemoryBlockConfig.open().await.unwrap();
+        store.create(&orbit).await.unwrap();
+        assert_eq!(store.total_size(&orbit).await.unwrap(), Some(0));
+
+        let mut staged = MemoryStaging.stage(&orbit).await.unwrap();
+        staged.write_all(b"hello").await.unwrap();
+        let hash = ImmutableWriteStore::<MemoryStaging>::persist(&store, &orbit, staged)
+            .await
+            .unwrap();
+
+        // clones share content
+        let clone = store.clone();
+        assert!(clone.contains(&orbit, &hash).await.unwrap());
+        assert_eq!(
+            clone.read_to_vec(&orbit, &hash).await.unwrap(),
+            Some(b"hello".to_vec())
+        );
+        assert_eq!(clone.total_size(&orbit).await.unwrap(), Some(5));
+
+        // persisting the same content again doesn't count it twice
+        let mut staged = MemoryStaging.stage(&orbit).await.unwrap();
+        staged.write_all(b"hello").await.unwrap();
+        ImmutableWriteStore::<MemoryStaging>::persist(&store, &orbit, staged)
+            .await
+            .unwrap();
+        assert_eq!(store.total_size(&orbit).await.unwrap(), Some(5));
+
+        assert_eq!(store.remove(&orbit, &hash).await.unwrap(), Some(()));
+        assert_eq!(store.remove(&orbit, &hash).await.unwrap(), None);
+        assert!(!store.contains(&orbit, &hash).await.unwrap());
+        assert_eq!(store.total_size(&orbit).await.unwrap(), Some(0));
+    }
+}
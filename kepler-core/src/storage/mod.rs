@@ -1,4 +1,4 @@
-use crate::hash::Hash;
+use crate::hash::{Hash, HashAlgorithm};
 use kepler_lib::resource::OrbitId;
 use sea_orm_migration::async_trait::async_trait;
 use std::error::Error as StdError;
@@ -6,7 +6,7 @@ use std::error::Error as StdError;
 pub mod either;
 pub mod memory;
 mod util;
-pub use util::{Content, HashBuffer};
+pub use util::{with_timeout, Content, HashBuffer, StorageTimeout};
 
 #[async_trait]
 pub trait StorageConfig<S> {
@@ -78,9 +78,131 @@ pub trait ImmutableStaging: Send + Sync {
     async fn stage(&self, orbit: &OrbitId) -> Result<HashBuffer<Self::Writable>, Self::Error> {
         self.get_staging_buffer(orbit).await.map(HashBuffer::new)
     }
+    /// Stage content which is addressed with the given hash algorithm.
+    async fn stage_with(
+        &self,
+        orbit: &OrbitId,
+        algorithm: HashAlgorithm,
+    ) -> Result<HashBuffer<Self::Writable>, Self::Error> {
+        self.get_staging_buffer(orbit)
+            .await
+            .map(|b| HashBuffer::with_algorithm(b, algorithm))
+    }
     async fn get_staging_buffer(&self, orbit: &OrbitId) -> Result<Self::Writable, Self::Error>;
 }
 
+/// Staged content which can be read back into memory, as stores which keep content in
+/// memory require of the staging they persist from.
+#[async_trait]
+pub trait IntoBytes: Send {
+    async fn into_bytes(self) -> Result<Vec<u8>, std::io::Error>;
+}
+
+#[async_trait]
+impl IntoBytes for Vec<u8> {
+    async fn into_bytes(self) -> Result<Vec<u8>, std::io::Error> {
+        Ok(self)
+    }
+}
+
+/// Staged content which can be read back as a stream, for stores which persist an encoding
+/// of the content without holding all of it in memory.
+#[async_trait]
+pub trait IntoReader: Send {
+    type Reader: futures::io::AsyncRead + Send + Unpin;
+    async fn into_reader(self) -> Result<Self::Reader, std::io::Error>;
+}
+
+#[async_trait]
+impl IntoReader for Vec<u8> {
+    type Reader = futures::io::Cursor<Vec<u8>>;
+    async fn into_reader(self) -> Result<Self::Reader, std::io::Error> {
+        Ok(futures::io::Cursor::new(self))
+    }
+}
+
+#[async_trait]
+impl<A, B> IntoReader for futures::future::Either<A, B>
+where
+    A: IntoReader,
+    B: IntoReader,
+{
+    type Reader = futures::future::Either<A::Reader, B::Reader>;
+    async fn into_reader(self) -> Result<Self::Reader, std::io::Error> {
+        Ok(match self {
+            Self::Left(a) => futures::future::Either::Left(a.into_reader().await?),
+            Self::Right(b) => futures::future::Either::Right(b.into_reader().await?),
+        })
+    }
+}
+
+#[async_trait]
+impl<A, B> IntoBytes for futures::future::Either<A, B>
+where
+    A: IntoBytes,
+    B: IntoBytes,
+{
+    async fn into_bytes(self) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Self::Left(a) => a.into_bytes().await,
+            Self::Right(b) => b.into_bytes().await,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ResumableError<E> {
+    #[error("Upload not found")]
+    NotFound,
+    #[error("Upload was declared with size {0}")]
+    SizeMismatch(u64),
+    #[error("Upload is at offset {0}")]
+    OffsetMismatch(u64),
+    #[error("Upload exceeds its declared size")]
+    TooLarge,
+    #[error("Upload is incomplete")]
+    Incomplete,
+    #[error("Upload is already being appended to")]
+    Busy,
+    #[error(transparent)]
+    Store(#[from] E),
+}
+
+/// Staging which keeps partially uploaded content across requests, keyed by the hash the
+/// content is declared to have, so that an interrupted upload can be resumed.
+#[async_trait]
+pub trait ResumableStaging: ImmutableStaging {
+    type Readable: futures::io::AsyncRead + Send + Sync + Unpin;
+    /// Declare content of `size` bytes expected to hash to `hash`, returning how many bytes
+    /// have been staged for it so far.
+    async fn begin(
+        &self,
+        orbit: &OrbitId,
+        hash: &Hash,
+        size: u64,
+    ) -> Result<u64, ResumableError<Self::Error>>;
+    /// Append bytes at `offset`, which must be the number of bytes staged so far, returning
+    /// the new number of bytes staged. Appends to an upload are made one at a time, others
+    /// fail with [`ResumableError::Busy`] meanwhile.
+    async fn append<R>(
+        &self,
+        orbit: &OrbitId,
+        hash: &Hash,
+        offset: u64,
+        data: R,
+    ) -> Result<u64, ResumableError<Self::Error>>
+    where
+        R: futures::io::AsyncRead + Send;
+    /// Read the staged content, once all of the declared bytes have been staged.
+    async fn open(
+        &self,
+        orbit: &OrbitId,
+        hash: &Hash,
+    ) -> Result<Content<Self::Readable>, ResumableError<Self::Error>>;
+    /// Discard staged content, returning whether there was any.
+    async fn discard(&self, orbit: &OrbitId, hash: &Hash) -> Result<bool, Self::Error>;
+}
+
 #[async_trait]
 pub trait ImmutableWriteStore<S>: Send + Sync
 where
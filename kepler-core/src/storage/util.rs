@@ -1,11 +1,14 @@
-use crate::hash::{Hash, Hasher};
+use crate::hash::{Hash, HashAlgorithm, Hasher};
 use core::pin::Pin;
 use futures::{
+    future::{select, Either},
     io::AsyncWrite,
     task::{Context, Poll},
+    Future,
 };
+use futures_timer::Delay;
 use pin_project::pin_project;
-use std::io::Error as IoError;
+use std::{io::Error as IoError, time::Duration};
 
 #[pin_project]
 #[derive(Debug)]
@@ -13,6 +16,7 @@ pub struct HashBuffer<B> {
     #[pin]
     buffer: B,
     hasher: Hasher,
+    size: u64,
 }
 
 impl<B> HashBuffer<B> {
@@ -25,6 +29,10 @@ impl<B> HashBuffer<B> {
     pub fn hash(&mut self) -> Hash {
         self.hasher.finalize()
     }
+    /// Number of bytes written to the buffer.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
 }
 
 impl<B> HashBuffer<B> {
@@ -32,6 +40,25 @@ impl<B> HashBuffer<B> {
         Self {
             buffer,
             hasher: Hasher::new(),
+            size: 0,
+        }
+    }
+
+    pub fn with_algorithm(buffer: B, algorithm: HashAlgorithm) -> Self {
+        Self {
+            buffer,
+            hasher: Hasher::with_algorithm(algorithm),
+            size: 0,
+        }
+    }
+
+    /// Buffer holding `size` bytes, addressed by the given hasher rather than its contents,
+    /// for stores which persist an encoding of the content that was hashed.
+    pub fn from_parts(hasher: Hasher, buffer: B, size: u64) -> Self {
+        Self {
+            buffer,
+            hasher,
+            size,
         }
     }
 }
@@ -47,6 +74,7 @@ where
     ) -> Poll<Result<usize, IoError>> {
         let p = self.project();
         p.hasher.update(buf);
+        *p.size += buf.len() as u64;
         p.buffer.poll_write(cx, buf)
     }
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
@@ -105,3 +133,25 @@ where
         this.content.poll_read_vectored(cx, bufs)
     }
 }
+
+/// A call to block storage which took longer than it was allowed to.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Block storage did not respond within {0:?}")]
+pub struct StorageTimeout(pub Duration);
+
+/// Run a call to block storage, giving up on it after `limit`, if one is set.
+///
+/// The timer doesn't depend on the async runtime, so this works under any of them.
+pub async fn with_timeout<F: Future>(
+    limit: Option<Duration>,
+    call: F,
+) -> Result<F::Output, StorageTimeout> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return Ok(call.await),
+    };
+    match select(Box::pin(call), Delay::new(limit)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(StorageTimeout(limit)),
+    }
+}
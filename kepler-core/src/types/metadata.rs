@@ -1,10 +1,102 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq, PartialOrd, Ord, Hash)]
 pub struct Metadata(pub BTreeMap<String, String>);
 
+/// Metadata entry giving the time, in seconds since the unix epoch, after which an object
+/// expires.
+pub const EXPIRES: &str = "x-kepler-expires";
+
+/// Metadata entry giving the time an object was written, as an HTTP date, which is added to
+/// the metadata of reads rather than stored.
+pub const LAST_MODIFIED: &str = "last-modified";
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Format a time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: OffsetDateTime) -> String {
+    let t = time.to_offset(UtcOffset::UTC);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[t.weekday().number_days_from_monday() as usize],
+        t.day(),
+        MONTHS[t.month() as usize - 1],
+        t.year(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    )
+}
+
+/// Parse an HTTP date in the format of [`http_date`], the one HTTP requires of senders.
+pub fn parse_http_date(s: &str) -> Option<OffsetDateTime> {
+    let mut parts = s.trim().split_ascii_whitespace();
+    let (_, day, month, year, time, zone) = (
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+    );
+    if zone != "GMT" || parts.next().is_some() {
+        return None;
+    }
+    let month = MONTHS.iter().position(|m| *m == month)? as u8 + 1;
+    let date = Date::from_calendar_date(
+        year.parse().ok()?,
+        Month::try_from(month).ok()?,
+        day.parse().ok()?,
+    )
+    .ok()?;
+    let mut hms = time.split(':').map(|n| n.parse::<u8>().ok());
+    let time = Time::from_hms(hms.next()??, hms.next()??, hms.next()??).ok()?;
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+impl Metadata {
+    /// Get an entry by case-insensitive name, as for HTTP headers.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The expiry time of the object, if it has a valid [`EXPIRES`] entry.
+    pub fn expiry(&self) -> Option<OffsetDateTime> {
+        self.get(EXPIRES)
+            .and_then(|v| v.trim().parse().ok())
+            .and_then(|s| OffsetDateTime::from_unix_timestamp(s).ok())
+    }
+
+    /// The time the object was written, if it has a valid [`LAST_MODIFIED`] entry.
+    pub fn last_modified(&self) -> Option<OffsetDateTime> {
+        self.get(LAST_MODIFIED).and_then(parse_http_date)
+    }
+
+    /// Set the [`LAST_MODIFIED`] entry, replacing any of another case.
+    pub fn set_last_modified(&mut self, time: OffsetDateTime) {
+        self.0.retain(|k, _| !k.eq_ignore_ascii_case(LAST_MODIFIED));
+        self.0.insert(LAST_MODIFIED.to_string(), http_date(time));
+    }
+
+    /// The media type of the object's `content-type` entry, without parameters and in
+    /// lowercase.
+    pub fn content_type(&self) -> Option<String> {
+        self.get("content-type")
+            .and_then(|v| v.split(';').next())
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+    }
+}
+
 impl From<Metadata> for Value {
     fn from(source: Metadata) -> Self {
         Value::Json(serde_json::to_value(source).ok().map(Box::new))
@@ -41,3 +133,34 @@ impl sea_orm::sea_query::Nullable for Metadata {
         Value::Json(None)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn http_dates() {
+        let time = OffsetDateTime::from_unix_timestamp(784111777).unwrap();
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        for invalid in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun, 31 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+        ] {
+            assert_eq!(parse_http_date(invalid), None);
+        }
+
+        let mut md = Metadata(
+            [("Last-Modified".to_string(), "yesterday".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(md.last_modified(), None);
+        md.set_last_modified(time);
+        assert_eq!(md.0.len(), 1);
+        assert_eq!(md.last_modified(), Some(time));
+    }
+}
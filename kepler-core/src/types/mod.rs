@@ -6,6 +6,6 @@ mod resource;
 
 pub use caveats::Caveats;
 pub use facts::Facts;
-pub use metadata::Metadata;
+pub use metadata::{http_date, parse_http_date, Metadata, LAST_MODIFIED};
 pub use orbit_id_wrap::OrbitIdWrap;
 pub use resource::Resource;
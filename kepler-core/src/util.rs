@@ -8,7 +8,10 @@ use kepler_lib::{
     ssi::ucan::Capability as UcanCap,
 };
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+};
 use time::OffsetDateTime;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, PartialEq, Eq)]
@@ -230,3 +233,38 @@ impl TryFrom<KeplerRevocation> for RevocationInfo {
         }
     }
 }
+
+/// DID methods allowed to issue or receive delegations, invocations and revocations,
+/// e.g. `key` or `pkh:eip155`. An empty list allows every method.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MethodAllowlist {
+    pub global: BTreeSet<String>,
+    /// Methods allowed in particular orbits, instead of the global ones.
+    pub orbits: HashMap<OrbitId, BTreeSet<String>>,
+}
+
+impl MethodAllowlist {
+    /// Whether `did` is allowed to act in every one of `orbits`, or globally if there are none.
+    pub fn allows<'a>(&self, did: &str, orbits: impl IntoIterator<Item = &'a OrbitId>) -> bool {
+        let mut orbits = orbits.into_iter().peekable();
+        if orbits.peek().is_none() {
+            return method_in(&self.global, did);
+        }
+        orbits.all(|o| method_in(self.orbits.get(o).unwrap_or(&self.global), did))
+    }
+}
+
+/// Whether `issuer`, a DID or a DID URL of one of its verification methods, controls
+/// `orbit`, and so is the root authority over it.
+pub fn is_controller(orbit: &OrbitId, issuer: &str) -> bool {
+    issuer.split('#').next() == Some(orbit.did().as_str())
+}
+
+fn method_in(methods: &BTreeSet<String>, did: &str) -> bool {
+    methods.is_empty()
+        || methods.iter().any(|m| {
+            did.strip_prefix("did:")
+                .and_then(|d| d.strip_prefix(m.as_str()))
+                .map_or(false, |rest| rest.starts_with(':'))
+        })
+}
@@ -15,6 +15,26 @@ pub trait HeaderEncode {
     fn decode(s: &str) -> Result<(Self, Vec<u8>), EncodingError>
     where
         Self: Sized;
+    /// Like [`HeaderEncode::decode`], but rejects CBOR payloads which are not in
+    /// canonical DAG-CBOR form.
+    fn decode_strict(s: &str) -> Result<(Self, Vec<u8>), EncodingError>
+    where
+        Self: Sized,
+    {
+        Self::decode(s)
+    }
+}
+
+// decode DAG-CBOR, rejecting any encoding which does not re-encode to the same bytes
+fn decode_canonical<T>(v: &[u8]) -> Result<T, EncodingError>
+where
+    T: Decode<DagCborCodec> + Encode<DagCborCodec>,
+{
+    let t: T = DagCborCodec.decode(v)?;
+    if DagCborCodec.encode(&t)? != v {
+        return Err(EncodingError::NonCanonical);
+    }
+    Ok(t)
 }
 
 #[derive(Clone, Debug)]
@@ -45,6 +65,15 @@ impl HeaderEncode for KeplerDelegation {
             (Self::Cacao(Box::new(DagCborCodec.decode(&v)?)), v)
         })
     }
+
+    fn decode_strict(s: &str) -> Result<(Self, Vec<u8>), EncodingError> {
+        Ok(if s.contains('.') {
+            Self::decode(s)?
+        } else {
+            let v = base64::decode_config(s, base64::URL_SAFE)?;
+            (Self::Cacao(Box::new(decode_canonical(&v)?)), v)
+        })
+    }
 }
 
 impl KeplerDelegation {
@@ -89,6 +118,10 @@ impl HeaderEncode for KeplerRevocation {
         let v = base64::decode_config(s, base64::URL_SAFE)?;
         Ok((Self::Cacao(DagCborCodec.decode(&v)?), v))
     }
+    fn decode_strict(s: &str) -> Result<(Self, Vec<u8>), EncodingError> {
+        let v = base64::decode_config(s, base64::URL_SAFE)?;
+        Ok((Self::Cacao(decode_canonical(&v)?), v))
+    }
 }
 
 pub async fn make_invocation(
@@ -116,6 +149,32 @@ pub async fn make_invocation(
     .sign(jwk.get_algorithm().unwrap_or_default(), jwk)?)
 }
 
+/// Build the unsigned payload of a UCAN in which `issuer` delegates `delegation_target`
+/// to `audience`, for controllers which sign outside of the SDK (e.g. Solana wallets).
+pub fn make_delegation_payload(
+    delegation_target: Vec<ResourceId>,
+    issuer: String,
+    audience: String,
+    parents: Vec<Cid>,
+    expiration: f64,
+    not_before: Option<f64>,
+    nonce: Option<String>,
+) -> Result<Payload, InvocationError> {
+    Ok(Payload {
+        issuer,
+        audience,
+        not_before: not_before.map(NumericDate::try_from_seconds).transpose()?,
+        expiration: NumericDate::try_from_seconds(expiration)?,
+        nonce: Some(nonce.unwrap_or_else(|| format!("urn:uuid:{}", Uuid::new_v4()))),
+        facts: None,
+        proof: parents,
+        attenuation: delegation_target
+            .into_iter()
+            .map(|t| t.try_into())
+            .collect::<Result<Vec<ssi::ucan::Capability>, _>>()?,
+    })
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum InvocationError {
     #[error(transparent)]
@@ -134,8 +193,116 @@ pub enum EncodingError {
     IpldError(#[from] libipld::error::Error),
     #[error(transparent)]
     Base64(#[from] base64::DecodeError),
+    #[error("CBOR payload is not canonically encoded")]
+    NonCanonical,
 }
 
 pub enum CapabilitiesQuery {
     All,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libipld::Ipld;
+
+    #[test]
+    fn canonical_cbor() {
+        // {"a": 1, "b": 2}, with keys in canonical order
+        let canonical = [0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x02];
+        // the same map with keys out of order
+        let non_canonical = [0xa2, 0x61, 0x62, 0x02, 0x61, 0x61, 0x01];
+
+        let lenient: Ipld = DagCborCodec.decode(&non_canonical).unwrap();
+        assert_eq!(lenient, DagCborCodec.decode::<Ipld>(&canonical).unwrap());
+
+        assert_eq!(decode_canonical::<Ipld>(&canonical).unwrap(), lenient);
+        assert!(matches!(
+            decode_canonical::<Ipld>(&non_canonical),
+            Err(EncodingError::NonCanonical)
+        ));
+    }
+
+    // the map encoded by `v`, re-encoded with its entries in reverse order
+    fn reversed_map(v: &[u8]) -> Vec<u8> {
+        let map = match DagCborCodec.decode::<Ipld>(v).unwrap() {
+            Ipld::Map(map) => map,
+            _ => panic!("not a map"),
+        };
+        assert!(map.len() < 24);
+        let mut reversed = vec![0xa0 | map.len() as u8];
+        for (k, v) in map.iter().rev() {
+            reversed.extend(DagCborCodec.encode(&Ipld::String(k.clone())).unwrap());
+            reversed.extend(DagCborCodec.encode(v).unwrap());
+        }
+        reversed
+    }
+
+    #[test]
+    fn canonical_cacaos() {
+        use cacaos::{
+            siwe::{Message, Version},
+            siwe_cacao::SiweCacao,
+        };
+        use libipld::multihash::{Code, MultihashDigest};
+
+        let cacao = |uri: &str| {
+            SiweCacao::new(
+                Message {
+                    domain: "example.com".parse().unwrap(),
+                    address: [0; 20],
+                    statement: None,
+                    uri: uri.parse().unwrap(),
+                    version: Version::V1,
+                    chain_id: 1,
+                    nonce: "12345678".to_string(),
+                    issued_at: "2022-01-01T00:00:00Z".parse().unwrap(),
+                    expiration_time: None,
+                    not_before: None,
+                    request_id: None,
+                    resources: vec![],
+                }
+                .into(),
+                [0; 65].into(),
+                None,
+            )
+        };
+        let non_canonical = |encoded: &str| {
+            let bytes = base64::decode_config(encoded, base64::URL_SAFE).unwrap();
+            let reordered = reversed_map(&bytes);
+            assert_ne!(reordered, bytes);
+            (bytes, base64::encode_config(reordered, base64::URL_SAFE))
+        };
+
+        let delegation = KeplerDelegation::Cacao(Box::new(cacao(
+            "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK",
+        )))
+        .encode()
+        .unwrap();
+        let (bytes, reordered) = non_canonical(&delegation);
+        assert_eq!(
+            KeplerDelegation::decode_strict(&delegation).unwrap().1,
+            bytes
+        );
+        assert!(matches!(
+            KeplerDelegation::decode_strict(&reordered),
+            Err(EncodingError::NonCanonical)
+        ));
+
+        let revocation = KeplerRevocation::Cacao(cacao(&format!(
+            "ucan:{}",
+            Cid::new_v1(0x55, Code::Sha2_256.digest(b"delegation"))
+        )))
+        .encode()
+        .unwrap();
+        let (bytes, reordered) = non_canonical(&revocation);
+        assert_eq!(
+            KeplerRevocation::decode_strict(&revocation).unwrap().1,
+            bytes
+        );
+        assert!(matches!(
+            KeplerRevocation::decode_strict(&reordered),
+            Err(EncodingError::NonCanonical)
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 pub mod authorization;
+pub mod peer;
 pub mod resolver;
 pub mod resource;
 
@@ -0,0 +1,97 @@
+//! Identifiers of orbit peers.
+//!
+//! A host holds an ed25519 keypair for each orbit it hosts, whose public key
+//! `/peer/generate/<orbit>` returns as a `did:key`, and which a host delegation names as
+//! the orbit's peer. The orbit's libp2p `PeerId` is derived from the same public key, as
+//! libp2p derives the ID of any ed25519 key: the key is encoded as a `PublicKey` protobuf
+//! message (`0x08 0x01` for the key type `Ed25519`, then `0x12 0x20` and the 32 key
+//! bytes), which, being shorter than 42 bytes, is wrapped in an identity multihash
+//! rather than hashed, and the multihash is base58btc encoded without a multibase
+//! prefix. Such IDs always start with `12D3KooW`.
+//!
+//! The derivation needs nothing but the public key, so clients can compute an orbit's
+//! peer ID offline.
+
+use libipld::cid::multibase::{self, Base};
+
+/// Multicodec of ed25519 public keys, whose unsigned varint encoding is `0xed 0x01`.
+const ED25519_PUB: [u8; 2] = [0xed, 0x01];
+/// Multicodec of the identity multihash.
+const IDENTITY: u8 = 0x00;
+/// Field tags and lengths of the protobuf encoded `PublicKey` of an ed25519 key.
+const PROTOBUF_ED25519: [u8; 4] = [0x08, 0x01, 0x12, 0x20];
+
+#[derive(Debug, thiserror::Error)]
+pub enum PeerIdError {
+    #[error("{0} is not a did:key")]
+    NotDidKey(String),
+    #[error(transparent)]
+    Encoding(#[from] multibase::Error),
+    #[error("only ed25519 keys identify orbit peers")]
+    UnsupportedKey,
+}
+
+/// The libp2p `PeerId` of an orbit peer with the given ed25519 public key.
+pub fn peer_id(public_key: &[u8; 32]) -> String {
+    let mut multihash = vec![IDENTITY, (PROTOBUF_ED25519.len() + public_key.len()) as u8];
+    multihash.extend_from_slice(&PROTOBUF_ED25519);
+    multihash.extend_from_slice(public_key);
+    Base::Base58Btc.encode(multihash)
+}
+
+/// The libp2p `PeerId` of an orbit peer given as a `did:key`, as returned by
+/// `/peer/generate/<orbit>`.
+///
+/// Hosts have encoded the key's multicodec as the single byte `0xed`, rather than as the
+/// varint `0xed 0x01`, so both encodings are accepted.
+pub fn peer_id_from_did_key(did: &str) -> Result<String, PeerIdError> {
+    let encoded = did
+        .strip_prefix("did:key:")
+        .ok_or_else(|| PeerIdError::NotDidKey(did.to_string()))?;
+    let (_, bytes) = multibase::decode(encoded)?;
+    let key = match bytes.strip_prefix(&ED25519_PUB) {
+        Some(key) if key.len() == 32 => key,
+        _ => bytes
+            .strip_prefix(&ED25519_PUB[..1])
+            .filter(|k| k.len() == 32)
+            .ok_or(PeerIdError::UnsupportedKey)?,
+    };
+    let mut public_key = [0; 32];
+    public_key.copy_from_slice(key);
+    Ok(peer_id(&public_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_ids() {
+        let key = [7; 32];
+        let id = peer_id(&key);
+        assert!(id.starts_with("12D3KooW"));
+
+        // both multicodec encodings of the key give the same peer
+        for codec in [&ED25519_PUB[..], &ED25519_PUB[..1]] {
+            let did = format!(
+                "did:key:{}",
+                multibase::encode(Base::Base58Btc, [codec, &key[..]].concat())
+            );
+            assert_eq!(peer_id_from_did_key(&did).unwrap(), id);
+        }
+
+        assert!(matches!(
+            peer_id_from_did_key("did:pkh:eip155:1:0x0000000000000000000000000000000000000000"),
+            Err(PeerIdError::NotDidKey(_))
+        ));
+        // a secp256k1 key
+        let did = format!(
+            "did:key:{}",
+            multibase::encode(Base::Base58Btc, [&[0xe7, 0x01][..], &[2; 33]].concat())
+        );
+        assert!(matches!(
+            peer_id_from_did_key(&did),
+            Err(PeerIdError::UnsupportedKey)
+        ));
+    }
+}
@@ -39,10 +39,47 @@ impl OrbitId {
         &self.suffix
     }
 
+    /// The orbit's name, as it appears in the orbit ID, i.e. percent-encoded.
     pub fn name(&self) -> &str {
         &self.id
     }
 
+    /// The orbit's name with percent-encoding decoded, e.g. `team/project` for an
+    /// orbit named `team%2Fproject`.
+    pub fn decoded_name(&self) -> String {
+        String::from_utf8_lossy(&percent_decode(&self.id)).into_owned()
+    }
+
+    /// Percent-encode a name for use in an orbit ID.
+    ///
+    /// Characters other than those allowed in a URI host, such as `/`, `:` and `%`, are
+    /// encoded, so hierarchical names like `team/project` stay within the name segment.
+    pub fn encode_name(name: &str) -> String {
+        name.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z'
+                | b'a'..=b'z'
+                | b'0'..=b'9'
+                | b'-'
+                | b'.'
+                | b'_'
+                | b'~'
+                | b'!'
+                | b'$'
+                | b'&'
+                | b'\''
+                | b'('
+                | b')'
+                | b'*'
+                | b'+'
+                | b','
+                | b';'
+                | b'=' => (b as char).to_string(),
+                _ => format!("%{b:02X}"),
+            })
+            .collect()
+    }
+
     pub fn get_cid(&self) -> Cid {
         Cid::new_v1(
             0x55, // raw codec
@@ -107,13 +144,19 @@ impl ResourceId {
     pub fn fragment(&self) -> Option<&str> {
         self.fragment.as_ref().map(|s| s.as_ref())
     }
+    /// Checks that this resource is within the scope of `base`.
+    ///
+    /// A `base` with neither service nor path covers every service and path in the orbit.
+    /// Fragments must always match exactly.
     pub fn extends(&self, base: &ResourceId) -> Result<(), ResourceCheckError> {
         if base.orbit() != self.orbit() {
             Err(ResourceCheckError::IncorrectOrbit)
-        } else if base.service() != self.service() {
-            Err(ResourceCheckError::IncorrectService)
         } else if base.fragment() != self.fragment() {
             Err(ResourceCheckError::IncorrectFragment)
+        } else if base.service().is_none() && base.path().is_none() {
+            Ok(())
+        } else if base.service() != self.service() {
+            Err(ResourceCheckError::IncorrectService)
         } else if !self
             .path()
             .unwrap_or("")
@@ -194,6 +237,52 @@ pub enum ResourceCheckError {
     DoesNotExtendPath,
 }
 
+/// The byte encoded by the two hex digits at the start of `s`, if any.
+fn hex_byte(s: &str) -> Option<u8> {
+    s.get(..2)
+        .filter(|h| h.bytes().all(|c| c.is_ascii_hexdigit()))
+        .and_then(|h| u8::from_str_radix(h, 16).ok())
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match s.as_bytes()[i] {
+            b'%' => match s.get(i + 1..).and_then(hex_byte) {
+                Some(b) => {
+                    bytes.push(b);
+                    i += 3;
+                    continue;
+                }
+                None => bytes.push(b'%'),
+            },
+            b => bytes.push(b),
+        }
+        i += 1;
+    }
+    bytes
+}
+
+/// Validate an orbit name taken from the host of an orbit or resource ID.
+///
+/// Names must decode to UTF-8, and are normalised to the encoding of
+/// [`OrbitId::encode_name`], so each name has a single ID: characters which need no
+/// encoding are decoded, as RFC 3986 §6.2.2.2 describes, and the remaining
+/// percent-encodings are upper case.
+fn parse_name(host: &str) -> Result<String, KRIParseError> {
+    if host
+        .split('%')
+        .skip(1)
+        .any(|escape| hex_byte(escape).is_none())
+    {
+        return Err(KRIParseError::IncorrectForm);
+    }
+    std::str::from_utf8(&percent_decode(host))
+        .map(OrbitId::encode_name)
+        .map_err(|_| KRIParseError::IncorrectForm)
+}
+
 impl fmt::Display for OrbitId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "kepler:{}://{}", &self.suffix, &self.id)
@@ -222,17 +311,63 @@ pub enum KRIParseError {
     IncorrectForm,
     #[error(transparent)]
     InvalidUri(#[from] UriError),
+    #[error("{0} is longer than {1} bytes")]
+    TooLong(&'static str, usize),
+}
+
+/// Maximum lengths, in bytes, of the components of orbit and resource IDs.
+///
+/// These keep IDs, which end up in storage paths and database keys, to a bounded size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KRILimits {
+    /// The orbit's DID suffix, e.g. `pkh:eip155:1:0x...`.
+    pub suffix: usize,
+    /// The orbit's name.
+    pub name: usize,
+    pub service: usize,
+    pub path: usize,
+}
+
+impl KRILimits {
+    /// The limits applied by `FromStr`.
+    pub const DEFAULT: Self = Self {
+        suffix: 512,
+        name: 255,
+        service: 64,
+        path: 1024,
+    };
+
+    fn check(&self, component: &'static str, value: &str, max: usize) -> Result<(), KRIParseError> {
+        if value.len() > max {
+            Err(KRIParseError::TooLong(component, max))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for KRILimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
 }
 
 impl FromStr for OrbitId {
     type Err = KRIParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, &KRILimits::DEFAULT)
+    }
+}
+
+impl OrbitId {
+    /// Parse an orbit ID, rejecting components longer than `limits`.
+    pub fn parse_with(s: &str, limits: &KRILimits) -> Result<Self, KRIParseError> {
         let s = s
             .strip_prefix("kepler:")
             .ok_or(KRIParseError::IncorrectForm)?;
         let p = match s.find("://") {
             Some(p) if p > 0 => p,
-            _ => return Err(Self::Err::IncorrectForm),
+            _ => return Err(KRIParseError::IncorrectForm),
         };
         let uri = UriString::from_str(&["dummy", &s[p..]].concat())?;
         match uri.authority_components().map(|a| {
@@ -245,11 +380,15 @@ impl FromStr for OrbitId {
                 uri.query_str(),
             )
         }) {
-            Some((id, None, None, "", None, None)) => Ok(Self {
-                suffix: s[..p].to_string(),
-                id,
-            }),
-            _ => Err(Self::Err::IncorrectForm),
+            Some((id, None, None, "", None, None)) => {
+                limits.check("suffix", &s[..p], limits.suffix)?;
+                limits.check("name", &id, limits.name)?;
+                Ok(Self {
+                    suffix: s[..p].to_string(),
+                    id: parse_name(&id)?,
+                })
+            }
+            _ => Err(KRIParseError::IncorrectForm),
         }
     }
 }
@@ -257,12 +396,19 @@ impl FromStr for OrbitId {
 impl FromStr for ResourceId {
     type Err = KRIParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, &KRILimits::DEFAULT)
+    }
+}
+
+impl ResourceId {
+    /// Parse a resource ID, rejecting components longer than `limits`.
+    pub fn parse_with(s: &str, limits: &KRILimits) -> Result<Self, KRIParseError> {
         let s = s
             .strip_prefix("kepler:")
             .ok_or(KRIParseError::IncorrectForm)?;
         let p = match s.find("://") {
             Some(p) if p > 0 => p,
-            _ => return Err(Self::Err::IncorrectForm),
+            _ => return Err(KRIParseError::IncorrectForm),
         };
         let uri = UriString::from_str(&["dummy", &s[p..]].concat())?;
         match uri.authority_components().map(|a| {
@@ -275,16 +421,25 @@ impl FromStr for ResourceId {
                 }),
             )
         }) {
-            Some((host, None, path)) => Ok(Self {
-                orbit: OrbitId {
-                    suffix: s[..p].to_string(),
-                    id: host.into(),
-                },
-                service: path.map(|(s, _)| s.into()),
-                path: path.map(|(_, pa)| format!("/{pa}")),
-                fragment: uri.fragment().map(|s| s.to_string()),
-            }),
-            _ => Err(Self::Err::IncorrectForm),
+            Some((host, None, path)) => {
+                limits.check("suffix", &s[..p], limits.suffix)?;
+                limits.check("name", host, limits.name)?;
+                if let Some((service, pa)) = path {
+                    limits.check("service", service, limits.service)?;
+                    // account for the leading '/' added to the path
+                    limits.check("path", pa, limits.path.saturating_sub(1))?;
+                }
+                Ok(Self {
+                    orbit: OrbitId {
+                        suffix: s[..p].to_string(),
+                        id: parse_name(host)?,
+                    },
+                    service: path.map(|(s, _)| s.into()),
+                    path: path.map(|(_, pa)| format!("/{pa}")),
+                    fragment: uri.fragment().map(|s| s.to_string()),
+                })
+            }
+            _ => Err(KRIParseError::IncorrectForm),
         }
     }
 }
@@ -346,6 +501,97 @@ mod tests {
         assert_eq!("list", res4.fragment().unwrap());
     }
 
+    #[test]
+    fn solana() {
+        let res: ResourceId = "kepler:pkh:solana:4sGjMW1sUnHzSxGspuhpqLDx6wiyjNtZ:CKg5d12Jhpej1JqtmxLJgaFqqeYjxgPqToJ4LBdvG9Ev://default/kv/path#get"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            "did:pkh:solana:4sGjMW1sUnHzSxGspuhpqLDx6wiyjNtZ:CKg5d12Jhpej1JqtmxLJgaFqqeYjxgPqToJ4LBdvG9Ev",
+            res.orbit().did()
+        );
+        assert_eq!("default", res.orbit().name());
+        assert_eq!("kv", res.service().unwrap());
+        assert_eq!("/path", res.path().unwrap());
+        assert_eq!("get", res.fragment().unwrap());
+    }
+
+    #[test]
+    fn web() {
+        let res: ResourceId = "kepler:web:example.com%3A8443:users:alice://default/kv/path#get"
+            .parse()
+            .unwrap();
+
+        assert_eq!("web:example.com%3A8443:users:alice", res.orbit().suffix());
+        assert_eq!("did:web:example.com%3A8443:users:alice", res.orbit().did());
+        assert_eq!("default", res.orbit().name());
+        assert_eq!("/path", res.path().unwrap());
+        assert_eq!(
+            "kepler:web:example.com%3A8443:users:alice://default/kv/path#get",
+            res.to_string()
+        );
+    }
+
+    #[test]
+    fn hierarchical_names() {
+        let uri =
+            "kepler:pkh:eip155:1:0xb1f5c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5://team%2Fproject/kv/x";
+        let res: ResourceId = uri.parse().unwrap();
+        assert_eq!("team%2Fproject", res.orbit().name());
+        assert_eq!("team/project", res.orbit().decoded_name());
+        assert_eq!("kv", res.service().unwrap());
+        assert_eq!("/x", res.path().unwrap());
+        assert_eq!(uri, res.to_string());
+
+        let orbit: OrbitId =
+            "kepler:pkh:eip155:1:0xb1f5c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5://team%2Fproject"
+                .parse()
+                .unwrap();
+        assert_eq!(&orbit, res.orbit());
+        assert_eq!("team%2Fproject", OrbitId::encode_name("team/project"));
+        assert_eq!("orbit0", OrbitId::encode_name("orbit0"));
+        assert_eq!("a%3Ab%25", OrbitId::encode_name("a:b%"));
+
+        // encodings are normalised, so there is one ID per name
+        let lower: ResourceId = uri.replace("%2F", "%2f").parse().unwrap();
+        assert_eq!(lower, res);
+        assert_eq!(uri, lower.to_string());
+
+        // as are characters encoded needlessly
+        let encoded: ResourceId = uri
+            .replace("team", "te%61%6D")
+            .replace("%2F", "%2f")
+            .parse()
+            .unwrap();
+        assert_eq!(encoded, res);
+        assert_eq!(uri, encoded.to_string());
+        let orbit: OrbitId = "kepler:ens:example.eth://team%41%21".parse().unwrap();
+        assert_eq!(
+            orbit,
+            "kepler:ens:example.eth://teamA!"
+                .parse::<OrbitId>()
+                .unwrap()
+        );
+        assert_eq!("teamA!", orbit.name());
+        assert_eq!(orbit, orbit.to_string().parse::<OrbitId>().unwrap());
+
+        // the orbit ends at the first unencoded slash
+        let base: ResourceId =
+            "kepler:pkh:eip155:1:0xb1f5c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5://team"
+                .parse()
+                .unwrap();
+        assert!(matches!(
+            res.extends(&base),
+            Err(ResourceCheckError::IncorrectOrbit)
+        ));
+
+        // and escapes must be valid UTF-8
+        assert!("kepler:ens:example.eth://team%FF/kv"
+            .parse::<ResourceId>()
+            .is_err());
+    }
+
     #[test]
     fn failures() {
         let no_suffix: Result<ResourceId, _> = "kepler:://orbit0/kv/path/to/image.jpg".parse();
@@ -356,10 +602,127 @@ mod tests {
         assert!(invalid_name.is_err());
     }
 
+    #[test]
+    fn too_long() {
+        let limits = KRILimits::DEFAULT;
+        let long = |n| "a".repeat(n);
+
+        // components at the limit are accepted
+        let at_limit = format!(
+            "kepler:{}://{}/{}/{}",
+            long(limits.suffix),
+            long(limits.name),
+            long(limits.service),
+            long(limits.path - 1)
+        );
+        let res: ResourceId = at_limit.parse().unwrap();
+        assert_eq!(res.path().unwrap().len(), limits.path);
+        assert!(
+            format!("kepler:{}://{}", long(limits.suffix), long(limits.name))
+                .parse::<OrbitId>()
+                .is_ok()
+        );
+
+        for (component, uri) in [
+            (
+                "suffix",
+                format!("kepler:{}://orbit0/kv/a", long(limits.suffix + 1)),
+            ),
+            (
+                "name",
+                format!("kepler:ens:example.eth://{}/kv/a", long(limits.name + 1)),
+            ),
+            (
+                "service",
+                format!(
+                    "kepler:ens:example.eth://orbit0/{}/a",
+                    long(limits.service + 1)
+                ),
+            ),
+            (
+                "path",
+                format!("kepler:ens:example.eth://orbit0/kv/{}", long(limits.path)),
+            ),
+        ] {
+            assert!(matches!(
+                uri.parse::<ResourceId>(),
+                Err(KRIParseError::TooLong(c, _)) if c == component
+            ));
+        }
+        assert!(matches!(
+            format!("kepler:{}://orbit0", long(limits.suffix + 1)).parse::<OrbitId>(),
+            Err(KRIParseError::TooLong("suffix", 512))
+        ));
+
+        // and the limits can be set per parse
+        let strict = KRILimits {
+            name: 4,
+            ..KRILimits::DEFAULT
+        };
+        assert!(matches!(
+            ResourceId::parse_with("kepler:ens:example.eth://orbit0/kv/a", &strict),
+            Err(KRIParseError::TooLong("name", 4))
+        ));
+        assert!(ResourceId::parse_with("kepler:ens:example.eth://orb/kv/a", &strict).is_ok());
+    }
+
     #[test]
     fn roundtrip() {
         let resource_uri: String = "kepler:ens:example.eth://orbit0/kv/prefix#list".into();
         let res4: ResourceId = resource_uri.parse().unwrap();
         assert_eq!(resource_uri, res4.to_string());
     }
+
+    #[test]
+    fn extends() {
+        let orbit_wide: ResourceId = "kepler:ens:example.eth://orbit0".parse().unwrap();
+        let kv: ResourceId = "kepler:ens:example.eth://orbit0/kv".parse().unwrap();
+        let kv_path: ResourceId = "kepler:ens:example.eth://orbit0/kv/path/to/image.jpg"
+            .parse()
+            .unwrap();
+        let capabilities: ResourceId = "kepler:ens:example.eth://orbit0/capabilities/all"
+            .parse()
+            .unwrap();
+
+        // an orbit-wide base covers any service and path in the orbit
+        assert!(kv.extends(&orbit_wide).is_ok());
+        assert!(kv_path.extends(&orbit_wide).is_ok());
+        assert!(capabilities.extends(&orbit_wide).is_ok());
+        assert!(orbit_wide.extends(&orbit_wide).is_ok());
+
+        // but not other orbits
+        let other: ResourceId = "kepler:ens:example.eth://orbit1/kv".parse().unwrap();
+        assert!(matches!(
+            other.extends(&orbit_wide),
+            Err(ResourceCheckError::IncorrectOrbit)
+        ));
+
+        // and fragments still have to match
+        let orbit_peer: ResourceId = "kepler:ens:example.eth://orbit0#peer".parse().unwrap();
+        let kv_list: ResourceId = "kepler:ens:example.eth://orbit0/kv#list".parse().unwrap();
+        assert!(matches!(
+            kv.extends(&orbit_peer),
+            Err(ResourceCheckError::IncorrectFragment)
+        ));
+        assert!(matches!(
+            kv_list.extends(&orbit_wide),
+            Err(ResourceCheckError::IncorrectFragment)
+        ));
+
+        // a service-specific base stays restricted to that service and path prefix
+        assert!(kv_path.extends(&kv).is_ok());
+        assert!(matches!(
+            capabilities.extends(&kv),
+            Err(ResourceCheckError::IncorrectService)
+        ));
+        assert!(matches!(
+            orbit_wide.extends(&kv),
+            Err(ResourceCheckError::IncorrectService)
+        ));
+        let kv_other: ResourceId = "kepler:ens:example.eth://orbit0/kv/other".parse().unwrap();
+        assert!(matches!(
+            kv_path.extends(&kv_other),
+            Err(ResourceCheckError::DoesNotExtendPath)
+        ));
+    }
 }
@@ -26,6 +26,8 @@ export type SessionConfig = {
   parents?: string[]
   /** Optional jwk to delegate to */
   jwk?: object
+  /** Type of session key to generate if no jwk is given, "ed25519" (default) or "secp256k1" */
+  keyType?: "ed25519" | "secp256k1"
 }
 "#;
 
@@ -68,3 +70,22 @@ export type HostConfig = {
   peerId: string,
 }
 "#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_DEF: &'static str = r#"
+/**
+ * Configuration object for generating a SIWE message revoking a delegation.
+ */
+export type RevocationConfig = {
+  /** Ethereum address of the delegator. */
+  address: string,
+  /** Chain ID. */
+  chainId: number,
+  /** Domain of the webpage. */
+  domain: string,
+  /** Current time for SIWE message. */
+  issuedAt: string,
+  /** The CID of the delegation to revoke, e.g. the `delegationCid` of a session. */
+  delegationCid: string,
+}
+"#;
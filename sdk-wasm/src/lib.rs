@@ -40,6 +40,28 @@ pub fn makeOrbitId(address: String, chainId: u32, name: Option<String>) -> Strin
     util::make_orbit_id_pkh_eip155(address, chainId, name)
 }
 
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn makeOrbitIdSolana(
+    address: String,
+    chainReference: Option<String>,
+    name: Option<String>,
+) -> String {
+    util::make_orbit_id_pkh_solana(address, chainReference, name)
+}
+
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn makeOrbitIdWeb(domain: String, name: Option<String>) -> String {
+    util::make_orbit_id_web(domain, name)
+}
+
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn orbitPeerId(hostKey: String) -> Result<String, JsValue> {
+    map_jsvalue(util::orbit_peer_id(&hostKey))
+}
+
 #[wasm_bindgen]
 #[allow(non_snake_case)]
 pub fn prepareSession(config: String) -> Promise {
@@ -67,6 +89,33 @@ pub fn completeSessionSetup(config: String) -> Result<String, JsValue> {
     )
 }
 
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn prepareSolanaSession(config: String) -> Promise {
+    map_async_jsvalue(async move {
+        session::prepare_solana_session(
+            serde_json::from_str(&config).map_err(session::Error::JSONDeserializing)?,
+        )
+        .await
+        .and_then(|preparation| {
+            serde_json::to_string(&preparation).map_err(session::Error::JSONSerializing)
+        })
+    })
+}
+
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn completeSolanaSessionSetup(config: String) -> Result<String, JsValue> {
+    map_jsvalue(
+        serde_json::from_str(&config)
+            .map_err(session::Error::JSONDeserializing)
+            .and_then(session::complete_solana_session_setup)
+            .and_then(|session| {
+                serde_json::to_string(&session).map_err(session::Error::JSONSerializing)
+            }),
+    )
+}
+
 #[wasm_bindgen]
 #[allow(non_snake_case)]
 pub fn invoke(session: String, service: String, path: String, action: String) -> Promise {
@@ -105,3 +154,27 @@ pub fn siweToDelegationHeaders(signedSIWEMessage: String) -> Result<String, JsVa
             }),
     )
 }
+
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn generateRevocationSIWEMessage(config: String) -> Result<String, JsValue> {
+    map_jsvalue(
+        serde_json::from_str(&config)
+            .map_err(siwe_utils::Error::JSONDeserializing)
+            .and_then(siwe_utils::generate_revocation_siwe_message)
+            .map(|message| message.to_string()),
+    )
+}
+
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn siweToRevocationHeaders(signedSIWEMessage: String) -> Result<String, JsValue> {
+    map_jsvalue(
+        serde_json::from_str(&signedSIWEMessage)
+            .map_err(siwe_utils::Error::JSONDeserializing)
+            .map(siwe_utils::siwe_to_revocation_headers)
+            .and_then(|headers| {
+                serde_json::to_string(&headers).map_err(siwe_utils::Error::JSONSerializing)
+            }),
+    )
+}
@@ -1,4 +1,4 @@
-use kepler_lib::authorization::{KeplerDelegation, KeplerInvocation};
+use kepler_lib::authorization::{KeplerDelegation, KeplerInvocation, KeplerRevocation};
 use serde::{Deserialize, Serialize};
 
 use crate::session::Session;
@@ -15,6 +15,12 @@ pub struct InvocationHeaders {
     invocation: KeplerInvocation,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RevocationHeaders {
+    #[serde(with = "header_enc", rename = "Authorization")]
+    revocation: KeplerRevocation,
+}
+
 impl InvocationHeaders {
     pub async fn from(
         session: Session,
@@ -35,6 +41,12 @@ impl DelegationHeaders {
     }
 }
 
+impl RevocationHeaders {
+    pub fn new(revocation: KeplerRevocation) -> Self {
+        Self { revocation }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("failed to generate proof for invocation: {0}")]
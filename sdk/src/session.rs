@@ -1,7 +1,10 @@
 use crate::authorization::DelegationHeaders;
 use http::uri::Authority;
 use kepler_lib::{
-    authorization::{make_invocation, InvocationError, KeplerInvocation},
+    authorization::{
+        make_delegation_payload, make_invocation, InvocationError, KeplerDelegation,
+        KeplerInvocation,
+    },
     cacaos::{
         siwe::{generate_nonce, Message, TimeStamp, Version as SIWEVersion},
         siwe_cacao::SIWESignature,
@@ -10,7 +13,12 @@ use kepler_lib::{
     resolver::DID_METHODS,
     resource::OrbitId,
     siwe_recap::Builder,
-    ssi::{did::Source, jwk::JWK, vc::get_verification_method},
+    ssi::{
+        did::Source,
+        jwk::{Algorithm, JWK},
+        ucan::Ucan,
+        vc::get_verification_method,
+    },
 };
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
@@ -40,6 +48,26 @@ pub struct SessionConfig {
     pub parents: Option<Vec<Cid>>,
     #[serde(default)]
     pub jwk: Option<JWK>,
+    /// Type of session key to generate if `jwk` is not given.
+    #[serde(default)]
+    pub key_type: KeyType,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    #[default]
+    Ed25519,
+    Secp256k1,
+}
+
+impl KeyType {
+    fn generate(self) -> Result<JWK, kepler_lib::ssi::jwk::Error> {
+        match self {
+            Self::Ed25519 => JWK::generate_ed25519(),
+            Self::Secp256k1 => JWK::generate_secp256k1(),
+        }
+    }
 }
 
 #[serde_as]
@@ -63,6 +91,48 @@ pub struct SignedSession {
     pub signature: SIWESignature,
 }
 
+/// Configuration of a session delegated by a `did:pkh:solana` orbit controller, which
+/// signs a UCAN with its ed25519 key instead of a SIWE message.
+#[serde_as]
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SolanaSessionConfig {
+    pub actions: HashMap<String, HashMap<String, Vec<String>>>,
+    pub orbit_id: OrbitId,
+    /// Unix timestamp, in seconds.
+    #[serde(default)]
+    pub not_before: Option<f64>,
+    /// Unix timestamp, in seconds.
+    pub expiration_time: f64,
+    #[serde_as(as = "Option<Vec<DisplayFromStr>>")]
+    #[serde(default)]
+    pub parents: Option<Vec<Cid>>,
+    #[serde(default)]
+    pub jwk: Option<JWK>,
+    #[serde(default)]
+    pub key_type: KeyType,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PreparedSolanaSession {
+    pub jwk: JWK,
+    pub orbit_id: OrbitId,
+    /// The bytes for the controller to sign, i.e. the encoded header and payload of the
+    /// delegation UCAN.
+    pub signing_input: String,
+    pub verification_method: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedSolanaSession {
+    #[serde(flatten)]
+    pub session: PreparedSolanaSession,
+    /// Hex-encoded ed25519 signature of `signingInput`.
+    pub signature: String,
+}
+
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -155,12 +225,13 @@ impl Session {
     }
 }
 
-pub async fn prepare_session(config: SessionConfig) -> Result<PreparedSession, Error> {
-    let mut jwk = match &config.jwk {
-        Some(k) => k.clone(),
-        None => JWK::generate_ed25519()?,
+async fn session_key(jwk: Option<JWK>, key_type: KeyType) -> Result<(JWK, String), Error> {
+    let mut jwk = match jwk {
+        Some(k) => k,
+        None => key_type.generate()?,
     };
-    jwk.algorithm = Some(kepler_lib::ssi::jwk::Algorithm::EdDSA);
+    // EdDSA for ed25519 keys and ES256K for secp256k1 keys
+    jwk.algorithm = Some(jwk.get_algorithm().unwrap_or(Algorithm::EdDSA));
 
     let did = DID_METHODS
         .generate(&Source::KeyAndPattern(&jwk, "key"))
@@ -169,6 +240,11 @@ pub async fn prepare_session(config: SessionConfig) -> Result<PreparedSession, E
     let verification_method = get_verification_method(&did, did_resolver)
         .await
         .ok_or(Error::UnableToGenerateDID)?;
+    Ok((jwk, verification_method))
+}
+
+pub async fn prepare_session(config: SessionConfig) -> Result<PreparedSession, Error> {
+    let (jwk, verification_method) = session_key(config.jwk.clone(), config.key_type).await?;
 
     let orbit_id = config.orbit_id.clone();
 
@@ -186,7 +262,6 @@ pub async fn prepare_session(config: SessionConfig) -> Result<PreparedSession, E
 
 pub fn complete_session_setup(signed_session: SignedSession) -> Result<Session, Error> {
     use kepler_lib::{
-        authorization::KeplerDelegation,
         cacaos::siwe_cacao::SiweCacao,
         libipld::{cbor::DagCborCodec, multihash::Code, store::DefaultParams, Block},
     };
@@ -210,6 +285,92 @@ pub fn complete_session_setup(signed_session: SignedSession) -> Result<Session,
     })
 }
 
+pub async fn prepare_solana_session(
+    config: SolanaSessionConfig,
+) -> Result<PreparedSolanaSession, Error> {
+    if !config.orbit_id.suffix().starts_with("pkh:solana:") {
+        return Err(Error::NotASolanaOrbit(config.orbit_id.to_string()));
+    }
+    let (jwk, verification_method) = session_key(config.jwk, config.key_type).await?;
+
+    let targets = config
+        .actions
+        .into_iter()
+        .flat_map(|(service, paths)| {
+            paths.into_iter().flat_map(move |(path, actions)| {
+                let service = service.clone();
+                actions
+                    .into_iter()
+                    .map(move |a| (service.clone(), path.clone(), a))
+            })
+        })
+        .map(|(s, p, a)| {
+            config
+                .orbit_id
+                .clone()
+                .to_resource(Some(s), Some(p), Some(a))
+        })
+        .collect();
+    let payload = make_delegation_payload(
+        targets,
+        format!("{}#controller", config.orbit_id.did()),
+        verification_method.clone(),
+        config.parents.unwrap_or_default(),
+        config.expiration_time,
+        config.not_before,
+        None,
+    )
+    .map_err(Error::UnableToGenerateDelegation)?;
+
+    // sign with a throwaway key only to get the encoded header and payload, the
+    // controller's signature replaces this one in `complete_solana_session_setup`
+    let placeholder = JWK::generate_ed25519()?;
+    let encoded = payload
+        .sign(Algorithm::EdDSA, &placeholder)
+        .and_then(|ucan| ucan.encode())
+        .map_err(|e| Error::UnableToGenerateDelegation(e.into()))?;
+    let signing_input = encoded
+        .rsplit_once('.')
+        .map(|(input, _)| input.to_string())
+        .unwrap_or(encoded);
+
+    Ok(PreparedSolanaSession {
+        jwk,
+        orbit_id: config.orbit_id,
+        signing_input,
+        verification_method,
+    })
+}
+
+pub fn complete_solana_session_setup(
+    signed_session: SignedSolanaSession,
+) -> Result<Session, Error> {
+    use kepler_lib::libipld::multihash::{Code, MultihashDigest};
+    // multicodec of raw binary, which kepler addresses UCAN delegations with
+    const RAW_CODEC: u64 = 0x55;
+
+    let signature = &signed_session.signature;
+    let signature = hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+        .map_err(Error::InvalidSignature)?;
+    let encoded = format!(
+        "{}.{}",
+        signed_session.session.signing_input,
+        base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+    );
+    let delegation =
+        Ucan::decode(&encoded).map_err(|e| Error::UnableToGenerateDelegation(e.into()))?;
+    let delegation_cid = Cid::new_v1(RAW_CODEC, Code::Blake3_256.digest(encoded.as_bytes()));
+    let delegation_header = DelegationHeaders::new(KeplerDelegation::Ucan(Box::new(delegation)));
+
+    Ok(Session {
+        delegation_header,
+        delegation_cid,
+        jwk: signed_session.session.jwk,
+        orbit_id: signed_session.session.orbit_id,
+        verification_method: signed_session.session.verification_method,
+    })
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("unable to generate session key: {0}")]
@@ -218,6 +379,12 @@ pub enum Error {
     UnableToGenerateDID,
     #[error("unable to generate the SIWE message to start the session: {0}")]
     UnableToGenerateSIWEMessage(String),
+    #[error("unable to generate the delegation UCAN: {0}")]
+    UnableToGenerateDelegation(InvocationError),
+    #[error("{0} is not controlled by a Solana account")]
+    NotASolanaOrbit(String),
+    #[error("failed to parse the delegation signature: {0}")]
+    InvalidSignature(hex::FromHexError),
     #[error("unable to generate the CID: {0}")]
     UnableToGenerateCid(kepler_lib::libipld::error::Error),
     #[error("failed to translate response to JSON: {0}")]
@@ -231,6 +398,10 @@ pub mod test {
     use super::*;
     use serde_json::json;
     pub async fn test_session() -> Session {
+        test_session_with_key("ed25519").await
+    }
+
+    pub async fn test_session_with_key(key_type: &str) -> Session {
         let config = json!({
             "actions": { "kv": { "path": vec!["put", "get", "list", "del", "metadata"] },
             "capabilities": { "": vec!["read"] }},
@@ -240,6 +411,7 @@ pub mod test {
             "issuedAt": "2022-01-01T00:00:00.000Z",
             "orbitId": "kepler:pkh:eip155:1:0x7BD63AA37326a64d458559F44432103e3d6eEDE9://default",
             "expirationTime": "3000-01-01T00:00:00.000Z",
+            "keyType": key_type,
         });
         let prepared = prepare_session(serde_json::from_value(config).unwrap())
             .await
@@ -262,4 +434,84 @@ pub mod test {
             .await
             .expect("failed to create invocation");
     }
+
+    #[tokio::test]
+    async fn session_key_types() {
+        for (key_type, algorithm) in [
+            ("ed25519", Algorithm::EdDSA),
+            ("secp256k1", Algorithm::ES256K),
+        ] {
+            let session = test_session_with_key(key_type).await;
+            assert_eq!(session.jwk.algorithm, Some(algorithm));
+            let invocation = session
+                .invoke(vec![("kv".into(), "path".into(), "get".into())])
+                .await
+                .expect("failed to create invocation");
+            invocation
+                .verify_signature(DID_METHODS.to_resolver())
+                .await
+                .expect("invalid invocation signature");
+        }
+    }
+
+    #[tokio::test]
+    async fn solana_session_round_trip() {
+        use kepler_lib::ssi::jws::sign_bytes;
+
+        let controller = JWK::generate_ed25519().unwrap();
+        let did = DID_METHODS
+            .generate(&Source::KeyAndPattern(&controller, "solana"))
+            .expect("failed to generate did:pkh:solana");
+        let config = json!({
+            "actions": { "kv": { "path": vec!["put", "get"] } },
+            "orbitId": format!("kepler:{}://default", did.strip_prefix("did:").unwrap()),
+            "expirationTime": 32503680000f64,
+        });
+        let prepared = prepare_solana_session(serde_json::from_value(config).unwrap())
+            .await
+            .unwrap();
+        let signature = sign_bytes(
+            Algorithm::EdDSA,
+            prepared.signing_input.as_bytes(),
+            &controller,
+        )
+        .unwrap();
+        let mut signed = serde_json::to_value(prepared).unwrap();
+        signed
+            .as_object_mut()
+            .unwrap()
+            .insert("signature".into(), hex::encode(signature).into());
+        let session = complete_solana_session_setup(serde_json::from_value(signed).unwrap())
+            .expect("failed to complete the session");
+
+        let headers = serde_json::to_value(&session.delegation_header).unwrap();
+        let delegation = Ucan::decode(headers["Authorization"].as_str().unwrap()).unwrap();
+        assert_eq!(delegation.payload.issuer, format!("{did}#controller"));
+        assert_eq!(delegation.payload.audience, session.verification_method);
+        assert_eq!(delegation.payload.attenuation.len(), 2);
+        delegation
+            .verify_signature(DID_METHODS.to_resolver())
+            .await
+            .expect("invalid delegation signature");
+
+        let delegation_cid = session.delegation_cid;
+        let invocation = session
+            .invoke(vec![("kv".into(), "path".into(), "get".into())])
+            .await
+            .expect("failed to create invocation");
+        assert_eq!(invocation.payload.proof, vec![delegation_cid]);
+    }
+
+    #[tokio::test]
+    async fn solana_session_requires_solana_orbit() {
+        let config = json!({
+            "actions": { "kv": { "path": vec!["get"] } },
+            "orbitId": "kepler:pkh:eip155:1:0x7BD63AA37326a64d458559F44432103e3d6eEDE9://default",
+            "expirationTime": 32503680000f64,
+        });
+        assert!(matches!(
+            prepare_solana_session(serde_json::from_value(config).unwrap()).await,
+            Err(Error::NotASolanaOrbit(_))
+        ));
+    }
 }
@@ -1,15 +1,16 @@
 use http::uri::Authority;
-use kepler_lib::authorization::KeplerDelegation;
+use kepler_lib::authorization::{KeplerDelegation, KeplerRevocation};
 use kepler_lib::cacaos::{
     siwe::{generate_nonce, Message, TimeStamp, Version},
     siwe_cacao::{SIWESignature, SiweCacao},
 };
+use kepler_lib::libipld::Cid;
 use kepler_lib::resource::OrbitId;
 use kepler_lib::siwe_recap::Builder;
 use serde::Deserialize;
 use serde_with::{serde_as, DisplayFromStr};
 
-use crate::authorization::DelegationHeaders;
+use crate::authorization::{DelegationHeaders, RevocationHeaders};
 
 #[serde_as]
 #[derive(Deserialize)]
@@ -26,6 +27,24 @@ pub struct HostConfig {
     pub peer_id: String,
 }
 
+/// Configuration of a SIWE message revoking a delegation the signer issued, such as
+/// the delegation to a session key.
+#[serde_as]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevocationConfig {
+    #[serde(with = "crate::serde_siwe::address")]
+    pub address: [u8; 20],
+    pub chain_id: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub domain: Authority,
+    #[serde_as(as = "DisplayFromStr")]
+    pub issued_at: TimeStamp,
+    /// CID of the delegation to revoke.
+    #[serde_as(as = "DisplayFromStr")]
+    pub delegation_cid: Cid,
+}
+
 #[serde_as]
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -68,6 +87,29 @@ impl TryFrom<HostConfig> for Message {
     }
 }
 
+impl TryFrom<RevocationConfig> for Message {
+    type Error = String;
+    fn try_from(c: RevocationConfig) -> Result<Self, String> {
+        Ok(Self {
+            address: c.address,
+            chain_id: c.chain_id,
+            domain: c.domain,
+            issued_at: c.issued_at,
+            // the revoked delegation is the audience of the revocation
+            uri: format!("ucan:{}", c.delegation_cid)
+                .try_into()
+                .map_err(|e| format!("error parsing revoked delegation as a URI: {e}"))?,
+            nonce: generate_nonce(),
+            statement: None,
+            resources: vec![],
+            version: Version::V1,
+            not_before: None,
+            expiration_time: None,
+            request_id: None,
+        })
+    }
+}
+
 pub fn generate_host_siwe_message(config: HostConfig) -> Result<Message, Error> {
     Message::try_from(config).map_err(Error::UnableToGenerateSIWEMessage)
 }
@@ -80,6 +122,18 @@ pub fn siwe_to_delegation_headers(signed_message: SignedMessage) -> DelegationHe
     ))))
 }
 
+pub fn generate_revocation_siwe_message(config: RevocationConfig) -> Result<Message, Error> {
+    Message::try_from(config).map_err(Error::UnableToGenerateSIWEMessage)
+}
+
+pub fn siwe_to_revocation_headers(signed_message: SignedMessage) -> RevocationHeaders {
+    RevocationHeaders::new(KeplerRevocation::Cacao(SiweCacao::new(
+        signed_message.siwe.into(),
+        signed_message.signature,
+        None,
+    )))
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("unable to generate the SIWE message: {0}")]
@@ -89,3 +143,24 @@ pub enum Error {
     #[error("failed to parse input from JSON: {0}")]
     JSONDeserializing(serde_json::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kepler_lib::libipld::multihash::{Code, MultihashDigest};
+
+    #[test]
+    fn revocation_message() {
+        let delegation_cid = Cid::new_v1(0x71, Code::Sha2_256.digest(b"delegation"));
+        let message = generate_revocation_siwe_message(RevocationConfig {
+            address: [0; 20],
+            chain_id: 1,
+            domain: "example.com".parse().unwrap(),
+            issued_at: "2022-01-01T00:00:00Z".parse().unwrap(),
+            delegation_cid,
+        })
+        .unwrap();
+        assert_eq!(message.uri.as_str(), format!("ucan:{delegation_cid}"));
+        assert!(message.resources.is_empty());
+    }
+}
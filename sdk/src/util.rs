@@ -1,10 +1,82 @@
+use kepler_lib::resource::OrbitId;
+
+pub use kepler_lib::peer::PeerIdError;
+
 pub fn make_orbit_id_pkh_eip155(address: String, chain_id: u32, name: Option<String>) -> String {
     make_orbit_id(format!("pkh:eip155:{chain_id}:{address}"), name)
 }
 
+/// CAIP-2 chain reference for Solana mainnet, as used by `did:pkh`.
+pub const SOLANA_MAINNET: &str = "4sGjMW1sUnHzSxGspuhpqLDx6wiyjNtZ";
+
+/// Make an orbit ID controlled by a Solana address, on mainnet unless another chain
+/// reference is given.
+///
+/// A Solana controller delegates to session keys with a UCAN signed by its ed25519 key
+/// rather than a SIWE message, see [`crate::session::prepare_solana_session`].
+pub fn make_orbit_id_pkh_solana(
+    address: String,
+    chain_reference: Option<String>,
+    name: Option<String>,
+) -> String {
+    let chain = chain_reference.unwrap_or_else(|| SOLANA_MAINNET.to_string());
+    make_orbit_id(format!("pkh:solana:{chain}:{address}"), name)
+}
+
+/// Make an orbit ID controlled by a `did:web` DID, from the domain and optional path
+/// its DID document is served at, e.g. `example.com`, `example.com:8443` or
+/// `example.com/users/alice`.
+pub fn make_orbit_id_web(domain: String, name: Option<String>) -> String {
+    let (host, path) = domain.split_once('/').unwrap_or((&domain, ""));
+    // the port is percent-encoded, as colons separate the path segments
+    let suffix = std::iter::once(host.replace(':', "%3A"))
+        .chain(path.split('/').filter(|s| !s.is_empty()).map(String::from))
+        .collect::<Vec<_>>()
+        .join(":");
+    make_orbit_id(format!("web:{suffix}"), name)
+}
+
+/// The libp2p `PeerId` of an orbit's peer, from the `did:key` returned by
+/// `/peer/generate/<orbit>`, computed without asking the host. See [`kepler_lib::peer`]
+/// for the derivation.
+pub fn orbit_peer_id(host_key: &str) -> Result<String, PeerIdError> {
+    kepler_lib::peer::peer_id_from_did_key(host_key)
+}
+
+/// Orbit names are percent-encoded, so they may contain `/` to form hierarchical
+/// names like `team/project`.
 fn make_orbit_id(did_suffix: String, name: Option<String>) -> String {
     format!(
         "kepler:{did_suffix}://{}",
-        name.unwrap_or_else(|| String::from("default"))
+        name.map(|n| OrbitId::encode_name(&n))
+            .unwrap_or_else(|| String::from("default"))
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn web() {
+        assert_eq!(
+            make_orbit_id_web("example.com".into(), None),
+            "kepler:web:example.com://default"
+        );
+        assert_eq!(
+            make_orbit_id_web(
+                "example.com:8443/users/alice/".into(),
+                Some("photos".into())
+            ),
+            "kepler:web:example.com%3A8443:users:alice://photos"
+        );
+    }
+
+    #[test]
+    fn hierarchical_name() {
+        assert_eq!(
+            make_orbit_id_pkh_eip155("0xabc".into(), 1, Some("team/project".into())),
+            "kepler:pkh:eip155:1:0xabc://team%2Fproject"
+        );
+    }
+}
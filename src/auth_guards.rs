@@ -1,17 +1,23 @@
+use crate::{config::EmptyListPolicy, routes::util::format_version};
 use anyhow::Result;
 use kepler_core::{
-    types::Metadata,
+    hash::Hash,
+    types::{http_date, parse_http_date, Metadata, LAST_MODIFIED},
     util::{Capability, DelegationInfo},
-    InvocationOutcome,
+    InvocationOutcome, KeyStream, KvVersion,
 };
 use kepler_lib::{
     authorization::{EncodingError, HeaderEncode},
     libipld::cid::Cid,
     resource::OrbitId,
 };
+use rand::{distributions::Alphanumeric, Rng};
 use rocket::{
     data::{Capped, FromData},
-    futures::io::AsyncRead,
+    futures::{
+        io::{empty, AsyncRead, AsyncReadExt, Cursor},
+        StreamExt, TryStreamExt,
+    },
     http::{ContentType, Header, Status},
     outcome::Outcome as DataOutcome,
     request::{FromRequest, Outcome, Request},
@@ -20,7 +26,10 @@ use rocket::{
     Data,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+};
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 use tracing::{info_span, Instrument};
 
@@ -72,46 +81,341 @@ impl<'r> FromData<'r> for DataIn<'r> {
     }
 }
 
+/// Header giving the status of each part of a batch response.
+pub const PART_STATUS: &str = "x-kepler-status";
+/// Header of a page of sessions giving the cursor of the next page, if there are more.
+pub const NEXT_CURSOR: &str = "x-kepler-next";
+
+/// Headers and body of one part of a batch response.
+pub(crate) type Part = (Vec<(String, String)>, Box<dyn AsyncRead + Send + Unpin>);
+
+fn sessions_json(
+    sessions: HashMap<Hash, DelegationInfo>,
+) -> Result<HashMap<String, CapJsonRep>, Status> {
+    sessions
+        .into_iter()
+        .map(|(hash, del)| {
+            Ok((
+                hash.to_cid(0x55).to_string(),
+                CapJsonRep::from_delegation(del)?,
+            ))
+        })
+        .collect::<Result<HashMap<String, CapJsonRep>>>()
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[derive(Serialize)]
+struct VersionJson {
+    version: String,
+    deleted: bool,
+    metadata: Metadata,
+}
+
+fn versions_json(versions: Vec<KvVersion>) -> Vec<VersionJson> {
+    versions
+        .into_iter()
+        .map(|v| VersionJson {
+            version: format_version(&v.version),
+            deleted: v.deleted,
+            metadata: v.metadata,
+        })
+        .collect()
+}
+
+/// Newline delimited JSON, in which streamed listings are returned.
+pub fn ndjson_type() -> ContentType {
+    ContentType::new("application", "x-ndjson")
+}
+
+/// A streamed listing as newline delimited JSON, each key a JSON string on its own line.
+/// Keys are read from the database only as the body is read, and a failure part way
+/// through ends the body early.
+fn ndjson(keys: KeyStream) -> impl AsyncRead + Send + Unpin {
+    keys.map(|key| {
+        let key = key.map_err(|e| {
+            tracing::warn!(error = %e, "failed to list keys");
+            io::Error::new(io::ErrorKind::Other, e.to_string())
+        })?;
+        let mut line = serde_json::to_vec(&key)?;
+        line.push(b'\n');
+        Ok::<_, io::Error>(line)
+    })
+    .into_async_read()
+}
+
+/// `Content-Disposition` value having clients download content as `filename`, with an
+/// ASCII fallback for clients which don't support the RFC 5987 encoded name.
+pub fn attachment(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            ' '..='~' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+/// Headers describing how a request is transferred rather than its content, such as
+/// `Transfer-Encoding: chunked`, which aren't stored with objects nor sent back with them.
+const TRANSFER_HEADERS: [&str; 7] = [
+    "connection",
+    "expect",
+    "keep-alive",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn is_transfer_header(name: &str) -> bool {
+    TRANSFER_HEADERS
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(name))
+}
+
+/// Headers of an object's metadata sent with it, without its stored `content-length`, as
+/// the length sent is that of the response.
+fn metadata_headers(md: Metadata) -> impl Iterator<Item = (String, String)> {
+    md.0.into_iter()
+        .filter(|(k, _)| !k.eq_ignore_ascii_case("content-length") && !is_transfer_header(k))
+}
+
+/// A `304 Not Modified` response, if the object was last modified no later than the
+/// request's `If-Modified-Since`, compared to the second as HTTP dates are.
+fn not_modified(request: &Request<'_>, md: &Metadata) -> Option<Response<'static>> {
+    let since = request
+        .headers()
+        .get_one("If-Modified-Since")
+        .and_then(parse_http_date)?;
+    let modified = md.last_modified()?;
+    (modified.unix_timestamp() <= since.unix_timestamp()).then(|| {
+        Response::build()
+            .status(Status::NotModified)
+            .raw_header(LAST_MODIFIED, http_date(modified))
+            .finalize()
+    })
+}
+
+/// The configured response to a `kv/list` which finds no keys.
+fn empty_list_policy(request: &Request<'_>) -> EmptyListPolicy {
+    request
+        .rocket()
+        .state::<crate::config::Config>()
+        .map(|c| c.storage.emptylist)
+        .unwrap_or_default()
+}
+
+impl<R> InvOut<R> {
+    /// Have a read of content downloaded by clients as `filename`, rather than displayed.
+    pub fn as_attachment(mut self, filename: &str) -> Self {
+        if let InvocationOutcome::KvRead(Some((md, _))) = &mut self.0 {
+            md.0.retain(|k, _| !k.eq_ignore_ascii_case("content-disposition"));
+            md.0.insert("content-disposition".to_string(), attachment(filename));
+        }
+        self
+    }
+}
+
+impl<R> InvOut<R> {
+    /// Read the first key of a streamed `kv/list`, so one which finds no keys is answered
+    /// as an empty list is under `policy`, as responders can't wait on the stream.
+    pub(crate) async fn peek_list(self, policy: EmptyListPolicy) -> Self {
+        match self.0 {
+            InvocationOutcome::KvKeys(mut keys) if policy == EmptyListPolicy::NotFound => {
+                Self(if keys.is_empty().await {
+                    InvocationOutcome::KvList(vec![])
+                } else {
+                    InvocationOutcome::KvKeys(keys)
+                })
+            }
+            outcome => Self(outcome),
+        }
+    }
+}
+
+impl<R> InvOut<R>
+where
+    R: 'static + AsyncRead + Send,
+{
+    /// Whether the outcome is a `kv/list` which found no keys, and so does not exist under `policy`.
+    fn is_empty_list(&self, policy: EmptyListPolicy) -> bool {
+        matches!(&self.0, InvocationOutcome::KvList(list) if list.is_empty())
+            && policy == EmptyListPolicy::NotFound
+    }
+
+    /// Convert the outcome into a part of a `multipart/mixed` batch response.
+    pub(crate) fn into_part(self, empty_list: EmptyListPolicy) -> Result<Part, Status> {
+        fn status(s: Status) -> (String, String) {
+            (PART_STATUS.to_string(), s.code.to_string())
+        }
+        fn json<T: Serialize>(v: &T) -> Result<Part, Status> {
+            let body = serde_json::to_vec(v).map_err(|_| Status::InternalServerError)?;
+            Ok((
+                vec![
+                    status(Status::Ok),
+                    ("content-type".into(), ContentType::JSON.to_string()),
+                    ("content-length".into(), body.len().to_string()),
+                ],
+                Box::new(Cursor::new(body)),
+            ))
+        }
+        if self.is_empty_list(empty_list) {
+            return Ok((vec![status(Status::NotFound)], Box::new(empty())));
+        }
+        Ok(match self.0 {
+            InvocationOutcome::KvList(list) => json(&list)?,
+            InvocationOutcome::KvKeys(keys) => (
+                vec![
+                    status(Status::Ok),
+                    ("content-type".into(), ndjson_type().to_string()),
+                ],
+                Box::new(ndjson(keys)),
+            ),
+            InvocationOutcome::KvChanges(changes) => json(&changes)?,
+            InvocationOutcome::KvVersions(versions) => json(&versions_json(versions))?,
+            InvocationOutcome::OpenSessions(sessions, next) => {
+                let (mut headers, body) = json(&sessions_json(sessions)?)?;
+                headers.extend(next.map(|n| (NEXT_CURSOR.to_string(), n.to_cid(0x55).to_string())));
+                (headers, body)
+            }
+            InvocationOutcome::KvDelete
+            | InvocationOutcome::KvWrite
+            | InvocationOutcome::KvExists(true) => (vec![status(Status::Ok)], Box::new(empty())),
+            InvocationOutcome::KvMetadata(None)
+            | InvocationOutcome::KvRead(None)
+            | InvocationOutcome::KvLocation(None)
+            | InvocationOutcome::KvExists(false) => {
+                (vec![status(Status::NotFound)], Box::new(empty()))
+            }
+            InvocationOutcome::KvMetadata(Some(md))
+            | InvocationOutcome::KvLocation(Some((md, _))) => (
+                std::iter::once(status(Status::Ok))
+                    .chain(metadata_headers(md))
+                    .collect(),
+                Box::new(empty()),
+            ),
+            InvocationOutcome::KvRead(Some((md, c))) => (
+                std::iter::once(status(Status::Ok))
+                    .chain(metadata_headers(md))
+                    .chain(std::iter::once((
+                        "content-length".to_string(),
+                        c.len().to_string(),
+                    )))
+                    .collect(),
+                Box::new(Box::pin(c)),
+            ),
+        })
+    }
+}
+
 impl<'r, R> Responder<'r, 'static> for InvOut<R>
 where
     R: 'static + AsyncRead + Send,
 {
     fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        if self.is_empty_list(empty_list_policy(request)) {
+            return Err(Status::NotFound);
+        }
         match self.0 {
             InvocationOutcome::KvList(list) => Json(list).respond_to(request),
+            InvocationOutcome::KvKeys(keys) => Response::build()
+                .header(ndjson_type())
+                .streamed_body(ndjson(keys).compat())
+                .ok(),
+            InvocationOutcome::KvChanges(changes) => Json(changes).respond_to(request),
+            InvocationOutcome::KvVersions(versions) => {
+                Json(versions_json(versions)).respond_to(request)
+            }
             InvocationOutcome::KvDelete => ().respond_to(request),
-            InvocationOutcome::KvMetadata(meta) => meta.map(ObjectHeaders).respond_to(request),
+            InvocationOutcome::KvMetadata(Some(md)) => match not_modified(request, &md) {
+                Some(response) => Ok(response),
+                None => ObjectHeaders(md).respond_to(request),
+            },
+            InvocationOutcome::KvMetadata(None) => Err(Status::NotFound),
+            InvocationOutcome::KvLocation(loc) => {
+                loc.map(|(md, _)| ObjectHeaders(md)).respond_to(request)
+            }
             InvocationOutcome::KvWrite => ().respond_to(request),
-            InvocationOutcome::KvRead(data) => {
-                data.map(|(md, c)| KVResponse(c, md)).respond_to(request)
+            InvocationOutcome::KvExists(true) => ().respond_to(request),
+            InvocationOutcome::KvExists(false) => Err(Status::NotFound),
+            InvocationOutcome::KvRead(Some((md, c))) => match not_modified(request, &md) {
+                Some(response) => Ok(response),
+                None => KVResponse(c, md).respond_to(request),
+            },
+            InvocationOutcome::KvRead(None) => Err(Status::NotFound),
+            InvocationOutcome::OpenSessions(sessions, next) => {
+                let mut response = Json(sessions_json(sessions)?).respond_to(request)?;
+                if let Some(next) = next {
+                    response.set_raw_header(NEXT_CURSOR, next.to_cid(0x55).to_string());
+                }
+                Ok(response)
             }
-            InvocationOutcome::OpenSessions(sessions) => Json(
-                sessions
-                    .into_iter()
-                    .map(|(hash, del)| {
-                        Ok((
-                            hash.to_cid(0x55).to_string(),
-                            CapJsonRep::from_delegation(del)?,
-                        ))
-                    })
-                    .collect::<Result<HashMap<String, CapJsonRep>>>()
-                    .map_err(|_| Status::InternalServerError)?,
-            )
-            .respond_to(request),
         }
     }
 }
 
+/// Outcomes of an invocation with several capabilities are returned as a
+/// `multipart/mixed` response, with one part per capability in the order they
+/// were invoked. Each part has an `x-kepler-status` header and the headers and
+/// body the outcome would have as a response on its own.
 impl<'r, R> Responder<'r, 'static> for DataOut<R>
 where
     R: 'static + AsyncRead + Send,
 {
     fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
-        match self {
-            DataHolder::None => ().respond_to(request),
-            DataHolder::One(inv) => inv.respond_to(request),
-            DataHolder::Many(_invs) => Err(Status::NotImplemented),
+        let invs = match self {
+            DataHolder::None => return ().respond_to(request),
+            DataHolder::One(inv) => return inv.respond_to(request),
+            DataHolder::Many(invs) => invs,
+        };
+        let boundary: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let empty_list = empty_list_policy(request);
+        let mut body: Box<dyn AsyncRead + Send + Unpin> = Box::new(empty());
+        for inv in invs {
+            let (headers, content) = inv.into_part(empty_list)?;
+            let mut head = format!("--{boundary}\r\n");
+            for (k, v) in headers {
+                head.push_str(&format!("{k}: {v}\r\n"));
+            }
+            head.push_str("\r\n");
+            body = Box::new(
+                body.chain(Cursor::new(head.into_bytes()))
+                    .chain(content)
+                    .chain(Cursor::new(&b"\r\n"[..])),
+            );
         }
+        body = Box::new(body.chain(Cursor::new(format!("--{boundary}--\r\n").into_bytes())));
+        Response::build()
+            .header(ContentType::new("multipart", "mixed").with_params(("boundary", boundary)))
+            .streamed_body(body.compat())
+            .ok()
     }
 }
 
@@ -145,19 +449,39 @@ impl<'r> FromRequest<'r> for ObjectHeaders {
         let md: BTreeMap<String, String> = request
             .headers()
             .iter()
+            .filter(|h| !is_transfer_header(h.name.as_str()))
             .map(|h| (h.name.into_string(), h.value.to_string()))
             .collect();
         Outcome::Success(ObjectHeaders(Metadata(md)))
     }
 }
 
+/// Request guard for the configured admin key, given in the `X-Admin-Key` header.
+///
+/// Forwards when the header is absent, and fails when it does not match or no admin key
+/// is configured.
+pub struct AdminKey;
+
+#[async_trait]
+impl<'r> FromRequest<'r> for AdminKey {
+    type Error = anyhow::Error;
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let given = match request.headers().get_one("X-Admin-Key") {
+            Some(k) => k,
+            None => return Outcome::Forward(()),
+        };
+        match request.rocket().state::<crate::config::Config>() {
+            Some(c) if c.admin.accepts(given) => Outcome::Success(AdminKey),
+            _ => Outcome::Failure((Status::Unauthorized, anyhow!("Invalid admin key"))),
+        }
+    }
+}
+
 impl<'r> Responder<'r, 'static> for ObjectHeaders {
     fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
         let mut r = Response::build();
-        for (k, v) in self.0 .0 {
-            if k != "content-length" {
-                r.header(Header::new(k, v));
-            }
+        for (k, v) in metadata_headers(self.0) {
+            r.header(Header::new(k, v));
         }
         Ok(r.finalize())
     }
@@ -176,9 +500,292 @@ where
     R: 'static + AsyncRead + Send,
 {
     fn respond_to(self, r: &'r Request<'_>) -> rocket::response::Result<'static> {
-        Ok(Response::build_from(ObjectHeaders(self.1).respond_to(r)?)
-            // must ensure that Metadata::respond_to does not set the body of the response
-            .streamed_body(self.0.compat())
-            .finalize())
+        let typed = self.1.get("content-type").is_some();
+        let mut response = Response::build_from(ObjectHeaders(self.1).respond_to(r)?);
+        if !typed {
+            response.header(ContentType::Binary);
+        }
+        // must ensure that Metadata::respond_to does not set the body of the response
+        Ok(response.streamed_body(self.0.compat()).finalize())
+    }
+}
+
+/// A response to an invocation, with the CIDs of the receipts of its commits in a comma
+/// separated `x-kepler-receipt` header.
+pub struct Receipted<R>(pub R, pub Vec<Hash>);
+
+impl<'r, R> Responder<'r, 'static> for Receipted<R>
+where
+    R: Responder<'r, 'static>,
+{
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = self.0.respond_to(request)?;
+        if !self.1.is_empty() {
+            let receipts: Vec<String> = self.1.iter().map(|h| h.to_cid(0x71).to_string()).collect();
+            response.set_raw_header("x-kepler-receipt", receipts.join(", "));
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kepler_core::storage::Content;
+    use rocket::local::asynchronous::Client;
+
+    #[get("/batch")]
+    fn batch() -> DataOut<Cursor<Vec<u8>>> {
+        let md = Metadata(BTreeMap::from([(
+            "content-type".to_string(),
+            "text/plain".to_string(),
+        )]));
+        DataOut::Many(vec![
+            InvOut(InvocationOutcome::KvRead(Some((
+                md,
+                Content::new(5, Cursor::new(b"hello".to_vec())),
+            )))),
+            InvOut(InvocationOutcome::KvRead(None)),
+            InvOut(InvocationOutcome::KvList(vec!["a".into(), "b".into()])),
+        ])
+    }
+
+    #[get("/download")]
+    fn download() -> DataOut<Cursor<Vec<u8>>> {
+        let md = Metadata(BTreeMap::from([(
+            "Content-Type".to_string(),
+            "text/plain".to_string(),
+        )]));
+        DataOut::One(
+            InvOut(InvocationOutcome::KvRead(Some((
+                md,
+                Content::new(5, Cursor::new(b"hello".to_vec())),
+            ))))
+            .as_attachment("résumé \"1\".txt"),
+        )
+    }
+
+    #[get("/untyped")]
+    fn untyped() -> DataOut<Cursor<Vec<u8>>> {
+        DataOut::One(InvOut(InvocationOutcome::KvRead(Some((
+            Metadata(BTreeMap::new()),
+            Content::new(5, Cursor::new(b"hello".to_vec())),
+        )))))
+    }
+
+    #[put("/headers")]
+    fn headers(headers: ObjectHeaders) -> Json<Metadata> {
+        Json(headers.0)
+    }
+
+    #[get("/stored")]
+    fn stored() -> DataOut<Cursor<Vec<u8>>> {
+        let md = Metadata(BTreeMap::from([
+            ("content-type".to_string(), "text/plain".to_string()),
+            ("transfer-encoding".to_string(), "chunked".to_string()),
+        ]));
+        DataOut::One(InvOut(InvocationOutcome::KvRead(Some((
+            md,
+            Content::new(5, Cursor::new(b"hello".to_vec())),
+        )))))
+    }
+
+    #[get("/modified")]
+    fn modified() -> DataOut<Cursor<Vec<u8>>> {
+        let md = Metadata(BTreeMap::from([(
+            LAST_MODIFIED.to_string(),
+            "Sun, 06 Nov 1994 08:49:37 GMT".to_string(),
+        )]));
+        DataOut::One(InvOut(InvocationOutcome::KvRead(Some((
+            md,
+            Content::new(5, Cursor::new(b"hello".to_vec())),
+        )))))
+    }
+
+    #[get("/keys")]
+    fn keys() -> DataOut<Cursor<Vec<u8>>> {
+        let keys = ["a", "b/\"c\""].map(|k| Ok(k.to_string()));
+        DataOut::One(InvOut(InvocationOutcome::KvKeys(KeyStream::new(
+            rocket::futures::stream::iter(keys),
+        ))))
+    }
+
+    #[get("/empty")]
+    fn empty_list() -> DataOut<Cursor<Vec<u8>>> {
+        DataOut::One(InvOut(InvocationOutcome::KvList(vec![])))
+    }
+
+    #[get("/empty-keys")]
+    async fn empty_keys(config: &rocket::State<crate::config::Config>) -> DataOut<Cursor<Vec<u8>>> {
+        DataOut::One(
+            InvOut(InvocationOutcome::KvKeys(KeyStream::new(
+                rocket::futures::stream::empty(),
+            )))
+            .peek_list(config.storage.emptylist)
+            .await,
+        )
+    }
+
+    #[test]
+    async fn empty_list_response() {
+        let rocket = |emptylist| {
+            let mut config = crate::config::Config::default();
+            config.storage.emptylist = emptylist;
+            rocket::build()
+                .mount("/", routes![empty_list, empty_keys])
+                .manage(config)
+        };
+
+        let client = Client::tracked(rocket(EmptyListPolicy::Empty))
+            .await
+            .unwrap();
+        let res = client.get("/empty").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().await.unwrap(), "[]");
+        // a streamed listing stays an empty stream
+        let res = client.get("/empty-keys").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.content_type(), Some(ndjson_type()));
+        assert_eq!(res.into_string().await.unwrap(), "");
+
+        let client = Client::tracked(rocket(EmptyListPolicy::NotFound))
+            .await
+            .unwrap();
+        let res = client.get("/empty").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        let res = client.get("/empty-keys").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    async fn streamed_list_response() {
+        let client = Client::tracked(rocket::build().mount("/", routes![keys]))
+            .await
+            .unwrap();
+        let res = client.get("/keys").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.content_type(), Some(ndjson_type()));
+        assert_eq!(res.into_string().await.unwrap(), "\"a\"\n\"b/\\\"c\\\"\"\n");
+    }
+
+    #[test]
+    async fn conditional_response() {
+        let client = Client::tracked(rocket::build().mount("/", routes![modified]))
+            .await
+            .unwrap();
+        let get = |since: Option<&'static str>| {
+            let mut req = client.get("/modified");
+            if let Some(since) = since {
+                req.add_header(Header::new("If-Modified-Since", since));
+            }
+            req.dispatch()
+        };
+
+        let res = get(None).await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(
+            res.headers().get_one("Last-Modified"),
+            Some("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+        assert_eq!(res.into_string().await.unwrap(), "hello");
+
+        for since in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Mon, 07 Nov 1994 00:00:00 GMT",
+        ] {
+            let res = get(Some(since)).await;
+            assert_eq!(res.status(), Status::NotModified);
+            assert_eq!(
+                res.headers().get_one("Last-Modified"),
+                Some("Sun, 06 Nov 1994 08:49:37 GMT")
+            );
+            assert!(res.into_string().await.unwrap_or_default().is_empty());
+        }
+
+        // the content is sent if it changed since, or the date isn't understood
+        for since in ["Sun, 06 Nov 1994 08:49:36 GMT", "yesterday"] {
+            let res = get(Some(since)).await;
+            assert_eq!(res.status(), Status::Ok);
+            assert_eq!(res.into_string().await.unwrap(), "hello");
+        }
+    }
+
+    #[test]
+    async fn chunked_request() {
+        let client = Client::tracked(rocket::build().mount("/", routes![headers, stored]))
+            .await
+            .unwrap();
+        let res = client
+            .put("/headers")
+            .header(ContentType::Plain)
+            .header(Header::new("Transfer-Encoding", "chunked"))
+            .header(Header::new("Expect", "100-continue"))
+            .body("hello")
+            .dispatch()
+            .await;
+        let md: Metadata = res.into_json().await.unwrap();
+        assert_eq!(md.content_type().as_deref(), Some("text/plain"));
+        assert_eq!(md.get("transfer-encoding"), None);
+        assert_eq!(md.get("expect"), None);
+
+        // objects stored with the headers of a chunked request are still sent as they are
+        let res = client.get("/stored").dispatch().await;
+        assert_eq!(res.headers().get_one("Transfer-Encoding"), None);
+        assert_eq!(res.into_string().await.unwrap(), "hello");
+    }
+
+    #[test]
+    async fn download_response() {
+        assert_eq!(
+            attachment("a b.txt"),
+            "attachment; filename=\"a b.txt\"; filename*=UTF-8''a%20b.txt"
+        );
+
+        let client = Client::tracked(rocket::build().mount("/", routes![download, untyped]))
+            .await
+            .unwrap();
+        let res = client.get("/download").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.content_type(), Some(ContentType::Plain));
+        assert_eq!(
+            res.headers().get_one("Content-Disposition"),
+            Some("attachment; filename=\"r_sum_ _1_.txt\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%221%22.txt")
+        );
+
+        let res = client.get("/untyped").dispatch().await;
+        assert_eq!(res.content_type(), Some(ContentType::Binary));
+        assert_eq!(res.headers().get_one("Content-Disposition"), None);
+        assert_eq!(res.into_string().await.unwrap(), "hello");
+    }
+
+    #[test]
+    async fn batch_response() {
+        let client = Client::tracked(rocket::build().mount("/", routes![batch]))
+            .await
+            .unwrap();
+        let res = client.get("/batch").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let content_type = res.content_type().unwrap();
+        assert_eq!(content_type.sub(), "mixed");
+        let boundary = content_type
+            .params()
+            .find(|(k, _)| k.as_str() == "boundary")
+            .map(|(_, v)| v.to_string())
+            .unwrap();
+        let body = res.into_string().await.unwrap();
+        let parts: Vec<&str> = body
+            .strip_suffix(&format!("--{boundary}--\r\n"))
+            .unwrap()
+            .split(&format!("--{boundary}\r\n"))
+            .skip(1)
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                "x-kepler-status: 200\r\ncontent-type: text/plain\r\ncontent-length: 5\r\n\r\nhello\r\n",
+                "x-kepler-status: 404\r\n\r\n\r\n",
+                "x-kepler-status: 200\r\ncontent-type: application/json\r\ncontent-length: 9\r\n\r\n[\"a\",\"b\"]\r\n",
+            ]
+        );
     }
 }
@@ -1,3 +1,4 @@
+use crate::config::Config;
 use kepler_core::{
     events::{FromReqErr, SerializedEvent},
     util::{DelegationInfo, InvocationInfo, RevocationInfo},
@@ -17,11 +18,18 @@ macro_rules! impl_fromreq {
         impl<'r> FromRequest<'r> for AuthHeaderGetter<$type> {
             type Error = FromReqErr<<$type as TryFrom<$inter>>::Error>;
             async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-                match request
-                    .headers()
-                    .get_one($name)
-                    .map(SerializedEvent::<$type>::from_header_ser::<$inter>)
-                {
+                let strict = request
+                    .rocket()
+                    .state::<Config>()
+                    .map(|c| c.encoding.strict)
+                    .unwrap_or(false);
+                match request.headers().get_one($name).map(|h| {
+                    if strict {
+                        SerializedEvent::<$type>::from_header_ser_strict::<$inter>(h)
+                    } else {
+                        SerializedEvent::<$type>::from_header_ser::<$inter>(h)
+                    }
+                }) {
                     Some(Ok(e)) => Outcome::Success(AuthHeaderGetter(e)),
                     Some(Err(e)) => Outcome::Failure((Status::Unauthorized, e)),
                     None => Outcome::Forward(()),
@@ -0,0 +1,172 @@
+use kepler_core::{
+    keys::StaticSecret,
+    sea_orm::{Database, TransactionTrait},
+    storage::{either::Either, StorageConfig},
+};
+use rocket::figment::Figment;
+use std::{fmt, future::Future, time::Duration};
+
+use crate::{
+    config::{Config, Keys},
+    cors::CorsFairing,
+};
+
+/// How long a backend may take to respond before its check fails.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of one check of the configuration made by `--check-config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Result<(), String>,
+}
+
+impl Check {
+    fn new<E: fmt::Display>(name: &'static str, outcome: Result<(), E>) -> Self {
+        Self {
+            name,
+            outcome: outcome.map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Ok(()) => write!(f, "{}: ok", self.name),
+            Err(e) => write!(f, "{}: {e}", self.name),
+        }
+    }
+}
+
+async fn probe<E: fmt::Display>(check: impl Future<Output = Result<(), E>>) -> Result<(), String> {
+    match tokio::time::timeout(PROBE_TIMEOUT, check).await {
+        Ok(r) => r.map_err(|e| e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+/// Check a configuration without starting the server: that it parses, that the settings
+/// validated at startup are valid, and that the database and block storage are reachable.
+///
+/// Nothing is written to either backend, in particular no migrations are run. Checks after
+/// a failure to parse the configuration are skipped.
+pub async fn check_config(figment: &Figment) -> Vec<Check> {
+    let config = match figment.extract::<Config>() {
+        Ok(config) => config,
+        Err(e) => {
+            let errors: Vec<String> = e.into_iter().map(|e| e.to_string()).collect();
+            return vec![Check::new("config", Err(errors.join("; ")))];
+        }
+    };
+    let mut checks = vec![Check::new("config", config.validate())];
+
+    checks.push(Check::new(
+        "keys",
+        match config.keys.clone() {
+            Keys::Static(s) => StaticSecret::try_from(s).map(|_| ()),
+        },
+    ));
+    checks.push(Check::new("dids", config.dids.allowlist().map(|_| ())));
+    checks.push(Check::new(
+        "cors",
+        CorsFairing::new(config.cors.clone()).map(|_| ()),
+    ));
+    checks.push(Check::new(
+        "admin",
+        match config
+            .admin
+            .hashes
+            .iter()
+            .find(|h| h.len() != 64 || !h.bytes().all(|b| b.is_ascii_hexdigit()))
+        {
+            Some(h) => Err(format!("{h} is not a hex encoded SHA-256 digest")),
+            None => Ok(()),
+        },
+    ));
+
+    // a transaction is begun but never committed, so nothing is written
+    checks.push(Check::new(
+        "database",
+        probe(async {
+            Database::connect(&config.storage.database)
+                .await?
+                .begin()
+                .await
+                .map(|_| ())
+        })
+        .await,
+    ));
+    checks.push(Check::new(
+        "storage",
+        probe(async {
+            match config
+                .storage
+                .blocks
+                .open()
+                .await
+                .map_err(|e| e.to_string())?
+            {
+                Either::A(s3) => s3.check().await.map_err(|e| e.to_string()),
+                Either::B(Either::A(fs)) => fs.check().await.map_err(|e| e.to_string()),
+                Either::B(Either::B(_)) => Ok(()),
+            }
+        })
+        .await,
+    ));
+    checks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rocket::figment::providers::{Format, Serialized, Toml};
+
+    const SECRET: &str = "U29tZSBsb25nIHBpZWNlIG9mIGVudHJvcHkgd2hpY2ggaXMgYSBzZWNyZXQgYW5kIG1vcmUgdGhhbiAzMiBieXRlcw";
+
+    async fn check(toml: &str) -> Vec<Check> {
+        check_config(
+            &Figment::from(Serialized::defaults(Config::default())).merge(Toml::string(toml)),
+        )
+        .await
+    }
+
+    fn failed(checks: &[Check]) -> Vec<&'static str> {
+        checks
+            .iter()
+            .filter(|c| !c.passed())
+            .map(|c| c.name)
+            .collect()
+    }
+
+    #[test]
+    async fn config_checks() {
+        let keys = format!("[keys]\ntype = \"Static\"\nsecret = \"{SECRET}\"");
+        let checks = check(&format!("storage.blocks.type = \"Memory\"\n{keys}")).await;
+        assert_eq!(failed(&checks), Vec::<&str>::new());
+        assert_eq!(checks[0].to_string(), "config: ok");
+
+        // every problem is reported, not just the first
+        let checks = check(&format!(
+            "admin.hashes = [\"letmein\"]\n{keys}\n[storage.blocks]\ntype = \"Local\"\npath = \"/nonexistent/kepler\""
+        ))
+        .await;
+        assert_eq!(failed(&checks), ["admin", "storage"]);
+        assert_eq!(
+            checks[4].to_string(),
+            "admin: letmein is not a hex encoded SHA-256 digest"
+        );
+
+        let checks = check("storage.blocks.type = \"Memory\"").await;
+        assert_eq!(failed(&checks), ["keys"]);
+
+        // nothing else is checked if the config doesn't parse
+        let checks = check("storage.blocks.type = \"Floppy\"").await;
+        assert_eq!(failed(&checks), ["config"]);
+        assert_eq!(checks.len(), 1);
+    }
+}
@@ -0,0 +1,139 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Request, Response,
+};
+
+use crate::config;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CorsError {
+    #[error("CORS credentials cannot be allowed for every origin")]
+    WildcardCredentials,
+}
+
+pub struct CorsFairing {
+    cors: config::Cors,
+}
+
+impl CorsFairing {
+    /// Fairing for the configured CORS policy, if any origin is allowed.
+    pub fn new(cors: config::Cors) -> Result<Option<Self>, CorsError> {
+        if cors.credentials && (cors.allowall || cors.origins.contains("*")) {
+            return Err(CorsError::WildcardCredentials);
+        }
+        Ok((cors.allowall || !cors.origins.is_empty()).then_some(Self { cors }))
+    }
+
+    /// Response headers for a request from the given origin.
+    fn headers(&self, origin: Option<&str>) -> Vec<Header<'static>> {
+        let mut headers = match origin {
+            _ if self.cors.allowall || self.cors.origins.contains("*") => {
+                vec![Header::new("Access-Control-Allow-Origin", "*")]
+            }
+            Some(origin) if self.cors.origins.contains(origin) => {
+                let mut headers = vec![
+                    Header::new("Access-Control-Allow-Origin", origin.to_string()),
+                    Header::new("Vary", "Origin"),
+                ];
+                if self.cors.credentials {
+                    headers.push(Header::new("Access-Control-Allow-Credentials", "true"));
+                }
+                headers
+            }
+            _ => return vec![],
+        };
+        let allowed = self.cors.headers.join(", ");
+        headers.extend([
+            Header::new("Access-Control-Allow-Methods", self.cors.methods.join(", ")),
+            // expose response headers to browser-run scripts
+            Header::new("Access-Control-Expose-Headers", allowed.clone()),
+            Header::new("Access-Control-Allow-Headers", allowed),
+        ]);
+        headers
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for CorsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        for header in self.headers(req.headers().get_one("Origin")) {
+            res.set_header(header);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fairing(origins: &[&str], credentials: bool) -> CorsFairing {
+        CorsFairing::new(config::Cors {
+            origins: origins.iter().map(|o| o.to_string()).collect(),
+            credentials,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    fn origin(headers: &[Header]) -> Option<String> {
+        headers
+            .iter()
+            .find(|h| h.name() == "Access-Control-Allow-Origin")
+            .map(|h| h.value().to_string())
+    }
+
+    #[test]
+    async fn allowed_origins() {
+        let cors = fairing(&["https://app.example.com"], true);
+
+        let headers = cors.headers(Some("https://app.example.com"));
+        assert_eq!(origin(&headers).as_deref(), Some("https://app.example.com"));
+        assert!(headers
+            .iter()
+            .any(|h| h.name() == "Access-Control-Allow-Credentials"));
+
+        assert!(cors.headers(Some("https://evil.example.com")).is_empty());
+        assert!(cors.headers(None).is_empty());
+    }
+
+    #[test]
+    async fn allow_all() {
+        let cors = CorsFairing::new(config::Cors {
+            allowall: true,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        let headers = cors.headers(Some("https://app.example.com"));
+        assert_eq!(origin(&headers).as_deref(), Some("*"));
+        assert!(!headers
+            .iter()
+            .any(|h| h.name() == "Access-Control-Allow-Credentials"));
+    }
+
+    #[test]
+    async fn wildcard_credentials() {
+        assert!(CorsFairing::new(config::Cors {
+            allowall: true,
+            credentials: true,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(CorsFairing::new(config::Cors {
+            origins: ["*".to_string()].into(),
+            credentials: true,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(CorsFairing::new(Default::default()).unwrap().is_none());
+    }
+}
@@ -8,40 +8,67 @@ extern crate tokio;
 
 use anyhow::Result;
 use kepler_lib::libipld::{block::Block as OBlock, store::DefaultParams};
-use rocket::{fairing::AdHoc, figment::Figment, http::Header, Build, Rocket};
+use rocket::{fairing::AdHoc, figment::Figment, Build, Rocket};
 
 pub mod allow_list;
 pub mod auth_guards;
 pub mod authorization;
+pub mod check;
 pub mod config;
+pub mod cors;
+pub mod notifications;
 pub mod prometheus;
+pub mod rate_limit;
 pub mod routes;
 pub mod storage;
+pub mod tasks;
 mod tracing;
 
 use config::{BlockStorage, Config, Keys, StagingStorage};
 use kepler_core::{
     keys::{SecretsSetup, StaticSecret},
     sea_orm::{ConnectOptions, Database, DatabaseConnection},
-    storage::{either::Either, memory::MemoryStaging, StorageConfig},
+    storage::{
+        either::Either,
+        memory::{MemoryBlockConfig, MemoryBlockStore, MemoryStaging},
+        StorageConfig,
+    },
     OrbitDatabase,
 };
-use routes::{delegate, invoke, open_host_key, util_routes::*};
+use routes::{
+    abilities,
+    batch::batch,
+    block, compact_orbit, delegate, invoke, open_host_key, orbit_exists, orbit_features,
+    orbit_heads, orbit_pointer, presign, purge_orbit, receipt, remove_orbit_alias, revoke,
+    set_maintenance, set_orbit_alias, set_orbit_feature, set_orbit_limit,
+    upload::{append_upload, begin_upload, discard_upload},
+    util_routes::*,
+};
 use storage::{
+    compressed::CompressedStore,
+    encrypted::EncryptedStore,
     file_system::{FileSystemConfig, FileSystemStore, TempFileSystemStage},
     s3::{S3BlockConfig, S3BlockStore},
 };
 
 pub type Block = OBlock<DefaultParams>;
-pub type BlockStores = Either<S3BlockStore, FileSystemStore>;
-pub type BlockConfig = Either<S3BlockConfig, FileSystemConfig>;
+pub type BlockStores = CompressedStore<
+    EncryptedStore<
+        Either<S3BlockStore, Either<FileSystemStore, MemoryBlockStore>>,
+        BlockStage,
+        StaticSecret,
+    >,
+    BlockStage,
+>;
+pub type BlockConfig = Either<S3BlockConfig, Either<FileSystemConfig, MemoryBlockConfig>>;
 pub type BlockStage = Either<TempFileSystemStage, MemoryStaging>;
 
 impl From<BlockStorage> for BlockConfig {
     fn from(c: BlockStorage) -> BlockConfig {
         match c {
             BlockStorage::S3(s) => Self::A(s),
-            BlockStorage::Local(l) => Self::B(l),
+            BlockStorage::Local(l) => Self::B(Either::A(l)),
+            BlockStorage::Memory => Self::B(Either::B(MemoryBlockConfig)),
         }
     }
 }
@@ -50,7 +77,8 @@ impl From<BlockConfig> for BlockStorage {
     fn from(c: BlockConfig) -> Self {
         match c {
             BlockConfig::A(a) => Self::S3(a),
-            BlockConfig::B(b) => Self::Local(b),
+            BlockConfig::B(Either::A(b)) => Self::Local(b),
+            BlockConfig::B(Either::B(_)) => Self::Memory,
         }
     }
 }
@@ -77,10 +105,38 @@ pub type Kepler = OrbitDatabase<DatabaseConnection, BlockStores, StaticSecret>;
 
 pub async fn app(config: &Figment) -> Result<Rocket<Build>> {
     let kepler_config: Config = config.extract::<Config>()?;
+    kepler_config.validate()?;
 
     tracing::tracing_try_init(&kepler_config.log);
 
-    let routes = routes![healthcheck, cors, open_host_key, invoke, delegate,];
+    let routes = routes![
+        healthcheck,
+        readiness,
+        cors,
+        open_host_key,
+        orbit_exists,
+        orbit_heads,
+        orbit_pointer,
+        invoke,
+        batch,
+        presign,
+        abilities,
+        delegate,
+        revoke,
+        receipt,
+        block,
+        purge_orbit,
+        compact_orbit,
+        orbit_features,
+        set_orbit_feature,
+        set_orbit_limit,
+        set_maintenance,
+        set_orbit_alias,
+        remove_orbit_alias,
+        begin_upload,
+        append_upload,
+        discard_upload,
+    ];
 
     let key_setup: StaticSecret = match kepler_config.keys {
         Keys::Static(s) => s.try_into()?,
@@ -89,45 +145,104 @@ pub async fn app(config: &Figment) -> Result<Rocket<Build>> {
     let mut connect_opts = ConnectOptions::from(&kepler_config.storage.database);
     connect_opts.max_connections(100);
 
-    let kepler = Kepler::new(
-        Database::connect(connect_opts).await?,
-        kepler_config.storage.blocks.open().await?,
-        key_setup.setup(()).await?,
-    )
-    .await?;
+    let blocks = match kepler_config.storage.blocks.open().await? {
+        Either::A(s3) => Either::A(s3.with_retry(kepler_config.storage.retry.clone())),
+        fs => fs,
+    };
+    let staging = kepler_config.storage.staging.open().await?;
+    let secrets = key_setup.setup(()).await?;
+    // content is compressed before it is encrypted, as ciphertext doesn't compress
+    let blocks = EncryptedStore::new(
+        blocks,
+        staging.clone(),
+        kepler_config.storage.encryption.then(|| secrets.clone()),
+    );
+    let blocks = CompressedStore::new(blocks, staging.clone(), kepler_config.storage.compression);
+
+    let kepler = Kepler::new(Database::connect(connect_opts).await?, blocks, secrets)
+        .await?
+        .with_hash_algorithm(kepler_config.storage.hash)
+        .with_did_methods(kepler_config.dids.allowlist()?)
+        .with_did_resolver(
+            kepler_config
+                .dids
+                .resolver()
+                .with_observer(|r| prometheus::DID_CACHE.with_label_values(&[r.as_str()]).inc()),
+        )
+        .with_read_only(kepler_config.readonly)
+        .with_maintenance(kepler_config.maintenance.enabled)
+        .with_pointers(kepler_config.orbits.pointers)
+        .with_max_parents(kepler_config.storage.maxparents)
+        .with_max_delegation_depth(kepler_config.delegations.maxdepth)
+        .with_replay_protection(kepler_config.invocations.replay_protection())
+        .with_storage_timeout(
+            kepler_config
+                .storage
+                .timeout
+                .map(std::time::Duration::from_millis),
+        )
+        .with_tombstone_retention(
+            kepler_config
+                .storage
+                .compaction
+                .retention
+                .map(std::time::Duration::from_secs),
+        );
+
+    let mut tasks = tasks::BackgroundTasks::default();
+    let notifier = notifications::CommitNotifier::new(&kepler_config.notifications);
+    if let Some(webhook) = kepler_config.notifications.webhook.clone() {
+        let commits = notifier.subscribe();
+        tasks.spawn("webhook", |stop| {
+            notifications::webhook::deliver(webhook, commits, stop)
+        });
+    }
+
+    let uploads = kepler_config
+        .storage
+        .uploads
+        .clone()
+        .map(storage::resumable::ResumableFileSystemStage::new);
+    tasks.spawn("reaper", |stop| {
+        storage::reaper::reap(
+            kepler.clone(),
+            uploads.clone(),
+            kepler_config.storage.reaper.clone(),
+            stop,
+        )
+    });
+    tasks.spawn("compaction", |stop| {
+        storage::compaction::compact(
+            kepler.clone(),
+            kepler_config.storage.compaction.clone(),
+            stop,
+        )
+    });
 
     let rocket = rocket::custom(config)
         .mount("/", routes)
+        .register("/", catchers![routes::error::default_catcher])
         .attach(AdHoc::config::<Config>())
+        .attach(tasks.fairing())
         .attach(tracing::TracingFairing {
             header_name: kepler_config.log.tracing.traceheader,
         })
         .manage(kepler)
-        .manage(kepler_config.storage.staging.open().await?);
-
-    if kepler_config.cors {
-        Ok(rocket.attach(AdHoc::on_response("CORS", |_, resp| {
-            Box::pin(async move {
-                resp.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-                resp.set_header(Header::new(
-                    // allow these methods for requests
-                    "Access-Control-Allow-Methods",
-                    "POST, PUT, GET, OPTIONS, DELETE",
-                ));
-                resp.set_header(Header::new(
-                    // expose response headers to browser-run scripts
-                    "Access-Control-Expose-Headers",
-                    "*, Authorization",
-                ));
-                resp.set_header(Header::new(
-                    // allow custom headers + Authorization in requests
-                    "Access-Control-Allow-Headers",
-                    "*, Authorization",
-                ));
-                resp.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
-            })
-        })))
+        .manage(notifier)
+        .manage(rate_limit::RateLimiter::new(
+            kepler_config.ratelimit.clone(),
+        ))
+        .manage(uploads)
+        .manage(staging);
+
+    let rocket = if kepler_config.prometheus.route {
+        rocket.mount("/", routes![metrics])
     } else {
-        Ok(rocket)
-    }
+        rocket
+    };
+
+    Ok(match cors::CorsFairing::new(kepler_config.cors)? {
+        Some(cors) => rocket.attach(cors),
+        None => rocket,
+    })
 }
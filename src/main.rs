@@ -2,30 +2,55 @@ use hyper::{
     service::{make_service_fn, service_fn},
     Server,
 };
-use kepler::{app, config, prometheus};
-use rocket::{
-    figment::providers::{Env, Format, Serialized, Toml},
-    tokio,
-};
+use kepler::{app, check, config, prometheus};
+use rocket::tokio;
 
 #[rocket::main]
 async fn main() {
-    let config = rocket::figment::Figment::from(rocket::Config::default())
-        .merge(Serialized::defaults(config::Config::default()))
-        .merge(Toml::file("kepler.toml").nested())
-        .merge(Env::prefixed("KEPLER_").split("_").global())
-        .merge(Env::prefixed("ROCKET_").global()); // That's just for easy access to ROCKET_LOG_LEVEL
+    let config = config::figment();
+
+    // report every problem with the config and its backends, rather than panicking on the
+    // first one at startup
+    if std::env::args().skip(1).any(|a| a == "--check-config") {
+        let checks = check::check_config(&config).await;
+        for c in &checks {
+            println!("{c}");
+        }
+        std::process::exit(if checks.iter().all(check::Check::passed) {
+            0
+        } else {
+            1
+        });
+    }
+
     let kepler_config = config.extract::<config::Config>().unwrap();
 
+    if std::env::args().skip(1).any(|a| a == "--dump-config") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&kepler_config.redacted().unwrap()).unwrap()
+        );
+        return;
+    }
+
     let rocket = app(&config).await.unwrap().ignite().await.unwrap();
 
     let prom_addr = (rocket.config().address, kepler_config.prometheus.port).into();
-    let prometheus = Server::bind(&prom_addr).serve(make_service_fn(|_| async {
-        Ok::<_, hyper::Error>(service_fn(prometheus::serve_req))
-    }));
+    let shutdown = rocket.shutdown();
+    let prometheus = Server::bind(&prom_addr)
+        .serve(make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(prometheus::serve_req))
+        }))
+        .with_graceful_shutdown(shutdown.clone());
 
-    tokio::select! {
-        r = rocket.launch() => {let _ = r.unwrap();},
-        r = prometheus => r.unwrap()
-    };
+    // on SIGINT/SIGTERM rocket stops accepting requests and lets in-flight ones finish
+    // within its grace period, so wait for it rather than exiting with the metrics server
+    let (r, p) = tokio::join!(rocket.launch(), async move {
+        prometheus.await.map_err(|e| {
+            shutdown.notify();
+            e
+        })
+    });
+    let _ = r.unwrap();
+    p.unwrap();
 }
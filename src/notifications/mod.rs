@@ -0,0 +1,226 @@
+use crate::config::{Notifications, OverflowPolicy};
+use kepler_core::Commit;
+use kepler_lib::resource::OrbitId;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+pub mod webhook;
+
+/// A commit to an orbit, published after the transaction which made it has committed.
+#[derive(Debug, Clone)]
+pub struct CommitNotification {
+    pub orbit: OrbitId,
+    pub commit: Commit,
+}
+
+/// Publishes commits to every subscriber over bounded queues.
+///
+/// A slow subscriber never holds up a commit for longer than the configured overflow
+/// policy allows; notifications which don't fit are dropped and counted in
+/// `kepler_commit_notifications_dropped_total`.
+#[derive(Clone)]
+pub struct CommitNotifier {
+    capacity: usize,
+    overflow: OverflowPolicy,
+    timeout: Duration,
+    subscribers: Arc<Mutex<Vec<Arc<Queue>>>>,
+}
+
+#[derive(Default)]
+struct Queue {
+    items: Mutex<VecDeque<CommitNotification>>,
+    item_added: Notify,
+    space_freed: Notify,
+    closed: AtomicBool,
+}
+
+/// Receives commit notifications from a [`CommitNotifier`].
+pub struct CommitReceiver(Arc<Queue>);
+
+impl CommitNotifier {
+    pub fn new(config: &Notifications) -> Self {
+        Self {
+            capacity: config.capacity.max(1),
+            overflow: config.overflow,
+            timeout: Duration::from_millis(config.timeout),
+            subscribers: Default::default(),
+        }
+    }
+
+    pub fn subscribe(&self) -> CommitReceiver {
+        let queue = Arc::new(Queue::default());
+        self.subscribers.lock().unwrap().push(queue.clone());
+        CommitReceiver(queue)
+    }
+
+    pub async fn publish(&self, commits: &HashMap<OrbitId, Commit>) {
+        let subscribers: Vec<Arc<Queue>> = {
+            let mut subscribers = self.subscribers.lock().unwrap();
+            subscribers.retain(|q| !q.closed.load(Ordering::Acquire));
+            subscribers.clone()
+        };
+        for queue in subscribers {
+            for (orbit, commit) in commits {
+                self.push(
+                    &queue,
+                    CommitNotification {
+                        orbit: orbit.clone(),
+                        commit: commit.clone(),
+                    },
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn push(&self, queue: &Queue, notification: CommitNotification) {
+        match self.overflow {
+            OverflowPolicy::DropOldest => {
+                let mut items = queue.items.lock().unwrap();
+                if items.len() >= self.capacity {
+                    items.pop_front();
+                    dropped(self.overflow);
+                }
+                items.push_back(notification);
+            }
+            OverflowPolicy::Block => {
+                let wait = async {
+                    loop {
+                        let space_freed = queue.space_freed.notified();
+                        if queue.items.lock().unwrap().len() < self.capacity {
+                            break;
+                        }
+                        space_freed.await;
+                    }
+                };
+                if tokio::time::timeout(self.timeout, wait).await.is_err() {
+                    dropped(self.overflow);
+                    return;
+                }
+                queue.items.lock().unwrap().push_back(notification);
+            }
+        }
+        queue.item_added.notify_one();
+    }
+}
+
+fn dropped(policy: OverflowPolicy) {
+    crate::prometheus::DROPPED_COMMIT_NOTIFICATIONS
+        .with_label_values(&[match policy {
+            OverflowPolicy::DropOldest => "drop_oldest",
+            OverflowPolicy::Block => "block",
+        }])
+        .inc();
+}
+
+impl CommitReceiver {
+    pub async fn recv(&self) -> CommitNotification {
+        loop {
+            let item_added = self.0.item_added.notified();
+            if let Some(n) = self.0.items.lock().unwrap().pop_front() {
+                self.0.space_freed.notify_one();
+                return n;
+            }
+            item_added.await;
+        }
+    }
+}
+
+impl Drop for CommitReceiver {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kepler_core::hash::hash;
+
+    fn commits(seq: i64) -> HashMap<OrbitId, Commit> {
+        let mut commits = HashMap::new();
+        commits.insert(
+            "kepler:example://default".parse().unwrap(),
+            Commit {
+                rev: hash(&seq.to_be_bytes()),
+                seq,
+                committed_events: vec![],
+                consumed_epochs: vec![],
+                receipt: None,
+            },
+        );
+        commits
+    }
+
+    fn notifier(overflow: OverflowPolicy) -> CommitNotifier {
+        CommitNotifier::new(&Notifications {
+            capacity: 2,
+            overflow,
+            timeout: 10,
+            webhook: None,
+        })
+    }
+
+    #[test]
+    async fn drop_oldest_with_stalled_consumer() {
+        let counter =
+            crate::prometheus::DROPPED_COMMIT_NOTIFICATIONS.with_label_values(&["drop_oldest"]);
+        let before = counter.get();
+        let notifier = notifier(OverflowPolicy::DropOldest);
+        let stalled = notifier.subscribe();
+
+        // publishing never waits for the consumer
+        for seq in 0..5 {
+            tokio::time::timeout(Duration::from_millis(100), notifier.publish(&commits(seq)))
+                .await
+                .unwrap();
+        }
+
+        assert!(counter.get() >= before + 3);
+        assert_eq!(stalled.recv().await.commit.seq, 3);
+        assert_eq!(stalled.recv().await.commit.seq, 4);
+    }
+
+    #[test]
+    async fn block_with_timeout() {
+        let counter = crate::prometheus::DROPPED_COMMIT_NOTIFICATIONS.with_label_values(&["block"]);
+        let before = counter.get();
+        let notifier = notifier(OverflowPolicy::Block);
+        let stalled = notifier.subscribe();
+
+        notifier.publish(&commits(0)).await;
+        notifier.publish(&commits(1)).await;
+        // the queue is full, so this waits for the timeout and is then dropped
+        tokio::time::timeout(Duration::from_secs(1), notifier.publish(&commits(2)))
+            .await
+            .unwrap();
+        assert!(counter.get() >= before + 1);
+
+        // once the consumer makes space, publishing goes through again
+        assert_eq!(stalled.recv().await.commit.seq, 0);
+        notifier.publish(&commits(3)).await;
+        assert_eq!(stalled.recv().await.commit.seq, 1);
+        assert_eq!(stalled.recv().await.commit.seq, 3);
+    }
+
+    #[test]
+    async fn no_subscribers() {
+        let notifier = notifier(OverflowPolicy::Block);
+        drop(notifier.subscribe());
+        // nobody is listening, so nothing can block
+        tokio::time::timeout(Duration::from_millis(5), async {
+            for seq in 0..5 {
+                notifier.publish(&commits(seq)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use super::{CommitNotification, CommitReceiver};
+use crate::{config::Webhook, tasks::Stop};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+
+/// Header carrying the hex encoded HMAC-SHA256 of the request body, prefixed with `sha256=`.
+pub const SIGNATURE_HEADER: &str = "X-Kepler-Signature";
+
+#[derive(Serialize)]
+struct CommitBody {
+    orbit: String,
+    seq: i64,
+    rev: String,
+    committed_events: Vec<String>,
+}
+
+impl From<&CommitNotification> for CommitBody {
+    fn from(n: &CommitNotification) -> Self {
+        Self {
+            orbit: n.orbit.to_string(),
+            seq: n.commit.seq,
+            rev: n.commit.rev.to_cid(0x55).to_string(),
+            committed_events: n
+                .commit
+                .committed_events
+                .iter()
+                .map(|h| h.to_cid(0x55).to_string())
+                .collect(),
+        }
+    }
+}
+
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(body);
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("sha256={signature}")
+}
+
+/// POST each commit to the webhook, retrying failures with exponential backoff.
+///
+/// Runs until `stop` is requested, finishing the delivery under way. Commits which still
+/// fail after all retries are logged and skipped.
+pub async fn deliver(config: Webhook, commits: CommitReceiver, mut stop: Stop) {
+    let client = reqwest::Client::new();
+    loop {
+        let notification = tokio::select! {
+            n = commits.recv() => n,
+            _ = stop.requested() => return,
+        };
+        let body = match serde_json::to_vec(&CommitBody::from(&notification)) {
+            Ok(b) => b,
+            Err(e) => {
+                tracing::error!("failed to serialize commit notification: {}", e);
+                continue;
+            }
+        };
+        let mut backoff = Duration::from_millis(config.backoff);
+        for attempt in 0..=config.retries {
+            let mut request = client
+                .post(&config.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(secret) = &config.secret {
+                request = request.header(SIGNATURE_HEADER, sign(secret.as_bytes(), &body));
+            }
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => break,
+                Err(e) if attempt == config.retries => {
+                    tracing::warn!(
+                        "giving up on commit notification for {} after {} attempts: {}",
+                        notification.orbit,
+                        attempt + 1,
+                        e
+                    );
+                }
+                Err(e) => {
+                    tracing::debug!("commit notification failed, retrying: {}", e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    async fn signature() {
+        assert_eq!(
+            sign(b"key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
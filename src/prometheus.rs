@@ -1,6 +1,9 @@
 use hyper::{header::CONTENT_TYPE, Body, Request, Response};
 use lazy_static::lazy_static;
-use prometheus::{register_histogram_vec, Encoder, HistogramVec, TextEncoder};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, Encoder, HistogramVec,
+    IntCounter, IntCounterVec, TextEncoder,
+};
 
 lazy_static! {
     pub static ref AUTHORIZED_INVOKE_HISTOGRAM: HistogramVec = register_histogram_vec!(
@@ -15,18 +18,46 @@ lazy_static! {
         &["request"]
     )
     .unwrap();
+    pub static ref STORE_INCONSISTENCY_COUNTER: IntCounter = register_int_counter!(
+        "kepler_store_inconsistency_total",
+        "The number of reads where the database referenced content missing from block storage."
+    )
+    .unwrap();
+    pub static ref DROPPED_COMMIT_NOTIFICATIONS: IntCounterVec = register_int_counter_vec!(
+        "kepler_commit_notifications_dropped_total",
+        "The number of commit notifications dropped because a subscriber's queue was full.",
+        &["policy"]
+    )
+    .unwrap();
+    pub static ref STORAGE_RETRIES: IntCounterVec = register_int_counter_vec!(
+        "kepler_storage_retries_total",
+        "The number of storage backend calls retried after a transient failure.",
+        &["backend"]
+    )
+    .unwrap();
+    pub static ref DID_CACHE: IntCounterVec = register_int_counter_vec!(
+        "kepler_did_resolutions_total",
+        "The number of DID resolutions by whether they hit the DID document cache.",
+        &["cache"]
+    )
+    .unwrap();
 }
 
-pub async fn serve_req(_req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+/// The registered metrics in the Prometheus text format, and its content type.
+pub fn encode() -> (&'static str, Vec<u8>) {
     let encoder = TextEncoder::new();
 
     let metric_families = prometheus::gather();
     let mut buffer = vec![];
     encoder.encode(&metric_families, &mut buffer).unwrap();
+    (encoder.format_type(), buffer)
+}
 
+pub async fn serve_req(_req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    let (format, buffer) = encode();
     let response = Response::builder()
         .status(200)
-        .header(CONTENT_TYPE, encoder.format_type())
+        .header(CONTENT_TYPE, format)
         .body(Body::from(buffer))
         .unwrap();
     Ok(response)
@@ -0,0 +1,234 @@
+use crate::config::{RateLimit, RateLimits};
+use kepler_lib::resource::OrbitId;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// At most this many buckets are kept, the least recently used being evicted first.
+const MAX_BUCKETS: usize = 10_000;
+
+type Key = (OrbitId, Option<String>);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    fn capacity(&self) -> f64 {
+        f64::from(self.burst.unwrap_or(self.requests).max(1))
+    }
+
+    // tokens added per second
+    fn rate(&self) -> f64 {
+        f64::from(self.requests) / self.period.max(1) as f64
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(limit.capacity());
+        self.updated = now;
+    }
+}
+
+/// Buckets by key, bounded by evicting the least recently used.
+#[derive(Debug)]
+struct Buckets {
+    capacity: usize,
+    buckets: HashMap<Key, (Bucket, u64)>,
+    // keys by the use count at which they were last used
+    used: BTreeMap<u64, Key>,
+    uses: u64,
+}
+
+impl Buckets {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            buckets: HashMap::new(),
+            used: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    fn get_or_insert_with(&mut self, key: Key, new: impl FnOnce() -> Bucket) -> &mut Bucket {
+        if !self.buckets.contains_key(&key) && self.buckets.len() >= self.capacity {
+            if let Some(oldest) = self.used.keys().next().copied() {
+                if let Some(evicted) = self.used.remove(&oldest) {
+                    self.buckets.remove(&evicted);
+                }
+            }
+        }
+        self.uses += 1;
+        let uses = self.uses;
+        match self.buckets.entry(key) {
+            Entry::Occupied(e) => {
+                let (bucket, used) = e.into_mut();
+                if let Some(key) = self.used.remove(used) {
+                    self.used.insert(uses, key);
+                }
+                *used = uses;
+                bucket
+            }
+            Entry::Vacant(e) => {
+                self.used.insert(uses, e.key().clone());
+                &mut e.insert((new(), uses)).0
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
+/// Token bucket rate limiter for requests to orbits, shared between routes.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimits) -> Self {
+        Self::with_capacity(config, MAX_BUCKETS)
+    }
+
+    fn with_capacity(config: RateLimits, capacity: usize) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(Buckets::new(capacity)),
+        }
+    }
+
+    /// Take a request to `orbit` by `invoker` from its bucket, or return how long to wait
+    /// before retrying if the bucket is empty. Only requests with verified signatures should
+    /// be checked, as the bucket is picked by the claimed orbit and invoker.
+    pub fn check(&self, orbit: &OrbitId, invoker: &str) -> Result<(), Duration> {
+        self.check_at(orbit, invoker, Instant::now())
+    }
+
+    /// Check a request which touches several orbits, failing if any of them is limited.
+    pub fn check_all<'a>(
+        &self,
+        orbits: impl IntoIterator<Item = &'a OrbitId>,
+        invoker: &str,
+    ) -> Result<(), Duration> {
+        orbits
+            .into_iter()
+            .try_for_each(|orbit| self.check(orbit, invoker))
+    }
+
+    fn check_at(&self, orbit: &OrbitId, invoker: &str, now: Instant) -> Result<(), Duration> {
+        let limit = match self
+            .config
+            .orbits
+            .get(&orbit.to_string())
+            .or(self.config.default.as_ref())
+        {
+            Some(l) => l,
+            None => return Ok(()),
+        };
+        let key = (
+            orbit.clone(),
+            self.config.invoker.then(|| invoker.to_string()),
+        );
+
+        // a poisoned lock only means another request panicked mid-update
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets.get_or_insert_with(key, || Bucket {
+            tokens: limit.capacity(),
+            updated: now,
+        });
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / limit.rate().max(f64::MIN_POSITIVE),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limit(requests: u32, burst: Option<u32>) -> RateLimit {
+        RateLimit {
+            requests,
+            period: 1,
+            burst,
+        }
+    }
+
+    #[test]
+    async fn exhausts_and_refills() {
+        let limiter = RateLimiter::new(RateLimits {
+            default: Some(limit(2, None)),
+            ..Default::default()
+        });
+        let orbit: OrbitId = "kepler:example://default".parse().unwrap();
+        let start = Instant::now();
+        assert!(limiter.check_at(&orbit, "alice", start).is_ok());
+        assert!(limiter.check_at(&orbit, "alice", start).is_ok());
+        let wait = limiter.check_at(&orbit, "alice", start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        // the limit is per orbit, so other invokers share it
+        assert!(limiter.check_at(&orbit, "bob", start).is_err());
+        assert!(limiter
+            .check_at(&orbit, "alice", start + Duration::from_millis(500))
+            .is_ok());
+    }
+
+    #[test]
+    async fn per_orbit_and_invoker() {
+        let limited: OrbitId = "kepler:example://limited".parse().unwrap();
+        let other: OrbitId = "kepler:example://default".parse().unwrap();
+        let limiter = RateLimiter::new(RateLimits {
+            default: None,
+            invoker: true,
+            orbits: [(limited.to_string(), limit(1, Some(1)))].into(),
+        });
+        let now = Instant::now();
+        assert!(limiter.check_at(&limited, "alice", now).is_ok());
+        assert!(limiter.check_at(&limited, "alice", now).is_err());
+        assert!(limiter.check_at(&limited, "bob", now).is_ok());
+        // orbits without a limit are not limited
+        for _ in 0..10 {
+            assert!(limiter.check_at(&other, "alice", now).is_ok());
+        }
+        assert!(limiter.check_all([&other, &limited], "bob").is_err());
+    }
+
+    #[test]
+    async fn evicts_least_recently_used() {
+        let limiter = RateLimiter::with_capacity(
+            RateLimits {
+                default: Some(limit(1, Some(1))),
+                ..Default::default()
+            },
+            2,
+        );
+        let [a, b, c]: [OrbitId; 3] =
+            ["a", "b", "c"].map(|n| format!("kepler:example://{n}").parse().unwrap());
+        let now = Instant::now();
+        assert!(limiter.check_at(&a, "alice", now).is_ok());
+        assert!(limiter.check_at(&b, "alice", now).is_ok());
+        // using a's bucket again makes b's the least recently used
+        assert!(limiter.check_at(&a, "alice", now).is_err());
+        assert!(limiter.check_at(&c, "alice", now).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+        assert!(limiter.check_at(&a, "alice", now).is_err());
+        // b's bucket was evicted, so it starts full again
+        assert!(limiter.check_at(&b, "alice", now).is_ok());
+    }
+}
@@ -0,0 +1,396 @@
+use futures::io::{AsyncRead, AsyncReadExt, Cursor};
+use kepler_core::{
+    events::SerializedEvent,
+    storage::{HashBuffer, ImmutableStaging},
+    types::{Metadata, Resource},
+    util::InvocationInfo,
+    Commit, InvocationOutcome, InvokeOptions,
+};
+use kepler_lib::{authorization::KeplerInvocation, resource::OrbitId};
+use rocket::{http::Status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tracing::{info_span, Instrument};
+
+use super::{
+    charge_invocation,
+    error::{ApiError, ErrorCode},
+    invoke_error, stage_input, BODY_TOO_LARGE,
+};
+use crate::{
+    auth_guards::{InvOut, PART_STATUS},
+    config::{Config, EmptyListPolicy},
+    notifications::CommitNotifier,
+    rate_limit::RateLimiter,
+    tracing::TracingSpan,
+    BlockStage, Kepler,
+};
+
+type Invocation = SerializedEvent<InvocationInfo>;
+type Inputs = HashMap<
+    (OrbitId, String),
+    (
+        Metadata,
+        HashBuffer<<BlockStage as ImmutableStaging>::Writable>,
+    ),
+>;
+
+/// One invocation of a batch.
+#[derive(Deserialize)]
+pub struct BatchItem {
+    /// The invocation, as it would be given in the `Authorization` header.
+    pub authorization: String,
+    /// Headers of the content written by a `kv/put`, such as its `content-type`.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// The base64 encoded content written by a `kv/put`.
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+/// Result of one invocation of a batch, at the same position as the invocation.
+#[derive(Serialize, Debug, Default)]
+pub struct BatchResult {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// One outcome per invoked capability, in the order they were invoked.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outcomes: Vec<BatchOutcome>,
+    /// CIDs of the invocation's receipts, as given in the `x-kepler-receipt` header.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub receipts: Vec<String>,
+}
+
+/// Outcome of one capability, with the status, headers and body it would have as a
+/// response on its own, the body base64 encoded.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct BatchOutcome {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl From<ApiError> for BatchResult {
+    fn from(e: ApiError) -> Self {
+        Self {
+            status: e.status.code,
+            error: Some(e.code),
+            message: Some(e.message),
+            ..Default::default()
+        }
+    }
+}
+
+/// Render an outcome as the part it would be of a `multipart/mixed` response.
+async fn batch_outcome<R>(
+    outcome: InvocationOutcome<R>,
+    empty_list: EmptyListPolicy,
+) -> Result<BatchOutcome, Status>
+where
+    R: 'static + AsyncRead + Send,
+{
+    let (headers, mut content) = InvOut(outcome)
+        .peek_list(empty_list)
+        .await
+        .into_part(empty_list)?;
+    let mut body = Vec::new();
+    content
+        .read_to_end(&mut body)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let mut status = Status::Ok.code;
+    let headers = headers
+        .into_iter()
+        .filter_map(|(k, v)| match k.as_str() {
+            PART_STATUS => {
+                status = v.parse().unwrap_or(status);
+                None
+            }
+            _ => Some((k, v)),
+        })
+        .collect();
+    Ok(BatchOutcome {
+        status,
+        headers,
+        body: base64::encode(body),
+    })
+}
+
+/// Parse an item's invocation, rate limit it and stage the content it writes.
+async fn prepare(
+    item: &BatchItem,
+    staging: &BlockStage,
+    kepler: &Kepler,
+    config: &Config,
+    limiter: &RateLimiter,
+) -> Result<(Invocation, Inputs), ApiError> {
+    let invocation = if config.encoding.strict {
+        Invocation::from_header_ser_strict::<KeplerInvocation>(&item.authorization)
+    } else {
+        Invocation::from_header_ser::<KeplerInvocation>(&item.authorization)
+    }
+    .map_err(|e| ApiError::new(Status::Unauthorized, ErrorCode::Unauthorized, e.to_string()))?;
+    charge_invocation(limiter, &invocation.0).await?;
+
+    let mut put_iter =
+        invocation
+            .0
+            .capabilities
+            .iter()
+            .filter_map(|c| match (&c.resource, c.action.as_str()) {
+                (Resource::Kepler(r), "put") if r.service() == Some("kv") => {
+                    r.path().map(|p| (r.orbit(), p))
+                }
+                _ => None,
+            });
+    let mut inputs = HashMap::new();
+    match (&item.content, put_iter.next(), put_iter.next()) {
+        (None, None, _) => {}
+        (Some(content), Some((orbit, path)), None) => {
+            let content = base64::decode(content).map_err(|_| {
+                ApiError::new(
+                    Status::BadRequest,
+                    ErrorCode::BadRequest,
+                    "Content is not valid base64",
+                )
+            })?;
+            let max_body = config.requests.maxbody.as_u64();
+            if content.len() as u64 > max_body {
+                return Err(ApiError::new(
+                    Status::PayloadTooLarge,
+                    ErrorCode::PayloadTooLarge,
+                    BODY_TOO_LARGE,
+                ));
+            }
+            let metadata = Metadata(item.headers.clone());
+            let stage = stage_input(
+                Cursor::new(content),
+                None,
+                orbit,
+                &metadata,
+                staging,
+                kepler,
+                config,
+            )
+            .await?;
+            inputs.insert((orbit.clone(), path.to_string()), (metadata, stage));
+        }
+        (Some(_), Some(_), Some(_)) => {
+            return Err(ApiError::new(
+                Status::BadRequest,
+                ErrorCode::BadRequest,
+                "Multipart not yet supported",
+            ));
+        }
+        _ => {
+            return Err(ApiError::new(
+                Status::BadRequest,
+                ErrorCode::MissingInput,
+                "Invalid inputs",
+            ));
+        }
+    }
+    Ok((invocation, inputs))
+}
+
+/// Render an invocation's commits and outcomes as its result.
+async fn result<R>(
+    commits: HashMap<OrbitId, Commit>,
+    outcomes: Vec<InvocationOutcome<R>>,
+    config: &Config,
+) -> Result<BatchResult, ApiError>
+where
+    R: 'static + AsyncRead + Send,
+{
+    let mut rendered = Vec::with_capacity(outcomes.len());
+    for outcome in outcomes {
+        rendered.push(
+            batch_outcome(outcome, config.storage.emptylist)
+                .await
+                .map_err(|s| ApiError::new(s, s.into(), "Failed to render the outcome"))?,
+        );
+    }
+    Ok(BatchResult {
+        status: Status::Ok.code,
+        outcomes: rendered,
+        receipts: commits
+            .values()
+            .filter_map(|c| c.receipt)
+            .map(|h| h.to_cid(0x71).to_string())
+            .collect(),
+        ..Default::default()
+    })
+}
+
+/// Prepare and run one item of a batch, returning its outcomes.
+async fn run(
+    item: &BatchItem,
+    staging: &BlockStage,
+    kepler: &Kepler,
+    config: &Config,
+    notifier: &CommitNotifier,
+    limiter: &RateLimiter,
+) -> Result<BatchResult, ApiError> {
+    let (invocation, inputs) = prepare(item, staging, kepler, config, limiter).await?;
+    let (commits, outcomes) = kepler
+        .invoke_with::<BlockStage>(invocation, inputs, invoke_options(config))
+        .await
+        .map_err(|e| invoke_error(e, config))?;
+    notifier.publish(&commits).await;
+    result(commits, outcomes, config).await
+}
+
+/// Prepare every item of a batch and run them in one transaction, failing them all if
+/// any of them fails.
+async fn run_atomic(
+    items: &[BatchItem],
+    staging: &BlockStage,
+    kepler: &Kepler,
+    config: &Config,
+    notifier: &CommitNotifier,
+    limiter: &RateLimiter,
+) -> Vec<BatchResult> {
+    // the items other than the one which failed are reported as not applied
+    let aborted = |failed: Option<usize>, e: ApiError| -> Vec<BatchResult> {
+        (0..items.len())
+            .map(|i| match failed {
+                Some(f) if f != i => ApiError::new(
+                    Status::FailedDependency,
+                    ErrorCode::BatchAborted,
+                    "Not applied, as another invocation of the batch failed",
+                )
+                .into(),
+                _ => e.clone().into(),
+            })
+            .collect()
+    };
+
+    let mut prepared = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        match prepare(item, staging, kepler, config, limiter).await {
+            Ok(p) => prepared.push(p),
+            Err(e) => return aborted(Some(i), e),
+        }
+    }
+    let applied = match kepler
+        .invoke_batch::<BlockStage>(prepared, invoke_options(config))
+        .await
+    {
+        Ok(applied) => applied,
+        Err((failed, e)) => return aborted(failed, invoke_error(e, config)),
+    };
+    let mut results = Vec::with_capacity(applied.len());
+    for (commits, outcomes) in applied {
+        notifier.publish(&commits).await;
+        results.push(
+            result(commits, outcomes, config)
+                .await
+                .unwrap_or_else(BatchResult::from),
+        );
+    }
+    results
+}
+
+fn invoke_options(config: &Config) -> InvokeOptions {
+    InvokeOptions {
+        max_operations: config.invocations.operations,
+        check_orbits: config.invocations.strict,
+        ..Default::default()
+    }
+}
+
+/// Run a batch of independent invocations, returning one result per invocation in order.
+///
+/// A failing invocation doesn't prevent the others from being applied, unless `atomic` is
+/// set, in which case the invocations are applied in one transaction, and none is applied
+/// if any of them fails.
+#[post("/batch?<atomic>", data = "<items>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn batch(
+    items: Json<Vec<BatchItem>>,
+    atomic: Option<bool>,
+    req_span: TracingSpan,
+    staging: &State<BlockStage>,
+    kepler: &State<Kepler>,
+    config: &State<Config>,
+    notifier: &State<CommitNotifier>,
+    limiter: &State<RateLimiter>,
+) -> Json<Vec<BatchResult>> {
+    let span = info_span!(parent: &req_span.0, "batch", items = items.len());
+    async move {
+        if atomic.unwrap_or(false) {
+            return Json(run_atomic(&items, staging, kepler, config, notifier, limiter).await);
+        }
+        let mut results = Vec::with_capacity(items.len());
+        for item in items.iter() {
+            results.push(
+                run(item, staging, kepler, config, notifier, limiter)
+                    .await
+                    .unwrap_or_else(BatchResult::from),
+            );
+        }
+        Json(results)
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kepler_core::storage::Content;
+
+    #[test]
+    async fn outcomes() {
+        let read = batch_outcome(
+            InvocationOutcome::KvRead(Some((
+                Metadata(
+                    [("content-type".to_string(), "text/plain".to_string())]
+                        .into_iter()
+                        .collect(),
+                ),
+                Content::new(5, Cursor::new(b"hello".to_vec())),
+            ))),
+            EmptyListPolicy::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            read,
+            BatchOutcome {
+                status: 200,
+                headers: [
+                    ("content-type".to_string(), "text/plain".to_string()),
+                    ("content-length".to_string(), "5".to_string()),
+                ]
+                .into_iter()
+                .collect(),
+                body: base64::encode("hello"),
+            }
+        );
+
+        let missing = batch_outcome::<Cursor<Vec<u8>>>(
+            InvocationOutcome::KvRead(None),
+            EmptyListPolicy::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(missing.status, 404);
+        assert!(missing.headers.is_empty());
+        assert!(missing.body.is_empty());
+
+        let failed = serde_json::to_value(BatchResult::from(ApiError::new(
+            Status::Forbidden,
+            ErrorCode::ReadOnly,
+            "read only",
+        )))
+        .unwrap();
+        assert_eq!(
+            failed,
+            serde_json::json!({ "status": 403, "error": "read_only", "message": "read only" })
+        );
+    }
+}
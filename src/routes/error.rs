@@ -0,0 +1,282 @@
+use kepler_core::{
+    keys::Secrets,
+    sea_orm::DbErr,
+    storage::{
+        ImmutableDeleteStore, ImmutableReadStore, ImmutableStaging, ImmutableWriteStore,
+        StorageSetup,
+    },
+    TxError, TxStoreError,
+};
+use rocket::{
+    http::Status,
+    request::Request,
+    response::{Responder, Response},
+    serde::json::Json,
+};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Stable, machine readable code identifying the kind of an error response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    OrbitNotFound,
+    Conflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RateLimited,
+    InvalidUcan,
+    InvalidCacao,
+    InvalidDelegation,
+    InvalidInvocation,
+    InvalidRevocation,
+    InvalidEncoding,
+    MissingInput,
+    TooManyOperations,
+    UndelegatedOrbit,
+    ReadOnly,
+    Maintenance,
+    Replayed,
+    InvalidKey,
+    MissingContent,
+    BatchAborted,
+    Database,
+    Storage,
+    StorageTimeout,
+    Internal,
+}
+
+impl From<Status> for ErrorCode {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Unauthorized | Status::Forbidden => Self::Unauthorized,
+            Status::NotFound => Self::NotFound,
+            Status::Conflict => Self::Conflict,
+            Status::PayloadTooLarge => Self::PayloadTooLarge,
+            Status::UnsupportedMediaType => Self::UnsupportedMediaType,
+            Status::TooManyRequests => Self::RateLimited,
+            s if s.code < 500 => Self::BadRequest,
+            _ => Self::Internal,
+        }
+    }
+}
+
+impl<S: StorageSetup, K: Secrets> From<&TxError<S, K>> for ErrorCode {
+    fn from(e: &TxError<S, K>) -> Self {
+        match e {
+            TxError::Db(_) => Self::Database,
+            TxError::Ucan(_) => Self::InvalidUcan,
+            TxError::Cacao(_) => Self::InvalidCacao,
+            TxError::InvalidDelegation(_) => Self::InvalidDelegation,
+            TxError::InvalidInvocation(_) => Self::InvalidInvocation,
+            TxError::InvalidRevocation(_) => Self::InvalidRevocation,
+            TxError::Encoding(_) => Self::InvalidEncoding,
+            TxError::StoreSetup(_) => Self::Storage,
+            TxError::OrbitNotFound => Self::OrbitNotFound,
+            _ => Self::Internal,
+        }
+    }
+}
+
+impl<B, S, K> From<&TxStoreError<B, S, K>> for ErrorCode
+where
+    B: ImmutableReadStore + ImmutableWriteStore<S> + ImmutableDeleteStore + StorageSetup,
+    S: ImmutableStaging,
+    S::Writable: 'static + Unpin,
+    K: Secrets,
+{
+    fn from(e: &TxStoreError<B, S, K>) -> Self {
+        match e {
+            TxStoreError::Tx(e) => e.into(),
+            TxStoreError::StoreRead(_)
+            | TxStoreError::StoreWrite(_)
+            | TxStoreError::StoreDelete(_) => Self::Storage,
+            TxStoreError::MissingInput => Self::MissingInput,
+            TxStoreError::TooManyOperations { .. } => Self::TooManyOperations,
+            TxStoreError::UndelegatedOrbit(_) => Self::UndelegatedOrbit,
+            TxStoreError::ReadOnly(..) => Self::ReadOnly,
+            TxStoreError::Maintenance(..) => Self::Maintenance,
+            TxStoreError::Replayed(_) => Self::Replayed,
+            TxStoreError::InvalidKey(_) => Self::InvalidKey,
+            TxStoreError::StorageTimeout(_) => Self::StorageTimeout,
+            TxStoreError::MissingContent { .. } => Self::MissingContent,
+            _ => Self::Internal,
+        }
+    }
+}
+
+/// Response status for a failed transaction.
+pub fn tx_status<S: StorageSetup, K: Secrets>(e: &TxError<S, K>) -> Status {
+    match e {
+        TxError::OrbitNotFound => Status::NotFound,
+        TxError::Db(DbErr::ConnectionAcquire) | TxError::Receipt(_) => Status::InternalServerError,
+        _ => Status::Unauthorized,
+    }
+}
+
+/// Error response of the API.
+///
+/// The body is the plain text message, unless the client prefers JSON, in which
+/// case it is `{ "error": "<code>", "message": "<message>" }`.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: Status,
+    pub code: ErrorCode,
+    pub message: String,
+    /// How long the client should wait before retrying, sent as `Retry-After`.
+    pub retry_after: Option<Duration>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorCode,
+    message: &'a str,
+}
+
+impl ApiError {
+    pub fn new(status: Status, code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    /// A request refused by the rate limiter, which may be retried after the given time.
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        Self {
+            retry_after: Some(retry_after),
+            ..Self::new(
+                Status::TooManyRequests,
+                ErrorCode::RateLimited,
+                "Too many requests, try again later",
+            )
+        }
+    }
+}
+
+impl From<(Status, String)> for ApiError {
+    fn from((status, message): (Status, String)) -> Self {
+        Self::new(status, status.into(), message)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let json = request
+            .accept()
+            .map(|a| a.preferred().is_json())
+            .unwrap_or(false);
+        let body = if json {
+            Json(ErrorBody {
+                error: self.code,
+                message: &self.message,
+            })
+            .respond_to(request)?
+        } else {
+            self.message.respond_to(request)?
+        };
+        let mut response = Response::build_from(body);
+        response.status(self.status);
+        if let Some(retry_after) = self.retry_after {
+            // round up, so retrying after the given number of seconds succeeds
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.raw_header("Retry-After", secs.to_string());
+        }
+        response.ok()
+    }
+}
+
+/// Catcher for errors raised outside of route handlers, e.g. by failing request guards.
+#[catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> ApiError {
+    ApiError::new(
+        status,
+        status.into(),
+        status.reason().unwrap_or("Unknown error"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rocket::{http::Accept, local::asynchronous::Client};
+
+    #[get("/limited")]
+    fn limited() -> ApiError {
+        ApiError::rate_limited(Duration::from_millis(1500))
+    }
+
+    #[get("/missing")]
+    fn missing() -> ApiError {
+        ApiError::new(
+            Status::NotFound,
+            ErrorCode::OrbitNotFound,
+            "Orbit not found",
+        )
+    }
+
+    async fn client() -> Client {
+        let rocket = rocket::build()
+            .mount("/", routes![limited, missing])
+            .register("/", catchers![default_catcher]);
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    #[test]
+    async fn status_codes() {
+        assert_eq!(ErrorCode::from(Status::NotFound), ErrorCode::NotFound);
+        assert_eq!(
+            ErrorCode::from(Status::PayloadTooLarge),
+            ErrorCode::PayloadTooLarge
+        );
+        assert_eq!(ErrorCode::from(Status::ImATeapot), ErrorCode::BadRequest);
+        assert_eq!(ErrorCode::from(Status::BadGateway), ErrorCode::Internal);
+        assert_eq!(
+            serde_json::to_value(ErrorCode::OrbitNotFound).unwrap(),
+            "orbit_not_found"
+        );
+    }
+
+    #[test]
+    async fn orbit_not_found() {
+        use crate::storage::file_system::FileSystemStore;
+        use kepler_core::keys::StaticSecret;
+
+        let e = TxError::<FileSystemStore, StaticSecret>::OrbitNotFound;
+        assert_eq!(tx_status(&e), Status::NotFound);
+        assert_eq!(ErrorCode::from(&e), ErrorCode::OrbitNotFound);
+    }
+
+    #[test]
+    async fn negotiation() {
+        let client = client().await;
+
+        let res = client.get("/missing").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        assert_eq!(res.into_string().await.unwrap(), "Orbit not found");
+
+        let res = client.get("/missing").header(Accept::JSON).dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        let body: serde_json::Value =
+            serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "orbit_not_found", "message": "Orbit not found" })
+        );
+
+        let res = client.get("/limited").header(Accept::JSON).dispatch().await;
+        assert_eq!(res.status(), Status::TooManyRequests);
+        assert_eq!(res.headers().get_one("Retry-After"), Some("2"));
+
+        let res = client.get("/unknown").header(Accept::JSON).dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        let body: serde_json::Value =
+            serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+        assert_eq!(body["error"], "not_found");
+    }
+}
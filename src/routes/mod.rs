@@ -1,26 +1,56 @@
 use anyhow::Result;
-use rocket::{data::ToByteUnit, http::Status, State};
-use std::collections::HashMap;
+use futures::io::AsyncRead;
+use rocket::{
+    data::{ByteUnit, ToByteUnit},
+    http::{Accept, ContentType, Status},
+    serde::json::Json,
+    State,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+use time::OffsetDateTime;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 use tracing::{info_span, Instrument};
 
 use crate::{
-    auth_guards::{DataIn, DataOut, InvOut, ObjectHeaders},
+    auth_guards::{ndjson_type, AdminKey, DataIn, DataOut, InvOut, ObjectHeaders, Receipted},
     authorization::AuthHeaderGetter,
     config::Config,
+    notifications::CommitNotifier,
+    rate_limit::RateLimiter,
     tracing::TracingSpan,
     BlockStage, BlockStores, Kepler,
 };
 use kepler_core::{
-    sea_orm::DbErr,
-    storage::{ImmutableReadStore, ImmutableStaging},
-    types::Resource,
-    util::{DelegationInfo, InvocationInfo},
-    TxError, TxStoreError,
+    hash::{hash, Hash},
+    keys::StaticSecret,
+    models::{invocation, orbit_alias::is_valid_alias},
+    storage::{either::Either, HashBuffer, ImmutableReadStore, ImmutableStaging, ResumableStaging},
+    types::{Caveats, Metadata, Resource},
+    util::{is_controller, DelegationInfo, InvocationInfo, RevocationInfo},
+    AliasError, Commit, CompactOutcome, InvocationOutcome, InvokeOptions, PurgeError, PurgeOutcome,
+    SessionsQuery, TxStoreError,
+};
+use kepler_lib::{
+    authorization::{KeplerDelegation, KeplerRevocation},
+    libipld::cid::Cid,
+    resolver::DID_METHODS,
+    resource::OrbitId,
 };
 
+pub mod batch;
+pub mod error;
+pub mod upload;
 pub mod util;
-use util::LimitedReader;
+use error::{tx_status, ApiError, ErrorCode};
+use upload::{parse_hash, upload_error, Uploads};
+use util::{
+    check_content_type, limit_exceeded, missing_content_status, parse_version, LimitedReader,
+    SniffReader,
+};
 
 #[allow(clippy::let_unit_value)]
 pub mod util_routes {
@@ -29,41 +59,417 @@ pub mod util_routes {
     #[options("/<_s..>")]
     pub async fn cors(_s: std::path::PathBuf) {}
 
+    /// Liveness probe, responding `200` while the database accepts connections, with
+    /// whether the node is in maintenance mode.
     #[get("/healthz")]
-    pub async fn healthcheck(s: &State<Kepler>) -> Status {
-        if s.check_db_connection().await.is_ok() {
+    pub async fn healthcheck(s: &State<Kepler>) -> (Status, Json<Health>) {
+        let status = if s.check_db_connection().await.is_ok() {
             Status::Ok
         } else {
             Status::InternalServerError
+        };
+        (
+            status,
+            Json(Health {
+                maintenance: s.maintenance(),
+            }),
+        )
+    }
+
+    /// Metrics in the Prometheus text format, as served on `prometheus.port`. Only mounted
+    /// when `prometheus.route` is set.
+    #[get("/metrics")]
+    pub async fn metrics(
+        admin: Option<AdminKey>,
+        config: &State<Config>,
+    ) -> Result<(ContentType, Vec<u8>), (Status, String)> {
+        if config.prometheus.auth {
+            require_admin(admin)?;
         }
+        let (format, body) = crate::prometheus::encode();
+        Ok((
+            ContentType::parse_flexible(format).unwrap_or(ContentType::Plain),
+            body,
+        ))
+    }
+
+    /// Readiness probe, checking the database and block storage are reachable. Responds
+    /// `200` if both are, `503` otherwise, with the outcome of each check.
+    #[get("/readyz")]
+    pub async fn readiness(s: &State<Kepler>) -> (Status, Json<BTreeMap<&'static str, String>>) {
+        let storage = async {
+            match s.storage().inner().inner() {
+                Either::A(s3) => s3.check().await.map_err(|e| e.to_string()),
+                Either::B(Either::A(fs)) => fs.check().await.map_err(|e| e.to_string()),
+                Either::B(Either::B(_)) => Ok(()),
+            }
+        };
+        let (database, storage) = tokio::join!(
+            probe(READINESS_TIMEOUT, s.check_db_connection()),
+            probe(READINESS_TIMEOUT, storage)
+        );
+        let status = match (&database, &storage) {
+            (Ok(()), Ok(())) => Status::Ok,
+            _ => Status::ServiceUnavailable,
+        };
+        let checks = [("database", database), ("storage", storage)]
+            .into_iter()
+            .map(|(name, r)| (name, r.err().unwrap_or_else(|| "ok".to_string())))
+            .collect();
+        (status, Json(checks))
+    }
+}
+
+/// How long a readiness check may take before the dependency counts as unready.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+async fn probe<E: std::fmt::Display>(
+    timeout: Duration,
+    check: impl std::future::Future<Output = Result<(), E>>,
+) -> Result<(), String> {
+    match tokio::time::timeout(timeout, check).await {
+        Ok(r) => r.map_err(|e| e.to_string()),
+        Err(_) => Err("timed out".to_string()),
     }
 }
 
+/// Status reported by the liveness probe.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Health {
+    /// Whether writes and deletes are refused while the node is in maintenance.
+    pub maintenance: bool,
+}
+
+/// Whether an orbit is provisioned on this host.
+#[derive(Serialize)]
+pub struct OrbitStatus {
+    pub exists: bool,
+    /// Unix time in seconds the orbit was created at, if it was recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<i64>,
+    /// Sequence number of the orbit's latest commit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+}
+
+/// Whether an orbit, by its ID or alias, is provisioned, responding `404` if not, so
+/// clients can tell whether a host delegation is still needed.
+#[get("/orbit/<orbit>/exists")]
+pub async fn orbit_exists(
+    orbit: &str,
+    kepler: &State<Kepler>,
+) -> Result<(Status, Json<OrbitStatus>), (Status, String)> {
+    let info = kepler
+        .orbit_info(&resolve_orbit(kepler, orbit).await?)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?;
+    Ok(match info {
+        Some(info) => (
+            Status::Ok,
+            Json(OrbitStatus {
+                exists: true,
+                created: info.created_at.map(|t| t.unix_timestamp()),
+                seq: Some(info.seq),
+            }),
+        ),
+        None => (
+            Status::NotFound,
+            Json(OrbitStatus {
+                exists: false,
+                created: None,
+                seq: None,
+            }),
+        ),
+    })
+}
+
 #[get("/peer/generate/<orbit>")]
-pub async fn open_host_key(
-    s: &State<Kepler>,
+pub async fn open_host_key(s: &State<Kepler>, orbit: &str) -> Result<String, (Status, String)> {
+    s.stage_key(&resolve_orbit(s, orbit).await?)
+        .await
+        .map_err(|_| {
+            (
+                Status::InternalServerError,
+                "Failed to stage keypair for orbit".to_string(),
+            )
+        })
+}
+
+/// The hash of an invocation which is a valid request from the orbit's controller to
+/// purge it, addressed to the orbit's host.
+async fn controller_purge(kepler: &Kepler, orbit: &OrbitId, i: &InvocationInfo) -> Option<Hash> {
+    let valid = is_controller(orbit, &i.invoker)
+        && i.capabilities.iter().any(|c| {
+            c.action == "purge"
+                && matches!(&c.resource, Resource::Kepler(r)
+                    if r.orbit() == orbit && r.service().is_none() && r.path().is_none())
+        })
+        && i.invocation.payload.validate_time(None).is_ok()
+        && i.invocation
+            .verify_signature(DID_METHODS.to_resolver())
+            .await
+            .is_ok();
+    if !valid {
+        return None;
+    }
+    match kepler.stage_key(orbit).await {
+        Ok(host) if i.invocation.payload.audience == host => {}
+        _ => return None,
+    }
+    i.invocation
+        .encode()
+        .ok()
+        .map(|encoded| hash(encoded.as_bytes()))
+}
+
+#[delete("/admin/orbit/<orbit>")]
+pub async fn purge_orbit(
     orbit: &str,
-) -> Result<String, (Status, &'static str)> {
-    s.stage_key(
-        &orbit
-            .parse()
-            .map_err(|_| (Status::BadRequest, "Invalid orbit ID"))?,
-    )
-    .await
-    .map_err(|_| {
+    admin: Option<AdminKey>,
+    invocation: Option<AuthHeaderGetter<InvocationInfo>>,
+    kepler: &State<Kepler>,
+) -> Result<Json<PurgeOutcome>, (Status, String)> {
+    let orbit = resolve_orbit(kepler, orbit).await?;
+    // purges by invocation are recorded, so that each is accepted only once
+    let invocation = match (&admin, invocation) {
+        (None, Some(i)) => controller_purge(kepler, &orbit, &i.0 .0).await,
+        _ => None,
+    };
+    if admin.is_none() && invocation.is_none() {
+        return Err((
+            Status::Unauthorized,
+            "Purging requires the admin key or an invocation from the orbit controller".to_string(),
+        ));
+    }
+    kepler
+        .purge(&orbit, invocation)
+        .await
+        .map(Json)
+        .map_err(|e| match e {
+            PurgeError::Replayed(_) => (Status::Conflict, e.to_string()),
+            _ => (Status::InternalServerError, e.to_string()),
+        })
+}
+
+/// Resolve an orbit given either as a full orbit ID or as an alias. Invocations name
+/// the orbits of their resources by ID, as those are covered by their signatures, so an
+/// alias in a route only selects the orbit the invocation must be for.
+pub(crate) async fn resolve_orbit(
+    kepler: &Kepler,
+    orbit: &str,
+) -> Result<OrbitId, (Status, String)> {
+    if let Ok(id) = orbit.parse() {
+        return Ok(id);
+    }
+    if !is_valid_alias(orbit) {
+        return Err((Status::BadRequest, "Invalid orbit ID or alias".to_string()));
+    }
+    kepler
+        .resolve_alias(orbit)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .ok_or_else(|| (Status::NotFound, "Unknown orbit alias".to_string()))
+}
+
+fn require_admin(admin: Option<AdminKey>) -> Result<(), (Status, String)> {
+    admin
+        .map(|_| ())
+        .ok_or_else(|| (Status::Unauthorized, "Admin key required".to_string()))
+}
+
+#[post("/admin/orbit/<orbit>/compact")]
+pub async fn compact_orbit(
+    orbit: &str,
+    admin: Option<AdminKey>,
+    kepler: &State<Kepler>,
+) -> Result<Json<CompactOutcome>, (Status, String)> {
+    require_admin(admin)?;
+    kepler
+        .compact(&resolve_orbit(kepler, orbit).await?)
+        .await
+        .map(Json)
+        .map_err(|e| (Status::InternalServerError, e.to_string()))
+}
+
+#[get("/admin/orbit/<orbit>/features")]
+pub async fn orbit_features(
+    orbit: &str,
+    admin: Option<AdminKey>,
+    kepler: &State<Kepler>,
+) -> Result<Json<BTreeMap<String, bool>>, (Status, String)> {
+    require_admin(admin)?;
+    kepler
+        .features(&resolve_orbit(kepler, orbit).await?)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .map(Json)
+        .ok_or_else(|| (Status::NotFound, "orbit not found".to_string()))
+}
+
+#[put("/admin/orbit/<orbit>/features/<flag>", data = "<enabled>")]
+pub async fn set_orbit_feature(
+    orbit: &str,
+    flag: &str,
+    enabled: Json<bool>,
+    admin: Option<AdminKey>,
+    kepler: &State<Kepler>,
+) -> Result<Json<BTreeMap<String, bool>>, (Status, String)> {
+    require_admin(admin)?;
+    kepler
+        .set_feature(&resolve_orbit(kepler, orbit).await?, flag, enabled.0)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .map(Json)
+        .ok_or_else(|| (Status::NotFound, "orbit not found".to_string()))
+}
+
+#[put("/admin/orbit/<orbit>/limit", data = "<limit>")]
+pub async fn set_orbit_limit(
+    orbit: &str,
+    limit: Json<Option<ByteUnit>>,
+    admin: Option<AdminKey>,
+    kepler: &State<Kepler>,
+) -> Result<(), (Status, String)> {
+    require_admin(admin)?;
+    kepler
+        .set_storage_limit(
+            &resolve_orbit(kepler, orbit).await?,
+            limit.0.map(|l| l.as_u64()),
+        )
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .then_some(())
+        .ok_or_else(|| (Status::NotFound, "orbit not found".to_string()))
+}
+
+/// Enter or leave maintenance mode, without a restart, responding with the mode as it now
+/// is.
+#[put("/admin/maintenance", data = "<enabled>")]
+pub async fn set_maintenance(
+    enabled: Json<bool>,
+    admin: Option<AdminKey>,
+    kepler: &State<Kepler>,
+) -> Result<Json<Health>, (Status, String)> {
+    require_admin(admin)?;
+    if kepler.set_maintenance(enabled.0) != enabled.0 {
+        tracing::info!(maintenance = enabled.0, "maintenance mode changed");
+    }
+    Ok(Json(Health {
+        maintenance: enabled.0,
+    }))
+}
+
+#[put("/admin/alias/<alias>", data = "<orbit>")]
+pub async fn set_orbit_alias(
+    alias: &str,
+    orbit: Json<String>,
+    admin: Option<AdminKey>,
+    kepler: &State<Kepler>,
+) -> Result<(), (Status, String)> {
+    require_admin(admin)?;
+    let orbit: OrbitId = orbit
+        .parse()
+        .map_err(|_| (Status::BadRequest, "Invalid orbit ID".to_string()))?;
+    kepler.set_alias(alias, &orbit).await.map_err(|e| {
         (
-            Status::InternalServerError,
-            "Failed to stage keypair for orbit",
+            match e {
+                AliasError::InvalidAlias => Status::BadRequest,
+                AliasError::Duplicate => Status::Conflict,
+                AliasError::OrbitNotFound => Status::NotFound,
+                _ => Status::InternalServerError,
+            },
+            e.to_string(),
         )
     })
 }
 
-#[post("/delegate")]
+#[delete("/admin/alias/<alias>")]
+pub async fn remove_orbit_alias(
+    alias: &str,
+    admin: Option<AdminKey>,
+    kepler: &State<Kepler>,
+) -> Result<(), (Status, String)> {
+    require_admin(admin)?;
+    match kepler.remove_alias(alias).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((Status::NotFound, "Unknown orbit alias".to_string())),
+        Err(e) => Err((Status::InternalServerError, e.to_string())),
+    }
+}
+
+/// The commit of a delegation to one of the orbits it affects.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct DelegationCommit {
+    pub seq: i64,
+    pub rev: String,
+    pub committed_events: Vec<String>,
+}
+
+impl From<&Commit> for DelegationCommit {
+    fn from(c: &Commit) -> Self {
+        Self {
+            seq: c.seq,
+            rev: c.rev.to_cid(0x55).to_string(),
+            committed_events: c
+                .committed_events
+                .iter()
+                .map(|h| h.to_cid(0x55).to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Response to a delegation: the commit to each affected orbit, or with `format=cid` only
+/// the CID of the first committed event.
+type DelegationResponse = rocket::Either<String, Json<BTreeMap<String, DelegationCommit>>>;
+
+fn delegation_response(
+    commits: &HashMap<OrbitId, Commit>,
+    format: Option<&str>,
+) -> Result<DelegationResponse, ApiError> {
+    if commits.is_empty() {
+        return Err(ApiError::new(
+            Status::Unauthorized,
+            ErrorCode::Unauthorized,
+            "Delegation not committed",
+        ));
+    }
+    match format {
+        None | Some("json") => Ok(rocket::Either::Right(Json(
+            commits
+                .iter()
+                .map(|(orbit, c)| (orbit.to_string(), c.into()))
+                .collect(),
+        ))),
+        Some("cid") => commits
+            .values()
+            .find_map(|c| c.committed_events.first())
+            .map(|h| rocket::Either::Left(h.to_cid(0x55).to_string()))
+            .ok_or_else(|| {
+                ApiError::new(
+                    Status::Unauthorized,
+                    ErrorCode::Unauthorized,
+                    "Delegation not committed",
+                )
+            }),
+        Some(f) => Err(ApiError::new(
+            Status::BadRequest,
+            ErrorCode::BadRequest,
+            format!("Unknown response format {f}, expected json or cid"),
+        )),
+    }
+}
+
+#[post("/delegate?<format>")]
 pub async fn delegate(
     d: AuthHeaderGetter<DelegationInfo>,
+    format: Option<&str>,
     req_span: TracingSpan,
     kepler: &State<Kepler>,
-) -> Result<String, (Status, String)> {
+    notifier: &State<CommitNotifier>,
+    limiter: &State<RateLimiter>,
+) -> Result<DelegationResponse, ApiError> {
+    charge_delegation(limiter, &d.0 .0).await?;
     let action_label = "delegation";
     let span = info_span!(parent: &req_span.0, "delegate", action = %action_label);
     // Instrumenting async block to handle yielding properly
@@ -71,24 +477,53 @@ pub async fn delegate(
         let timer = crate::prometheus::AUTHORIZED_INVOKE_HISTOGRAM
             .with_label_values(&["delegate"])
             .start_timer();
-        let res = kepler
-            .delegate(d.0)
-            .await
-            .map_err(|e| {
-                (
-                    match e {
-                        TxError::OrbitNotFound => Status::NotFound,
-                        TxError::Db(DbErr::ConnectionAcquire) => Status::InternalServerError,
-                        _ => Status::Unauthorized,
-                    },
-                    e.to_string(),
-                )
-            })
+        let res = kepler.delegate(d.0).await;
+        if let Ok(commits) = &res {
+            notifier.publish(commits).await;
+        }
+        let res = res
+            .map_err(|e| ApiError::new(tx_status(&e), (&e).into(), e.to_string()))
+            .and_then(|c| delegation_response(&c, format));
+        timer.observe_duration();
+        res
+    }
+    .instrument(span)
+    .await
+}
+
+#[post("/revoke")]
+pub async fn revoke(
+    r: AuthHeaderGetter<RevocationInfo>,
+    req_span: TracingSpan,
+    kepler: &State<Kepler>,
+    notifier: &State<CommitNotifier>,
+    limiter: &State<RateLimiter>,
+) -> Result<String, ApiError> {
+    charge_revocation(limiter, kepler, &r.0 .0).await?;
+    let action_label = "revocation";
+    let span = info_span!(parent: &req_span.0, "revoke", action = %action_label);
+    // Instrumenting async block to handle yielding properly
+    async move {
+        let timer = crate::prometheus::AUTHORIZED_INVOKE_HISTOGRAM
+            .with_label_values(&["revoke"])
+            .start_timer();
+        let res = kepler.revoke(r.0).await;
+        if let Ok(commits) = &res {
+            notifier.publish(commits).await;
+        }
+        let res = res
+            .map_err(|e| ApiError::new(tx_status(&e), (&e).into(), e.to_string()))
             .and_then(|c| {
                 c.into_iter()
                     .next()
                     .and_then(|(_, c)| c.committed_events.into_iter().next())
-                    .ok_or_else(|| (Status::Unauthorized, "Delegation not committed".to_string()))
+                    .ok_or_else(|| {
+                        ApiError::new(
+                            Status::Unauthorized,
+                            ErrorCode::Unauthorized,
+                            "Revocation not committed",
+                        )
+                    })
             })
             .map(|h| h.to_cid(0x55).to_string());
         timer.observe_duration();
@@ -98,18 +533,246 @@ pub async fn delegate(
     .await
 }
 
-#[post("/invoke", data = "<data>")]
+/// Abilities held by the session which signed the invocation in the `Authorization` header,
+/// as a map from resource to ability to the caveats of each grant of it.
+#[get("/abilities")]
+pub async fn abilities(
+    i: AuthHeaderGetter<InvocationInfo>,
+    kepler: &State<Kepler>,
+) -> Result<Json<BTreeMap<String, BTreeMap<String, Vec<Caveats>>>>, ApiError> {
+    let granted = kepler.granted_abilities(&i.0).await.map_err(|e| match e {
+        invocation::Error::InvalidInvocation(e) => ApiError::new(
+            Status::Unauthorized,
+            ErrorCode::InvalidInvocation,
+            e.to_string(),
+        ),
+        invocation::Error::Db(e) => ApiError::new(
+            Status::InternalServerError,
+            ErrorCode::Database,
+            e.to_string(),
+        ),
+    })?;
+    let mut abilities = BTreeMap::<String, BTreeMap<String, Vec<Caveats>>>::new();
+    for a in granted {
+        abilities
+            .entry(a.resource.to_string())
+            .or_default()
+            .entry(a.ability)
+            .or_default()
+            .push(a.caveats);
+    }
+    Ok(Json(abilities))
+}
+
+const BODY_TOO_LARGE: &str = "The request body is too large";
+
+/// Charge an invocation to the rate limits of the orbits it touches, once its signature is
+/// verified, so that forged invocations can't use up the limits of others.
+async fn charge_invocation(limiter: &RateLimiter, i: &InvocationInfo) -> Result<(), ApiError> {
+    if i.invocation
+        .verify_signature(DID_METHODS.to_resolver())
+        .await
+        .is_err()
+    {
+        return Err(ApiError::new(
+            Status::Unauthorized,
+            ErrorCode::InvalidInvocation,
+            "Invalid invocation signature",
+        ));
+    }
+    limiter
+        .check_all(i.orbits(), &i.invoker)
+        .map_err(ApiError::rate_limited)
+}
+
+/// Charge a delegation to the rate limits of the orbits it touches, once its signature is
+/// verified.
+async fn charge_delegation(limiter: &RateLimiter, d: &DelegationInfo) -> Result<(), ApiError> {
+    let signed = match &d.delegation {
+        KeplerDelegation::Ucan(u) => u.verify_signature(DID_METHODS.to_resolver()).await.is_ok(),
+        KeplerDelegation::Cacao(c) => c.verify().await.is_ok(),
+    };
+    if !signed {
+        return Err(ApiError::new(
+            Status::Unauthorized,
+            ErrorCode::InvalidDelegation,
+            "Invalid delegation signature",
+        ));
+    }
+    limiter
+        .check_all(d.orbits(), &d.delegator)
+        .map_err(ApiError::rate_limited)
+}
+
+/// Charge a revocation to the rate limits of the orbits of the delegation it revokes, once
+/// its signature is verified.
+async fn charge_revocation(
+    limiter: &RateLimiter,
+    kepler: &Kepler,
+    r: &RevocationInfo,
+) -> Result<(), ApiError> {
+    let signed = match &r.revocation {
+        KeplerRevocation::Cacao(c) => c.verify().await.is_ok(),
+    };
+    if !signed {
+        return Err(ApiError::new(
+            Status::Unauthorized,
+            ErrorCode::InvalidRevocation,
+            "Invalid revocation signature",
+        ));
+    }
+    let orbits = kepler.event_orbits(r.revoked.into()).await.map_err(|e| {
+        ApiError::new(
+            Status::InternalServerError,
+            ErrorCode::Database,
+            e.to_string(),
+        )
+    })?;
+    limiter
+        .check_all(&orbits, &r.revoker)
+        .map_err(ApiError::rate_limited)
+}
+
+/// The bytes an orbit using `size` bytes of storage may still write under `limit`.
+fn remaining_storage(limit: u64, size: u64) -> Result<u64, (Status, String)> {
+    match limit.checked_sub(size) {
+        // the current size is already equal or greater than the limit
+        None | Some(0) => Err((
+            Status::PayloadTooLarge,
+            format!("The data storage limit of {limit} bytes has been reached"),
+        )),
+        Some(remaining) => Ok(remaining),
+    }
+}
+
+/// Stage the content of a KV write, enforcing the orbit's storage limit and content types.
+///
+/// `body_limit` limits the content read from a request body, and the smaller of it and
+/// the orbit's remaining storage applies.
+async fn stage_input<R: AsyncRead>(
+    data: R,
+    body_limit: Option<u64>,
+    orbit: &OrbitId,
+    metadata: &Metadata,
+    staging: &BlockStage,
+    kepler: &Kepler,
+    config: &Config,
+) -> Result<HashBuffer<<BlockStage as ImmutableStaging>::Writable>, (Status, String)> {
+    let algorithm = kepler
+        .hash_algorithm(orbit)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .ok_or_else(|| (Status::NotFound, "orbit not found".to_string()))?;
+    let mut stage = staging
+        .stage_with(orbit, algorithm)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?;
+    let mut prefix = Vec::new();
+    let open_data = SniffReader::new(data, &mut prefix);
+
+    // an orbit's own limit takes precedence over the global one
+    let storage_limit = kepler
+        .storage_limit(orbit)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .or(config.storage.limit.map(|l| l.as_u64()));
+    let remaining = match storage_limit {
+        Some(limit) => {
+            let current_size = kepler
+                .store_size(orbit)
+                .await
+                .map_err(|e| (Status::InternalServerError, e.to_string()))?
+                .ok_or_else(|| (Status::NotFound, "orbit not found".to_string()))?;
+            Some((remaining_storage(limit, current_size)?, limit))
+        }
+        None => None,
+    };
+
+    // whichever limit is smaller is the one exceeded first
+    let limit = [
+        body_limit.map(|l| (l, BODY_TOO_LARGE.to_string())),
+        remaining.map(|(l, limit)| {
+            (
+                l,
+                format!("The data storage limit of {limit} bytes would be exceeded"),
+            )
+        }),
+    ]
+    .into_iter()
+    .flatten()
+    .min_by_key(|(l, _)| *l);
+    match limit {
+        Some((l, _)) => futures::io::copy(LimitedReader::new(open_data, l), &mut stage).await,
+        // no limit, just use the data as is
+        None => futures::io::copy(open_data, &mut stage).await,
+    }
+    .map_err(|e| match limit {
+        Some((_, message)) if limit_exceeded(&e) => (Status::PayloadTooLarge, message),
+        _ => (Status::InternalServerError, e.to_string()),
+    })?;
+
+    check_content_type(&config.content, orbit, metadata, &prefix)?;
+    Ok(stage)
+}
+
+/// Whether a client asked for listings to be streamed, by accepting newline delimited JSON.
+fn accepts_ndjson(accept: Option<&Accept>) -> bool {
+    let ndjson = ndjson_type();
+    accept.map_or(false, |a| a.media_types().any(|m| m == ndjson.media_type()))
+}
+
+#[post(
+    "/invoke?<since>&<upload>&<dry_run>&<version>&<download>&<after>&<limit>&<delegate>",
+    data = "<data>"
+)]
 pub async fn invoke(
     i: AuthHeaderGetter<InvocationInfo>,
+    since: Option<i64>,
+    upload: Option<&str>,
+    dry_run: Option<bool>,
+    version: Option<&str>,
+    download: Option<bool>,
+    after: Option<&str>,
+    limit: Option<u64>,
+    delegate: Option<&str>,
     req_span: TracingSpan,
     headers: ObjectHeaders,
+    accept: Option<&Accept>,
     data: DataIn<'_>,
     staging: &State<BlockStage>,
+    uploads: &State<Uploads>,
     kepler: &State<Kepler>,
     config: &State<Config>,
-) -> Result<DataOut<<BlockStores as ImmutableReadStore>::Readable>, (Status, String)> {
+    notifier: &State<CommitNotifier>,
+    limiter: &State<RateLimiter>,
+) -> Result<Receipted<DataOut<<BlockStores as ImmutableReadStore>::Readable>>, ApiError> {
+    charge_invocation(limiter, &i.0 .0).await?;
     let action_label = "invocation";
     let span = info_span!(parent: &req_span.0, "invoke", action = %action_label);
+    let dry_run = dry_run.unwrap_or(false);
+    let version = version.map(parse_version).transpose()?;
+    let sessions = SessionsQuery {
+        after: after
+            .map(|a| {
+                a.parse::<Cid>().map(Hash::from).map_err(|_| {
+                    ApiError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid cursor")
+                })
+            })
+            .transpose()?,
+        limit,
+        delegate: delegate.map(str::to_string),
+    };
+    // a single read is downloaded as the last segment of its key
+    let filename = match i.0 .0.capabilities.as_slice() {
+        [c] if download.unwrap_or(false) && c.action == "get" => match &c.resource {
+            Resource::Kepler(r) if r.service() == Some("kv") => r
+                .path()
+                .and_then(|p| p.rsplit('/').find(|s| !s.is_empty()))
+                .map(str::to_string),
+            _ => None,
+        },
+        _ => None,
+    };
     // Instrumenting async block to handle yielding properly
     async move {
         let timer = crate::prometheus::AUTHORIZED_INVOKE_HISTOGRAM
@@ -127,84 +790,148 @@ pub async fn invoke(
                     _ => None,
                 });
 
-        let inputs = match (data, put_iter.next(), put_iter.next()) {
-            (DataIn::None | DataIn::One(_), None, _) => HashMap::new(),
-            (DataIn::One(d), Some((orbit, path)), None) => {
-                let mut stage = staging
-                    .stage(orbit)
+        let mut completed_upload = None;
+        let inputs = match (data, upload, put_iter.next(), put_iter.next()) {
+            (DataIn::None | DataIn::One(_), _, None, _) => HashMap::new(),
+            (DataIn::One(_), Some(upload), Some((orbit, path)), None) => {
+                // the body is ignored, the content comes from a completed resumable upload
+                let hash = parse_hash(upload)?;
+                let uploads = uploads.as_ref().ok_or_else(|| {
+                    (
+                        Status::NotFound,
+                        "Resumable uploads are not enabled".to_string(),
+                    )
+                })?;
+                let written = kepler
+                    .storage()
+                    .read(orbit, &hash)
                     .await
                     .map_err(|e| (Status::InternalServerError, e.to_string()))?;
-                let open_data = d.open(1u8.gigabytes()).compat();
-
-                if let Some(limit) = config.storage.limit {
-                    let current_size = kepler
-                        .store_size(orbit)
-                        .await
-                        .map_err(|e| (Status::InternalServerError, e.to_string()))?
-                        .ok_or_else(|| (Status::NotFound, "orbit not found".to_string()))?;
-                    // get the remaining allocated space for the given orbit storage
-                    match limit.as_u64().checked_sub(current_size) {
-                        // the current size is already equal or greater than the limit
-                        None | Some(0) => {
-                            return Err((
-                                Status::PayloadTooLarge,
-                                "The data storage limit has been reached".into(),
-                            ))
-                        }
-                        Some(remaining) => {
-                            futures::io::copy(LimitedReader::new(open_data, remaining), &mut stage)
-                                .await
-                                .map_err(|e| (Status::InternalServerError, e.to_string()))?;
-                        }
+                let mut stage = match written {
+                    // an upload which was already written, and so discarded, is written
+                    // again from block storage
+                    Some(content) => {
+                        stage_input(content, None, orbit, &headers.0, staging, kepler, config)
+                            .await?
+                    }
+                    None => {
+                        let content = uploads.open(orbit, &hash).await.map_err(upload_error)?;
+                        completed_upload = Some((orbit.clone(), hash));
+                        stage_input(content, None, orbit, &headers.0, staging, kepler, config)
+                            .await?
                     }
-                } else {
-                    // no limit on storage, just use the data as is
-                    futures::io::copy(open_data, &mut stage)
-                        .await
-                        .map_err(|e| (Status::InternalServerError, e.to_string()))?;
                 };
-
+                if stage.hash() != hash {
+                    return Err(ApiError::new(
+                        Status::BadRequest,
+                        ErrorCode::BadRequest,
+                        "Uploaded content does not match its declared hash",
+                    ));
+                }
                 let mut inputs = HashMap::new();
                 inputs.insert((orbit.clone(), path.to_string()), (headers.0, stage));
                 inputs
             }
-            (DataIn::Many(_), Some(_), Some(_)) => {
-                return Err((
+            (DataIn::One(d), None, Some((orbit, path)), None) => {
+                let max_body = config.requests.maxbody.as_u64();
+                // chunked bodies declare no length, the limit is then enforced as they're read
+                let declared = headers
+                    .0
+                    .get("content-length")
+                    .and_then(|l| l.parse::<u64>().ok());
+                if declared.map_or(false, |l| l > max_body) {
+                    return Err(ApiError::new(
+                        Status::PayloadTooLarge,
+                        ErrorCode::PayloadTooLarge,
+                        BODY_TOO_LARGE,
+                    ));
+                }
+                // read a byte past the limit, so a longer body fails rather than being cut
+                let stage = stage_input(
+                    d.open(max_body.saturating_add(1).bytes()).compat(),
+                    Some(max_body),
+                    orbit,
+                    &headers.0,
+                    staging,
+                    kepler,
+                    config,
+                )
+                .await?;
+                let mut inputs = HashMap::new();
+                inputs.insert((orbit.clone(), path.to_string()), (headers.0, stage));
+                inputs
+            }
+            (DataIn::Many(_), _, Some(_), Some(_)) => {
+                return Err(ApiError::new(
                     Status::BadRequest,
-                    "Multipart not yet supported".to_string(),
+                    ErrorCode::BadRequest,
+                    "Multipart not yet supported",
                 ));
             }
             _ => {
-                return Err((Status::BadRequest, "Invalid inputs".to_string()));
+                return Err(ApiError::new(
+                    Status::BadRequest,
+                    ErrorCode::MissingInput,
+                    "Invalid inputs",
+                ));
             }
         };
         let res = kepler
-            .invoke::<BlockStage>(i.0, inputs)
-            .await
-            .map(
-                |(_, mut outcomes)| match (outcomes.pop(), outcomes.pop(), outcomes.drain(..)) {
-                    (None, None, _) => DataOut::None,
-                    (Some(o), None, _) => DataOut::One(InvOut(o)),
-                    (Some(o), Some(next), rest) => {
-                        let mut v = vec![InvOut(o), InvOut(next)];
-                        v.extend(rest.map(InvOut));
-                        DataOut::Many(v)
-                    }
-                    _ => unreachable!(),
+            .invoke_with::<BlockStage>(
+                i.0,
+                inputs,
+                InvokeOptions {
+                    list_since: since,
+                    stream_list: accepts_ndjson(accept),
+                    max_operations: config.invocations.operations,
+                    check_orbits: config.invocations.strict,
+                    locate: false,
+                    dry_run,
+                    version,
+                    sessions,
                 },
             )
-            .map_err(|e| {
-                (
-                    match e {
-                        TxStoreError::Tx(TxError::OrbitNotFound) => Status::NotFound,
-                        TxStoreError::Tx(TxError::Db(DbErr::ConnectionAcquire)) => {
-                            Status::InternalServerError
-                        }
-                        _ => Status::Unauthorized,
-                    },
-                    e.to_string(),
-                )
-            });
+            .await;
+        match &res {
+            Ok((commits, _)) if !dry_run => {
+                notifier.publish(commits).await;
+                if let (Some((orbit, hash)), Some(uploads)) = (&completed_upload, uploads.as_ref())
+                {
+                    // the content is in block storage now, so the staged copy isn't needed
+                    if let Err(e) = uploads.discard(orbit, hash).await {
+                        tracing::warn!(error = %e, "failed to discard a completed upload");
+                    }
+                }
+            }
+            _ => {}
+        }
+        let res = match res {
+            Ok((commits, outcomes)) => {
+                let mut peeked = Vec::with_capacity(outcomes.len());
+                for outcome in outcomes {
+                    peeked.push(InvOut(outcome).peek_list(config.storage.emptylist).await.0);
+                }
+                Ok((commits, peeked))
+            }
+            Err(e) => Err(e),
+        };
+        let res = res
+            .map(|(commits, mut outcomes)| {
+                let receipts = commits.values().filter_map(|c| c.receipt).collect();
+                let out = match outcomes.len() {
+                    0 => DataOut::None,
+                    1 => {
+                        let out = InvOut(outcomes.remove(0));
+                        DataOut::One(match &filename {
+                            Some(filename) => out.as_attachment(filename),
+                            None => out,
+                        })
+                    }
+                    _ => DataOut::Many(outcomes.into_iter().map(InvOut).collect()),
+                };
+                Receipted(out, receipts)
+            })
+            .map_err(|e| invoke_error(e, config));
 
         timer.observe_duration();
         res
@@ -212,3 +939,835 @@ pub async fn invoke(
     .instrument(span)
     .await
 }
+
+/// The DAG-CBOR block of an invocation receipt, by its CID.
+#[get("/receipt/<cid>")]
+pub async fn receipt(
+    cid: &str,
+    kepler: &State<Kepler>,
+) -> Result<(ContentType, Vec<u8>), (Status, String)> {
+    let id = cid
+        .parse::<Cid>()
+        .map(Hash::from)
+        .map_err(|_| (Status::BadRequest, "Invalid receipt CID".to_string()))?;
+    kepler
+        .receipt(id)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .map(|r| (ContentType::new("application", "vnd.ipld.dag-cbor"), r))
+        .ok_or_else(|| (Status::NotFound, "receipt not found".to_string()))
+}
+
+/// Content of an orbit by its CID, whichever keys reference it, authorized by an invocation
+/// of `blocks/get` on the CID.
+#[get("/ipfs/<cid>")]
+pub async fn block(
+    cid: &str,
+    i: AuthHeaderGetter<InvocationInfo>,
+    req_span: TracingSpan,
+    kepler: &State<Kepler>,
+    config: &State<Config>,
+    notifier: &State<CommitNotifier>,
+    limiter: &State<RateLimiter>,
+) -> Result<DataOut<<BlockStores as ImmutableReadStore>::Readable>, ApiError> {
+    charge_invocation(limiter, &i.0 .0).await?;
+    let span = info_span!(parent: &req_span.0, "invoke", action = "block");
+    async move {
+        match i.0 .0.capabilities.as_slice() {
+            [c] if c.action == "get" => match &c.resource {
+                Resource::Kepler(r)
+                    if r.service() == Some("blocks")
+                        && r.path().and_then(|p| p.strip_prefix('/')) == Some(cid) =>
+                {
+                    Some(())
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::BadRequest,
+                ErrorCode::BadRequest,
+                "Reading content by CID requires an invocation of a single blocks/get of it",
+            )
+        })?;
+        let (commits, outcomes) = kepler
+            .invoke_with::<BlockStage>(
+                i.0,
+                HashMap::new(),
+                InvokeOptions {
+                    max_operations: config.invocations.operations,
+                    check_orbits: config.invocations.strict,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| invoke_error(e, config))?;
+        notifier.publish(&commits).await;
+        outcomes
+            .into_iter()
+            .next()
+            .map(|outcome| DataOut::One(InvOut(outcome)))
+            .ok_or_else(|| {
+                ApiError::new(
+                    Status::InternalServerError,
+                    ErrorCode::Internal,
+                    "No outcome",
+                )
+            })
+    }
+    .instrument(span)
+    .await
+}
+
+/// The current heads of an orbit, as epoch CIDs, and their largest sequence number.
+#[derive(Serialize)]
+pub struct HeadsJson {
+    pub heads: Vec<String>,
+    pub height: i64,
+}
+
+/// Current heads of an orbit, authorized by an invocation of `read` on
+/// `<orbit>/epochs/heads`, which is checked but not committed so as not to move the heads.
+#[get("/orbit/<orbit>/heads")]
+pub async fn orbit_heads(
+    orbit: &str,
+    i: AuthHeaderGetter<InvocationInfo>,
+    kepler: &State<Kepler>,
+    limiter: &State<RateLimiter>,
+) -> Result<Json<HeadsJson>, ApiError> {
+    charge_invocation(limiter, &i.0 .0).await?;
+    let orbit = resolve_orbit(kepler, orbit).await?;
+    match i.0 .0.capabilities.as_slice() {
+        [c] if c.action == "read" => match &c.resource {
+            Resource::Kepler(r)
+                if r.orbit() == &orbit
+                    && r.service() == Some("epochs")
+                    && r.path().and_then(|p| p.strip_prefix('/')) == Some("heads") =>
+            {
+                Some(())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+    .ok_or_else(|| {
+        ApiError::new(
+            Status::BadRequest,
+            ErrorCode::BadRequest,
+            "Reading heads requires an invocation of a single epochs/heads read on the orbit",
+        )
+    })?;
+    let failures = kepler.check_invocation(&i.0).await.map_err(|e| {
+        ApiError::new(
+            Status::InternalServerError,
+            ErrorCode::Database,
+            e.to_string(),
+        )
+    })?;
+    if !failures.is_empty() {
+        return Err(ApiError::new(
+            Status::Unauthorized,
+            ErrorCode::InvalidInvocation,
+            failures
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ));
+    }
+    kepler
+        .heads(&orbit)
+        .await
+        .map_err(|e| {
+            ApiError::new(
+                Status::InternalServerError,
+                ErrorCode::Database,
+                e.to_string(),
+            )
+        })?
+        .map(|h| {
+            Json(HeadsJson {
+                heads: h.heads.iter().map(|h| h.to_cid(0x55).to_string()).collect(),
+                height: h.height,
+            })
+        })
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::NotFound,
+                ErrorCode::OrbitNotFound,
+                "Orbit not found",
+            )
+        })
+}
+
+/// The DAG-CBOR block of the pointer last published for an orbit, signed with the orbit's
+/// key, if pointers are published.
+#[get("/orbit/<orbit>/pointer")]
+pub async fn orbit_pointer(
+    orbit: &str,
+    kepler: &State<Kepler>,
+) -> Result<(ContentType, Vec<u8>), (Status, String)> {
+    kepler
+        .pointer(&resolve_orbit(kepler, orbit).await?)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .map(|p| (ContentType::new("application", "vnd.ipld.dag-cbor"), p))
+        .ok_or_else(|| (Status::NotFound, "pointer not found".to_string()))
+}
+
+type InvokeError = TxStoreError<BlockStores, BlockStage, StaticSecret>;
+
+fn invoke_error(e: InvokeError, config: &Config) -> ApiError {
+    let status = match &e {
+        TxStoreError::Tx(e) => tx_status(e),
+        TxStoreError::TooManyOperations { .. } | TxStoreError::InvalidKey(_) => Status::BadRequest,
+        TxStoreError::ReadOnly(..) => Status::Forbidden,
+        TxStoreError::Maintenance(..) => Status::ServiceUnavailable,
+        TxStoreError::Replayed(_) => Status::Conflict,
+        TxStoreError::StorageTimeout(_) => Status::GatewayTimeout,
+        TxStoreError::MissingContent { .. } => {
+            tracing::error!("{}", e);
+            missing_content_status(config.storage.inconsistency)
+        }
+        _ => Status::Unauthorized,
+    };
+    let retry_after = matches!(e, TxStoreError::Maintenance(..))
+        .then_some(Duration::from_secs(config.maintenance.retryafter));
+    ApiError {
+        retry_after,
+        ..ApiError::new(status, (&e).into(), e.to_string())
+    }
+}
+
+/// A URL from which an object can be read directly from block storage.
+#[derive(Serialize)]
+pub struct PresignedRead {
+    pub url: String,
+    /// Unix time in seconds after which the URL is no longer valid.
+    pub expires: i64,
+}
+
+/// How long a presigned URL may be valid for, at most `ttl` seconds and never past the
+/// unix time `expiration` of the invocation it was requested with.
+fn presign_duration(ttl: u64, expiration: f64) -> Duration {
+    let remaining = expiration - OffsetDateTime::now_utc().unix_timestamp() as f64;
+    Duration::from_secs(ttl).min(Duration::from_secs_f64(remaining.max(0.0)))
+}
+
+#[post("/presign")]
+pub async fn presign(
+    i: AuthHeaderGetter<InvocationInfo>,
+    req_span: TracingSpan,
+    kepler: &State<Kepler>,
+    config: &State<Config>,
+    notifier: &State<CommitNotifier>,
+    limiter: &State<RateLimiter>,
+) -> Result<
+    rocket::Either<Json<PresignedRead>, DataOut<<BlockStores as ImmutableReadStore>::Readable>>,
+    ApiError,
+> {
+    charge_invocation(limiter, &i.0 .0).await?;
+    let span = info_span!(parent: &req_span.0, "invoke", action = "presign");
+    async move {
+        let orbit = match i.0 .0.capabilities.as_slice() {
+            [c] if c.action == "get" => match &c.resource {
+                Resource::Kepler(r) if r.service() == Some("kv") && r.path().is_some() => {
+                    Some(r.orbit().clone())
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::BadRequest,
+                ErrorCode::BadRequest,
+                "Presigning requires an invocation of a single kv/get",
+            )
+        })?;
+        let ttl = presign_duration(
+            config.storage.presign.ttl,
+            i.0 .0.invocation.payload.expiration.as_seconds(),
+        );
+        // only S3 can serve content directly, other stores serve it as a normal read, as
+        // does S3 when content is stored compressed or encrypted
+        let encrypted = kepler.storage().inner();
+        let s3 = match encrypted.inner() {
+            Either::A(s3) if kepler.storage().compression().is_none() && !encrypted.encrypted() => {
+                Some(s3)
+            }
+            _ => None,
+        };
+        let (commits, mut outcomes) = kepler
+            .invoke_with::<BlockStage>(
+                i.0,
+                HashMap::new(),
+                InvokeOptions {
+                    max_operations: config.invocations.operations,
+                    check_orbits: config.invocations.strict,
+                    locate: s3.is_some(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| invoke_error(e, config))?;
+        notifier.publish(&commits).await;
+        let outcome = outcomes.pop().ok_or_else(|| {
+            ApiError::new(
+                Status::InternalServerError,
+                ErrorCode::Internal,
+                "No outcome",
+            )
+        })?;
+        match (s3, outcome) {
+            (Some(s3), InvocationOutcome::KvLocation(Some((_, hash)))) => {
+                let url = s3.presign_read(&orbit, &hash, ttl).await.map_err(|e| {
+                    ApiError::new(
+                        Status::InternalServerError,
+                        ErrorCode::Storage,
+                        e.to_string(),
+                    )
+                })?;
+                Ok(rocket::Either::Left(Json(PresignedRead {
+                    url,
+                    expires: OffsetDateTime::now_utc().unix_timestamp() + ttl.as_secs() as i64,
+                })))
+            }
+            (Some(_), _) => Err(ApiError::new(
+                Status::NotFound,
+                ErrorCode::NotFound,
+                "No such key",
+            )),
+            (None, outcome) => Ok(rocket::Either::Right(DataOut::One(InvOut(outcome)))),
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    async fn readiness_timeout() {
+        let timeout = Duration::from_millis(10);
+        assert_eq!(probe(timeout, async { Ok::<_, String>(()) }).await, Ok(()));
+        assert_eq!(
+            probe(timeout, async { Err::<(), _>("unreachable") }).await,
+            Err("unreachable".to_string())
+        );
+        // a stuck dependency is unready rather than hanging the probe
+        assert_eq!(
+            probe(timeout, futures::future::pending::<Result<(), String>>()).await,
+            Err("timed out".to_string())
+        );
+    }
+
+    #[test]
+    async fn delegation_commits() {
+        let [rev, event] = [b"epoch", b"event"].map(|b| kepler_core::hash::hash(b));
+        let orbit = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let commits = HashMap::from([(
+            orbit.clone(),
+            Commit {
+                rev,
+                seq: 2,
+                committed_events: vec![event],
+                consumed_epochs: vec![],
+                receipt: None,
+            },
+        )]);
+
+        match delegation_response(&commits, None).unwrap() {
+            rocket::Either::Right(Json(c)) => assert_eq!(
+                c[&orbit.to_string()],
+                DelegationCommit {
+                    seq: 2,
+                    rev: rev.to_cid(0x55).to_string(),
+                    committed_events: vec![event.to_cid(0x55).to_string()],
+                }
+            ),
+            rocket::Either::Left(_) => panic!("expected commits"),
+        }
+        assert!(matches!(
+            delegation_response(&commits, Some("cid")).unwrap(),
+            rocket::Either::Left(cid) if cid == event.to_cid(0x55).to_string()
+        ));
+        assert_eq!(
+            delegation_response(&commits, Some("xml"))
+                .unwrap_err()
+                .status,
+            Status::BadRequest
+        );
+        assert_eq!(
+            delegation_response(&HashMap::new(), None)
+                .unwrap_err()
+                .status,
+            Status::Unauthorized
+        );
+    }
+
+    #[test]
+    async fn storage_limits() {
+        assert_eq!(remaining_storage(100, 40), Ok(60));
+        // the limit which was hit is reported
+        for size in [100, 150] {
+            assert_eq!(
+                remaining_storage(100, size),
+                Err((
+                    Status::PayloadTooLarge,
+                    "The data storage limit of 100 bytes has been reached".to_string()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    async fn maintenance_errors() {
+        let orbit = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let resource = Resource::Kepler(orbit.to_resource(
+            Some("kv".to_string()),
+            Some("key".to_string()),
+            None,
+        ));
+        let e = invoke_error(
+            TxStoreError::Maintenance(resource.clone(), "put".to_string()),
+            &Config::default(),
+        );
+        assert_eq!(e.status, Status::ServiceUnavailable);
+        assert_eq!(e.code, ErrorCode::Maintenance);
+        assert_eq!(e.retry_after, Some(Duration::from_secs(60)));
+
+        // only writes refused in maintenance are worth retrying
+        let e = invoke_error(
+            TxStoreError::ReadOnly(resource, "put".to_string()),
+            &Config::default(),
+        );
+        assert_eq!(e.status, Status::Forbidden);
+        assert_eq!(e.retry_after, None);
+    }
+
+    // a node with content in memory, configured by `toml`
+    async fn test_client(toml: &str) -> rocket::local::asynchronous::Client {
+        use rocket::figment::{
+            providers::{Format, Serialized, Toml},
+            Figment,
+        };
+
+        let figment = Figment::from(rocket::Config::debug_default())
+            .merge(Serialized::defaults(Config::default()))
+            .merge(Toml::string(&format!(
+                "keys.secret = \"{}\"\nstorage.blocks.type = \"Memory\"\n{toml}",
+                "A".repeat(43)
+            )));
+        rocket::local::asynchronous::Client::tracked(crate::app(&figment).await.unwrap())
+            .await
+            .unwrap()
+    }
+
+    // the key of a did:key orbit controller, its verification method and its orbit
+    fn test_controller() -> (kepler_lib::ssi::jwk::JWK, String, OrbitId) {
+        use kepler_lib::ssi::{
+            did::Source,
+            jwk::{Algorithm, JWK},
+        };
+
+        let mut jwk = JWK::generate_ed25519().unwrap();
+        jwk.algorithm = Some(Algorithm::EdDSA);
+        let did = DID_METHODS
+            .generate(&Source::KeyAndPattern(&jwk, "key"))
+            .unwrap();
+        let controller = format!("{did}#{}", did.trim_start_matches("did:key:"));
+        let orbit = format!("kepler:{}://default", did.trim_start_matches("did:"))
+            .parse()
+            .unwrap();
+        (jwk, controller, orbit)
+    }
+
+    // the controller invokes `action` on `key` in its orbit, with `parent` as its proof
+    async fn controller_invocation(
+        controller: &(kepler_lib::ssi::jwk::JWK, String, OrbitId),
+        key: &str,
+        action: &str,
+        parent: Cid,
+    ) -> rocket::http::Header<'static> {
+        service_invocation(controller, "kv", key, action, parent).await
+    }
+
+    // the controller invokes `action` on `path` of `service` in its orbit
+    async fn service_invocation(
+        (jwk, controller, orbit): &(kepler_lib::ssi::jwk::JWK, String, OrbitId),
+        service: &str,
+        path: &str,
+        action: &str,
+        parent: Cid,
+    ) -> rocket::http::Header<'static> {
+        let invocation = kepler_lib::authorization::make_invocation(
+            vec![orbit.clone().to_resource(
+                Some(service.to_string()),
+                Some(path.to_string()),
+                Some(action.to_string()),
+            )],
+            parent,
+            jwk,
+            controller.clone(),
+            (OffsetDateTime::now_utc().unix_timestamp() + 60) as f64,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        rocket::http::Header::new("Authorization", invocation.encode().unwrap())
+    }
+
+    // the controller creates its orbit by delegating to a host, returning the delegation's CID
+    async fn create_orbit(
+        client: &rocket::local::asynchronous::Client,
+        (jwk, vm, orbit): &(kepler_lib::ssi::jwk::JWK, String, OrbitId),
+    ) -> Cid {
+        use kepler_lib::{authorization::make_delegation_payload, ssi::jwk::Algorithm};
+
+        let host = make_delegation_payload(
+            vec![orbit
+                .clone()
+                .to_resource(None, None, Some("host".to_string()))],
+            vm.clone(),
+            "did:example:host".to_string(),
+            vec![],
+            (OffsetDateTime::now_utc().unix_timestamp() + 60) as f64,
+            None,
+            None,
+        )
+        .unwrap()
+        .sign(Algorithm::EdDSA, jwk)
+        .unwrap()
+        .encode()
+        .unwrap();
+        let res = client
+            .post("/delegate")
+            .header(rocket::http::Header::new("Authorization", host.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        kepler_core::hash::hash(host.as_bytes()).to_cid(0x55)
+    }
+
+    // the controller writes `value` to `key` in its orbit
+    async fn put_value(
+        client: &rocket::local::asynchronous::Client,
+        controller: &(kepler_lib::ssi::jwk::JWK, String, OrbitId),
+        host: Cid,
+        key: &str,
+        value: &'static str,
+    ) {
+        let res = client
+            .post("/invoke")
+            .header(controller_invocation(controller, key, "put", host).await)
+            .body(value)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    // written content can be read by its CID, and only by its CID
+    #[test]
+    async fn block_by_cid() {
+        let controller = test_controller();
+        let client = test_client("").await;
+        let host = create_orbit(&client, &controller).await;
+        put_value(&client, &controller, host, "key", "value").await;
+
+        let cid = kepler_core::hash::hash(b"value").to_cid(0x55).to_string();
+        let res = client
+            .get(format!("/ipfs/{cid}"))
+            .header(service_invocation(&controller, "blocks", &cid, "get", host).await)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().await.as_deref(), Some("value"));
+
+        let other = kepler_core::hash::hash(b"other").to_cid(0x55).to_string();
+        let res = client
+            .get(format!("/ipfs/{cid}"))
+            .header(service_invocation(&controller, "blocks", &other, "get", host).await)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    // the heads of an orbit are its latest epoch, which reading them doesn't move
+    #[test]
+    async fn heads() {
+        let controller = test_controller();
+        let orbit = &controller.2;
+        let client = test_client("").await;
+        let host = create_orbit(&client, &controller).await;
+        put_value(&client, &controller, host, "key", "value").await;
+
+        let kepler = client.rocket().state::<Kepler>().unwrap();
+        let expected = kepler.heads(orbit).await.unwrap().unwrap();
+        // the orbit ID is a single path segment
+        let heads_path = format!("/orbit/{}/heads", orbit.to_string().replace('/', "%2F"));
+        assert_eq!(expected.heads.len(), 1);
+        for _ in 0..2 {
+            let res = client
+                .get(&heads_path)
+                .header(service_invocation(&controller, "epochs", "heads", "read", host).await)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok);
+            let heads: serde_json::Value =
+                serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+            assert_eq!(
+                heads,
+                serde_json::json!({
+                    "heads": [expected.heads[0].to_cid(0x55).to_string()],
+                    "height": expected.height,
+                })
+            );
+        }
+
+        let res = client
+            .get(&heads_path)
+            .header(service_invocation(&controller, "epochs", "tails", "read", host).await)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    // an orbit is found by its alias in routes which take one, for both admin and
+    // invoked requests
+    #[test]
+    async fn aliases() {
+        let controller = test_controller();
+        let orbit = &controller.2;
+        let client = test_client("admin.key = \"admin\"").await;
+        let admin = || rocket::http::Header::new("X-Admin-Key", "admin");
+        let set_alias = |alias: &'static str| {
+            client
+                .put(format!("/admin/alias/{alias}"))
+                .header(admin())
+                .json(&orbit.to_string())
+                .dispatch()
+        };
+
+        // only orbits which exist can be aliased
+        assert_eq!(set_alias("photos").await.status(), Status::NotFound);
+        let host = create_orbit(&client, &controller).await;
+        put_value(&client, &controller, host, "key", "value").await;
+        assert_eq!(set_alias("photos").await.status(), Status::Ok);
+        assert_eq!(set_alias("photos").await.status(), Status::Conflict);
+
+        let res = client.get("/orbit/photos/exists").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let kepler = client.rocket().state::<Kepler>().unwrap();
+        let res = client.get("/peer/generate/photos").dispatch().await;
+        assert_eq!(
+            res.into_string().await,
+            Some(kepler.stage_key(orbit).await.unwrap())
+        );
+
+        // the invocation is of the orbit the alias names
+        let heads = kepler.heads(orbit).await.unwrap().unwrap();
+        let res = client
+            .get("/orbit/photos/heads")
+            .header(service_invocation(&controller, "epochs", "heads", "read", host).await)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().await.unwrap()).unwrap();
+        assert_eq!(json["height"], heads.height);
+
+        // but not of another orbit
+        let res = client
+            .get("/orbit/photos/heads")
+            .header(service_invocation(&test_controller(), "epochs", "heads", "read", host).await)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client.get("/orbit/unknown/exists").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        let res = client
+            .delete("/admin/alias/photos")
+            .header(admin())
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/orbit/photos/exists").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    // a completed upload sent again writes the same content, although it was discarded
+    // once first written
+    #[test]
+    async fn resend_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let controller = test_controller();
+        let orbit = &controller.2;
+        let client = test_client(&format!("storage.uploads = \"{}\"", dir.path().display())).await;
+        let host = create_orbit(&client, &controller).await;
+
+        let cid = kepler_core::hash::hash(b"value").to_cid(0x55).to_string();
+        let upload = format!("/upload/{}/{cid}", orbit.to_string().replace('/', "%2F"));
+        let res = client
+            .post(format!("{upload}?size=5"))
+            .header(controller_invocation(&controller, "key", "put", host).await)
+            .dispatch()
+            .await;
+        assert_eq!(res.into_string().await.as_deref(), Some("0"));
+        let res = client
+            .patch(format!("{upload}?offset=0"))
+            .header(controller_invocation(&controller, "key", "put", host).await)
+            .body("value")
+            .dispatch()
+            .await;
+        assert_eq!(res.into_string().await.as_deref(), Some("5"));
+
+        for key in ["key", "key", "other"] {
+            let res = client
+                .post(format!("/invoke?upload={cid}"))
+                .header(controller_invocation(&controller, key, "put", host).await)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok);
+            let res = client
+                .post("/invoke")
+                .header(controller_invocation(&controller, key, "get", host).await)
+                .dispatch()
+                .await;
+            assert_eq!(res.into_string().await.as_deref(), Some("value"));
+        }
+
+        // declaring it again reports it complete, without staging it
+        let res = client
+            .post(format!("{upload}?size=5"))
+            .header(controller_invocation(&controller, "key", "put", host).await)
+            .dispatch()
+            .await;
+        assert_eq!(res.into_string().await.as_deref(), Some("5"));
+        let res = client
+            .delete(&upload)
+            .header(controller_invocation(&controller, "key", "put", host).await)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    // a did:key controller creates an orbit and writes to it, then the written block is
+    // lost from block storage
+    #[test]
+    async fn missing_content() {
+        use kepler_core::storage::ImmutableDeleteStore;
+
+        let controller = test_controller();
+        let orbit = &controller.2;
+        for (policy, status) in [
+            ("Error", Status::BadGateway),
+            ("NotFound", Status::NotFound),
+        ] {
+            let client = test_client(&format!("storage.inconsistency = \"{policy}\"")).await;
+
+            let host = create_orbit(&client, &controller).await;
+            put_value(&client, &controller, host, "key", "value").await;
+            let kepler = client.rocket().state::<Kepler>().unwrap();
+            assert!(kepler
+                .storage()
+                .remove(orbit, &kepler_core::hash::hash(b"value"))
+                .await
+                .unwrap()
+                .is_some());
+
+            let before = crate::prometheus::STORE_INCONSISTENCY_COUNTER.get();
+            let res = client
+                .post("/invoke")
+                .header(controller_invocation(&controller, "key", "get", host).await)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), status);
+            assert!(crate::prometheus::STORE_INCONSISTENCY_COUNTER.get() > before);
+        }
+    }
+
+    // listing in an orbit which was never created is a 404, whatever the response to
+    // an empty listing is, and whether or not the keys are streamed
+    #[test]
+    async fn list_missing_orbit() {
+        let controller = test_controller();
+        for policy in ["Empty", "NotFound"] {
+            let client = test_client(&format!("storage.emptylist = \"{policy}\"")).await;
+            for accept in ["application/json", "application/x-ndjson"] {
+                let invocation = controller_invocation(
+                    &controller,
+                    "prefix/",
+                    "list",
+                    kepler_core::hash::hash(b"host").to_cid(0x55),
+                )
+                .await;
+                let res = client
+                    .post("/invoke")
+                    .header(invocation)
+                    .header(rocket::http::Header::new("Accept", accept))
+                    .dispatch()
+                    .await;
+                assert_eq!(res.status(), Status::NotFound);
+            }
+        }
+    }
+
+    #[test]
+    async fn presign_expiry() {
+        let now = OffsetDateTime::now_utc().unix_timestamp() as f64;
+        assert_eq!(
+            presign_duration(300, now + 3600.0),
+            Duration::from_secs(300)
+        );
+        // never outlives the invocation
+        assert!(presign_duration(300, now + 60.0) <= Duration::from_secs(60));
+        assert_eq!(presign_duration(300, now - 60.0), Duration::ZERO);
+    }
+
+    // a controller's purge invocation is accepted once, even after the orbit is recreated
+    #[test]
+    async fn purge_replay() {
+        use kepler_lib::{authorization::make_delegation_payload, ssi::jwk::Algorithm};
+
+        let controller = test_controller();
+        let (jwk, vm, orbit) = &controller;
+        let client = test_client("").await;
+        let kepler = client.rocket().state::<Kepler>().unwrap();
+        let path = format!("/admin/orbit/{}", orbit.to_string().replace('/', "%2F"));
+        create_orbit(&client, &controller).await;
+
+        let purge = make_delegation_payload(
+            vec![orbit
+                .clone()
+                .to_resource(None, None, Some("purge".to_string()))],
+            vm.clone(),
+            kepler.stage_key(orbit).await.unwrap(),
+            vec![],
+            (OffsetDateTime::now_utc().unix_timestamp() + 60) as f64,
+            None,
+            None,
+        )
+        .unwrap()
+        .sign(Algorithm::EdDSA, jwk)
+        .unwrap()
+        .encode()
+        .unwrap();
+        let purge = || rocket::http::Header::new("Authorization", purge.clone());
+
+        let res = client.delete(&path).header(purge()).dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(kepler.orbit_info(orbit).await.unwrap(), None);
+
+        create_orbit(&client, &controller).await;
+        let res = client.delete(&path).header(purge()).dispatch().await;
+        assert_eq!(res.status(), Status::Conflict);
+        assert!(kepler.orbit_info(orbit).await.unwrap().is_some());
+    }
+}
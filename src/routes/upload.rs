@@ -0,0 +1,195 @@
+use kepler_core::{
+    hash::Hash,
+    storage::{ImmutableReadStore, ResumableError, ResumableStaging},
+    types::Resource,
+    util::InvocationInfo,
+};
+use kepler_lib::{libipld::cid::Cid, resource::OrbitId};
+use rocket::{data::Data, http::Status, serde::json::Json, State};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+use crate::{
+    authorization::AuthHeaderGetter,
+    config::Config,
+    routes::resolve_orbit,
+    storage::{file_system::FileSystemStoreError, resumable::ResumableFileSystemStage},
+    Kepler,
+};
+
+pub type Uploads = Option<ResumableFileSystemStage>;
+
+fn uploads(uploads: &Uploads) -> Result<&ResumableFileSystemStage, (Status, String)> {
+    uploads.as_ref().ok_or_else(|| {
+        (
+            Status::NotFound,
+            "Resumable uploads are not enabled".to_string(),
+        )
+    })
+}
+
+pub fn parse_hash(hash: &str) -> Result<Hash, (Status, String)> {
+    hash.parse::<Cid>()
+        .map(Hash::from)
+        .map_err(|_| (Status::BadRequest, "Invalid content hash".to_string()))
+}
+
+pub fn upload_error(e: ResumableError<FileSystemStoreError>) -> (Status, String) {
+    (
+        match e {
+            ResumableError::NotFound => Status::NotFound,
+            ResumableError::SizeMismatch(_)
+            | ResumableError::OffsetMismatch(_)
+            | ResumableError::Incomplete
+            | ResumableError::Busy => Status::Conflict,
+            ResumableError::TooLarge => Status::PayloadTooLarge,
+            ResumableError::Store(_) => Status::InternalServerError,
+        },
+        e.to_string(),
+    )
+}
+
+/// Check that the uploader has been delegated to put content in the orbit's kv service, by
+/// a currently valid invocation, before anything is staged.
+async fn authorize(
+    orbit: &str,
+    i: AuthHeaderGetter<InvocationInfo>,
+    kepler: &Kepler,
+) -> Result<OrbitId, (Status, String)> {
+    let orbit = resolve_orbit(kepler, orbit).await?;
+    let i = i.0;
+    let puts = i.0.capabilities.iter().any(|c| {
+        c.action == "put"
+            && matches!(&c.resource, Resource::Kepler(r)
+                if r.orbit() == &orbit && r.service() == Some("kv"))
+    });
+    if !puts {
+        return Err((
+            Status::Unauthorized,
+            "Uploads require an invocation to put content in the orbit".to_string(),
+        ));
+    }
+    // checks the signature, time bounds and delegation chain
+    let failures = kepler
+        .check_invocation(&i)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?;
+    if failures.is_empty() {
+        Ok(orbit)
+    } else {
+        Err((
+            Status::Unauthorized,
+            failures
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ))
+    }
+}
+
+/// Declare an upload of `size` bytes with the given content hash, or find how much of it
+/// has already been uploaded. Responds with the number of bytes staged so far.
+#[post("/upload/<orbit>/<hash>?<size>")]
+pub async fn begin_upload(
+    orbit: &str,
+    hash: &str,
+    size: u64,
+    invocation: AuthHeaderGetter<InvocationInfo>,
+    staging: &State<Uploads>,
+    kepler: &State<Kepler>,
+    config: &State<Config>,
+) -> Result<Json<u64>, (Status, String)> {
+    let staging = uploads(staging)?;
+    let orbit = authorize(orbit, invocation, kepler).await?;
+    let hash = parse_hash(hash)?;
+    // content which is already written is complete, whether or not it was uploaded here
+    if let Some(content) = kepler
+        .storage()
+        .read(&orbit, &hash)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+    {
+        return match content.len() {
+            len if len == size => Ok(Json(size)),
+            len => Err(upload_error(ResumableError::SizeMismatch(len))),
+        };
+    }
+    let limit = kepler
+        .storage_limit(&orbit)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))?
+        .or(config.storage.limit.map(|l| l.as_u64()));
+    if let Some(limit) = limit {
+        let current_size = kepler
+            .store_size(&orbit)
+            .await
+            .map_err(|e| (Status::InternalServerError, e.to_string()))?
+            .ok_or_else(|| (Status::NotFound, "orbit not found".to_string()))?;
+        // other uploads in progress will be written too, so their sizes are reserved
+        let staged_size = staging
+            .declared_total(&orbit, &hash)
+            .await
+            .map_err(|e| (Status::InternalServerError, e.to_string()))?;
+        if current_size
+            .saturating_add(staged_size)
+            .saturating_add(size)
+            > limit
+        {
+            return Err((
+                Status::PayloadTooLarge,
+                format!("The data storage limit of {limit} bytes would be exceeded"),
+            ));
+        }
+    }
+    staging
+        .begin(&orbit, &hash, size)
+        .await
+        .map(Json)
+        .map_err(upload_error)
+}
+
+/// Append the request body to an upload at `offset`, which must be the number of bytes
+/// staged so far. Responds with the new number of bytes staged.
+#[patch("/upload/<orbit>/<hash>?<offset>", data = "<data>")]
+pub async fn append_upload(
+    orbit: &str,
+    hash: &str,
+    offset: u64,
+    data: Data<'_>,
+    invocation: AuthHeaderGetter<InvocationInfo>,
+    staging: &State<Uploads>,
+    kepler: &State<Kepler>,
+    config: &State<Config>,
+) -> Result<Json<u64>, (Status, String)> {
+    let staging = uploads(staging)?;
+    let orbit = authorize(orbit, invocation, kepler).await?;
+    let hash = parse_hash(hash)?;
+    // a longer body is cut short, and the client resumes from the returned offset
+    staging
+        .append(
+            &orbit,
+            &hash,
+            offset,
+            data.open(config.requests.maxbody).compat(),
+        )
+        .await
+        .map(Json)
+        .map_err(upload_error)
+}
+
+#[delete("/upload/<orbit>/<hash>")]
+pub async fn discard_upload(
+    orbit: &str,
+    hash: &str,
+    invocation: AuthHeaderGetter<InvocationInfo>,
+    staging: &State<Uploads>,
+    kepler: &State<Kepler>,
+) -> Result<(), (Status, String)> {
+    let staging = uploads(staging)?;
+    let orbit = authorize(orbit, invocation, kepler).await?;
+    match staging.discard(&orbit, &parse_hash(hash)?).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((Status::NotFound, "Upload not found".to_string())),
+        Err(e) => Err((Status::InternalServerError, e.to_string())),
+    }
+}
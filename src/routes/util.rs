@@ -1,5 +1,9 @@
+use crate::config::{ContentTypes, InconsistencyPolicy};
 use futures::io::AsyncRead;
+use kepler_core::{hash::Hash, types::Metadata, Version};
+use kepler_lib::{libipld::cid::Cid, resource::OrbitId};
 use pin_project::pin_project;
+use rocket::http::Status;
 use std::{
     io::{Error as IoError, ErrorKind},
     task::Poll,
@@ -60,6 +64,129 @@ where
     }
 }
 
+/// Whether a read failed because it exceeded the limit of a [`LimitedReader`].
+pub fn limit_exceeded(e: &IoError) -> bool {
+    e.get_ref().map_or(false, |e| e.is::<LimitExceeded>())
+}
+
+/// Number of leading bytes of written content kept for content type sniffing.
+pub const SNIFF_LEN: usize = 1024;
+
+/// SniffReader wraps an AsyncRead and keeps a copy of the first bytes read through it, up
+/// to [`SNIFF_LEN`].
+#[pin_project]
+#[derive(Debug)]
+pub struct SniffReader<'a, R> {
+    #[pin]
+    inner: R,
+    prefix: &'a mut Vec<u8>,
+}
+
+impl<'a, R> SniffReader<'a, R> {
+    pub fn new(inner: R, prefix: &'a mut Vec<u8>) -> Self {
+        Self { inner, prefix }
+    }
+}
+
+impl<'a, R> AsyncRead for SniffReader<'a, R>
+where
+    R: AsyncRead,
+{
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let this = self.project();
+
+        match this.inner.poll_read(cx, buf) {
+            Poll::Ready(Ok(n)) => {
+                let wanted = SNIFF_LEN.saturating_sub(this.prefix.len()).min(n);
+                this.prefix.extend_from_slice(&buf[..wanted]);
+                Poll::Ready(Ok(n))
+            }
+            r => r,
+        }
+    }
+}
+
+/// Check the declared content type of a write to `orbit` against the orbit's allowlist
+/// and, if sniffing is enabled, against the leading bytes of the content.
+pub fn check_content_type(
+    config: &ContentTypes,
+    orbit: &OrbitId,
+    metadata: &Metadata,
+    prefix: &[u8],
+) -> Result<(), (Status, String)> {
+    let declared = metadata.content_type();
+    if let Some(allowed) = config.allow.get(&orbit.to_string()) {
+        match &declared {
+            Some(t) if allowed.contains(t) => {}
+            Some(t) => {
+                return Err((
+                    Status::UnsupportedMediaType,
+                    format!("Content type {t} is not accepted by this orbit"),
+                ))
+            }
+            None => {
+                return Err((
+                    Status::UnsupportedMediaType,
+                    "A content type is required by this orbit".to_string(),
+                ))
+            }
+        }
+    }
+    if !config.sniff {
+        return Ok(());
+    }
+    let sniffed = infer::get(prefix).map(|t| t.mime_type());
+    match (declared.as_deref(), sniffed) {
+        // content matching no known signature can't be checked, unless the declared
+        // type is one which would have been recognised
+        (Some(d), None) if infer::is_mime_supported(d) => Err((
+            Status::UnsupportedMediaType,
+            format!("Content does not match declared type {d}"),
+        )),
+        (Some(d), Some(s)) if d != s => Err((
+            Status::UnsupportedMediaType,
+            format!("Content of type {s} does not match declared type {d}"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Record a read of content which the database references but block storage is missing,
+/// returning the response status for it under the given policy.
+pub fn missing_content_status(policy: InconsistencyPolicy) -> Status {
+    crate::prometheus::STORE_INCONSISTENCY_COUNTER.inc();
+    match policy {
+        InconsistencyPolicy::Error => Status::BadGateway,
+        InconsistencyPolicy::NotFound => Status::NotFound,
+    }
+}
+
+/// Render an object version as `<seq>.<epoch>.<epoch seq>`, with the epoch as a CID.
+pub fn format_version((seq, epoch, epoch_seq): &Version) -> String {
+    format!("{seq}.{}.{epoch_seq}", epoch.to_cid(0x55))
+}
+
+/// Parse an object version rendered by [`format_version`].
+pub fn parse_version(version: &str) -> Result<Version, (Status, String)> {
+    let invalid = || (Status::BadRequest, "Invalid object version".to_string());
+    let mut parts = version.split('.');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(seq), Some(epoch), Some(epoch_seq), None) => Ok((
+            seq.parse().map_err(|_| invalid())?,
+            epoch
+                .parse::<Cid>()
+                .map(Hash::from)
+                .map_err(|_| invalid())?,
+            epoch_seq.parse().map_err(|_| invalid())?,
+        )),
+        _ => Err(invalid()),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -84,6 +211,114 @@ mod test {
         // use a reader with limit below data len
         let mut reader = LimitedReader::new(&data[..], data.len() as u64 - 1);
         let r = reader.read_to_end(&mut buf).await;
-        assert!(r.is_err());
+        assert!(limit_exceeded(&r.unwrap_err()));
+        assert!(!limit_exceeded(&IoError::new(ErrorKind::Other, "other")));
+    }
+
+    #[test]
+    async fn test_sniff() {
+        let data = vec![7u8; SNIFF_LEN * 2];
+        let mut prefix = Vec::new();
+        let mut buf = Vec::new();
+        let mut reader = SniffReader::new(&data[..], &mut prefix);
+        let n = reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(n, data.len());
+        assert_eq!(buf, data);
+        assert_eq!(prefix, &data[..SNIFF_LEN]);
+    }
+
+    #[test]
+    async fn test_check_content_type() {
+        const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        const JPEG: &[u8] = b"\xff\xd8\xff\xe0\0\x10JFIF\0";
+        let orbit: OrbitId = "kepler:example://gallery".parse().unwrap();
+        let other: OrbitId = "kepler:example://default".parse().unwrap();
+        let md = |t: &str| Metadata([("Content-Type".to_string(), t.to_string())].into());
+        let status = |r: Result<(), (Status, String)>| r.map_err(|(s, _)| s);
+
+        // disabled by default
+        let config = ContentTypes::default();
+        assert!(check_content_type(&config, &orbit, &md("image/jpeg"), PNG).is_ok());
+
+        let config = ContentTypes {
+            sniff: true,
+            allow: [(
+                orbit.to_string(),
+                ["image/png".to_string(), "image/jpeg".to_string()].into(),
+            )]
+            .into(),
+        };
+        assert!(check_content_type(&config, &orbit, &md("image/png"), PNG).is_ok());
+        assert!(check_content_type(&config, &orbit, &md("Image/JPEG; q=1"), JPEG).is_ok());
+        // mismatched magic bytes
+        assert_eq!(
+            status(check_content_type(&config, &orbit, &md("image/jpeg"), PNG)),
+            Err(Status::UnsupportedMediaType)
+        );
+        assert_eq!(
+            status(check_content_type(
+                &config,
+                &orbit,
+                &md("image/png"),
+                b"hello"
+            )),
+            Err(Status::UnsupportedMediaType)
+        );
+        // not in the orbit's allowlist, or not declared
+        assert_eq!(
+            status(check_content_type(
+                &config,
+                &orbit,
+                &md("text/plain"),
+                b"hello"
+            )),
+            Err(Status::UnsupportedMediaType)
+        );
+        assert_eq!(
+            status(check_content_type(
+                &config,
+                &orbit,
+                &Metadata(Default::default()),
+                PNG
+            )),
+            Err(Status::UnsupportedMediaType)
+        );
+        // orbits without an allowlist accept any type which matches
+        assert!(check_content_type(&config, &other, &md("text/plain"), b"hello").is_ok());
+        assert_eq!(
+            status(check_content_type(&config, &other, &md("text/plain"), PNG)),
+            Err(Status::UnsupportedMediaType)
+        );
+    }
+
+    #[test]
+    async fn test_missing_content_status() {
+        let counter = &crate::prometheus::STORE_INCONSISTENCY_COUNTER;
+        let before = counter.get();
+        assert_eq!(
+            missing_content_status(InconsistencyPolicy::Error),
+            Status::BadGateway
+        );
+        assert_eq!(
+            missing_content_status(InconsistencyPolicy::NotFound),
+            Status::NotFound
+        );
+        assert!(counter.get() >= before + 2);
+    }
+
+    #[test]
+    async fn test_version() {
+        let version = (3, kepler_core::hash::hash(b"epoch"), 1);
+        let formatted = format_version(&version);
+        assert_eq!(parse_version(&formatted).unwrap(), version);
+        for invalid in [
+            "",
+            "3",
+            "3.abc.1",
+            format!("{formatted}.2").as_str(),
+            "x.y.z",
+        ] {
+            assert!(parse_version(invalid).is_err());
+        }
     }
 }
@@ -0,0 +1,40 @@
+use crate::{config::Compaction, tasks::Stop, Kepler};
+use std::time::Duration;
+
+/// Periodically compact the history of every orbit, if an interval is configured.
+///
+/// Each orbit is compacted in its own transaction, so an interrupted run leaves every
+/// orbit either compacted or untouched. Once `stop` is requested no further orbit is
+/// compacted.
+pub async fn compact(kepler: Kepler, config: Compaction, mut stop: Stop) {
+    let interval = match config.interval {
+        Some(interval) => interval,
+        None => return,
+    };
+    let mut interval = tokio::time::interval(Duration::from_secs(interval.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop.requested() => return,
+        }
+        let orbits = match kepler.orbits().await {
+            Ok(orbits) => orbits,
+            Err(e) => {
+                tracing::warn!("failed to list orbits for compaction: {}", e);
+                continue;
+            }
+        };
+        for orbit in orbits {
+            if stop.is_requested() {
+                return;
+            }
+            match kepler.compact(&orbit).await {
+                Ok(outcome) if outcome.epochs > 0 => {
+                    tracing::debug!("compacted {} epochs of {}", outcome.epochs, orbit)
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("failed to compact {}: {}", orbit, e),
+            }
+        }
+    }
+}
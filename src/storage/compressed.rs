@@ -0,0 +1,417 @@
+use super::encoded_key;
+use futures::{
+    future::Either as AsyncEither,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Cursor},
+};
+use kepler_core::{hash::Hash, storage::*};
+use kepler_lib::resource::OrbitId;
+use rocket::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error as StdError,
+    io::{Error as IoError, Read, Write},
+    pin::Pin,
+};
+
+/// Bytes of staged content compressed at a time.
+const CHUNK: usize = 64 * 1024;
+
+/// Algorithm compressing content before it is written to block storage.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// A compressor writing to a buffer which is drained as it fills.
+trait Encoder: Write + Send {
+    fn output(&mut self) -> &mut Vec<u8>;
+    fn finish(self: Box<Self>) -> Result<Vec<u8>, IoError>;
+}
+
+impl Encoder for flate2::write::GzEncoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<u8>, IoError> {
+        (*self).finish()
+    }
+}
+
+impl Encoder for zstd::stream::write::Encoder<'static, Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<u8>, IoError> {
+        (*self).finish()
+    }
+}
+
+impl Compression {
+    const ALL: [Self; 2] = [Self::Gzip, Self::Zstd];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    fn encoder(self) -> Result<Box<dyn Encoder>, IoError> {
+        Ok(match self {
+            Self::Gzip => Box::new(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            Self::Zstd => Box::new(zstd::stream::write::Encoder::new(Vec::new(), 0)?),
+        })
+    }
+
+    /// Compress `content` into `out` a chunk at a time, returning the number of bytes
+    /// written.
+    async fn compress<R, W>(self, mut content: R, out: &mut W) -> Result<u64, IoError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut encoder = self.encoder()?;
+        let mut chunk = vec![0; CHUNK];
+        let mut written = 0;
+        loop {
+            let n = content.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            encoder.write_all(&chunk[..n])?;
+            let compressed = std::mem::take(encoder.output());
+            out.write_all(&compressed).await?;
+            written += compressed.len() as u64;
+        }
+        let compressed = encoder.finish()?;
+        out.write_all(&compressed).await?;
+        Ok(written + compressed.len() as u64)
+    }
+
+    fn decompress(self, compressed: &[u8]) -> Result<Vec<u8>, IoError> {
+        let mut out = Vec::new();
+        match self {
+            Self::Gzip => {
+                flate2::read::GzDecoder::new(compressed).read_to_end(&mut out)?;
+            }
+            Self::Zstd => zstd::stream::copy_decode(compressed, &mut out)?,
+        };
+        Ok(out)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CompressedStoreError<E> {
+    #[error(transparent)]
+    Store(E),
+    #[error("Failed to stage compressed content: {0}")]
+    Staging(Box<dyn StdError + Send + Sync>),
+    #[error(transparent)]
+    Io(#[from] IoError),
+}
+
+/// Block store compressing content before handing it to another store.
+///
+/// Content keeps the hash of its uncompressed bytes, while the size of the store is that of
+/// what it actually holds. Compressed content is kept in the inner store under a key derived
+/// from its hash and the algorithm, so content persisted before compression was enabled, or
+/// with another algorithm, is still read back correctly, whatever its bytes. Content is
+/// compressed as it is streamed from staging, but decompressed in memory when read, so this
+/// suits many small values better than large files.
+#[derive(Debug, Clone)]
+pub struct CompressedStore<B, S> {
+    inner: B,
+    staging: S,
+    compression: Option<Compression>,
+}
+
+impl<B, S> CompressedStore<B, S> {
+    /// Wraps `inner`, staging compressed content in `staging` before persisting it, or
+    /// passing content through uncompressed if `compression` is unset.
+    pub fn new(inner: B, staging: S, compression: Option<Compression>) -> Self {
+        Self {
+            inner,
+            staging,
+            compression,
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Where content addressed by `id` may be kept, with the algorithm it is compressed
+    /// with there, where the configured algorithm would put it first.
+    fn locations(&self, id: &Hash) -> Vec<(Hash, Option<Compression>)> {
+        let compressed = Compression::ALL
+            .iter()
+            .map(|c| (encoded_key(id, c.name()).finalize(), Some(*c)));
+        let mut locations: Vec<_> = std::iter::once((*id, None)).chain(compressed).collect();
+        locations.sort_by_key(|(_, c)| *c != self.compression);
+        locations
+    }
+}
+
+#[async_trait]
+impl<B, S> StorageSetup for CompressedStore<B, S>
+where
+    B: StorageSetup + Send + Sync,
+    S: Send + Sync,
+{
+    type Error = B::Error;
+    async fn create(&self, orbit: &OrbitId) -> Result<(), Self::Error> {
+        self.inner.create(orbit).await
+    }
+}
+
+#[async_trait]
+impl<B, S> ImmutableReadStore for CompressedStore<B, S>
+where
+    B: ImmutableReadStore,
+    S: Send + Sync,
+{
+    type Error = CompressedStoreError<B::Error>;
+    type Readable = AsyncEither<Pin<Box<B::Readable>>, Cursor<Vec<u8>>>;
+    async fn contains(&self, orbit: &OrbitId, id: &Hash) -> Result<bool, Self::Error> {
+        for (key, _) in self.locations(id) {
+            if self
+                .inner
+                .contains(orbit, &key)
+                .await
+                .map_err(CompressedStoreError::Store)?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn read(
+        &self,
+        orbit: &OrbitId,
+        id: &Hash,
+    ) -> Result<Option<Content<Self::Readable>>, Self::Error> {
+        for (key, compression) in self.locations(id) {
+            let (size, reader) = match self
+                .inner
+                .read(orbit, &key)
+                .await
+                .map_err(CompressedStoreError::Store)?
+            {
+                Some(content) => content.into_inner(),
+                None => continue,
+            };
+            return Ok(Some(match compression {
+                Some(compression) => {
+                    let mut compressed = Vec::with_capacity(size as usize);
+                    Box::pin(reader).read_to_end(&mut compressed).await?;
+                    let content = compression.decompress(&compressed)?;
+                    Content::new(
+                        content.len() as u64,
+                        AsyncEither::Right(Cursor::new(content)),
+                    )
+                }
+                None => Content::new(size, AsyncEither::Left(Box::pin(reader))),
+            }));
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl<B, S> ImmutableWriteStore<S> for CompressedStore<B, S>
+where
+    B: ImmutableWriteStore<S>,
+    S: ImmutableStaging,
+    S::Error: 'static,
+    S::Writable: IntoReader + Unpin + 'static,
+{
+    type Error = CompressedStoreError<B::Error>;
+    async fn persist(
+        &self,
+        orbit: &OrbitId,
+        mut staged: HashBuffer<S::Writable>,
+    ) -> Result<Hash, Self::Error> {
+        let compression = match self.compression {
+            Some(c) => c,
+            None => {
+                return self
+                    .inner
+                    .persist(orbit, staged)
+                    .await
+                    .map_err(CompressedStoreError::Store)
+            }
+        };
+        let id = staged.hash();
+        let (_, buffer) = staged.into_inner();
+        let mut writable = self
+            .staging
+            .get_staging_buffer(orbit)
+            .await
+            .map_err(|e| CompressedStoreError::Staging(Box::new(e)))?;
+        let size = compression
+            .compress(buffer.into_reader().await?, &mut writable)
+            .await?;
+        writable.flush().await?;
+        self.inner
+            .persist(
+                orbit,
+                HashBuffer::from_parts(encoded_key(&id, compression.name()), writable, size),
+            )
+            .await
+            .map_err(CompressedStoreError::Store)?;
+        // content is addressed by its uncompressed bytes, whichever key it is kept under
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl<B, S> ImmutableDeleteStore for CompressedStore<B, S>
+where
+    B: ImmutableDeleteStore,
+    S: Send + Sync,
+{
+    type Error = B::Error;
+    async fn remove(&self, orbit: &OrbitId, id: &Hash) -> Result<Option<()>, Self::Error> {
+        let mut removed = None;
+        for (key, _) in self.locations(id) {
+            removed = removed.or(self.inner.remove(orbit, &key).await?);
+        }
+        Ok(removed)
+    }
+}
+
+#[async_trait]
+impl<B, S> StoreSize for CompressedStore<B, S>
+where
+    B: StoreSize,
+    S: Send + Sync,
+{
+    type Error = B::Error;
+    async fn total_size(&self, orbit: &OrbitId) -> Result<Option<u64>, Self::Error> {
+        self.inner.total_size(orbit).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kepler_core::storage::memory::{MemoryBlockStore, MemoryStaging};
+
+    async fn persist<B: ImmutableWriteStore<MemoryStaging>>(
+        store: &B,
+        orbit: &OrbitId,
+        content: &[u8],
+    ) -> Hash {
+        let mut staged = MemoryStaging.stage(orbit).await.unwrap();
+        staged.write_all(content).await.unwrap();
+        store.persist(orbit, staged).await.unwrap()
+    }
+
+    #[test]
+    async fn round_trip() {
+        let orbit = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let content = b"hello world, ".repeat(100);
+        let plain = MemoryBlockStore::default();
+        plain.create(&orbit).await.unwrap();
+        let expected = persist(&plain, &orbit, &content).await;
+
+        for compression in Compression::ALL {
+            let inner = MemoryBlockStore::default();
+            let store = CompressedStore::new(inner.clone(), MemoryStaging, Some(compression));
+            store.create(&orbit).await.unwrap();
+
+            // content is addressed by its uncompressed bytes
+            let hash = persist(&store, &orbit, &content).await;
+            assert_eq!(hash, expected);
+            assert!(store.contains(&orbit, &hash).await.unwrap());
+            assert_eq!(
+                store.read_to_vec(&orbit, &hash).await.unwrap(),
+                Some(content.clone())
+            );
+            assert_eq!(
+                store.read(&orbit, &hash).await.unwrap().unwrap().len(),
+                content.len() as u64
+            );
+
+            // the store holds, and is sized by, the compressed bytes, kept under a key of
+            // their own
+            let key = encoded_key(&hash, compression.name()).finalize();
+            assert!(!inner.contains(&orbit, &hash).await.unwrap());
+            let stored = inner.read_to_vec(&orbit, &key).await.unwrap().unwrap();
+            assert_ne!(stored, content);
+            let size = store.total_size(&orbit).await.unwrap().unwrap();
+            assert_eq!(size, stored.len() as u64);
+            assert!(size < content.len() as u64);
+
+            // compressed content is still read once compression is disabled
+            let uncompressed = CompressedStore::new(inner.clone(), MemoryStaging, None);
+            assert_eq!(
+                uncompressed.read_to_vec(&orbit, &hash).await.unwrap(),
+                Some(content.clone())
+            );
+
+            assert_eq!(store.remove(&orbit, &hash).await.unwrap(), Some(()));
+            assert!(!store.contains(&orbit, &hash).await.unwrap());
+            assert!(!inner.contains(&orbit, &key).await.unwrap());
+        }
+    }
+
+    #[test]
+    async fn large_content() {
+        let orbit = OrbitId::new("example:alice".to_string(), "default".to_string());
+        // spans several chunks
+        let content: Vec<u8> = (0..CHUNK * 3 + 5).map(|i| (i % 251) as u8).collect();
+        let store = CompressedStore::new(
+            MemoryBlockStore::default(),
+            MemoryStaging,
+            Some(Compression::Gzip),
+        );
+        store.create(&orbit).await.unwrap();
+        let hash = persist(&store, &orbit, &content).await;
+        assert_eq!(
+            store.read_to_vec(&orbit, &hash).await.unwrap(),
+            Some(content)
+        );
+    }
+
+    #[test]
+    async fn uncompressed_content() {
+        let orbit = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let inner = MemoryBlockStore::default();
+        inner.create(&orbit).await.unwrap();
+        let short = persist(&inner, &orbit, b"hi").await;
+        let long = persist(&inner, &orbit, b"hello world").await;
+        // content which happens to be compressed is not mistaken for content this store
+        // compressed
+        let mut zstd = Vec::new();
+        zstd::stream::copy_encode(&b"hello world"[..], &mut zstd, 0).unwrap();
+        let compressed = persist(&inner, &orbit, &zstd).await;
+
+        for compression in [None, Some(Compression::Zstd)] {
+            let store = CompressedStore::new(inner.clone(), MemoryStaging, compression);
+            assert_eq!(
+                store.read_to_vec(&orbit, &short).await.unwrap(),
+                Some(b"hi".to_vec())
+            );
+            assert_eq!(
+                store.read_to_vec(&orbit, &long).await.unwrap(),
+                Some(b"hello world".to_vec())
+            );
+            assert_eq!(
+                store.read_to_vec(&orbit, &compressed).await.unwrap(),
+                Some(zstd.clone())
+            );
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use super::encoded_key;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use futures::{
+    future::Either as AsyncEither,
+    io::{AsyncReadExt, AsyncWriteExt, Cursor},
+};
+use kepler_core::{hash::Hash, keys::Secrets, storage::*};
+use kepler_lib::resource::OrbitId;
+use rand::RngCore;
+use rocket::async_trait;
+use std::{
+    error::Error as StdError,
+    io::{Error as IoError, ErrorKind},
+    pin::Pin,
+};
+
+/// Names the scheme content is encrypted with in the key it is kept under.
+const XCHACHA20_POLY1305: &str = "xchacha20poly1305";
+const NONCE_LEN: usize = 24;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EncryptedStoreError<E> {
+    #[error(transparent)]
+    Store(E),
+    #[error("Failed to stage encrypted content: {0}")]
+    Staging(Box<dyn StdError + Send + Sync>),
+    #[error("Failed to get the data key of the orbit: {0}")]
+    Key(Box<dyn StdError + Send + Sync>),
+    #[error("Failed to decrypt content {}", .0.to_cid(0x55))]
+    Decrypt(Hash),
+    #[error(transparent)]
+    Io(#[from] IoError),
+}
+
+/// Block store encrypting content at rest before handing it to another store.
+///
+/// Each orbit's content is encrypted with XChaCha20-Poly1305 under the orbit's data key,
+/// which the [`Secrets`] backend derives from its master secret, so the master secret is
+/// needed to recover any orbit's key and no per-orbit key is stored. Content keeps the hash
+/// of its plaintext, computed before it is encrypted, so CIDs and deduplication within an
+/// orbit are unaffected. Each write is encrypted with a random nonce, so the stored bytes
+/// reveal nothing about whether content is shared between orbits, and are bound to the
+/// hash of their plaintext, so they can't be swapped for other content of the orbit.
+/// Encrypted content is kept in the inner store under a key derived from its hash and the
+/// scheme, so content persisted before encryption was enabled is still read back as it is,
+/// whatever its bytes.
+#[derive(Debug, Clone)]
+pub struct EncryptedStore<B, S, K> {
+    inner: B,
+    staging: S,
+    keys: Option<K>,
+}
+
+impl<B, S, K> EncryptedStore<B, S, K> {
+    /// Wraps `inner`, staging encrypted content in `staging` before persisting it, or
+    /// passing content through unencrypted if `keys` is unset.
+    pub fn new(inner: B, staging: S, keys: Option<K>) -> Self {
+        Self {
+            inner,
+            staging,
+            keys,
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.keys.is_some()
+    }
+}
+
+/// The key encrypted content addressed by `id` is kept under.
+fn sealed_key(id: &Hash) -> Hash {
+    encoded_key(id, XCHACHA20_POLY1305).finalize()
+}
+
+async fn cipher<K: Secrets, E>(
+    keys: &K,
+    orbit: &OrbitId,
+) -> Result<XChaCha20Poly1305, EncryptedStoreError<E>>
+where
+    K::Error: Send + Sync + 'static,
+{
+    let key = keys
+        .get_data_key(orbit)
+        .await
+        .map_err(|e| EncryptedStoreError::Key(Box::new(e)))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+fn encrypt(cipher: &XChaCha20Poly1305, id: &Hash, content: &[u8]) -> Result<Vec<u8>, IoError> {
+    let mut nonce = [0; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: content,
+                aad: id.as_ref(),
+            },
+        )
+        .map_err(|_| IoError::new(ErrorKind::Other, "encryption failed"))?;
+    let mut out = nonce.to_vec();
+    out.extend(ciphertext);
+    Ok(out)
+}
+
+fn decrypt(cipher: &XChaCha20Poly1305, id: &Hash, sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: id.as_ref(),
+            },
+        )
+        .ok()
+}
+
+#[async_trait]
+impl<B, S, K> StorageSetup for EncryptedStore<B, S, K>
+where
+    B: StorageSetup + Send + Sync,
+    S: Send + Sync,
+    K: Send + Sync,
+{
+    type Error = B::Error;
+    async fn create(&self, orbit: &OrbitId) -> Result<(), Self::Error> {
+        self.inner.create(orbit).await
+    }
+}
+
+#[async_trait]
+impl<B, S, K> ImmutableReadStore for EncryptedStore<B, S, K>
+where
+    B: ImmutableReadStore,
+    S: Send + Sync,
+    K: Secrets + Send + Sync,
+    K::Error: Send + Sync + 'static,
+{
+    type Error = EncryptedStoreError<B::Error>;
+    type Readable = AsyncEither<Pin<Box<B::Readable>>, Cursor<Vec<u8>>>;
+    async fn contains(&self, orbit: &OrbitId, id: &Hash) -> Result<bool, Self::Error> {
+        for key in [sealed_key(id), *id] {
+            if self
+                .inner
+                .contains(orbit, &key)
+                .await
+                .map_err(EncryptedStoreError::Store)?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn read(
+        &self,
+        orbit: &OrbitId,
+        id: &Hash,
+    ) -> Result<Option<Content<Self::Readable>>, Self::Error> {
+        let sealed = sealed_key(id);
+        let keys = match &self.keys {
+            Some(keys) => keys,
+            // encrypted content exists but can't be read without the secret
+            None => {
+                return match self
+                    .inner
+                    .read(orbit, id)
+                    .await
+                    .map_err(EncryptedStoreError::Store)?
+                {
+                    Some(content) => {
+                        let (size, reader) = content.into_inner();
+                        Ok(Some(Content::new(
+                            size,
+                            AsyncEither::Left(Box::pin(reader)),
+                        )))
+                    }
+                    None if self
+                        .inner
+                        .contains(orbit, &sealed)
+                        .await
+                        .map_err(EncryptedStoreError::Store)? =>
+                    {
+                        Err(EncryptedStoreError::Decrypt(*id))
+                    }
+                    None => Ok(None),
+                }
+            }
+        };
+        let (size, reader) = match self
+            .inner
+            .read(orbit, &sealed)
+            .await
+            .map_err(EncryptedStoreError::Store)?
+        {
+            Some(content) => content.into_inner(),
+            // content persisted before encryption was enabled
+            None => {
+                return Ok(self
+                    .inner
+                    .read(orbit, id)
+                    .await
+                    .map_err(EncryptedStoreError::Store)?
+                    .map(|content| {
+                        let (size, reader) = content.into_inner();
+                        Content::new(size, AsyncEither::Left(Box::pin(reader)))
+                    }))
+            }
+        };
+        let mut ciphertext = Vec::with_capacity(size as usize);
+        Box::pin(reader).read_to_end(&mut ciphertext).await?;
+        let content = decrypt(&cipher(keys, orbit).await?, id, &ciphertext)
+            .ok_or(EncryptedStoreError::Decrypt(*id))?;
+        Ok(Some(Content::new(
+            content.len() as u64,
+            AsyncEither::Right(Cursor::new(content)),
+        )))
+    }
+}
+
+#[async_trait]
+impl<B, S, K> ImmutableWriteStore<S> for EncryptedStore<B, S, K>
+where
+    B: ImmutableWriteStore<S>,
+    S: ImmutableStaging,
+    S::Error: 'static,
+    S::Writable: IntoBytes + Unpin + 'static,
+    K: Secrets + Send + Sync,
+    K::Error: Send + Sync + 'static,
+{
+    type Error = EncryptedStoreError<B::Error>;
+    async fn persist(
+        &self,
+        orbit: &OrbitId,
+        mut staged: HashBuffer<S::Writable>,
+    ) -> Result<Hash, Self::Error> {
+        let keys = match &self.keys {
+            Some(k) => k,
+            None => {
+                return self
+                    .inner
+                    .persist(orbit, staged)
+                    .await
+                    .map_err(EncryptedStoreError::Store)
+            }
+        };
+        let id = staged.hash();
+        let (_, buffer) = staged.into_inner();
+        let sealed = encrypt(
+            &cipher(keys, orbit).await?,
+            &id,
+            &buffer.into_bytes().await?,
+        )?;
+        let mut writable = self
+            .staging
+            .get_staging_buffer(orbit)
+            .await
+            .map_err(|e| EncryptedStoreError::Staging(Box::new(e)))?;
+        writable.write_all(&sealed).await?;
+        writable.flush().await?;
+        self.inner
+            .persist(
+                orbit,
+                HashBuffer::from_parts(
+                    encoded_key(&id, XCHACHA20_POLY1305),
+                    writable,
+                    sealed.len() as u64,
+                ),
+            )
+            .await
+            .map_err(EncryptedStoreError::Store)?;
+        // content is addressed by its plaintext, whichever key it is kept under
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl<B, S, K> ImmutableDeleteStore for EncryptedStore<B, S, K>
+where
+    B: ImmutableDeleteStore,
+    S: Send + Sync,
+    K: Send + Sync,
+{
+    type Error = B::Error;
+    async fn remove(&self, orbit: &OrbitId, id: &Hash) -> Result<Option<()>, Self::Error> {
+        let sealed = self.inner.remove(orbit, &sealed_key(id)).await?;
+        Ok(self.inner.remove(orbit, id).await?.or(sealed))
+    }
+}
+
+#[async_trait]
+impl<B, S, K> StoreSize for EncryptedStore<B, S, K>
+where
+    B: StoreSize,
+    S: Send + Sync,
+    K: Send + Sync,
+{
+    type Error = B::Error;
+    async fn total_size(&self, orbit: &OrbitId) -> Result<Option<u64>, Self::Error> {
+        self.inner.total_size(orbit).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kepler_core::{
+        keys::StaticSecret,
+        storage::memory::{MemoryBlockStore, MemoryStaging},
+    };
+
+    async fn persist<B: ImmutableWriteStore<MemoryStaging>>(
+        store: &B,
+        orbit: &OrbitId,
+        content: &[u8],
+    ) -> Hash {
+        let mut staged = MemoryStaging.stage(orbit).await.unwrap();
+        staged.write_all(content).await.unwrap();
+        store.persist(orbit, staged).await.unwrap()
+    }
+
+    fn secret(byte: u8) -> StaticSecret {
+        StaticSecret::new(vec![byte; 32]).unwrap()
+    }
+
+    #[test]
+    async fn round_trip() {
+        let alice = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let bob = OrbitId::new("example:bob".to_string(), "default".to_string());
+        let content = b"hello world".to_vec();
+        let plain = MemoryBlockStore::default();
+        plain.create(&alice).await.unwrap();
+        let expected = persist(&plain, &alice, &content).await;
+
+        let inner = MemoryBlockStore::default();
+        let store = EncryptedStore::new(inner.clone(), MemoryStaging, Some(secret(0)));
+        store.create(&alice).await.unwrap();
+        store.create(&bob).await.unwrap();
+
+        // content is addressed by its plaintext
+        let hash = persist(&store, &alice, &content).await;
+        assert_eq!(hash, expected);
+        assert_eq!(
+            store.read_to_vec(&alice, &hash).await.unwrap(),
+            Some(content.clone())
+        );
+
+        // the store holds ciphertext, encrypted differently in each orbit
+        let stored = inner
+            .read_to_vec(&alice, &sealed_key(&hash))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!stored.windows(content.len()).any(|w| w == content));
+        assert!(!inner.contains(&alice, &hash).await.unwrap());
+        assert_eq!(persist(&store, &bob, &content).await, hash);
+        assert_ne!(
+            inner
+                .read_to_vec(&bob, &sealed_key(&hash))
+                .await
+                .unwrap()
+                .unwrap(),
+            stored
+        );
+
+        // content can't be read without the master secret it was encrypted under
+        let other = EncryptedStore::new(inner.clone(), MemoryStaging, Some(secret(1)));
+        assert!(matches!(
+            other.read(&alice, &hash).await,
+            Err(EncryptedStoreError::Decrypt(_))
+        ));
+        let disabled = EncryptedStore::new(inner.clone(), MemoryStaging, None::<StaticSecret>);
+        assert!(matches!(
+            disabled.read(&alice, &hash).await,
+            Err(EncryptedStoreError::Decrypt(_))
+        ));
+
+        assert_eq!(store.remove(&alice, &hash).await.unwrap(), Some(()));
+        assert!(!store.contains(&alice, &hash).await.unwrap());
+        assert!(!inner.contains(&alice, &sealed_key(&hash)).await.unwrap());
+    }
+
+    #[test]
+    async fn unencrypted_content() {
+        let orbit = OrbitId::new("example:alice".to_string(), "default".to_string());
+        let inner = MemoryBlockStore::default();
+        inner.create(&orbit).await.unwrap();
+        let short = persist(&inner, &orbit, b"hi").await;
+        let long = persist(&inner, &orbit, b"hello world").await;
+
+        // content which looks like it was sealed by an earlier version of this store
+        let lookalike = [&[0, b'k', b'p', b'e', 1][..], &[7; 40]].concat();
+        let sealed = persist(&inner, &orbit, &lookalike).await;
+
+        for keys in [Some(secret(0)), None] {
+            let store = EncryptedStore::new(inner.clone(), MemoryStaging, keys);
+            assert_eq!(
+                store.read_to_vec(&orbit, &short).await.unwrap(),
+                Some(b"hi".to_vec())
+            );
+            assert_eq!(
+                store.read_to_vec(&orbit, &long).await.unwrap(),
+                Some(b"hello world".to_vec())
+            );
+            assert_eq!(
+                store.read_to_vec(&orbit, &sealed).await.unwrap(),
+                Some(lookalike.clone())
+            );
+        }
+    }
+}
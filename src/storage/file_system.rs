@@ -2,7 +2,7 @@ use super::size::OrbitSizes;
 use core::pin::Pin;
 use futures::{
     future::{Either as AsyncEither, TryFutureExt},
-    io::{AsyncWrite, AsyncWriteExt},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     stream::TryStreamExt,
     task::{Context, Poll},
 };
@@ -14,24 +14,53 @@ use std::{
     collections::HashMap,
     io::{Error as IoError, ErrorKind},
     path::{Path, PathBuf},
+    sync::Arc,
+};
+use tempfile::{NamedTempFile, PathPersistError, TempPath};
+use tokio::{
+    fs::{create_dir_all, hard_link, metadata, remove_file, File},
+    sync::Mutex,
 };
-use tempfile::{NamedTempFile, PathPersistError};
-use tokio::fs::{create_dir_all, metadata, remove_file, File};
 use tokio_stream::wrappers::ReadDirStream;
 
 use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
 
+// directory under the store root holding hardlink targets, never a valid DID suffix
+const SHARED_DIR: &str = ".shared";
+
 #[derive(Debug, Clone)]
 pub struct FileSystemStore {
     path: PathBuf,
     sizes: OrbitSizes,
+    duplicates: DuplicateContent,
+    // held while shared content is linked to or removed
+    shared: Arc<Mutex<()>>,
 }
 
 impl FileSystemStore {
-    async fn new(path: PathBuf) -> Result<Self, IoError> {
+    async fn new(path: PathBuf, duplicates: DuplicateContent) -> Result<Self, IoError> {
         // get the size of the directory
         let sizes = store_sizes(&path).await?.into();
-        Ok(Self { path, sizes })
+        if duplicates == DuplicateContent::Hardlink {
+            create_dir_all(path.join(SHARED_DIR)).await?;
+        }
+        Ok(Self {
+            path,
+            sizes,
+            duplicates,
+            shared: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Check the store's directory is still accessible.
+    pub async fn check(&self) -> Result<(), IoError> {
+        match metadata(&self.path).await? {
+            m if m.is_dir() => Ok(()),
+            _ => Err(IoError::new(
+                ErrorKind::Other,
+                "block storage path is not a directory",
+            )),
+        }
     }
 
     fn get_path(&self, orbit: &OrbitId, mh: &Hash) -> PathBuf {
@@ -41,6 +70,75 @@ impl FileSystemStore {
             .join(base64::encode_config(mh.as_ref(), base64::URL_SAFE))
     }
 
+    fn shared_path(&self, mh: &Hash) -> PathBuf {
+        self.path
+            .join(SHARED_DIR)
+            .join(base64::encode_config(mh.as_ref(), base64::URL_SAFE))
+    }
+
+    // where new content for a block is written, depending on the duplicate content mode
+    fn content_path(&self, orbit: &OrbitId, mh: &Hash) -> PathBuf {
+        match self.duplicates {
+            DuplicateContent::Copy => self.get_path(orbit, mh),
+            DuplicateContent::Hardlink => self.shared_path(mh),
+        }
+    }
+
+    // move staged content to `content_path` and make it visible under the orbit
+    async fn place(
+        &self,
+        orbit: &OrbitId,
+        mh: &Hash,
+        staged: TempPath,
+    ) -> Result<(), FileSystemStoreError> {
+        match self.duplicates {
+            DuplicateContent::Copy => Ok(persist_noclobber(staged, &self.get_path(orbit, mh))?),
+            DuplicateContent::Hardlink => {
+                // otherwise the shared file could be removed between being found and linked
+                let _shared = self.shared.lock().await;
+                persist_noclobber(staged, &self.shared_path(mh))?;
+                match hard_link(self.shared_path(mh), self.get_path(orbit, mh)).await {
+                    Err(e) if e.kind() != ErrorKind::AlreadyExists => Err(e.into()),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    // remove the shared copy of a block once no orbit links to it any more
+    async fn unlink_shared(&self, mh: &Hash) -> Result<(), IoError> {
+        if self.duplicates != DuplicateContent::Hardlink {
+            return Ok(());
+        }
+        let _shared = self.shared.lock().await;
+        let path = self.shared_path(mh);
+        match metadata(&path).await {
+            Ok(m) if link_count(&m) <= 1 => match remove_file(path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn write_bytes(
+        &self,
+        orbit: &OrbitId,
+        hash: &Hash,
+        v: &[u8],
+    ) -> Result<(), FileSystemStoreError> {
+        // written next to its destination, so it is moved into place whole
+        let content_path = self.content_path(orbit, hash);
+        let dir = content_path.parent().unwrap_or(&self.path);
+        let (file, path) = NamedTempFile::new_in(dir)?.into_parts();
+        let mut writer = futures::io::BufWriter::new(File::from_std(file).compat());
+        writer.write_all(v).await?;
+        writer.flush().await?;
+        drop(writer);
+        self.place(orbit, hash, path).await
+    }
+
     async fn increment_size(&self, orbit: &OrbitId, size: u64) {
         self.sizes.increment_size(orbit, size).await;
     }
@@ -49,20 +147,64 @@ impl FileSystemStore {
     }
 }
 
+// content is addressed by its hash, so content already in place is the same
+fn persist_noclobber(staged: TempPath, to: &Path) -> Result<(), PathPersistError> {
+    match staged.persist_noclobber(to) {
+        Err(e) if e.error.kind() != ErrorKind::AlreadyExists => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn link_count(m: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    m.nlink()
+}
+
+// without link counts we can't tell if a shared block is still in use, so never remove it
+#[cfg(not(unix))]
+fn link_count(_: &std::fs::Metadata) -> u64 {
+    u64::MAX
+}
+
+/// How blocks with identical content stored by different orbits are laid out on disk.
+///
+/// In both modes each orbit's size accounts for every block it references, so
+/// [`StoreSize`] reports the same per-orbit totals. Only the physical disk usage differs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
+pub enum DuplicateContent {
+    /// Every orbit gets its own independent copy of a block.
+    #[default]
+    Copy,
+    /// Content is written once to a shared directory and hardlinked into each orbit
+    /// which stores it. The shared file is removed when the last orbit removes the block.
+    Hardlink,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
 pub struct FileSystemConfig {
     path: PathBuf,
+    #[serde(default)]
+    duplicates: DuplicateContent,
 }
 
 impl FileSystemConfig {
     pub fn new<P: AsRef<Path>>(p: P) -> Self {
         Self {
             path: p.as_ref().into(),
+            duplicates: DuplicateContent::default(),
         }
     }
     pub fn path(&self) -> &Path {
         &self.path
     }
+    pub fn duplicates(&self) -> DuplicateContent {
+        self.duplicates
+    }
+    pub fn with_duplicates(mut self, duplicates: DuplicateContent) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
 }
 
 #[async_trait]
@@ -70,7 +212,7 @@ impl StorageConfig<FileSystemStore> for FileSystemConfig {
     type Error = IoError;
     async fn open(&self) -> Result<FileSystemStore, Self::Error> {
         if self.path.is_dir() {
-            Ok(FileSystemStore::new(self.path.clone()).await?)
+            Ok(FileSystemStore::new(self.path.clone(), self.duplicates).await?)
         } else {
             Err(IoError::new(ErrorKind::NotFound, "path is not a directory"))
         }
@@ -94,6 +236,7 @@ impl Default for FileSystemConfig {
     fn default() -> Self {
         Self {
             path: PathBuf::from(r"/tmp/kepler/blocks"),
+            duplicates: DuplicateContent::default(),
         }
     }
 }
@@ -144,6 +287,10 @@ async fn store_sizes<P: AsRef<Path>>(path: &P) -> Result<HashMap<OrbitId, u64>,
                 entry.metadata().await?.is_dir(),
                 entry.file_name().into_string(),
             ) {
+                // shared hardlink targets don't belong to any one orbit
+                if suffix == SHARED_DIR {
+                    return Ok(acc);
+                }
                 let mut ds = ReadDirStream::new(tokio::fs::read_dir(entry.path()).await?);
                 // go through each suffix directory
                 while let Some(entry) = ds.try_next().await? {
@@ -214,6 +361,35 @@ impl AsyncWrite for TempFileStage {
     }
 }
 
+#[async_trait]
+impl IntoBytes for TempFileStage {
+    async fn into_bytes(self) -> Result<Vec<u8>, IoError> {
+        tokio::fs::read(&self.1).await
+    }
+}
+
+impl AsyncRead for TempFileStage {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, IoError>> {
+        self.project().0.poll_read(cx, buf)
+    }
+}
+
+#[async_trait]
+impl IntoReader for TempFileStage {
+    type Reader = Self;
+    /// Reopen the staged file for reading from its start, keeping it until the reader is
+    /// dropped.
+    async fn into_reader(self) -> Result<Self, IoError> {
+        let (mut file, path) = self.into_inner();
+        file.flush().await?;
+        Ok(Self(File::open(&path).await?.compat(), path))
+    }
+}
+
 #[async_trait]
 impl ImmutableStaging for TempFileSystemStage {
     type Error = FileSystemStoreError;
@@ -237,7 +413,7 @@ impl ImmutableWriteStore<TempFileSystemStage> for FileSystemStore {
         if !self.contains(orbit, &hash).await? {
             let size = f.size().await?;
             let (_, path) = f.into_inner();
-            path.persist(self.get_path(orbit, &hash))?;
+            self.place(orbit, &hash, path).await?;
             self.increment_size(orbit, size).await;
         }
         Ok(hash)
@@ -255,11 +431,8 @@ impl ImmutableWriteStore<memory::MemoryStaging> for FileSystemStore {
         let (mut h, v) = staged.into_inner();
         let hash = h.finalize();
         if !self.contains(orbit, &hash).await? {
-            let file = File::create(self.get_path(orbit, &hash)).await?;
             let size = v.len() as u64;
-            let mut writer = futures::io::BufWriter::new(file.compat());
-            writer.write_all(&v).await?;
-            writer.flush().await?;
+            self.write_bytes(orbit, &hash, &v).await?;
             self.increment_size(orbit, size).await;
         }
         Ok(hash)
@@ -284,15 +457,12 @@ impl ImmutableWriteStore<either::Either<TempFileSystemStage, memory::MemoryStagi
                 AsyncEither::Left(t_file) => {
                     let size = t_file.size().await?;
                     let (_, path) = t_file.into_inner();
-                    path.persist(self.get_path(orbit, &hash))?;
+                    self.place(orbit, &hash, path).await?;
                     self.increment_size(orbit, size).await;
                 }
                 AsyncEither::Right(v) => {
-                    let file = File::create(self.get_path(orbit, &hash)).await?;
                     let size = v.len() as u64;
-                    let mut writer = futures::io::BufWriter::new(file.compat());
-                    writer.write_all(&v).await?;
-                    writer.flush().await?;
+                    self.write_bytes(orbit, &hash, &v).await?;
                     self.increment_size(orbit, size).await;
                 }
             }
@@ -313,6 +483,7 @@ impl ImmutableDeleteStore for FileSystemStore {
         };
         match remove_file(path).await {
             Ok(()) => {
+                self.unlink_shared(id).await?;
                 self.decrement_size(orbit, size).await;
                 Ok(Some(()))
             }
@@ -380,4 +551,137 @@ mod test {
         assert_eq!(store.total_size(&orbit).await.unwrap(), Some(0));
         assert_eq!(store.read(&orbit, &hash).await.unwrap().map(|_| ()), None);
     }
+
+    async fn setup(
+        duplicates: DuplicateContent,
+    ) -> (tempfile::TempDir, FileSystemStore, OrbitId, OrbitId) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemConfig::new(dir.path())
+            .with_duplicates(duplicates)
+            .open()
+            .await
+            .unwrap();
+        let a: OrbitId = "kepler:example://a".parse().unwrap();
+        let b: OrbitId = "kepler:example://b".parse().unwrap();
+        store.create(&a).await.unwrap();
+        store.create(&b).await.unwrap();
+        (dir, store, a, b)
+    }
+
+    async fn persist(store: &FileSystemStore, orbit: &OrbitId, data: &[u8]) -> Hash {
+        let mut stage = memory::MemoryStaging.stage(orbit).await.unwrap();
+        futures::io::copy(&mut &data[..], &mut stage).await.unwrap();
+        ImmutableWriteStore::<memory::MemoryStaging>::persist(store, orbit, stage)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    async fn check() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("blocks")).unwrap();
+        let store = FileSystemConfig::new(dir.path().join("blocks"))
+            .open()
+            .await
+            .unwrap();
+        store.check().await.unwrap();
+        // e.g. an unmounted volume
+        std::fs::remove_dir_all(dir.path().join("blocks")).unwrap();
+        assert!(store.check().await.is_err());
+    }
+
+    #[test]
+    async fn hash_algorithms() {
+        use kepler_core::hash::HashAlgorithm;
+        use kepler_lib::libipld::cid::multihash::Code;
+
+        let (_dir, store, orbit, _) = setup(DuplicateContent::Copy).await;
+        let mut hashes = Vec::new();
+        for (algorithm, code, data) in [
+            (HashAlgorithm::Blake3_256, Code::Blake3_256, b"blake3"),
+            (HashAlgorithm::Sha2_256, Code::Sha2_256, b"sha2-2"),
+        ] {
+            let mut stage = memory::MemoryStaging
+                .stage_with(&orbit, algorithm)
+                .await
+                .unwrap();
+            futures::io::copy(&mut &data[..], &mut stage).await.unwrap();
+            let hash = ImmutableWriteStore::<memory::MemoryStaging>::persist(&store, &orbit, stage)
+                .await
+                .unwrap();
+            assert_eq!(hash.to_cid(0x55).hash().code(), u64::from(code));
+            hashes.push((hash, data));
+        }
+
+        // content written under either algorithm reads back
+        for (hash, data) in hashes {
+            assert_eq!(
+                store.read_to_vec(&orbit, &hash).await.unwrap().unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    async fn hardlink_duplicates() {
+        let data = b"hello world";
+        let (_dir, store, a, b) = setup(DuplicateContent::Hardlink).await;
+        let hash = persist(&store, &a, data).await;
+        assert_eq!(persist(&store, &b, data).await, hash);
+
+        // a single physical file, linked into both orbits
+        let shared = store.shared_path(&hash);
+        assert_eq!(link_count(&std::fs::metadata(&shared).unwrap()), 3);
+        assert_eq!(store.total_size(&a).await.unwrap(), Some(data.len() as u64));
+        assert_eq!(store.total_size(&b).await.unwrap(), Some(data.len() as u64));
+
+        // removing from one orbit leaves the other intact
+        assert_eq!(store.remove(&a, &hash).await.unwrap(), Some(()));
+        assert!(!store.contains(&a, &hash).await.unwrap());
+        assert_eq!(store.read_to_vec(&b, &hash).await.unwrap().unwrap(), data);
+        assert!(shared.exists());
+
+        // removing the last reference removes the shared content
+        assert_eq!(store.remove(&b, &hash).await.unwrap(), Some(()));
+        assert!(!shared.exists());
+        assert_eq!(store.total_size(&b).await.unwrap(), Some(0));
+    }
+
+    #[test]
+    async fn copy_duplicates() {
+        let data = b"hello world";
+        let (dir, store, a, b) = setup(DuplicateContent::Copy).await;
+        let hash = persist(&store, &a, data).await;
+        assert_eq!(persist(&store, &b, data).await, hash);
+
+        // independent files per orbit, nothing shared
+        assert!(!dir.path().join(SHARED_DIR).exists());
+        let (path_a, path_b) = (store.get_path(&a, &hash), store.get_path(&b, &hash));
+        assert_ne!(path_a, path_b);
+        assert_eq!(link_count(&std::fs::metadata(&path_a).unwrap()), 1);
+        assert_eq!(link_count(&std::fs::metadata(&path_b).unwrap()), 1);
+
+        assert_eq!(store.remove(&a, &hash).await.unwrap(), Some(()));
+        assert!(!path_a.exists());
+        assert_eq!(store.read_to_vec(&b, &hash).await.unwrap().unwrap(), data);
+        assert_eq!(store.total_size(&a).await.unwrap(), Some(0));
+        assert_eq!(store.total_size(&b).await.unwrap(), Some(data.len() as u64));
+    }
+
+    #[test]
+    async fn concurrent_hardlinks() {
+        let data = b"hello world";
+        let (_dir, store, a, b) = setup(DuplicateContent::Hardlink).await;
+        for _ in 0..50 {
+            let hash = persist(&store, &a, data).await;
+            // the last link from one orbit is removed as another links to the content
+            let (removed, written) =
+                futures::join!(store.remove(&a, &hash), persist(&store, &b, data));
+            assert_eq!(removed.unwrap(), Some(()));
+            assert_eq!(written, hash);
+            assert_eq!(store.read_to_vec(&b, &hash).await.unwrap().unwrap(), data);
+            assert_eq!(store.remove(&b, &hash).await.unwrap(), Some(()));
+            assert!(!store.shared_path(&hash).exists());
+        }
+    }
 }
@@ -1,3 +1,27 @@
+use kepler_core::hash::{Hash, Hasher};
+
+pub mod compaction;
+pub mod compressed;
+pub mod encrypted;
 pub mod file_system;
+pub mod reaper;
+pub mod resumable;
+pub mod retry;
 pub mod s3;
 pub mod size;
+
+/// Hasher addressing an encoding of the content addressed by `id`, such as its compressed
+/// or encrypted bytes.
+///
+/// Stores which encode content keep the encoding under this key rather than under `id`, so
+/// how content was encoded is known from where it is kept, not guessed from its bytes, and
+/// content of any bytes written without the encoding is still read back as it is.
+fn encoded_key(id: &Hash, encoding: &str) -> Hasher {
+    let mut hasher = Hasher::new();
+    hasher
+        .update(b"kepler/encoded/")
+        .update(encoding.as_bytes())
+        .update(b"/")
+        .update(id.as_ref());
+    hasher
+}
@@ -0,0 +1,40 @@
+use super::resumable::ResumableFileSystemStage;
+use crate::{config::Reaper, tasks::Stop, Kepler};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Periodically remove the content of kv entries which have expired, and the uploads which
+/// have been abandoned.
+///
+/// Each run considers every entry which has expired, so content is reclaimed even if its
+/// entry had expired before it was written. Runs stop once `stop` is requested, but one
+/// which is under way is finished.
+pub async fn reap(
+    kepler: Kepler,
+    uploads: Option<ResumableFileSystemStage>,
+    config: Reaper,
+    mut stop: Stop,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop.requested() => return,
+        }
+        match kepler.reap_expired(OffsetDateTime::now_utc()).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::debug!("reaped {} expired blocks", removed),
+            Err(e) => tracing::warn!("failed to reap expired kv entries: {}", e),
+        }
+        if let Some(uploads) = &uploads {
+            match uploads
+                .discard_stale(Duration::from_secs(config.uploads))
+                .await
+            {
+                Ok(0) => {}
+                Ok(discarded) => tracing::debug!("discarded {} stale uploads", discarded),
+                Err(e) => tracing::warn!("failed to discard stale uploads: {}", e),
+            }
+        }
+    }
+}
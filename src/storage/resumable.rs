@@ -0,0 +1,415 @@
+use super::file_system::{FileSystemStoreError, TempFileStage};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use kepler_core::{hash::Hash, storage::*};
+use kepler_lib::resource::OrbitId;
+use rocket::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tempfile::NamedTempFile;
+use tokio::fs::{
+    create_dir_all, metadata, read_dir, read_to_string, remove_file, write, File, OpenOptions,
+};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+/// Staging for resumable uploads, kept on disk until discarded.
+///
+/// Each upload is a data file named by the declared hash, next to a `.size` file holding
+/// the declared size, and a `.lock` file while it is being appended to.
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
+pub struct ResumableFileSystemStage {
+    path: PathBuf,
+}
+
+impl ResumableFileSystemStage {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn data_path(&self, orbit: &OrbitId, hash: &Hash) -> PathBuf {
+        self.path
+            .join(orbit.suffix())
+            .join(orbit.name())
+            .join(base64::encode_config(hash.as_ref(), base64::URL_SAFE))
+    }
+
+    fn size_path(&self, orbit: &OrbitId, hash: &Hash) -> PathBuf {
+        self.data_path(orbit, hash).with_extension("size")
+    }
+
+    fn lock_path(&self, orbit: &OrbitId, hash: &Hash) -> PathBuf {
+        self.data_path(orbit, hash).with_extension("lock")
+    }
+
+    /// Take the lock on appending to an upload, which is released when dropped.
+    async fn lock(
+        &self,
+        orbit: &OrbitId,
+        hash: &Hash,
+    ) -> Result<AppendLock, ResumableError<FileSystemStoreError>> {
+        let path = self.lock_path(orbit, hash);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(_) => Ok(AppendLock(path)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(ResumableError::Busy),
+            Err(e) => Err(FileSystemStoreError::from(e).into()),
+        }
+    }
+
+    async fn declared_size(&self, orbit: &OrbitId, hash: &Hash) -> Result<Option<u64>, IoError> {
+        match read_to_string(self.size_path(orbit, hash)).await {
+            Ok(s) => s
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| IoError::new(ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn staged_size(&self, orbit: &OrbitId, hash: &Hash) -> Result<u64, IoError> {
+        match metadata(self.data_path(orbit, hash)).await {
+            Ok(m) => Ok(m.len()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Total declared size of the orbit's uploads, other than the one for `except`.
+    pub async fn declared_total(&self, orbit: &OrbitId, except: &Hash) -> Result<u64, IoError> {
+        let except = self.size_path(orbit, except);
+        let mut total = 0u64;
+        for path in files(&self.path.join(orbit.suffix()).join(orbit.name())).await? {
+            if path.extension().map_or(false, |e| e == "size") && path != except {
+                let size = read_to_string(&path)
+                    .await?
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|e| IoError::new(ErrorKind::InvalidData, e))?;
+                total = total.saturating_add(size);
+            }
+        }
+        Ok(total)
+    }
+
+    /// Discard the uploads which haven't been declared or appended to for `max_age`,
+    /// returning how many were discarded.
+    pub async fn discard_stale(&self, max_age: Duration) -> Result<usize, IoError> {
+        let cutoff = SystemTime::now() - max_age;
+        let mut discarded = 0;
+        for suffix in dirs(&self.path).await? {
+            for orbit in dirs(&suffix).await? {
+                // an upload's files share a name, it was last touched when any of them was
+                let mut uploads: HashMap<PathBuf, (SystemTime, Vec<PathBuf>)> = HashMap::new();
+                for path in files(&orbit).await? {
+                    let modified = metadata(&path).await?.modified()?;
+                    let upload = uploads
+                        .entry(path.with_extension(""))
+                        .or_insert((SystemTime::UNIX_EPOCH, Vec::new()));
+                    upload.0 = upload.0.max(modified);
+                    upload.1.push(path);
+                }
+                for (_, (modified, paths)) in uploads {
+                    if modified < cutoff {
+                        for path in paths {
+                            match remove_file(path).await {
+                                Ok(()) => {}
+                                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                                Err(e) => return Err(e),
+                            }
+                        }
+                        discarded += 1;
+                    }
+                }
+            }
+        }
+        Ok(discarded)
+    }
+}
+
+/// Held while appending to an upload, removing its lock file when dropped.
+struct AppendLock(PathBuf);
+
+impl Drop for AppendLock {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            tracing::warn!(error = %e, "failed to release an upload's lock");
+        }
+    }
+}
+
+async fn entries(dir: &Path, want_dirs: bool) -> Result<Vec<PathBuf>, IoError> {
+    let mut read = match read_dir(dir).await {
+        Ok(r) => r,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut paths = Vec::new();
+    while let Some(entry) = read.next_entry().await? {
+        if entry.file_type().await?.is_dir() == want_dirs {
+            paths.push(entry.path());
+        }
+    }
+    Ok(paths)
+}
+
+async fn dirs(dir: &Path) -> Result<Vec<PathBuf>, IoError> {
+    entries(dir, true).await
+}
+
+async fn files(dir: &Path) -> Result<Vec<PathBuf>, IoError> {
+    entries(dir, false).await
+}
+
+#[async_trait]
+impl ImmutableStaging for ResumableFileSystemStage {
+    type Error = FileSystemStoreError;
+    type Writable = TempFileStage;
+    async fn get_staging_buffer(&self, _: &OrbitId) -> Result<Self::Writable, Self::Error> {
+        create_dir_all(&self.path).await?;
+        Ok(TempFileStage::new(NamedTempFile::new_in(&self.path)?))
+    }
+}
+
+#[async_trait]
+impl ResumableStaging for ResumableFileSystemStage {
+    type Readable = Compat<File>;
+
+    async fn begin(
+        &self,
+        orbit: &OrbitId,
+        hash: &Hash,
+        size: u64,
+    ) -> Result<u64, ResumableError<Self::Error>> {
+        match self
+            .declared_size(orbit, hash)
+            .await
+            .map_err(FileSystemStoreError::from)?
+        {
+            Some(declared) if declared != size => Err(ResumableError::SizeMismatch(declared)),
+            Some(_) => Ok(self
+                .staged_size(orbit, hash)
+                .await
+                .map_err(FileSystemStoreError::from)?),
+            None => {
+                let path = self.data_path(orbit, hash);
+                if let Some(dir) = path.parent() {
+                    create_dir_all(dir)
+                        .await
+                        .map_err(FileSystemStoreError::from)?;
+                }
+                // a data file left without a size file is from an interrupted declaration
+                File::create(&path)
+                    .await
+                    .map_err(FileSystemStoreError::from)?;
+                write(self.size_path(orbit, hash), size.to_string())
+                    .await
+                    .map_err(FileSystemStoreError::from)?;
+                Ok(0)
+            }
+        }
+    }
+
+    async fn append<R>(
+        &self,
+        orbit: &OrbitId,
+        hash: &Hash,
+        offset: u64,
+        data: R,
+    ) -> Result<u64, ResumableError<Self::Error>>
+    where
+        R: AsyncRead + Send,
+    {
+        let declared = self
+            .declared_size(orbit, hash)
+            .await
+            .map_err(FileSystemStoreError::from)?
+            .ok_or(ResumableError::NotFound)?;
+        // the offset is checked and appended to under the lock, so appends can't interleave
+        let _lock = self.lock(orbit, hash).await?;
+        let staged = self
+            .staged_size(orbit, hash)
+            .await
+            .map_err(FileSystemStoreError::from)?;
+        if staged != offset {
+            return Err(ResumableError::OffsetMismatch(staged));
+        }
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(self.data_path(orbit, hash))
+            .await
+            .map_err(FileSystemStoreError::from)?
+            .compat_write();
+        let mut limited = Box::pin(data).take(declared - staged);
+        let written = futures::io::copy(&mut limited, &mut file)
+            .await
+            .map_err(FileSystemStoreError::from)?;
+        file.flush().await.map_err(FileSystemStoreError::from)?;
+
+        // the bytes up to the declared size are kept, anything more is refused
+        let mut extra = [0u8; 1];
+        if limited
+            .into_inner()
+            .read(&mut extra)
+            .await
+            .map_err(FileSystemStoreError::from)?
+            > 0
+        {
+            return Err(ResumableError::TooLarge);
+        }
+        Ok(staged + written)
+    }
+
+    async fn open(
+        &self,
+        orbit: &OrbitId,
+        hash: &Hash,
+    ) -> Result<Content<Self::Readable>, ResumableError<Self::Error>> {
+        let declared = self
+            .declared_size(orbit, hash)
+            .await
+            .map_err(FileSystemStoreError::from)?
+            .ok_or(ResumableError::NotFound)?;
+        if self
+            .staged_size(orbit, hash)
+            .await
+            .map_err(FileSystemStoreError::from)?
+            < declared
+        {
+            return Err(ResumableError::Incomplete);
+        }
+        let file = File::open(self.data_path(orbit, hash))
+            .await
+            .map_err(FileSystemStoreError::from)?;
+        Ok(Content::new(declared, file.compat()))
+    }
+
+    async fn discard(&self, orbit: &OrbitId, hash: &Hash) -> Result<bool, Self::Error> {
+        let mut existed = false;
+        for path in [self.size_path(orbit, hash), self.data_path(orbit, hash)] {
+            match remove_file(path).await {
+                Ok(()) => existed = true,
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(existed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kepler_core::hash::hash;
+
+    #[test]
+    async fn resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let stage = ResumableFileSystemStage::new(dir.path().to_path_buf());
+        let orbit: OrbitId = "kepler:example://default".parse().unwrap();
+        let content = b"hello resumable world";
+        let h = hash(content);
+        let size = content.len() as u64;
+
+        assert!(matches!(
+            stage.append(&orbit, &h, 0, &content[..]).await,
+            Err(ResumableError::NotFound)
+        ));
+        assert_eq!(stage.begin(&orbit, &h, size).await.unwrap(), 0);
+        assert_eq!(stage.append(&orbit, &h, 0, &content[..5]).await.unwrap(), 5);
+        assert!(matches!(
+            stage.open(&orbit, &h).await,
+            Err(ResumableError::Incomplete)
+        ));
+
+        // an interrupted client learns where to continue from
+        assert_eq!(stage.begin(&orbit, &h, size).await.unwrap(), 5);
+        assert!(matches!(
+            stage.begin(&orbit, &h, size + 1).await,
+            Err(ResumableError::SizeMismatch(s)) if s == size
+        ));
+        assert!(matches!(
+            stage.append(&orbit, &h, 3, &content[3..]).await,
+            Err(ResumableError::OffsetMismatch(5))
+        ));
+        assert_eq!(
+            stage.append(&orbit, &h, 5, &content[5..]).await.unwrap(),
+            size
+        );
+
+        let mut read = Vec::new();
+        let (len, mut r) = stage.open(&orbit, &h).await.unwrap().into_inner();
+        r.read_to_end(&mut read).await.unwrap();
+        assert_eq!(len, size);
+        assert_eq!(read, content);
+
+        // completed uploads are left as they are
+        assert_eq!(stage.begin(&orbit, &h, size).await.unwrap(), size);
+        assert!(matches!(
+            stage.append(&orbit, &h, size, &b"more"[..]).await,
+            Err(ResumableError::TooLarge)
+        ));
+
+        assert!(stage.discard(&orbit, &h).await.unwrap());
+        assert!(!stage.discard(&orbit, &h).await.unwrap());
+    }
+
+    #[test]
+    async fn appends_are_serialized() {
+        let dir = tempfile::tempdir().unwrap();
+        let stage = ResumableFileSystemStage::new(dir.path().to_path_buf());
+        let orbit: OrbitId = "kepler:example://default".parse().unwrap();
+        let content = b"hello resumable world";
+        let h = hash(content);
+        stage.begin(&orbit, &h, content.len() as u64).await.unwrap();
+
+        let lock = stage.lock(&orbit, &h).await.unwrap();
+        assert!(matches!(
+            stage.append(&orbit, &h, 0, &content[..]).await,
+            Err(ResumableError::Busy)
+        ));
+        drop(lock);
+        assert_eq!(
+            stage.append(&orbit, &h, 0, &content[..]).await.unwrap(),
+            content.len() as u64
+        );
+        assert!(!stage.lock_path(&orbit, &h).exists());
+    }
+
+    #[test]
+    async fn declared_and_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let stage = ResumableFileSystemStage::new(dir.path().to_path_buf());
+        let orbit: OrbitId = "kepler:example://default".parse().unwrap();
+        let (a, b) = (hash(b"a"), hash(b"b"));
+        stage.begin(&orbit, &a, 10).await.unwrap();
+        stage.begin(&orbit, &b, 32).await.unwrap();
+        assert_eq!(stage.declared_total(&orbit, &a).await.unwrap(), 32);
+        assert_eq!(stage.declared_total(&orbit, &hash(b"c")).await.unwrap(), 42);
+
+        assert_eq!(
+            stage
+                .discard_stale(Duration::from_secs(3600))
+                .await
+                .unwrap(),
+            0
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(stage.discard_stale(Duration::ZERO).await.unwrap(), 2);
+        assert_eq!(stage.declared_total(&orbit, &a).await.unwrap(), 0);
+        assert!(matches!(
+            stage.append(&orbit, &a, 0, &b"a"[..]).await,
+            Err(ResumableError::NotFound)
+        ));
+    }
+}
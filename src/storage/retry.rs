@@ -0,0 +1,144 @@
+use crate::{config::Retry, prometheus::STORAGE_RETRIES};
+use aws_smithy_http::{operation::Response, result::SdkError};
+use rand::Rng;
+use std::{future::Future, time::Duration};
+
+/// The longest delay between two attempts, however many have been made.
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Errors which can tell whether the failed call may succeed if repeated.
+pub trait Retryable {
+    fn retryable(&self) -> bool;
+}
+
+impl<E> Retryable for SdkError<E, Response> {
+    fn retryable(&self) -> bool {
+        match self {
+            SdkError::TimeoutError(_)
+            | SdkError::DispatchFailure(_)
+            | SdkError::ResponseError { .. } => true,
+            SdkError::ServiceError { raw, .. } => {
+                let status = raw.http().status();
+                status.is_server_error() || status.as_u16() == 429
+            }
+            SdkError::ConstructionFailure(_) => false,
+        }
+    }
+}
+
+impl Retry {
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = Duration::from_millis(self.backoff)
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(MAX_DELAY);
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+}
+
+/// Run `op`, repeating it with backoff while it fails with a retryable error and the
+/// policy allows more attempts. Each retry is counted against `backend` in
+/// `kepler_storage_retries_total`.
+pub async fn retry<T, E, F, Fut>(policy: &Retry, backend: &str, mut op: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Retryable,
+{
+    let mut retries = 0;
+    loop {
+        match op().await {
+            Err(e) if retries + 1 < policy.attempts && e.retryable() => {
+                STORAGE_RETRIES.with_label_values(&[backend]).inc();
+                tokio::time::sleep(policy.delay(retries)).await;
+                retries += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug, PartialEq)]
+    enum MockError {
+        Transient,
+        Permanent,
+    }
+
+    impl Retryable for MockError {
+        fn retryable(&self) -> bool {
+            matches!(self, MockError::Transient)
+        }
+    }
+
+    fn policy(attempts: u32) -> Retry {
+        Retry {
+            attempts,
+            backoff: 1,
+            jitter: true,
+        }
+    }
+
+    /// A backend call which fails transiently `failures` times before succeeding.
+    async fn flaky(calls: &AtomicU32, failures: u32) -> Result<u32, MockError> {
+        let call = calls.fetch_add(1, Ordering::SeqCst);
+        if call < failures {
+            Err(MockError::Transient)
+        } else {
+            Ok(call)
+        }
+    }
+
+    #[test]
+    async fn succeeds_after_transient_failures() {
+        let counter = STORAGE_RETRIES.with_label_values(&["mock-transient"]);
+        let calls = AtomicU32::new(0);
+        let res = retry(&policy(3), "mock-transient", || flaky(&calls, 2)).await;
+        assert_eq!(res, Ok(2));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(counter.get(), 2);
+    }
+
+    #[test]
+    async fn gives_up_after_attempts() {
+        let counter = STORAGE_RETRIES.with_label_values(&["mock-exhausted"]);
+        let calls = AtomicU32::new(0);
+        let res = retry(&policy(3), "mock-exhausted", || flaky(&calls, 5)).await;
+        assert_eq!(res, Err(MockError::Transient));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(counter.get(), 2);
+    }
+
+    #[test]
+    async fn permanent_errors_are_not_retried() {
+        let counter = STORAGE_RETRIES.with_label_values(&["mock-permanent"]);
+        let calls = AtomicU32::new(0);
+        let res: Result<(), _> = retry(&policy(3), "mock-permanent", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(MockError::Permanent)
+        })
+        .await;
+        assert_eq!(res, Err(MockError::Permanent));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(counter.get(), 0);
+    }
+
+    #[test]
+    async fn delay_is_capped() {
+        let policy = Retry {
+            attempts: 100,
+            backoff: 1000,
+            jitter: false,
+        };
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+        assert_eq!(policy.delay(50), MAX_DELAY);
+    }
+}
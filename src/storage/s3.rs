@@ -3,12 +3,13 @@ use aws_sdk_s3::{
         GetObjectAttributesError, GetObjectAttributesErrorKind, GetObjectError, GetObjectErrorKind,
         HeadObjectError, HeadObjectErrorKind,
     },
+    presigning::config::{Error as PresigningError, PresigningConfig},
     types::{ByteStream, SdkError},
     Client, // Config,
     Error as S3Error,
 };
 use aws_smithy_http::{byte_stream::Error as ByteStreamError, endpoint::Endpoint};
-use aws_types::sdk_config::SdkConfig;
+use aws_types::{region::Region, sdk_config::SdkConfig};
 use futures::{
     future::Either as AsyncEither,
     stream::{IntoAsyncRead, MapErr, TryStreamExt},
@@ -18,9 +19,13 @@ use kepler_lib::resource::OrbitId;
 use rocket::{async_trait, http::hyper::Uri};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
-use std::{collections::HashMap, io::Error as IoError, ops::AddAssign};
+use std::{collections::HashMap, io::Error as IoError, ops::AddAssign, time::Duration};
 
-use super::{file_system, size::OrbitSizes};
+use super::{file_system, retry::retry, size::OrbitSizes};
+use crate::config::Retry;
+
+/// Label for this backend in storage metrics.
+const BACKEND: &str = "s3";
 
 async fn aws_config() -> SdkConfig {
     aws_config::from_env().load().await
@@ -31,15 +36,20 @@ pub struct S3BlockStore {
     pub client: Client,
     pub bucket: String,
     sizes: OrbitSizes,
+    retry: Retry,
 }
 
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
 pub struct S3BlockConfig {
     pub bucket: String,
+    /// URL of an S3-compatible store to use instead of AWS, e.g. `http://minio:9000`.
     #[serde_as(as = "Option<DisplayFromStr>")]
     #[serde(default)]
     pub endpoint: Option<Uri>,
+    /// Region of the bucket, overriding the one from the environment.
+    #[serde(default)]
+    pub region: Option<String>,
 }
 
 #[async_trait]
@@ -66,6 +76,10 @@ async fn new_client(config: &S3BlockConfig) -> Client {
         Some(e) => sdk_config.endpoint_resolver(Endpoint::immutable(e.clone())),
         None => sdk_config,
     };
+    let sdk_config = match &config.region {
+        Some(r) => sdk_config.region(Region::new(r.clone())),
+        None => sdk_config,
+    };
     let sdk_config = sdk_config.build();
     Client::from_conf(sdk_config)
 }
@@ -102,9 +116,44 @@ impl S3BlockStore {
             client,
             bucket: config.bucket.clone(),
             sizes,
+            retry: Retry::default(),
         })
     }
 
+    /// Use `retry` for calls to S3 which fail transiently.
+    pub fn with_retry(self, retry: Retry) -> Self {
+        Self { retry, ..self }
+    }
+
+    /// Check the bucket is reachable, without retrying.
+    pub async fn check(&self) -> Result<(), S3Error> {
+        self.client
+            .head_bucket()
+            .bucket(&self.bucket)
+            .send()
+            .await
+            .map_err(S3Error::from)?;
+        Ok(())
+    }
+
+    /// A URL from which the content `id` of `orbit` can be read directly, for `ttl`.
+    pub async fn presign_read(
+        &self,
+        orbit: &OrbitId,
+        id: &Hash,
+        ttl: Duration,
+    ) -> Result<String, S3StoreError> {
+        let request = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(orbit, id))
+            .presigned(PresigningConfig::expires_in(ttl)?)
+            .await
+            .map_err(S3Error::from)?;
+        Ok(request.uri().to_string())
+    }
+
     fn key(&self, orbit: &OrbitId, id: &Hash) -> String {
         format!(
             "{}/{}",
@@ -119,6 +168,39 @@ impl S3BlockStore {
     async fn decrement_size(&self, orbit: &OrbitId, size: u64) {
         self.sizes.decrement_size(orbit, size).await;
     }
+
+    async fn put_object(
+        &self,
+        orbit: &OrbitId,
+        id: &Hash,
+        body: ByteStream,
+    ) -> Result<(), S3Error> {
+        let body = body.into_inner();
+        let key = self.key(orbit, id);
+        retry(&self.retry, BACKEND, || {
+            // in-memory and file-backed bodies can be rebuilt for each attempt
+            let body = body.try_clone();
+            let key = &key;
+            async move {
+                match body {
+                    Some(b) => self
+                        .client
+                        .put_object()
+                        .bucket(&self.bucket)
+                        .key(key)
+                        .body(ByteStream::new(b))
+                        .send()
+                        .await
+                        .map(|_| ()),
+                    None => Err(SdkError::ConstructionFailure(
+                        "request body can't be replayed".into(),
+                    )),
+                }
+            }
+        })
+        .await?;
+        Ok(())
+    }
 }
 
 pub fn convert(e: ByteStreamError) -> IoError {
@@ -135,6 +217,8 @@ pub enum S3StoreError {
     Bytestream(#[from] ByteStreamError),
     #[error(transparent)]
     Length(#[from] std::num::TryFromIntError),
+    #[error(transparent)]
+    Presigning(#[from] PresigningError),
 }
 
 #[async_trait]
@@ -142,13 +226,14 @@ impl ImmutableReadStore for S3BlockStore {
     type Error = S3StoreError;
     type Readable = IntoAsyncRead<MapErr<ByteStream, fn(ByteStreamError) -> IoError>>;
     async fn contains(&self, orbit: &OrbitId, id: &Hash) -> Result<bool, Self::Error> {
-        match self
-            .client
-            .head_object()
-            .bucket(&self.bucket)
-            .key(self.key(orbit, id))
-            .send()
-            .await
+        match retry(&self.retry, BACKEND, || {
+            self.client
+                .head_object()
+                .bucket(&self.bucket)
+                .key(self.key(orbit, id))
+                .send()
+        })
+        .await
         {
             Ok(_) => Ok(true),
             Err(SdkError::ServiceError {
@@ -168,13 +253,14 @@ impl ImmutableReadStore for S3BlockStore {
         orbit: &OrbitId,
         id: &Hash,
     ) -> Result<Option<Content<Self::Readable>>, Self::Error> {
-        let res = self
-            .client
-            .get_object()
-            .bucket(&self.bucket)
-            .key(self.key(orbit, id))
-            .send()
-            .await;
+        let res = retry(&self.retry, BACKEND, || {
+            self.client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(self.key(orbit, id))
+                .send()
+        })
+        .await;
         match res {
             Ok(o) => Ok(Some(Content::new(
                 o.content_length().try_into()?,
@@ -208,14 +294,7 @@ impl ImmutableWriteStore<memory::MemoryStaging> for S3BlockStore {
 
         if !self.contains(orbit, &hash).await? {
             let size = f.len() as u64;
-            self.client
-                .put_object()
-                .bucket(&self.bucket)
-                .key(self.key(orbit, &hash))
-                .body(ByteStream::from(f))
-                .send()
-                .await
-                .map_err(S3Error::from)?;
+            self.put_object(orbit, &hash, ByteStream::from(f)).await?;
             self.increment_size(orbit, size).await;
         }
         Ok(hash)
@@ -237,14 +316,8 @@ impl ImmutableWriteStore<file_system::TempFileSystemStage> for S3BlockStore {
             let size = f.size().await?;
             let (_file, path) = f.into_inner();
 
-            self.client
-                .put_object()
-                .bucket(&self.bucket)
-                .key(self.key(orbit, &hash))
-                .body(ByteStream::from_path(&path).await?)
-                .send()
-                .await
-                .map_err(S3Error::from)?;
+            self.put_object(orbit, &hash, ByteStream::from_path(&path).await?)
+                .await?;
             self.increment_size(orbit, size).await;
         }
         Ok(hash)
@@ -269,26 +342,13 @@ impl ImmutableWriteStore<either::Either<file_system::TempFileSystemStage, memory
                 AsyncEither::Left(t_file) => {
                     let size = t_file.size().await?;
                     let (_file, path) = t_file.into_inner();
-                    self.client
-                        .put_object()
-                        .bucket(&self.bucket)
-                        .key(self.key(orbit, &hash))
-                        .body(ByteStream::from_path(&path).await?)
-                        .send()
-                        .await
-                        .map_err(S3Error::from)?;
+                    self.put_object(orbit, &hash, ByteStream::from_path(&path).await?)
+                        .await?;
                     self.increment_size(orbit, size).await;
                 }
                 AsyncEither::Right(b) => {
                     let size = b.len() as u64;
-                    self.client
-                        .put_object()
-                        .bucket(&self.bucket)
-                        .key(self.key(orbit, &hash))
-                        .body(ByteStream::from(b))
-                        .send()
-                        .await
-                        .map_err(S3Error::from)?;
+                    self.put_object(orbit, &hash, ByteStream::from(b)).await?;
                     self.increment_size(orbit, size).await;
                 }
             }
@@ -301,13 +361,14 @@ impl ImmutableWriteStore<either::Either<file_system::TempFileSystemStage, memory
 impl ImmutableDeleteStore for S3BlockStore {
     type Error = S3StoreError;
     async fn remove(&self, orbit: &OrbitId, id: &Hash) -> Result<Option<()>, Self::Error> {
-        let size: u64 = match self
-            .client
-            .get_object_attributes()
-            .bucket(&self.bucket)
-            .key(self.key(orbit, id))
-            .send()
-            .await
+        let size: u64 = match retry(&self.retry, BACKEND, || {
+            self.client
+                .get_object_attributes()
+                .bucket(&self.bucket)
+                .key(self.key(orbit, id))
+                .send()
+        })
+        .await
         {
             Ok(o) if !o.delete_marker() => o.object_size().try_into()?,
             Ok(_) => return Ok(None),
@@ -321,13 +382,14 @@ impl ImmutableDeleteStore for S3BlockStore {
             }) => return Ok(None),
             Err(e) => return Err(S3Error::from(e).into()),
         };
-        match self
-            .client
-            .delete_object()
-            .bucket(&self.bucket)
-            .key(self.key(orbit, id))
-            .send()
-            .await
+        match retry(&self.retry, BACKEND, || {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(self.key(orbit, id))
+                .send()
+        })
+        .await
         {
             Ok(_) => {
                 self.decrement_size(orbit, size).await;
@@ -346,3 +408,59 @@ impl StoreSize for S3BlockStore {
         Ok(self.sizes.get_size(orbit).await)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Runs against the S3-compatible store at `KEPLER_TEST_S3_ENDPOINT`, in the existing
+    // bucket `KEPLER_TEST_S3_BUCKET`, e.g. MinIO or the localstack from test/docker-compose.yml.
+    #[test]
+    #[ignore]
+    async fn s3_compatible_store() {
+        let store = S3BlockConfig {
+            bucket: std::env::var("KEPLER_TEST_S3_BUCKET").unwrap(),
+            endpoint: Some(
+                std::env::var("KEPLER_TEST_S3_ENDPOINT")
+                    .unwrap()
+                    .parse()
+                    .unwrap(),
+            ),
+            region: Some("us-east-1".to_string()),
+        }
+        .open()
+        .await
+        .unwrap();
+        let orbit: OrbitId = "kepler:example://s3-test".parse().unwrap();
+        store.create(&orbit).await.unwrap();
+        let before = store.total_size(&orbit).await.unwrap().unwrap();
+
+        let data = b"hello world";
+        let mut stage = memory::MemoryStaging.stage(&orbit).await.unwrap();
+        futures::io::copy(&mut &data[..], &mut stage).await.unwrap();
+        let hash = ImmutableWriteStore::<memory::MemoryStaging>::persist(&store, &orbit, stage)
+            .await
+            .unwrap();
+
+        assert!(store.contains(&orbit, &hash).await.unwrap());
+        assert_eq!(
+            store.read_to_vec(&orbit, &hash).await.unwrap().unwrap(),
+            data
+        );
+        assert_eq!(
+            store.total_size(&orbit).await.unwrap(),
+            Some(before + data.len() as u64)
+        );
+
+        let url = store
+            .presign_read(&orbit, &hash, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(url.contains("X-Amz-Expires=60"));
+
+        assert_eq!(store.remove(&orbit, &hash).await.unwrap(), Some(()));
+        assert!(!store.contains(&orbit, &hash).await.unwrap());
+        assert_eq!(store.read_to_vec(&orbit, &hash).await.unwrap(), None);
+        assert_eq!(store.total_size(&orbit).await.unwrap(), Some(before));
+    }
+}
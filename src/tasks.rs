@@ -0,0 +1,95 @@
+use rocket::fairing::AdHoc;
+use std::{future::Future, time::Duration};
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+
+/// Tasks which run in the background for as long as the server does. When the server
+/// shuts down they are told to stop, and are waited on for up to its grace period.
+pub struct BackgroundTasks {
+    stop: watch::Sender<bool>,
+    handles: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+/// Resolves once the background tasks are told to stop.
+#[derive(Clone)]
+pub struct Stop(watch::Receiver<bool>);
+
+impl Stop {
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    pub async fn requested(&mut self) {
+        while !*self.0.borrow() {
+            // nothing is left to tell the task to stop, so it stops now
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for BackgroundTasks {
+    fn default() -> Self {
+        Self {
+            stop: watch::channel(false).0,
+            handles: Vec::new(),
+        }
+    }
+}
+
+impl BackgroundTasks {
+    pub fn spawn<F, T>(&mut self, name: &'static str, task: F)
+    where
+        F: FnOnce(Stop) -> T,
+        T: Future<Output = ()> + Send + 'static,
+    {
+        let stop = Stop(self.stop.subscribe());
+        self.handles.push((name, tokio::spawn(task(stop))));
+    }
+
+    /// Tell the tasks to stop, and wait up to `grace` for them to, aborting those which
+    /// are still running.
+    pub async fn shutdown(self, grace: Duration) {
+        // fails only if every task has already finished
+        let _ = self.stop.send(true);
+        let deadline = Instant::now() + grace;
+        for (name, mut handle) in self.handles {
+            match tokio::time::timeout_at(deadline, &mut handle).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::warn!(task = name, error = %e, "background task failed"),
+                Err(_) => {
+                    tracing::warn!(task = name, "background task did not stop in time");
+                    handle.abort();
+                }
+            }
+        }
+    }
+
+    /// A fairing which shuts the tasks down along with the server.
+    pub fn fairing(self) -> AdHoc {
+        AdHoc::on_shutdown("Background Tasks", move |rocket| {
+            let grace = Duration::from_secs(rocket.config().shutdown.grace.into());
+            Box::pin(self.shutdown(grace))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    async fn shutdown() {
+        let mut tasks = BackgroundTasks::default();
+        let (done, stopped) = tokio::sync::oneshot::channel();
+        tasks.spawn("stops", |mut stop| async move {
+            stop.requested().await;
+            let _ = done.send(());
+        });
+        tasks.spawn("hangs", |_| futures::future::pending());
+
+        // a task which ignores the signal is given up on after the grace period
+        tasks.shutdown(Duration::from_millis(10)).await;
+        assert!(stopped.await.is_ok());
+    }
+}
@@ -1,4 +1,5 @@
 use opentelemetry::trace::TraceContextExt;
+use opentelemetry_otlp::WithExportConfig;
 use rocket::{
     fairing::{Fairing, Info, Kind},
     http::Status,
@@ -15,6 +16,10 @@ use crate::config;
 #[derive(Clone)]
 pub struct TracingSpan(pub Span);
 
+/// Identifier of a request, returned in the trace header and recorded as the `trace_id`
+/// of its span, so each log line of the request can be correlated with it.
+struct RequestId(Option<String>);
+
 pub struct TracingFairing {
     pub header_name: String,
 }
@@ -29,18 +34,21 @@ impl Fairing for TracingFairing {
     }
     async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
         let span = info_span!(parent: None, "request", trace_id = field::Empty);
-        span.record(
-            "trace_id",
-            &field::display(&span.context().span().span_context().trace_id()),
-        );
+        let context = span.context().span().span_context().clone();
+        // without an exporter there is no trace, so requests get an id of their own
+        let trace_id = if context.is_valid() {
+            context.trace_id().to_string()
+        } else {
+            format!("{:032x}", rand::random::<u128>())
+        };
+        span.record("trace_id", &field::display(&trace_id));
+        req.local_cache(|| RequestId(Some(trace_id)));
         req.local_cache(|| Some(TracingSpan(span)));
     }
 
     async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
-        if let Some(TracingSpan(span)) = req.local_cache(|| Option::<TracingSpan>::None).to_owned()
-        {
-            let trace_id = span.context().span().span_context().trace_id();
-            res.set_raw_header(self.header_name.clone(), format!("{trace_id}"));
+        if let RequestId(Some(trace_id)) = req.local_cache(|| RequestId(None)) {
+            res.set_raw_header(self.header_name.clone(), trace_id.clone());
         }
     }
 }
@@ -63,13 +71,32 @@ pub fn tracing_try_init(config: &config::Logging) {
     let subscriber = tracing_subscriber::fmt::layer();
     let log = match config.format {
         config::LoggingFormat::Text => subscriber.boxed(),
-        config::LoggingFormat::Json => subscriber.json().boxed(),
+        config::LoggingFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+        config::LoggingFormat::Pretty => subscriber.pretty().boxed(),
+        config::LoggingFormat::Compact => subscriber.compact().boxed(),
     };
     let telemetry = if config.tracing.enabled {
-        let tracer = opentelemetry_jaeger::new_pipeline()
-            .with_service_name("kepler")
-            .install_batch(opentelemetry::runtime::Tokio)
-            .unwrap();
+        let tracer = match config.tracing.exporter {
+            config::TracingExporter::Jaeger => opentelemetry_jaeger::new_pipeline()
+                .with_service_name("kepler")
+                .install_batch(opentelemetry::runtime::Tokio)
+                .unwrap(),
+            config::TracingExporter::Otlp => opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_env())
+                .with_trace_config(opentelemetry::sdk::trace::config().with_resource(
+                    opentelemetry::sdk::Resource::new([opentelemetry::KeyValue::new(
+                        "service.name",
+                        "kepler",
+                    )]),
+                ))
+                .install_batch(opentelemetry::runtime::Tokio)
+                .unwrap(),
+        };
         let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
         Some(telemetry)
     } else {